@@ -0,0 +1,128 @@
+use crate::Interpreter;
+use serde::Serialize;
+use std::cell::RefCell;
+use std::io;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use tree_walking::runner::RunOptions;
+
+/// Summary of the times of repeated runs, in milliseconds.
+#[derive(Serialize)]
+pub(crate) struct Stats {
+  pub(crate) mean: f64,
+  pub(crate) median: f64,
+  pub(crate) min: f64,
+  pub(crate) max: f64,
+  pub(crate) runs: usize,
+}
+
+impl Stats {
+  /// Summarizes `times`, of which there must be at least one.
+  pub(crate) fn new(mut times: Vec<Duration>) -> Self {
+    times.sort();
+
+    let runs = times.len();
+    let mean = times.iter().sum::<Duration>() / runs as u32;
+    let median = if runs.is_multiple_of(2) {
+      (times[runs / 2 - 1] + times[runs / 2]) / 2
+    } else {
+      times[runs / 2]
+    };
+
+    let milliseconds = |duration: Duration| duration.as_secs_f64() * 1000.0;
+
+    Stats {
+      mean: milliseconds(mean),
+      median: milliseconds(median),
+      min: milliseconds(times[0]),
+      max: milliseconds(times[runs - 1]),
+      runs,
+    }
+  }
+}
+
+/// Timings of the same program run by both interpreters.
+#[derive(Serialize)]
+pub(crate) struct Comparison {
+  tree_walking: Stats,
+  vm: Stats,
+  /// How many times faster the bytecode interpreter is, by mean time
+  speedup: f64,
+}
+
+impl Comparison {
+  pub(crate) fn report(&self) -> String {
+    let row = |name: &str, stats: &Stats| {
+      format!(
+        "{:<14} mean {:>10.3} ms  median {:>10.3} ms  min {:>10.3} ms  max {:>10.3} ms  ({} runs)",
+        name, stats.mean, stats.median, stats.min, stats.max, stats.runs
+      )
+    };
+
+    format!(
+      "{}\n{}\nspeedup: {:.2}x",
+      row("tree-walking", &self.tree_walking),
+      row("vm", &self.vm),
+      self.speedup
+    )
+  }
+}
+
+/// Runs `source` as a whole, discarding its output.
+fn run(source: &str, runner: Interpreter) -> anyhow::Result<()> {
+  match runner {
+    Interpreter::TreeWalking => {
+      let options = RunOptions {
+        stdout: Rc::new(RefCell::new(io::sink())),
+        stderr: Rc::new(RefCell::new(io::sink())),
+        ..RunOptions::default()
+      };
+
+      Ok(tree_walking::runner::run_with_options(source.to_string(), &options)?)
+    }
+    Interpreter::VM => {
+      let options = vm::runner::RunOptions {
+        stdout: Rc::new(RefCell::new(io::sink())),
+        stderr: Rc::new(RefCell::new(io::sink())),
+        ..vm::runner::RunOptions::default()
+      };
+
+      Ok(vm::runner::run_with_options(source.to_string(), &options)?)
+    }
+  }
+}
+
+/// Times `iterations` runs of `source` with `runner`, after `warmup` untimed
+/// ones.
+fn time(source: &str, runner: Interpreter, warmup: usize, iterations: usize) -> anyhow::Result<Stats> {
+  let name = match runner {
+    Interpreter::TreeWalking => "tree-walking",
+    Interpreter::VM => "bytecode",
+  };
+
+  for _ in 0..warmup {
+    run(source, runner).map_err(|e| anyhow::anyhow!("the {name} interpreter failed: {e}"))?;
+  }
+
+  let mut times = Vec::with_capacity(iterations);
+
+  for _ in 0..iterations {
+    let start = Instant::now();
+    run(source, runner).map_err(|e| anyhow::anyhow!("the {name} interpreter failed: {e}"))?;
+    times.push(start.elapsed());
+  }
+
+  Ok(Stats::new(times))
+}
+
+/// Runs the whole of `source` on both interpreters and compares their times.
+pub(crate) fn compare(source: &str, warmup: usize, iterations: usize) -> anyhow::Result<Comparison> {
+  let tree_walking = time(source, Interpreter::TreeWalking, warmup, iterations)?;
+  let vm = time(source, Interpreter::VM, warmup, iterations)?;
+
+  Ok(Comparison {
+    speedup: tree_walking.mean / vm.mean,
+    tree_walking,
+    vm,
+  })
+}
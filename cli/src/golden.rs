@@ -0,0 +1,268 @@
+//! Golden-file tests: Lox programs stating in comments what they print, as
+//! in the test suite of Crafting Interpreters. `// expect: text` is a line
+//! of output and `// expect-error: text` a part of the error the program
+//! fails with.
+
+use crate::{collect_lox_files, error_diagnostics, Interpreter};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::{fs, io, thread};
+use tree_walking::runner::RunOptions;
+
+const EXPECT: &str = "// expect: ";
+const EXPECT_ERROR: &str = "// expect-error: ";
+
+/// What a test program should do, read from its comments.
+struct Expectations {
+  /// Lines of output, in order
+  output: Vec<String>,
+  /// Parts of the messages of the errors it fails with
+  errors: Vec<String>,
+}
+
+impl Expectations {
+  fn read(source: &str) -> Self {
+    let mut expectations = Expectations {
+      output: vec![],
+      errors: vec![],
+    };
+
+    for line in source.lines() {
+      if let Some((_, expected)) = line.split_once(EXPECT) {
+        expectations.output.push(expected.trim_end().to_string());
+      } else if let Some((_, expected)) = line.split_once(EXPECT_ERROR) {
+        expectations.errors.push(expected.trim_end().to_string());
+      }
+    }
+
+    expectations
+  }
+}
+
+/// Runs `source` with `runner`, returning its output and the messages of the
+/// errors it failed with.
+fn run(source: String, runner: Interpreter) -> (String, Vec<String>) {
+  let stdout = Rc::new(RefCell::new(Vec::<u8>::new()));
+
+  let result = match runner {
+    Interpreter::TreeWalking => {
+      let options = RunOptions {
+        stdout: stdout.clone(),
+        stderr: Rc::new(RefCell::new(io::sink())),
+        ..RunOptions::default()
+      };
+
+      tree_walking::runner::run_with_options(source, &options).map_err(anyhow::Error::from)
+    }
+    Interpreter::VM => {
+      let options = vm::runner::RunOptions {
+        stdout: stdout.clone(),
+        stderr: Rc::new(RefCell::new(io::sink())),
+        ..vm::runner::RunOptions::default()
+      };
+
+      vm::runner::run_with_options(source, &options).map_err(anyhow::Error::from)
+    }
+  };
+
+  let errors = match result {
+    Ok(()) => vec![],
+    Err(e) => error_diagnostics(&e).into_iter().map(|diagnostic| diagnostic.message).collect(),
+  };
+
+  (String::from_utf8_lossy(&stdout.take()).into_owned(), errors)
+}
+
+/// How the run of `source` differs from what its comments expect, empty if
+/// the test passes.
+fn check(source: String, runner: Interpreter) -> Vec<String> {
+  let expectations = Expectations::read(&source);
+  let (output, errors) = run(source, runner);
+  let output = output.lines().collect::<Vec<&str>>();
+
+  let mut problems = vec![];
+
+  for (index, expected) in expectations.output.iter().enumerate() {
+    match output.get(index) {
+      Some(line) if line == expected => {}
+      Some(line) => problems.push(format!("expected output {:?}, got {:?}", expected, line)),
+      None => problems.push(format!("expected output {:?}, got nothing", expected)),
+    }
+  }
+
+  for line in output.iter().skip(expectations.output.len()) {
+    problems.push(format!("unexpected output {:?}", line));
+  }
+
+  for expected in &expectations.errors {
+    if !errors.iter().any(|error| error.contains(expected.as_str())) {
+      problems.push(format!("expected an error containing {:?}", expected));
+    }
+  }
+
+  if expectations.errors.is_empty() {
+    for error in errors {
+      problems.push(format!("unexpected error: {}", error));
+    }
+  }
+
+  problems
+}
+
+/// Runs the test programs at `path`, a file or a directory searched for
+/// `.lox` files, on `jobs` threads and prints the failures and a summary.
+/// Returns whether all of them passed.
+pub(crate) fn run_tests(path: &str, runner: Interpreter, jobs: NonZeroUsize) -> anyhow::Result<bool> {
+  report_tests(Path::new(path), runner, jobs, &mut io::stdout())
+}
+
+/// Runs the tests like `run_tests`, writing the report to `out`. Failures are
+/// reported in the order of the files, whichever test finishes first.
+fn report_tests(path: &Path, runner: Interpreter, jobs: NonZeroUsize, out: &mut dyn Write) -> anyhow::Result<bool> {
+  let files = collect_lox_files(path)?;
+  let sources = files
+    .iter()
+    .map(|file| fs::read_to_string(file).map_err(|e| anyhow::anyhow!("could not read {}: {e}", file.display())))
+    .collect::<anyhow::Result<Vec<String>>>()?;
+
+  let next = AtomicUsize::new(0);
+  let (sender, receiver) = mpsc::channel();
+  let mut failed = 0;
+
+  thread::scope(|scope| -> io::Result<()> {
+    for _ in 0..jobs.get().min(sources.len()) {
+      let (next, sources, sender) = (&next, &sources, sender.clone());
+
+      scope.spawn(move || loop {
+        let index = next.fetch_add(1, Ordering::Relaxed);
+        let Some(source) = sources.get(index) else {
+          break;
+        };
+
+        // Sending only fails once writing the report did
+        if sender.send((index, check(source.clone(), runner))).is_err() {
+          break;
+        }
+      });
+    }
+
+    drop(sender);
+
+    // Problems of the tests finished ahead of one still running
+    let mut pending = BTreeMap::new();
+    let mut reported = 0;
+
+    for (index, problems) in receiver {
+      pending.insert(index, problems);
+
+      while let Some(problems) = pending.remove(&reported) {
+        if !problems.is_empty() {
+          failed += 1;
+
+          writeln!(out, "FAIL {}", files[reported].display())?;
+
+          for problem in problems {
+            writeln!(out, "  {}", problem)?;
+          }
+        }
+
+        reported += 1;
+      }
+    }
+
+    Ok(())
+  })?;
+
+  writeln!(out, "{} passed, {} failed", files.len() - failed, failed)?;
+
+  Ok(failed == 0)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Checks that `source` does what its comments expect on both interpreters.
+  fn assert_passes(source: &str) {
+    for runner in [Interpreter::TreeWalking, Interpreter::VM] {
+      assert_eq!(check(source.to_string(), runner), Vec::<String>::new(), "{:?}", runner);
+    }
+  }
+
+  #[test]
+  fn test_report_tests() {
+    let dir = std::env::temp_dir().join(format!("rslox-golden-test-{}", std::process::id()));
+    let tests = [
+      ("a.lox", "print 1; // expect: 2"),
+      ("b.lox", "print 1; // expect: 1"),
+      ("c/d.lox", "print -nil; // expect-error: expected type"),
+      ("c/e.lox", "print -\"a\";"),
+      ("f.lox", "print 1;"),
+    ];
+
+    for (name, source) in tests {
+      let path = dir.join(name);
+
+      fs::create_dir_all(path.parent().unwrap()).unwrap();
+      fs::write(path, source).unwrap();
+    }
+
+    for jobs in [1, 2, 8] {
+      let mut out = vec![];
+      let passed = report_tests(&dir, Interpreter::TreeWalking, NonZeroUsize::new(jobs).unwrap(), &mut out).unwrap();
+
+      assert!(!passed);
+      assert_eq!(
+        String::from_utf8(out).unwrap(),
+        format!(
+          "FAIL {}\n  expected output \"2\", got \"1\"\n\
+           FAIL {}\n  unexpected error: expected type \"number\" given \"string\"\n\
+           FAIL {}\n  unexpected output \"1\"\n\
+           2 passed, 3 failed\n",
+          dir.join("a.lox").display(),
+          dir.join("c/e.lox").display(),
+          dir.join("f.lox").display(),
+        )
+      );
+    }
+
+    let mut out = vec![];
+
+    assert!(report_tests(&dir.join("b.lox"), Interpreter::VM, NonZeroUsize::MIN, &mut out).unwrap());
+    assert_eq!(String::from_utf8(out).unwrap(), "1 passed, 0 failed\n");
+
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn test_truthiness() {
+    assert_passes(
+      r#"
+      print !nil; // expect: true
+      print !false; // expect: true
+      print !0; // expect: false
+      print !""; // expect: false
+      print !!nil; // expect: false
+      if (nil) { print "t"; } else { print "f"; } // expect: f
+      "#,
+    );
+  }
+
+  #[test]
+  fn test_output_natives() {
+    assert_passes(
+      r#"
+      println("a", 1,); // expect: a 1
+      write("b",);
+      eprintln("not on stdout",);
+      println(); // expect: b
+      "#,
+    );
+  }
+}
@@ -0,0 +1,666 @@
+use scanner::{Scanner, Token, TokenType, KEYWORDS};
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, IsTerminal, Read, Write};
+use std::path::PathBuf;
+
+/// Lists the words that can complete the start of a word, sorted.
+pub(crate) type Completer<'a> = dyn Fn(&str) -> Vec<String> + 'a;
+
+/// Source of the lines typed into the REPL.
+pub(crate) trait LineEditor {
+  /// Shows `prompt` and reads a line, `None` once the input ends. Fails with
+  /// `ErrorKind::Interrupted` when the user cancels the line. Tab completes
+  /// the word before the cursor with `complete` where editing is supported.
+  fn read_line(&mut self, prompt: &str, complete: &Completer) -> io::Result<Option<String>>;
+
+  /// Remembers an entered line so it can be recalled later.
+  fn add_history(&mut self, line: &str);
+}
+
+/// The editor suited to stdin: one with editing and history for terminals,
+/// plain line reading otherwise. The line is highlighted if stdout is a
+/// terminal and `NO_COLOR` is not set.
+pub(crate) fn for_stdin(history_path: Option<PathBuf>) -> Box<dyn LineEditor> {
+  #[cfg(unix)]
+  if io::stdin().is_terminal() {
+    let highlight = io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none();
+
+    return Box::new(terminal::TerminalEditor::new(History::load(history_path), highlight));
+  }
+
+  let _ = history_path;
+
+  Box::new(PlainEditor)
+}
+
+/// Reads lines as they come, e.g. from a pipe.
+struct PlainEditor;
+
+impl LineEditor for PlainEditor {
+  fn read_line(&mut self, prompt: &str, _complete: &Completer) -> io::Result<Option<String>> {
+    print!("{prompt}");
+    io::stdout().flush()?;
+
+    let mut line = String::new();
+
+    if io::stdin().lock().read_line(&mut line)? == 0 {
+      return Ok(None);
+    }
+
+    Ok(Some(line.trim_end_matches(['\n', '\r']).to_string()))
+  }
+
+  fn add_history(&mut self, _line: &str) {}
+}
+
+/// Lines entered before, oldest first, kept in a file between sessions. The
+/// file holds at most `LIMIT` lines too.
+struct History {
+  entries: Vec<String>,
+  path: Option<PathBuf>,
+}
+
+impl History {
+  /// Most lines kept in memory and recalled
+  const LIMIT: usize = 1000;
+
+  fn load(path: Option<PathBuf>) -> Self {
+    let mut entries = path
+      .as_ref()
+      .and_then(|path| fs::read_to_string(path).ok())
+      .map(|contents| contents.lines().map(str::to_string).collect::<Vec<String>>())
+      .unwrap_or_default();
+
+    let dropped = entries.drain(..entries.len().saturating_sub(Self::LIMIT)).count();
+    let history = History { entries, path };
+
+    if dropped > 0 {
+      history.save();
+    }
+
+    history
+  }
+
+  /// Appends `line` unless it is blank or repeats the previous one. Failing
+  /// to save it only loses it for later sessions.
+  fn add(&mut self, line: &str) {
+    if line.trim().is_empty() || self.entries.last().is_some_and(|last| last == line) {
+      return;
+    }
+
+    self.entries.push(line.to_string());
+
+    // Once full, the file is rewritten so the oldest line leaves it too
+    if self.entries.len() > Self::LIMIT {
+      self.entries.remove(0);
+      self.save();
+    } else {
+      self.write(|file| writeln!(file, "{line}"), false);
+    }
+  }
+
+  /// Replaces the file with the entries kept in memory.
+  fn save(&self) {
+    self.write(|file| self.entries.iter().try_for_each(|entry| writeln!(file, "{entry}")), true);
+  }
+
+  /// Writes to the history file, if there is one, replacing its contents
+  /// when `truncate` is set and appending otherwise.
+  fn write(&self, contents: impl FnOnce(&mut fs::File) -> io::Result<()>, truncate: bool) {
+    if let Some(path) = &self.path {
+      let _ = path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| OpenOptions::new().create(true).write(true).append(!truncate).truncate(truncate).open(path))
+        .and_then(|mut file| contents(&mut file));
+    }
+  }
+}
+
+/// What a key press asks the editor to do.
+#[derive(Debug, PartialEq)]
+enum Key {
+  Char(char),
+  Enter,
+  Backspace,
+  Delete,
+  Left,
+  Right,
+  Home,
+  End,
+  Up,
+  Down,
+  Tab,
+  /// Ctrl-U
+  KillToStart,
+  /// Ctrl-K
+  KillToEnd,
+  /// Ctrl-C
+  Interrupt,
+  /// Ctrl-D
+  EndOfInput,
+  Unknown,
+}
+
+fn read_byte(input: &mut impl Read) -> io::Result<Option<u8>> {
+  let mut byte = [0];
+
+  match input.read(&mut byte)? {
+    0 => Ok(None),
+    _ => Ok(Some(byte[0])),
+  }
+}
+
+/// Decodes the next key press from the bytes a terminal in raw mode sends,
+/// `None` once the input ends.
+fn read_key(input: &mut impl Read) -> io::Result<Option<Key>> {
+  let Some(byte) = read_byte(input)? else {
+    return Ok(None);
+  };
+
+  let key = match byte {
+    b'\r' | b'\n' => Key::Enter,
+    b'\t' => Key::Tab,
+    127 | 8 => Key::Backspace,
+    1 => Key::Home,
+    2 => Key::Left,
+    3 => Key::Interrupt,
+    4 => Key::EndOfInput,
+    5 => Key::End,
+    6 => Key::Right,
+    11 => Key::KillToEnd,
+    14 => Key::Down,
+    16 => Key::Up,
+    21 => Key::KillToStart,
+    // Escape sequences, e.g. `ESC [ A` for the up arrow
+    27 => match (read_byte(input)?, read_byte(input)?) {
+      (Some(b'[' | b'O'), Some(b'A')) => Key::Up,
+      (Some(b'[' | b'O'), Some(b'B')) => Key::Down,
+      (Some(b'[' | b'O'), Some(b'C')) => Key::Right,
+      (Some(b'[' | b'O'), Some(b'D')) => Key::Left,
+      (Some(b'[' | b'O'), Some(b'H')) => Key::Home,
+      (Some(b'[' | b'O'), Some(b'F')) => Key::End,
+      (Some(b'['), Some(digit @ b'1'..=b'8')) => match (digit, read_byte(input)?) {
+        (b'3', Some(b'~')) => Key::Delete,
+        (b'1' | b'7', Some(b'~')) => Key::Home,
+        (b'4' | b'8', Some(b'~')) => Key::End,
+        _ => Key::Unknown,
+      },
+      _ => Key::Unknown,
+    },
+    byte if byte < 32 => Key::Unknown,
+    byte => {
+      // The rest of a multi-byte UTF-8 character follows its first byte
+      let length = match byte {
+        0xf0.. => 4,
+        0xe0.. => 3,
+        0xc0.. => 2,
+        _ => 1,
+      };
+      let mut bytes = vec![byte];
+
+      for _ in 1..length {
+        bytes.extend(read_byte(input)?);
+      }
+
+      match std::str::from_utf8(&bytes).ok().and_then(|text| text.chars().next()) {
+        Some(character) => Key::Char(character),
+        None => Key::Unknown,
+      }
+    }
+  };
+
+  Ok(Some(key))
+}
+
+/// The ANSI style a token is drawn in, `None` for plain text.
+fn token_style(token: &Token) -> Option<&'static str> {
+  match &token.kind {
+    TokenType::Number(_) => Some("33"),
+    TokenType::String(_) | TokenType::Interpolation(_) => Some("32"),
+    TokenType::Comment(_) => Some("90"),
+    TokenType::Identifier(_) => None,
+    _ if KEYWORDS.contains(&token.lexeme.as_str()) => Some("35"),
+    _ => None,
+  }
+}
+
+/// Colors the keywords, literals and comments of `text`. Whatever follows a
+/// scan error, like an unterminated string, is left plain.
+fn highlight(text: &str) -> String {
+  let mut highlighted = String::new();
+  let mut end = 0;
+
+  for token in Scanner::with_comments(text.to_string()).map_while(Result::ok) {
+    let Some(style) = token_style(&token) else {
+      continue;
+    };
+
+    highlighted.push_str(&text[end..token.span.start]);
+    highlighted.push_str(&format!("\x1b[{style}m{}\x1b[0m", &text[token.span.clone()]));
+    end = token.span.end;
+  }
+
+  highlighted.push_str(&text[end..]);
+
+  highlighted
+}
+
+/// Redraws the line after the prompt, highlighted if asked to, and puts the
+/// cursor back in place.
+fn refresh(output: &mut impl Write, prompt: &str, line: &[char], cursor: usize, colors: bool) -> io::Result<()> {
+  let text = line.iter().collect::<String>();
+  let text = if colors { highlight(&text) } else { text };
+
+  write!(output, "\r{prompt}{text}\x1b[K\r")?;
+
+  let column = prompt.chars().count() + cursor;
+
+  if column > 0 {
+    write!(output, "\x1b[{column}C")?;
+  }
+
+  output.flush()
+}
+
+/// Characters of the words Tab completes.
+fn is_word_char(character: &char) -> bool {
+  character.is_alphanumeric() || *character == '_'
+}
+
+/// The longest start all of `words` share.
+fn common_prefix(words: &[String]) -> String {
+  let Some((first, rest)) = words.split_first() else {
+    return String::new();
+  };
+
+  let length = rest.iter().fold(first.len(), |length, word| {
+    first[..length]
+      .char_indices()
+      .zip(word.chars())
+      .find(|((_, a), b)| a != b)
+      .map_or(length.min(word.len()), |((index, _), _)| index)
+  });
+
+  first[..length].to_string()
+}
+
+/// Reads a line from the key presses in `input`, drawing it in `output`, with
+/// the usual keys: arrows move the cursor and recall `history`, Ctrl-A/E jump
+/// to the start/end, Ctrl-U/K delete to the start/end, Ctrl-C cancels and
+/// Ctrl-D on an empty line quits. Tab extends the word before the cursor as
+/// far as the words from `complete` agree, and lists them when they don't.
+/// The line is highlighted when `colors` is set.
+fn edit_line(
+  prompt: &str,
+  history: &History,
+  complete: &Completer,
+  colors: bool,
+  input: &mut impl Read,
+  output: &mut impl Write,
+) -> io::Result<Option<String>> {
+  let mut line: Vec<char> = vec![];
+  let mut cursor = 0;
+  // Position in the history being shown, its length for the new line
+  let mut recalled = history.entries.len();
+  let mut draft: Vec<char> = vec![];
+
+  refresh(output, prompt, &line, cursor, colors)?;
+
+  loop {
+    let Some(key) = read_key(input)? else {
+      write!(output, "\r\n")?;
+      output.flush()?;
+
+      return Ok(None);
+    };
+
+    match key {
+      Key::Enter => {
+        write!(output, "\r\n")?;
+        output.flush()?;
+
+        return Ok(Some(line.into_iter().collect()));
+      }
+      Key::Interrupt => {
+        write!(output, "^C\r\n")?;
+        output.flush()?;
+
+        return Err(io::ErrorKind::Interrupted.into());
+      }
+      Key::EndOfInput if line.is_empty() => {
+        write!(output, "\r\n")?;
+        output.flush()?;
+
+        return Ok(None);
+      }
+      Key::EndOfInput | Key::Delete => {
+        if cursor < line.len() {
+          line.remove(cursor);
+        }
+      }
+      Key::Char(character) => {
+        line.insert(cursor, character);
+        cursor += 1;
+      }
+      Key::Backspace => {
+        if cursor > 0 {
+          cursor -= 1;
+          line.remove(cursor);
+        }
+      }
+      Key::Left => cursor = cursor.saturating_sub(1),
+      Key::Right => cursor = (cursor + 1).min(line.len()),
+      Key::Home => cursor = 0,
+      Key::End => cursor = line.len(),
+      Key::KillToStart => {
+        line.drain(..cursor);
+        cursor = 0;
+      }
+      Key::KillToEnd => line.truncate(cursor),
+      Key::Tab => {
+        let start = line[..cursor].iter().rposition(|character| !is_word_char(character)).map_or(0, |index| index + 1);
+        let word = line[start..cursor].iter().collect::<String>();
+        let candidates = if word.is_empty() { vec![] } else { complete(&word) };
+        let common = common_prefix(&candidates);
+
+        if common.len() > word.len() {
+          for character in common.chars().skip(cursor - start) {
+            line.insert(cursor, character);
+            cursor += 1;
+          }
+        } else if candidates.len() > 1 {
+          write!(output, "\r\n{}\r\n", candidates.join("  "))?;
+        }
+      }
+      Key::Up | Key::Down => {
+        let entries = &history.entries;
+        let target = match key {
+          Key::Up => recalled.checked_sub(1),
+          _ => Some(recalled + 1).filter(|target| *target <= entries.len()),
+        };
+
+        if let Some(target) = target {
+          if recalled == entries.len() {
+            draft = line.clone();
+          }
+
+          line = match entries.get(target) {
+            Some(entry) => entry.chars().collect(),
+            None => draft.clone(),
+          };
+          recalled = target;
+          cursor = line.len();
+        }
+      }
+      Key::Unknown => {}
+    }
+
+    refresh(output, prompt, &line, cursor, colors)?;
+  }
+}
+
+#[cfg(unix)]
+mod terminal {
+  use super::{edit_line, Completer, History, LineEditor};
+  use std::io;
+  use std::mem::MaybeUninit;
+
+  /// Puts the terminal in raw mode while alive, so keys arrive one by one
+  /// without being echoed. Dropping it, also while unwinding from a panic,
+  /// restores the settings the terminal had before.
+  struct RawMode {
+    original: libc::termios,
+  }
+
+  impl RawMode {
+    fn enable() -> io::Result<Self> {
+      let mut termios = MaybeUninit::<libc::termios>::uninit();
+
+      // SAFETY: `termios.as_mut_ptr()` points to writable memory the size of
+      // a `termios`, which `tcgetattr` fills in completely when it returns 0.
+      // It is only assumed initialized after that check.
+      let original = unsafe {
+        if libc::tcgetattr(libc::STDIN_FILENO, termios.as_mut_ptr()) != 0 {
+          return Err(io::Error::last_os_error());
+        }
+
+        termios.assume_init()
+      };
+
+      let mut raw = original;
+      raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG | libc::IEXTEN);
+      raw.c_iflag &= !(libc::IXON | libc::ICRNL);
+      raw.c_cc[libc::VMIN] = 1;
+      raw.c_cc[libc::VTIME] = 0;
+
+      // SAFETY: `raw` is an initialized `termios` that outlives the call, and
+      // `tcsetattr` only reads through the pointer.
+      if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, &raw) } != 0 {
+        return Err(io::Error::last_os_error());
+      }
+
+      Ok(RawMode { original })
+    }
+  }
+
+  impl Drop for RawMode {
+    fn drop(&mut self) {
+      // SAFETY: `self.original` was filled in by a successful `tcgetattr` in
+      // `enable` and `tcsetattr` only reads it. A failure can't be reported
+      // from `drop` and leaves the terminal as it is.
+      unsafe {
+        libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, &self.original);
+      }
+    }
+  }
+
+  /// Edits lines typed into a terminal, see `edit_line`.
+  pub(crate) struct TerminalEditor {
+    history: History,
+    /// Whether the line is syntax highlighted
+    colors: bool,
+  }
+
+  impl TerminalEditor {
+    pub(crate) fn new(history: History, colors: bool) -> Self {
+      TerminalEditor { history, colors }
+    }
+  }
+
+  impl LineEditor for TerminalEditor {
+    fn read_line(&mut self, prompt: &str, complete: &Completer) -> io::Result<Option<String>> {
+      let _raw_mode = RawMode::enable()?;
+
+      let (mut input, mut output) = (io::stdin().lock(), io::stdout().lock());
+
+      edit_line(prompt, &self.history, complete, self.colors, &mut input, &mut output)
+    }
+
+    fn add_history(&mut self, line: &str) {
+      self.history.add(line);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn history(entries: &[&str]) -> History {
+    History {
+      entries: entries.iter().map(|entry| entry.to_string()).collect(),
+      path: None,
+    }
+  }
+
+  fn no_completions(_: &str) -> Vec<String> {
+    vec![]
+  }
+
+  fn edit(history: &History, input: &str) -> io::Result<Option<String>> {
+    edit_line("> ", history, &no_completions, false, &mut input.as_bytes(), &mut io::sink())
+  }
+
+  #[test]
+  fn test_read_key() {
+    let mut input = concat!(
+      "a\r\t\x7f\x01\x05\x03\x04\x15\x0b",
+      "\x1b[A\x1bOB\x1b[C\x1b[D\x1b[3~\x1b[1~\x1b[4~\x1b[Z",
+      "é\x00"
+    )
+    .as_bytes();
+    let mut keys = vec![];
+
+    while let Some(key) = read_key(&mut input).unwrap() {
+      keys.push(key);
+    }
+
+    assert_eq!(
+      keys,
+      vec![
+        Key::Char('a'),
+        Key::Enter,
+        Key::Tab,
+        Key::Backspace,
+        Key::Home,
+        Key::End,
+        Key::Interrupt,
+        Key::EndOfInput,
+        Key::KillToStart,
+        Key::KillToEnd,
+        Key::Up,
+        Key::Down,
+        Key::Right,
+        Key::Left,
+        Key::Delete,
+        Key::Home,
+        Key::End,
+        Key::Unknown,
+        Key::Char('é'),
+        Key::Unknown,
+      ]
+    );
+  }
+
+  #[test]
+  fn test_edit_line() {
+    let empty = history(&[]);
+
+    assert_eq!(edit(&empty, "print 1;\r").unwrap(), Some("print 1;".to_string()));
+    // Left, then insert; Home, then delete the first character
+    assert_eq!(edit(&empty, "ac\x1b[Db\r").unwrap(), Some("abc".to_string()));
+    assert_eq!(edit(&empty, "xabc\x01\x1b[3~\r").unwrap(), Some("abc".to_string()));
+    assert_eq!(edit(&empty, "abd\x7fc\r").unwrap(), Some("abc".to_string()));
+    assert_eq!(edit(&empty, "xyabc\x1b[D\x1b[D\x1b[D\x15\x05\r").unwrap(), Some("abc".to_string()));
+    assert_eq!(edit(&empty, "abcxyz\x1b[D\x1b[D\x1b[D\x0b\r").unwrap(), Some("abc".to_string()));
+    assert_eq!(edit(&empty, "é\x1b[Dà\r").unwrap(), Some("àé".to_string()));
+
+    assert_eq!(edit(&empty, "").unwrap(), None);
+    assert_eq!(edit(&empty, "\x04").unwrap(), None);
+    assert_eq!(edit(&empty, "ab\x01\x04\r").unwrap(), Some("b".to_string()));
+    assert_eq!(edit(&empty, "ab\x03").unwrap_err().kind(), io::ErrorKind::Interrupted);
+  }
+
+  #[test]
+  fn test_edit_line_history() {
+    let history = history(&["first", "second"]);
+
+    assert_eq!(edit(&history, "\x1b[A\r").unwrap(), Some("second".to_string()));
+    assert_eq!(edit(&history, "\x1b[A\x1b[A\x1b[A\r").unwrap(), Some("first".to_string()));
+    assert_eq!(edit(&history, "\x1b[A!\r").unwrap(), Some("second!".to_string()));
+    // Going back down past the newest entry brings back the line being typed
+    let input = "dra\x1b[A\x1b[A\x1b[B\x1b[B\x1b[Bft\r";
+
+    assert_eq!(edit(&history, input).unwrap(), Some("draft".to_string()));
+  }
+
+  #[test]
+  fn test_edit_line_completion() {
+    let words = ["print", "println", "prompt"];
+    let complete = |prefix: &str| {
+      words.iter().filter(|word| word.starts_with(prefix)).map(|word| word.to_string()).collect::<Vec<String>>()
+    };
+    let edit = |input: &str, output: &mut Vec<u8>| {
+      edit_line("> ", &history(&[]), &complete, false, &mut input.as_bytes(), output).unwrap()
+    };
+
+    assert_eq!(edit("x = pri\t;\r", &mut vec![]), Some("x = print;".to_string()));
+    assert_eq!(edit("printl\t(1)\r", &mut vec![]), Some("println(1)".to_string()));
+    // Completes the word before the cursor, not the one after it
+    assert_eq!(edit("prin x\x1b[D\x1b[D\t\r", &mut vec![]), Some("print x".to_string()));
+    assert_eq!(edit("a\tb\r", &mut vec![]), Some("ab".to_string()));
+
+    let mut output = vec![];
+    assert_eq!(edit("pr\t\r", &mut output), Some("pr".to_string()));
+    assert!(String::from_utf8(output).unwrap().contains("\r\nprint  println  prompt\r\n"));
+  }
+
+  #[test]
+  fn test_edit_line_output() {
+    let mut output = vec![];
+
+    edit_line("> ", &history(&[]), &no_completions, false, &mut "ab\x1b[D\r".as_bytes(), &mut output).unwrap();
+
+    assert_eq!(
+      String::from_utf8(output).unwrap(),
+      "\r> \x1b[K\r\x1b[2C\r> a\x1b[K\r\x1b[3C\r> ab\x1b[K\r\x1b[4C\r> ab\x1b[K\r\x1b[3C\r\n"
+    );
+
+    let mut output = vec![];
+
+    edit_line("> ", &history(&[]), &no_completions, true, &mut "1\r".as_bytes(), &mut output).unwrap();
+
+    assert_eq!(String::from_utf8(output).unwrap(), "\r> \x1b[K\r\x1b[2C\r> \x1b[33m1\x1b[0m\x1b[K\r\x1b[3C\r\n");
+  }
+
+  #[test]
+  fn test_highlight() {
+    assert_eq!(
+      highlight(r#"var s = "a"; // c"#),
+      "\x1b[35mvar\x1b[0m s = \x1b[32m\"a\"\x1b[0m; \x1b[90m// c\x1b[0m"
+    );
+    assert_eq!(highlight("if (nil) x.y"), "\x1b[35mif\x1b[0m (\x1b[35mnil\x1b[0m) x.y");
+    assert_eq!(highlight(r#"print "open"#), "\x1b[35mprint\x1b[0m \"open");
+    assert_eq!(highlight("é + 1"), "é + \x1b[33m1\x1b[0m");
+  }
+
+  #[test]
+  fn test_history() {
+    let path = std::env::temp_dir().join(format!("rslox-history-test-{}", std::process::id()));
+    let _ = fs::remove_file(&path);
+    let mut history = History::load(Some(path.clone()));
+
+    history.add("print 1;");
+    history.add("print 1;");
+    history.add("  ");
+    history.add("print 2;");
+    history.add("print 1;");
+
+    assert_eq!(history.entries, vec!["print 1;", "print 2;", "print 1;"]);
+    assert_eq!(History::load(Some(path.clone())).entries, history.entries);
+
+    for index in 0..History::LIMIT {
+      history.add(&index.to_string());
+    }
+
+    let loaded = History::load(Some(path.clone()));
+
+    assert_eq!(history.entries.len(), History::LIMIT);
+    assert_eq!(history.entries.first().map(String::as_str), Some("0"));
+    assert_eq!(loaded.entries, history.entries);
+    assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), History::LIMIT);
+
+    // Files grown past the limit, e.g. by older versions, are cut on load
+    let lines = (0..History::LIMIT + 5).map(|index| format!("{index}\n")).collect::<String>();
+    fs::write(&path, lines).unwrap();
+
+    let loaded = History::load(Some(path.clone()));
+    let saved = fs::read_to_string(&path).unwrap();
+    let _ = fs::remove_file(&path);
+
+    assert_eq!(loaded.entries.first().map(String::as_str), Some("5"));
+    assert_eq!(saved.lines().collect::<Vec<&str>>(), loaded.entries);
+  }
+}
@@ -1,7 +1,27 @@
+use bench::Stats;
 use clap::{Parser, Subcommand, ValueEnum};
-use std::process;
+use std::cell::RefCell;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use std::io::{IsTerminal, Write};
+use std::{env, fs, io, process, thread};
+use tree_walking::diagnostics::{self, Diagnostic, DiagnosticFormat};
+use tree_walking::docs;
+use tree_walking::formatter::{self, FormatConfig};
+use tree_walking::lint::{self, Level, LintConfig};
+use tree_walking::runner::{self, RunOptions, TokenFormat};
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::EnvFilter;
 
-#[derive(Copy, Clone, ValueEnum)]
+mod bench;
+mod golden;
+mod line_editor;
+mod repl;
+mod watch;
+
+#[derive(Copy, Clone, Debug, ValueEnum)]
 enum Interpreter {
   /// Use tree-walking interpreter
   TreeWalking,
@@ -9,6 +29,43 @@ enum Interpreter {
   VM
 }
 
+#[derive(Copy, Clone, ValueEnum)]
+enum DocFormat {
+  /// Emit Markdown
+  Markdown,
+  /// Emit a standalone HTML page
+  Html,
+}
+
+impl From<DocFormat> for docs::DocFormat {
+  fn from(format: DocFormat) -> Self {
+    match format {
+      DocFormat::Markdown => docs::DocFormat::Markdown,
+      DocFormat::Html => docs::DocFormat::Html,
+    }
+  }
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+enum AstFormat {
+  /// Nested structures showing every field
+  Pretty,
+  /// Emit JSON
+  Json,
+  /// One S-expression per statement
+  Sexpr,
+}
+
+impl From<AstFormat> for runner::AstFormat {
+  fn from(format: AstFormat) -> Self {
+    match format {
+      AstFormat::Pretty => runner::AstFormat::Pretty,
+      AstFormat::Json => runner::AstFormat::Json,
+      AstFormat::Sexpr => runner::AstFormat::Sexpr,
+    }
+  }
+}
+
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 #[command(propagate_version = true)]
@@ -20,31 +77,790 @@ struct Cli {
 #[derive(Subcommand)]
 enum Commands {
   Run {
+    /// A path to a file containg source code, `-` reads it from stdin
+    #[arg(required_unless_present = "eval")]
+    path: Option<String>,
+
+    /// Run this source code instead of a file
+    #[arg(short, long, value_name = "SOURCE", conflicts_with = "path")]
+    eval: Option<String>,
+
+    /// Select an interpreter that should be used to run the code
+    #[arg(short, long, value_enum, default_value_t = Interpreter::TreeWalking)]
+    runner: Interpreter,
+
+    /// Comma separated optional native modules the program may use (e.g. `fs,math`)
+    #[arg(long, value_delimiter = ',')]
+    allow: Vec<String>,
+
+    /// Always scan and parse the source instead of reusing a cached parse
+    #[arg(long)]
+    no_cache: bool,
+
+    /// Print the stack and each instruction to stderr as the bytecode interpreter runs
+    #[arg(long)]
+    trace: bool,
+
+    /// Most values the bytecode interpreter's stack may hold
+    #[arg(long, default_value_t = vm::runner::RunOptions::default().max_stack)]
+    max_stack: usize,
+
+    /// Report errors and warnings on stderr as JSON objects, one per line
+    #[arg(long)]
+    json: bool,
+
+    /// Print how long each phase (e.g. parsing, executing) took to stderr
+    #[arg(long)]
+    time: bool,
+
+    /// Run the program again, on a cleared screen, every time its file changes
+    #[arg(long, conflicts_with = "eval")]
+    watch: bool,
+
+    /// Arguments passed to the program, returned by its `args()` native
+    #[arg(last = true, value_name = "ARGS")]
+    args: Vec<String>,
+  },
+  /// Read lines of code from stdin and run them one by one with the tree-walking interpreter
+  Repl {
+    /// Comma separated optional native modules the code may use (e.g. `fs,math`)
+    #[arg(long, value_delimiter = ',')]
+    allow: Vec<String>,
+  },
+  /// Time a program on both interpreters, or its `bench_*` functions on the one given with `--runner`
+  Bench {
     /// A path to a file containg source code
     path: String,
 
-    /// Select an interpreter that should be used to run the code
+    /// Run the `bench_*` functions of the program with this interpreter instead of comparing the interpreters
+    #[arg(short, long, value_enum)]
+    runner: Option<Interpreter>,
+
+    /// Untimed runs of every benchmark before measuring
+    #[arg(long, default_value_t = 3)]
+    warmup: usize,
+
+    /// Timed runs of every benchmark
+    #[arg(short = 'n', long, default_value_t = 10)]
+    iterations: usize,
+
+    /// Print the comparison of the interpreters as JSON
+    #[arg(long, conflicts_with = "runner")]
+    json: bool,
+  },
+  /// Run programs and check their output against their `// expect: ...` and `// expect-error: ...` comments
+  Test {
+    /// A path to a test file or to a directory searched for `.lox` files
+    path: String,
+
+    /// Select an interpreter that should be used to run the tests
     #[arg(short, long, value_enum, default_value_t = Interpreter::TreeWalking)]
-    runner: Interpreter
+    runner: Interpreter,
+
+    /// Tests run at the same time, the number of available cores by default
+    #[arg(short, long)]
+    jobs: Option<NonZeroUsize>,
   },
+  /// Print the tokens the scanner splits a program into
+  Tokenize {
+    /// A path to a file containg source code, `-` reads it from stdin
+    path: String,
+
+    /// Print the tokens as a JSON array
+    #[arg(long)]
+    json: bool,
+  },
+  /// Parse a program and print its syntax tree without running it
+  Ast {
+    /// A path to a file containg source code, `-` reads it from stdin
+    path: String,
+
+    /// Output format of the syntax tree
+    #[arg(short, long, value_enum, default_value_t = AstFormat::Pretty)]
+    format: AstFormat,
+  },
+  /// Compile a program to bytecode and print it without running it
+  #[command(visible_alias = "disassemble")]
+  Disasm {
+    /// A path to a file containg source code
+    path: String,
+
+    /// Print size and composition statistics instead of the listing
+    #[arg(long)]
+    stats: bool,
+  },
+  /// Compile a program to a `.loxb` bytecode file
+  Compile {
+    /// A path to a file containg source code
+    path: String,
+
+    /// Where to write the bytecode, defaults to the source path with a `.loxb` extension
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+  },
+  /// Run a `.loxb` file produced by `compile` with the bytecode interpreter
+  Exec {
+    /// A path to a `.loxb` file
+    path: String,
+
+    /// Print the stack and each instruction to stderr as the program runs
+    #[arg(long)]
+    trace: bool,
+
+    /// Most values the stack may hold
+    #[arg(long, default_value_t = vm::runner::RunOptions::default().max_stack)]
+    max_stack: usize,
+
+    /// Comma separated optional native modules the program may use (e.g. `fs,math`)
+    #[arg(long, value_delimiter = ',')]
+    allow: Vec<String>,
+  },
+  /// Reformat source files in place, keeping their comments
+  Fmt {
+    /// A path to a source file or to a directory searched for `.lox` files, `-` formats stdin to stdout
+    path: String,
+
+    /// Only report the files that are not formatted, failing if there are any
+    #[arg(long)]
+    check: bool,
+
+    /// Number of spaces used for one level of indentation
+    #[arg(long, default_value_t = FormatConfig::default().indent_width)]
+    indent_width: usize,
+
+    /// Longer runs of blank lines are collapsed to this many
+    #[arg(long, default_value_t = FormatConfig::default().max_blank_lines)]
+    max_blank_lines: usize,
+  },
+  /// Report code that is likely a mistake without running it
+  Lint {
+    /// A path to a source file or to a directory searched for `.lox` files, `-` reads stdin
+    path: String,
+
+    /// Comma separated lints that are not reported (e.g. `shadowed-name`)
+    #[arg(long, value_delimiter = ',')]
+    allow: Vec<String>,
+
+    /// Comma separated lints reported as errors, failing the check
+    #[arg(long, value_delimiter = ',')]
+    deny: Vec<String>,
+
+    /// Print the findings as JSON objects, one per line
+    #[arg(long)]
+    json: bool,
+  },
+  /// Generate documentation from `///` comments
+  Doc {
+    /// A path to a source file or to a directory searched for `.lox` files
+    path: String,
+
+    /// Output format of the generated documentation
+    #[arg(short, long, value_enum, default_value_t = DocFormat::Markdown)]
+    format: DocFormat,
+  },
+}
+
+/// Reads the source code at `path`, or from stdin if the path is `-`.
+fn read_source(path: &str) -> io::Result<String> {
+  if path == "-" {
+    return io::read_to_string(io::stdin());
+  }
+
+  fs::read_to_string(path)
+}
+
+/// Returns `path` itself if it is a file, or all `.lox` files below it
+/// (sorted) if it is a directory.
+fn collect_lox_files(path: &Path) -> io::Result<Vec<PathBuf>> {
+  if !path.is_dir() {
+    return Ok(vec![path.to_path_buf()]);
+  }
+
+  let mut files = vec![];
+
+  for entry in fs::read_dir(path)? {
+    let entry_path = entry?.path();
+
+    if entry_path.is_dir() {
+      files.extend(collect_lox_files(&entry_path)?);
+    } else if entry_path.extension().is_some_and(|ext| ext == "lox") {
+      files.push(entry_path);
+    }
+  }
+
+  files.sort();
+
+  Ok(files)
+}
+
+fn generate_docs(path: &str, format: DocFormat) -> anyhow::Result<String> {
+  let root = Path::new(path);
+  let mut modules = vec![];
+
+  for file in collect_lox_files(root)? {
+    let source = fs::read_to_string(&file)?;
+    let name = file.strip_prefix(root).unwrap_or(&file);
+    let name = if name.as_os_str().is_empty() { &file } else { name };
+
+    modules.push(docs::extract(name.display().to_string(), source)?);
+  }
+
+  Ok(docs::render(&modules, format.into()))
+}
+
+/// Formats the source at `path`, a file, a directory of `.lox` files or `-`
+/// for stdin. Returns whether everything was formatted already; with
+/// `check` nothing is changed and the unformatted files are listed.
+fn format_files(path: &str, check: bool, config: &FormatConfig) -> anyhow::Result<bool> {
+  if path == "-" {
+    let source = read_source(path)?;
+    let formatted = formatter::format_source(&source, config)?;
+
+    if !check {
+      print!("{formatted}");
+    }
+
+    return Ok(formatted == source);
+  }
+
+  let mut formatted_already = true;
+
+  for file in collect_lox_files(Path::new(path))? {
+    let source = fs::read_to_string(&file)?;
+    let formatted = formatter::format_source(&source, config)
+      .map_err(|e| anyhow::anyhow!("{}: {e}", file.display()))?;
+
+    if formatted == source {
+      continue;
+    }
+
+    formatted_already = false;
+
+    if check {
+      println!("{}", file.display());
+    } else {
+      fs::write(&file, formatted)?;
+    }
+  }
+
+  Ok(formatted_already)
+}
+
+/// Lints the source at `path`, a file, a directory of `.lox` files or `-`
+/// for stdin, printing what is found. Returns whether no denied lint was
+/// found.
+fn lint_files(path: &str, config: &LintConfig, format: DiagnosticFormat) -> anyhow::Result<bool> {
+  let files = if path == "-" {
+    vec![(PathBuf::from("<stdin>"), read_source(path)?)]
+  } else {
+    let mut files = vec![];
+
+    for file in collect_lox_files(Path::new(path))? {
+      let source = fs::read_to_string(&file)?;
+      files.push((file, source));
+    }
+
+    files
+  };
+
+  let mut passed = true;
+
+  for (file, source) in files {
+    let file = file.display().to_string();
+
+    let found = match lint::lint(&source, config) {
+      Ok(found) => found,
+      Err(e) => {
+        match format {
+          DiagnosticFormat::Json => {
+            for diagnostic in diagnostics::from_error(&e) {
+              println!("{}", diagnostic.with_file(&file).render(format, None));
+            }
+          }
+          _ => report_error(&e, Some(&source), Some(&file), diagnostic_format(false)),
+        }
+
+        passed = false;
+        continue;
+      }
+    };
+
+    for diagnostic in found {
+      passed &= diagnostic.level != Level::Deny;
+
+      println!("{}", Diagnostic::from(&diagnostic).with_file(&file).render(format, Some(&source)));
+    }
+  }
+
+  Ok(passed)
+}
+
+/// Prints the duration of each phase of a run and their total to stderr.
+fn print_timings(timings: &[(&str, Duration)]) {
+  for (phase, duration) in timings {
+    eprintln!("{:<8} {:>10.3} ms", phase, duration.as_secs_f64() * 1000.0);
+  }
+
+  let total = timings.iter().map(|(_, duration)| *duration).sum::<Duration>();
+
+  eprintln!("{:<8} {:>10.3} ms", "total", total.as_secs_f64() * 1000.0);
+}
+
+/// Calls every `bench_*` function of the program at `path` and prints the
+/// mean and median time of a call. Program output is discarded.
+fn run_benchmarks(
+  path: &str,
+  runner: Interpreter,
+  warmup: usize,
+  iterations: usize,
+) -> anyhow::Result<()> {
+  if let Interpreter::VM = runner {
+    anyhow::bail!("the bytecode interpreter cannot call functions yet");
+  }
+
+  if iterations == 0 {
+    anyhow::bail!("at least one iteration is required");
+  }
+
+  let source = fs::read_to_string(path)?;
+  let options = RunOptions {
+    stdout: Rc::new(RefCell::new(io::sink())),
+    ..RunOptions::default()
+  };
+
+  let mut program = tree_walking::runner::load(source, &options)?;
+  let benchmarks = program
+    .functions()
+    .iter()
+    .filter(|name| name.starts_with("bench_"))
+    .cloned()
+    .collect::<Vec<String>>();
+
+  if benchmarks.is_empty() {
+    anyhow::bail!("no `bench_*` functions found in {path}");
+  }
+
+  println!("tree-walking: {path}");
+
+  for name in benchmarks {
+    for _ in 0..warmup {
+      program.call(&name)?;
+    }
+
+    let mut times = Vec::with_capacity(iterations);
+
+    for _ in 0..iterations {
+      let start = Instant::now();
+      program.call(&name)?;
+      times.push(start.elapsed());
+    }
+
+    let stats = Stats::new(times);
+
+    println!(
+      "  {:<30} mean {:>10.3} ms  median {:>10.3} ms  ({} runs)",
+      name, stats.mean, stats.median, stats.runs
+    );
+  }
+
+  Ok(())
+}
+
+/// Runs the program at `path` on both interpreters and prints how long they
+/// took and how much faster the bytecode interpreter is. Program output is
+/// discarded.
+fn compare_interpreters(path: &str, warmup: usize, iterations: usize, json: bool) -> anyhow::Result<()> {
+  if iterations == 0 {
+    anyhow::bail!("at least one iteration is required");
+  }
+
+  let source = fs::read_to_string(path)?;
+  let comparison = bench::compare(&source, warmup, iterations)?;
+
+  if json {
+    println!("{}", serde_json::to_string_pretty(&comparison)?);
+  } else {
+    println!("{path}\n{}", comparison.report());
+  }
+
+  Ok(())
+}
+
+/// Input that is not a valid program, as in sysexits.h
+const EX_DATAERR: i32 = 65;
+/// A program failing while it runs
+const EX_SOFTWARE: i32 = 70;
+
+/// Exit code of a failed `run` or `exec`, telling apart programs that do not
+/// compile from ones failing at runtime like the book's jlox and clox do.
+/// Other failures, like unreadable files, exit with 1.
+fn exit_code(error: &anyhow::Error) -> i32 {
+  use tree_walking::runner::RunError as TreeWalkingError;
+  use vm::runner::RunError as VmError;
+
+  match (error.downcast_ref(), error.downcast_ref()) {
+    (Some(TreeWalkingError::Compile(_)), _) | (_, Some(VmError::Compile(_))) => EX_DATAERR,
+    (Some(TreeWalkingError::Runtime(_)), _) | (_, Some(VmError::Runtime(_))) => EX_SOFTWARE,
+    (None, None) => 1,
+  }
+}
+
+/// How diagnostics are shown on stderr: as JSON if asked for, with colors if
+/// stderr is a terminal and `NO_COLOR` is not set.
+fn diagnostic_format(json: bool) -> DiagnosticFormat {
+  if json {
+    DiagnosticFormat::Json
+  } else if io::stderr().is_terminal() && env::var_os("NO_COLOR").is_none() {
+    DiagnosticFormat::ColoredText
+  } else {
+    DiagnosticFormat::Text
+  }
+}
+
+/// Prints `error` to stderr, marking the lines of `source` it points to.
+fn report_error(error: &anyhow::Error, source: Option<&str>, file: Option<&str>, format: DiagnosticFormat) {
+  for mut diagnostic in error_diagnostics(error) {
+    diagnostic.file = file.map(str::to_string);
+
+    eprintln!("{}", diagnostic.render(format, source));
+  }
+}
+
+/// Reads the source code at `path` like `read_source`, exiting if it cannot.
+fn read_source_or_exit(path: &str, format: DiagnosticFormat) -> String {
+  read_source(path).unwrap_or_else(|e| {
+    report_error(&anyhow::anyhow!("could not read {path}: {e}"), None, None, format);
+    process::exit(1);
+  })
+}
+
+/// Name a file is reported under, `-` being stdin.
+fn display_path(path: &str) -> &str {
+  if path == "-" {
+    "<stdin>"
+  } else {
+    path
+  }
+}
+
+/// Diagnostics describing an error returned by either interpreter.
+fn error_diagnostics(error: &anyhow::Error) -> Vec<Diagnostic> {
+  match error.downcast_ref() {
+    Some(vm::runner::RunError::Compile(e)) => match e.downcast_ref::<vm::runner::LocatedError>() {
+      Some(located) => vec![Diagnostic::error(located.error.to_string())
+        .with_code("syntax")
+        .with_span(located.line, Some(located.column), located.length)],
+      None => diagnostics::from_error(e),
+    },
+    Some(vm::runner::RunError::Runtime(e)) => match e.downcast_ref::<vm::runner::LocatedRuntimeError>() {
+      Some(located) => vec![Diagnostic::error(located.error.to_string())
+        .with_code("runtime")
+        .with_span(located.line, None, 0)],
+      None => vec![Diagnostic::error(e.to_string()).with_code("runtime")],
+    },
+    None => diagnostics::from_error(error),
+  }
+}
+
+/// Per-user directory holding parsed programs, reused while their source is
+/// unchanged.
+fn cache_dir() -> Option<PathBuf> {
+  dirs::cache_dir().map(|dir| dir.join("rslox"))
+}
+
+/// Per-user file keeping the lines entered into the REPL.
+fn history_path() -> Option<PathBuf> {
+  dirs::data_local_dir().map(|dir| dir.join("rslox").join("history"))
+}
+
+/// Phase spans and counters are reported on stderr, filtered with `RUST_LOG`
+/// (e.g. `RUST_LOG=debug`).
+fn init_tracing() {
+  tracing_subscriber::fmt()
+    .with_env_filter(EnvFilter::from_default_env())
+    .with_span_events(FmtSpan::CLOSE)
+    .with_writer(io::stderr)
+    .init();
 }
 
 fn main() {
+  init_tracing();
+
   let cli = Cli::parse();
 
   match cli.command {
-    Commands::Run { path, runner } => {
-      let contents = std::fs::read_to_string(path).expect("Something went wrong reading the file");
+    Commands::Run {
+      path,
+      eval,
+      runner,
+      allow,
+      no_cache,
+      trace,
+      max_stack,
+      json,
+      time,
+      watch,
+      args,
+    } => {
+      let format = diagnostic_format(json);
+      let file = path.as_deref().map(display_path);
+
+      // Runs the program once, reporting how long its phases took if asked to
+      let run = |contents: String| {
+        let timings = time.then(runner::Timings::default);
+
+        let result = match runner {
+          Interpreter::TreeWalking if trace => Err(anyhow::anyhow!(
+            "--trace is only supported by the bytecode interpreter"
+          )),
+          Interpreter::TreeWalking => {
+            let options = RunOptions {
+              allowed_modules: allow.clone(),
+              cache_dir: if no_cache { None } else { cache_dir() },
+              diagnostic_format: format,
+              args: args.clone(),
+              timings: timings.clone(),
+              ..RunOptions::default()
+            };
+
+            tree_walking::runner::run_with_options(contents, &options).map_err(anyhow::Error::from)
+          }
+          Interpreter::VM if !args.is_empty() => Err(anyhow::anyhow!(
+            "program arguments are not supported by the bytecode interpreter yet"
+          )),
+          Interpreter::VM => {
+            let options = vm::runner::RunOptions {
+              trace,
+              max_stack,
+              timings: timings.clone(),
+              allowed_modules: allow.clone(),
+              ..vm::runner::RunOptions::default()
+            };
+
+            vm::runner::run_with_options(contents, &options).map_err(anyhow::Error::from)
+          }
+        };
+
+        if let Some(timings) = timings {
+          print_timings(&timings.borrow());
+        }
+
+        result
+      };
+
+      match path.as_deref() {
+        Some("-") if watch => {
+          report_error(&anyhow::anyhow!("stdin cannot be watched"), None, None, format);
+          process::exit(1);
+        }
+        Some(path) if watch => {
+          let result = watch::watch(Path::new(path), || {
+            // Clears the screen and moves the cursor to its top left corner
+            print!("\x1b[2J\x1b[H");
+            let _ = io::stdout().flush();
+
+            match read_source(path) {
+              Ok(contents) => {
+                if let Err(e) = run(contents.clone()) {
+                  report_error(&e, Some(&contents), file, format);
+                }
+              }
+              Err(e) => report_error(&anyhow::anyhow!("could not read {path}: {e}"), None, None, format),
+            }
+          });
+
+          result.unwrap_or_else(|e| {
+            report_error(&e, None, None, format);
+            process::exit(1);
+          })
+        }
+        _ => {
+          let contents = match (eval, &path) {
+            (Some(source), _) => source,
+            (None, Some(path)) => read_source_or_exit(path, format),
+            (None, None) => unreachable!("clap requires a path without --eval"),
+          };
+
+          run(contents.clone()).unwrap_or_else(|e| {
+            report_error(&e, Some(&contents), file, format);
+            process::exit(exit_code(&e));
+          })
+        }
+      }
+    }
+    Commands::Repl { allow } => {
+      let options = RunOptions {
+        allowed_modules: allow,
+        diagnostic_format: diagnostic_format(false),
+        ..RunOptions::default()
+      };
+
+      let mut editor = line_editor::for_stdin(history_path());
 
+      repl::run(&options, editor.as_mut()).unwrap_or_else(|e| {
+        report_error(&e, None, None, diagnostic_format(false));
+        process::exit(1);
+      })
+    }
+    Commands::Bench {
+      path,
+      runner,
+      warmup,
+      iterations,
+      json,
+    } => {
       let result = match runner {
-        Interpreter::TreeWalking => tree_walking::runner::run(contents),
-        Interpreter::VM => vm::runner::run(contents),
+        Some(runner) => run_benchmarks(&path, runner, warmup, iterations),
+        None => compare_interpreters(&path, warmup, iterations, json),
       };
 
       result.unwrap_or_else(|e| {
-        eprintln!("Error: {e}");
+        report_error(&e, None, None, diagnostic_format(false));
         process::exit(1);
       })
     }
+    Commands::Test { path, runner, jobs } => {
+      let jobs = jobs.unwrap_or_else(|| thread::available_parallelism().unwrap_or(NonZeroUsize::MIN));
+
+      match golden::run_tests(&path, runner, jobs) {
+        Ok(passed) => {
+          if !passed {
+            process::exit(1);
+          }
+        }
+        Err(e) => {
+          report_error(&e, None, None, diagnostic_format(false));
+          process::exit(1);
+        }
+      }
+    }
+    Commands::Tokenize { path, json } => {
+      let format = if json { TokenFormat::Json } else { TokenFormat::Text };
+      let source = read_source_or_exit(&path, diagnostic_format(false));
+
+      match runner::tokens(source.clone(), format) {
+        Ok(output) => println!("{output}"),
+        Err(e) => {
+          report_error(&e, Some(&source), Some(display_path(&path)), diagnostic_format(false));
+          process::exit(1);
+        }
+      }
+    }
+    Commands::Ast { path, format } => {
+      let source = read_source_or_exit(&path, diagnostic_format(false));
+
+      match runner::ast(source.clone(), format.into()) {
+        Ok(output) => println!("{output}"),
+        Err(e) => {
+          report_error(&e, Some(&source), Some(display_path(&path)), diagnostic_format(false));
+          process::exit(1);
+        }
+      }
+    }
+    Commands::Disasm { path, stats } => {
+      let result = fs::read_to_string(path).map_err(anyhow::Error::from).and_then(|source| {
+        if stats {
+          vm::runner::stats(source)
+        } else {
+          vm::runner::disassemble(source)
+        }
+      });
+
+      match result {
+        Ok(output) => println!("{output}"),
+        Err(e) => {
+          report_error(&e, None, None, diagnostic_format(false));
+          process::exit(1);
+        }
+      }
+    }
+    Commands::Compile { path, output } => {
+      let output = output.unwrap_or_else(|| Path::new(&path).with_extension("loxb"));
+      let result = fs::read_to_string(&path)
+        .map_err(anyhow::Error::from)
+        .and_then(vm::runner::compile_to_bytes)
+        .and_then(|bytes| Ok(fs::write(&output, bytes)?));
+
+      result.unwrap_or_else(|e| {
+        report_error(&e, None, None, diagnostic_format(false));
+        process::exit(1);
+      })
+    }
+    Commands::Exec {
+      path,
+      trace,
+      max_stack,
+      allow,
+    } => {
+      let options = vm::runner::RunOptions {
+        trace,
+        max_stack,
+        allowed_modules: allow,
+        ..vm::runner::RunOptions::default()
+      };
+      let result = fs::read(path)
+        .map_err(anyhow::Error::from)
+        .and_then(|bytes| Ok(vm::runner::run_bytes(&bytes, &options)?));
+
+      result.unwrap_or_else(|e| {
+        report_error(&e, None, None, diagnostic_format(false));
+        process::exit(exit_code(&e));
+      })
+    }
+    Commands::Fmt {
+      path,
+      check,
+      indent_width,
+      max_blank_lines,
+    } => {
+      let config = FormatConfig {
+        indent_width,
+        max_blank_lines,
+      };
+
+      match format_files(&path, check, &config) {
+        Ok(formatted_already) => {
+          if check && !formatted_already {
+            process::exit(1);
+          }
+        }
+        Err(e) => {
+          report_error(&e, None, None, diagnostic_format(false));
+          process::exit(1);
+        }
+      }
+    }
+    Commands::Lint {
+      path,
+      allow,
+      deny,
+      json,
+    } => {
+      let format = if json { DiagnosticFormat::Json } else { DiagnosticFormat::Text };
+      let mut config = LintConfig::default();
+      let result = config
+        .set_codes(&allow, Level::Allow)
+        .and_then(|_| config.set_codes(&deny, Level::Deny))
+        .and_then(|_| lint_files(&path, &config, format));
+
+      match result {
+        Ok(passed) => {
+          if !passed {
+            process::exit(1);
+          }
+        }
+        Err(e) => {
+          report_error(&e, None, None, diagnostic_format(false));
+          process::exit(1);
+        }
+      }
+    }
+    Commands::Doc { path, format } => match generate_docs(&path, format) {
+      Ok(documentation) => print!("{documentation}"),
+      Err(e) => {
+        report_error(&e, None, None, diagnostic_format(false));
+        process::exit(1);
+      }
+    },
   }
 }
@@ -0,0 +1,117 @@
+use crate::line_editor::{Completer, LineEditor};
+use std::io;
+use tree_walking::runner::{self, AstFormat, RunOptions, Session, TokenFormat};
+
+const HELP: &str = "\
+:tokens [code]  list the tokens of the code
+:ast [code]     print the syntax tree of the code
+:dis [code]     print the bytecode the code compiles to
+:env            list the variables declared so far
+:help           show this message
+
+Without code, the commands show the last entry that was run. Entries with
+unclosed brackets or strings continue on the next line; Ctrl-C cancels them.
+Tab completes keywords, natives and the names of variables.";
+
+/// Runs each entry read by `editor` in one session and prints the values of
+/// expressions, until the input ends. Errors are reported and the session
+/// goes on.
+pub(crate) fn run(options: &RunOptions, editor: &mut dyn LineEditor) -> anyhow::Result<()> {
+  let mut session = Session::new(options)?;
+  let mut last = String::new();
+
+  loop {
+    let Some(entry) = read_entry(editor, &|prefix| completions(&session, prefix))? else {
+      return Ok(());
+    };
+
+    if entry.trim().is_empty() {
+      continue;
+    }
+
+    // Source the errors point into, if it is the entry itself
+    let (result, source) = match entry.trim().strip_prefix(':') {
+      Some(command) => (meta_command(&session, command, &last), None),
+      None => {
+        let result = session.eval(&entry);
+        last = entry;
+
+        (result, Some(last.as_str()))
+      }
+    };
+
+    match result {
+      Ok(Some(output)) => println!("{output}"),
+      Ok(None) => {}
+      Err(e) => crate::report_error(&e, source, None, options.diagnostic_format),
+    }
+  }
+}
+
+/// Keywords, natives and variables declared so far that start with `prefix`.
+fn completions(session: &Session, prefix: &str) -> Vec<String> {
+  let mut names = scanner::KEYWORDS
+    .iter()
+    .map(|keyword| keyword.to_string())
+    .chain(session.natives().iter().cloned())
+    .chain(session.bindings().into_iter().map(|(name, _)| name))
+    .filter(|name| name.starts_with(prefix))
+    .collect::<Vec<String>>();
+
+  names.sort();
+  names.dedup();
+
+  names
+}
+
+/// Reads lines until they make up a complete entry. A cancelled entry is
+/// returned empty.
+fn read_entry(editor: &mut dyn LineEditor, complete: &Completer) -> io::Result<Option<String>> {
+  let mut entry = String::new();
+
+  loop {
+    let prompt = if entry.is_empty() { "> " } else { "... " };
+
+    let line = match editor.read_line(prompt, complete) {
+      Ok(Some(line)) => line,
+      Ok(None) if entry.is_empty() => return Ok(None),
+      Ok(None) => return Ok(Some(entry)),
+      Err(e) if e.kind() == io::ErrorKind::Interrupted => return Ok(Some(String::new())),
+      Err(e) => return Err(e),
+    };
+
+    editor.add_history(&line);
+
+    if !entry.is_empty() {
+      entry.push('\n');
+    }
+    entry.push_str(&line);
+
+    if entry.trim_start().starts_with(':') || !runner::needs_more_input(&entry) {
+      return Ok(Some(entry));
+    }
+  }
+}
+
+/// Runs a line starting with `:`, given without the colon. Commands taking
+/// code use `last` when none follows them.
+fn meta_command(session: &Session, command: &str, last: &str) -> anyhow::Result<Option<String>> {
+  let (name, code) = command.split_once(char::is_whitespace).unwrap_or((command, ""));
+  let code = if code.trim().is_empty() { last } else { code };
+
+  let output = match name {
+    "tokens" => runner::tokens(code.to_string(), TokenFormat::Text)?,
+    "ast" => runner::ast(runner::complete_entry(code)?, AstFormat::Sexpr)?,
+    "dis" => vm::runner::disassemble(runner::complete_entry(code)?)?,
+    "env" => session
+      .bindings()
+      .iter()
+      .map(|(name, value)| format!("{name} = {value}"))
+      .collect::<Vec<String>>()
+      .join("\n"),
+    "help" => HELP.to_string(),
+    _ => anyhow::bail!("unknown command :{name}, try :help"),
+  };
+
+  Ok(Some(output).filter(|output| !output.is_empty()))
+}
@@ -0,0 +1,39 @@
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use std::path::Path;
+use std::sync::mpsc;
+use std::time::Duration;
+
+/// Changes coming this soon after one another are handled together, as
+/// editors often save a file in a few steps.
+const SETTLE_TIME: Duration = Duration::from_millis(50);
+
+/// Calls `run` now and again every time the file at `path` changes. Only
+/// returns if watching fails.
+pub(crate) fn watch(path: &Path, mut run: impl FnMut()) -> anyhow::Result<()> {
+  let path = path.canonicalize()?;
+  // Editors often save by replacing the file, which a watch on the file
+  // itself would not outlive
+  let directory = path.parent().unwrap_or(&path);
+
+  let (sender, receiver) = mpsc::channel();
+  let mut watcher = notify::recommended_watcher(sender)?;
+  watcher.watch(directory, RecursiveMode::NonRecursive)?;
+
+  loop {
+    run();
+    eprintln!("[watching {} for changes]", path.display());
+
+    loop {
+      if changes(&receiver.recv()??, &path) {
+        break;
+      }
+    }
+
+    while receiver.recv_timeout(SETTLE_TIME).is_ok() {}
+  }
+}
+
+/// Whether `event` may have changed the contents of the file at `path`.
+fn changes(event: &Event, path: &Path) -> bool {
+  !matches!(event.kind, EventKind::Access(_)) && event.paths.iter().any(|changed| changed == path)
+}
@@ -1,12 +1,38 @@
-use anyhow::{anyhow, Result};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+use thiserror::Error;
+
+/// Source text that cannot be split into tokens.
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum ScanError {
+  #[error("unterminated string starting on line {line}, column {column}")]
+  UnterminatedString { line: u32, column: u32 },
+
+  #[error("invalid number literal {lexeme:?} on line {line}, column {column}")]
+  InvalidNumber {
+    lexeme: String,
+    line: u32,
+    column: u32,
+  },
+
+  #[error("unexpected character {character:?} on line {line}, column {column}")]
+  UnexpectedCharacter {
+    character: char,
+    line: u32,
+    column: u32,
+  },
+}
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub enum TokenType {
   // Single-character tokens
   LeftParen,
   RightParen,
   LeftBrace,
   RightBrace,
+  LeftBracket,
+  RightBracket,
   Comma,
   Dot,
   Minus,
@@ -16,8 +42,14 @@ pub enum TokenType {
   Star,
   Colon,
   Question,
+  Ampersand,
+  Pipe,
+  Caret,
 
   // One or two character tokens
+  DotDot,
+  DotDotEqual,
+  DotDotDot,
   Bang,
   BangEqual,
   Eqal,
@@ -26,20 +58,34 @@ pub enum TokenType {
   GreaterEqual,
   Less,
   LessEqual,
+  PlusPlus,
+  MinusMinus,
+  LessLess,
+  GreaterGreater,
 
   // Literals
   Identifier(String),
   Number(f64),
   String(String),
+  /// Part of a string literal before a `${`. Tokens of the embedded
+  /// expression follow, then the rest of the string as another
+  /// `Interpolation` or a `String`.
+  Interpolation(String),
 
   // Keywords
   And,
+  Assert,
+  Break,
   Class,
+  Continue,
   Else,
   False,
   Fun,
   For,
   If,
+  In,
+  Is,
+  Loop,
   Nil,
   Or,
   Print,
@@ -49,23 +95,68 @@ pub enum TokenType {
   True,
   Var,
   While,
+  Switch,
+  Case,
+  Default,
 
   // Other
+  /// Only emitted by scanners created with `Scanner::with_comments`
+  Comment(String),
   Eof,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+/// Words scanned as keywords instead of identifiers.
+pub const KEYWORDS: [&str; 25] = [
+  "and", "assert", "break", "case", "class", "continue", "default", "else", "false", "for", "fun",
+  "if", "in", "is", "loop", "nil", "or", "print", "return", "super", "switch", "this", "true",
+  "var", "while",
+];
+
+/// Source text that carries no meaning for the parsers but matters to tools
+/// like the formatter.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Trivia {
+  /// A `//` comment on its own line, including the slashes
+  Comment(String),
+  /// A `//` comment following other code on the same line
+  TrailingComment(String),
+  /// An empty line
+  BlankLine,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct Token {
   pub kind: TokenType,
   pub lexeme: String,
   pub line: u32,
+  /// Column of the first character, counting characters from 1
+  pub column: u32,
+  /// Byte range of the token in the source
+  pub span: Range<usize>,
+  /// Text of the `///` doc comment lines directly preceding this token, if any.
+  pub doc: Option<String>,
+  /// Comments and blank lines preceding this token.
+  pub leading_trivia: Vec<Trivia>,
 }
 
 pub struct Scanner {
   source: String,
   line: u32,
   index: usize,
+  /// Byte offset at which the token being scanned starts
+  start: usize,
+  /// Line on which the token being scanned starts, strings can span lines
+  start_line: u32,
+  /// Columns preceding the source on its first line
+  first_column_offset: u32,
   was_eof_yielded: bool,
+  doc_lines: Vec<String>,
+  trivia: Vec<Trivia>,
+  last_token_line: Option<u32>,
+  line_has_content: bool,
+  emit_comments: bool,
+  /// Brace depth inside each unfinished `${` interpolation, innermost last
+  interpolations: Vec<usize>,
 }
 
 impl Scanner {
@@ -73,19 +164,73 @@ impl Scanner {
     Scanner {
       line: 1,
       index: 0,
+      start: 0,
+      start_line: 1,
+      first_column_offset: 0,
       source,
       was_eof_yielded: false,
+      doc_lines: vec![],
+      trivia: vec![],
+      last_token_line: None,
+      line_has_content: false,
+      emit_comments: false,
+      interpolations: vec![],
+    }
+  }
+
+  /// Creates a scanner for a part of a larger source starting on `line` and
+  /// `column`, so tokens and errors report positions in the whole source.
+  pub fn starting_at(source: String, line: u32, column: u32) -> Self {
+    Scanner {
+      line,
+      start_line: line,
+      first_column_offset: column - 1,
+      ..Scanner::new(source)
+    }
+  }
+
+  /// Creates a scanner that yields comments as `TokenType::Comment` tokens
+  /// instead of recording them as trivia.
+  pub fn with_comments(source: String) -> Self {
+    Scanner {
+      emit_comments: true,
+      ..Scanner::new(source)
     }
   }
 
   fn add_token(&mut self, kind: TokenType, lexeme: String) -> Option<Result<Token>> {
+    // Doc comments belong to the declaration following them, not to the comment itself
+    let doc = if self.doc_lines.is_empty() || matches!(kind, TokenType::Comment(_)) {
+      None
+    } else {
+      Some(std::mem::take(&mut self.doc_lines).join("\n"))
+    };
+
+    self.last_token_line = Some(self.line);
+    self.line_has_content = true;
+
     Some(Ok(Token {
       kind,
       lexeme,
-      line: self.line,
+      line: self.start_line,
+      column: self.column(),
+      span: self.start..self.index,
+      doc,
+      leading_trivia: std::mem::take(&mut self.trivia),
     }))
   }
 
+  /// Column of the start of the token being scanned, counting characters
+  /// from 1.
+  fn column(&self) -> u32 {
+    let (line_start, offset) = match self.source[..self.start].rfind('\n') {
+      Some(index) => (index + 1, 0),
+      None => (0, self.first_column_offset),
+    };
+
+    self.source[line_start..self.start].chars().count() as u32 + offset + 1
+  }
+
   fn slice(&self) -> &str {
     &self.source[self.index..]
   }
@@ -121,21 +266,230 @@ impl Scanner {
     }
   }
 
+  /// Scans a string literal up to the closing quote, or up to a `${` that
+  /// starts an interpolation. Raw strings, `r"..."`, have neither escapes
+  /// nor interpolation.
+  fn string(&mut self, is_raw: bool) -> Option<Result<Token>> {
+    let mut value = String::new();
+
+    while let Some(char) = self.next_char_if(|c| *c != '"') {
+      if char == '\n' {
+        self.line += 1;
+      }
+
+      if is_raw {
+        value.push(char);
+        continue;
+      }
+
+      if char == '$' && self.next_char_if(|c| *c == '{').is_some() {
+        self.interpolations.push(0);
+
+        return self.add_token(TokenType::Interpolation(value.clone()), value);
+      }
+
+      if char != '\\' {
+        value.push(char);
+        continue;
+      }
+
+      // Unknown escapes are kept as they are
+      match self.next_char() {
+        Some('n') => value.push('\n'),
+        Some('t') => value.push('\t'),
+        Some('r') => value.push('\r'),
+        Some(escaped @ ('\\' | '"' | '$')) => value.push(escaped),
+        Some(other) => {
+          value.push('\\');
+          value.push(other);
+        }
+        None => value.push('\\'),
+      }
+    }
+
+    if self.next_char().is_none() {
+      return Some(Err(
+        ScanError::UnterminatedString {
+          line: self.start_line,
+          column: self.column(),
+        }
+        .into(),
+      ));
+    }
+
+    self.add_token(TokenType::String(value.clone()), value)
+  }
+
+  /// Scans a number literal: `0xFF`, `0b1010`, or decimal with an optional
+  /// fraction and exponent, like `1.5e-3`. Digits can be grouped with `_`.
+  fn number(&mut self, first: char) -> Option<Result<Token>> {
+    let radix = match (first, self.peek_char(0)) {
+      ('0', Some('x' | 'X')) => 16,
+      ('0', Some('b' | 'B')) => 2,
+      _ => 10,
+    };
+
+    let value = if radix == 10 {
+      let mut value = String::from(first);
+      value.push_str(&self.digits(10));
+
+      if self.peek_char(0).is_some_and(|c| c == '.')
+        && self.peek_char(1).is_some_and(|c| c.is_ascii_digit())
+      {
+        value.push(self.next_char().unwrap());
+        value.push_str(&self.digits(10));
+      }
+
+      let has_exponent = self.peek_char(0).is_some_and(|c| c == 'e' || c == 'E')
+        && match self.peek_char(1) {
+          Some('+' | '-') => self.peek_char(2).is_some_and(|c| c.is_ascii_digit()),
+          next => next.is_some_and(|c| c.is_ascii_digit()),
+        };
+
+      if has_exponent {
+        value.push(self.next_char().unwrap());
+
+        if let Some(sign) = self.next_char_if(|c| *c == '+' || *c == '-') {
+          value.push(sign);
+        }
+
+        value.push_str(&self.digits(10));
+      }
+
+      value.parse::<f64>().ok()
+    } else {
+      // Skip the prefix
+      self.next_char();
+
+      u64::from_str_radix(&self.digits(radix), radix)
+        .ok()
+        .map(|value| value as f64)
+    };
+
+    // Letters or digits right after the literal, like in `0b12` or `3e`, make
+    // it invalid as a whole
+    let is_malformed = self.peek_char(0).is_some_and(|c| c.is_ascii_alphanumeric());
+
+    while self.next_char_if(|c| c.is_ascii_alphanumeric()).is_some() {}
+
+    let lexeme = self.source[self.start..self.index].to_string();
+
+    match value {
+      Some(value) if !is_malformed => self.add_token(TokenType::Number(value), lexeme),
+      _ => Some(Err(
+        ScanError::InvalidNumber {
+          lexeme,
+          line: self.start_line,
+          column: self.column(),
+        }
+        .into(),
+      )),
+    }
+  }
+
+  /// Digits in `radix`, without the `_` separators between them.
+  fn digits(&mut self, radix: u32) -> String {
+    let mut digits = String::new();
+
+    while let Some(char) = self.next_char_if(|c| c.is_digit(radix) || *c == '_') {
+      if char != '_' {
+        digits.push(char);
+      }
+    }
+
+    digits
+  }
+
+  /// Yields a comment as a token, or records it as trivia of the next one.
+  fn comment(&mut self, text: String) -> Option<Result<Token>> {
+    if self.emit_comments {
+      return self.add_token(TokenType::Comment(text.clone()), text);
+    }
+
+    self
+      .trivia
+      .push(if self.last_token_line == Some(self.line) {
+        Trivia::TrailingComment(text)
+      } else {
+        Trivia::Comment(text)
+      });
+    self.line_has_content = true;
+
+    None
+  }
+
+  /// Whether the token being scanned starts the whole source, not just a
+  /// part of it given to `starting_at`.
+  fn is_at_source_start(&self) -> bool {
+    self.start == 0 && self.line == 1 && self.first_column_offset == 0
+  }
+
   fn next_token(&mut self) -> Option<Result<Token>> {
     while let Some(char) = self.next_char() {
+      self.start = self.index - char.len_utf8();
+      self.start_line = self.line;
+
       match char {
         '(' => return self.add_token(TokenType::LeftParen, char.to_string()),
         ')' => return self.add_token(TokenType::RightParen, char.to_string()),
-        '{' => return self.add_token(TokenType::LeftBrace, char.to_string()),
-        '}' => return self.add_token(TokenType::RightBrace, char.to_string()),
+        '{' => {
+          if let Some(depth) = self.interpolations.last_mut() {
+            *depth += 1;
+          }
+
+          return self.add_token(TokenType::LeftBrace, char.to_string());
+        }
+        '}' => match self.interpolations.last_mut() {
+          // Closes the interpolation, the string goes on
+          Some(0) => {
+            self.interpolations.pop();
+
+            return self.string(false);
+          }
+          Some(depth) => {
+            *depth -= 1;
+
+            return self.add_token(TokenType::RightBrace, char.to_string());
+          }
+          None => return self.add_token(TokenType::RightBrace, char.to_string()),
+        },
+        '[' => return self.add_token(TokenType::LeftBracket, char.to_string()),
+        ']' => return self.add_token(TokenType::RightBracket, char.to_string()),
         ',' => return self.add_token(TokenType::Comma, char.to_string()),
-        '.' => return self.add_token(TokenType::Dot, char.to_string()),
-        '-' => return self.add_token(TokenType::Minus, char.to_string()),
-        '+' => return self.add_token(TokenType::Plus, char.to_string()),
+        '.' => {
+          if self.next_char_if(|c| *c == '.').is_none() {
+            return self.add_token(TokenType::Dot, char.to_string());
+          }
+
+          return if self.next_char_if(|c| *c == '=').is_some() {
+            self.add_token(TokenType::DotDotEqual, "..=".to_string())
+          } else if self.next_char_if(|c| *c == '.').is_some() {
+            self.add_token(TokenType::DotDotDot, "...".to_string())
+          } else {
+            self.add_token(TokenType::DotDot, "..".to_string())
+          };
+        }
+        '-' => {
+          return if self.next_char_if(|c| *c == '-').is_some() {
+            self.add_token(TokenType::MinusMinus, "--".to_string())
+          } else {
+            self.add_token(TokenType::Minus, char.to_string())
+          };
+        }
+        '+' => {
+          return if self.next_char_if(|c| *c == '+').is_some() {
+            self.add_token(TokenType::PlusPlus, "++".to_string())
+          } else {
+            self.add_token(TokenType::Plus, char.to_string())
+          };
+        }
         ';' => return self.add_token(TokenType::Semicolon, char.to_string()),
         '*' => return self.add_token(TokenType::Star, char.to_string()),
         '?' => return self.add_token(TokenType::Question, char.to_string()),
         ':' => return self.add_token(TokenType::Colon, char.to_string()),
+        '&' => return self.add_token(TokenType::Ampersand, char.to_string()),
+        '|' => return self.add_token(TokenType::Pipe, char.to_string()),
+        '^' => return self.add_token(TokenType::Caret, char.to_string()),
         '!' => {
           let type_ = if self.peek_char(0).is_some_and(|c| c == '=') {
             self.next_char();
@@ -160,6 +514,8 @@ impl Scanner {
           let type_ = if self.peek_char(0).is_some_and(|c| c == '=') {
             self.next_char();
             TokenType::LessEqual
+          } else if self.next_char_if(|c| *c == '<').is_some() {
+            TokenType::LessLess
           } else {
             TokenType::Less
           };
@@ -170,6 +526,8 @@ impl Scanner {
           let type_ = if self.peek_char(0).is_some_and(|c| c == '=') {
             self.next_char();
             TokenType::GreaterEqual
+          } else if self.next_char_if(|c| *c == '>').is_some() {
+            TokenType::GreaterGreater
           } else {
             TokenType::Greater
           };
@@ -178,64 +536,85 @@ impl Scanner {
         }
         '/' => {
           if self.peek_char(0).is_some_and(|c| c == '/') {
-            while self.next_char_if(|char| *char != '\n').is_some() {}
+            let mut comment = String::new();
+
+            while let Some(char) = self.next_char_if(|char| *char != '\n') {
+              comment.push(char);
+            }
+
+            // `///` starts a doc comment, but `////` and longer are plain comments
+            if let Some(doc) = comment.strip_prefix("//") {
+              if !doc.starts_with('/') {
+                let doc = doc.strip_prefix(' ').unwrap_or(doc);
+
+                self.doc_lines.push(doc.trim_end().to_string());
+              }
+            }
+
+            let text = format!("/{}", comment.trim_end());
+
+            if let Some(token) = self.comment(text) {
+              return Some(token);
+            }
           } else {
             return self.add_token(TokenType::Slash, char.to_string());
           }
         }
-        ' ' | '\r' | '\t' => {}
-        '\n' => self.line += 1,
-        '"' => {
-          let mut value = String::new();
+        // A shebang line, `#!/usr/bin/env rslox`, is kept like a comment
+        '#' if self.is_at_source_start() && self.peek_char(0) == Some('!') => {
+          let mut text = String::from(char);
 
-          while let Some(char) = self.next_char_if(|c| *c != '"') {
-            value.push(char);
+          while let Some(char) = self.next_char_if(|char| *char != '\n') {
+            text.push(char);
           }
 
-          // consume the closing "
-          self.next_char();
+          if let Some(token) = self.comment(text.trim_end().to_string()) {
+            return Some(token);
+          }
+        }
+        ' ' | '\r' | '\t' => {}
+        '\n' => {
+          let is_blank = !self.line_has_content;
+          let has_content_before = self.last_token_line.is_some() || !self.trivia.is_empty();
+
+          if is_blank && has_content_before {
+            self.trivia.push(Trivia::BlankLine);
+          }
 
-          return self.add_token(TokenType::String(value.clone()), value);
+          self.line += 1;
+          self.line_has_content = false;
         }
+        '"' => return self.string(false),
         _ => {
           if char.is_ascii_digit() {
+            return self.number(char);
+          } else if char == 'r' && self.next_char_if(|c| *c == '"').is_some() {
+            return self.string(true);
+          } else if char.is_alphabetic() || char == '_' {
             let mut value = String::from(char);
 
-            while let Some(char) = self.next_char_if(|c| c.is_ascii_digit()) {
-              value.push(char);
-            }
-
-            if self.peek_char(0).is_some_and(|c| c == '.')
-              && self.peek_char(1).is_some_and(|c| c.is_ascii_digit())
-            {
-              value.push(self.next_char().unwrap());
-
-              while let Some(char) = self.next_char_if(|c| c.is_ascii_digit()) {
-                value.push(char);
-              }
-            }
-
-            return if let Ok(parsed) = value.parse::<f64>() {
-              self.add_token(TokenType::Number(parsed), value.clone())
-            } else {
-              Some(Err(anyhow!("cannot parse string into number")))
-            };
-          } else if char.is_alphabetic() {
-            let mut value = String::from(char);
-
-            while let Some(char) = self.next_char_if(|c| c.is_ascii_alphanumeric()) {
+            while let Some(char) = self.next_char_if(|c| c.is_ascii_alphanumeric() || *c == '_') {
               value.push(char);
             }
 
             let token_type = match value.as_str() {
               "if" => TokenType::If,
+              "in" => TokenType::In,
+              "is" => TokenType::Is,
               "else" => TokenType::Else,
               "true" => TokenType::True,
               "false" => TokenType::False,
               "nil" => TokenType::Nil,
               "while" => TokenType::While,
+              "switch" => TokenType::Switch,
+              "case" => TokenType::Case,
+              "default" => TokenType::Default,
+              "loop" => TokenType::Loop,
+              "break" => TokenType::Break,
+              "continue" => TokenType::Continue,
               "for" => TokenType::For,
               "and" => TokenType::And,
+              "assert" => TokenType::Assert,
               "or" => TokenType::Or,
               "fun" => TokenType::Fun,
               "return" => TokenType::Return,
@@ -248,6 +627,15 @@ impl Scanner {
             };
 
             return self.add_token(token_type, value);
+          } else {
+            return Some(Err(
+              ScanError::UnexpectedCharacter {
+                character: char,
+                line: self.line,
+                column: self.column(),
+              }
+              .into(),
+            ));
           }
         }
       }
@@ -257,6 +645,8 @@ impl Scanner {
       None
     } else {
       self.was_eof_yielded = true;
+      self.start = self.index;
+      self.start_line = self.line;
       self.add_token(TokenType::Eof, "".to_string())
     }
   }
@@ -269,3 +659,316 @@ impl Iterator for Scanner {
     self.next_token()
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn scan(scanner: Scanner) -> Vec<Token> {
+    scanner.collect::<Result<Vec<Token>>>().unwrap()
+  }
+
+  #[test]
+  fn test_comments_as_trivia() {
+    let tokens = scan(Scanner::new("a; // one\n\n// two\nb;".to_string()));
+
+    assert_eq!(tokens.len(), 5);
+    assert_eq!(
+      tokens[2].leading_trivia,
+      vec![
+        Trivia::TrailingComment("// one".to_string()),
+        Trivia::BlankLine,
+        Trivia::Comment("// two".to_string()),
+      ]
+    );
+  }
+
+  #[test]
+  fn test_comments_as_tokens() {
+    let tokens = scan(Scanner::with_comments("/// doc\n// plain\nvar".to_string()));
+
+    let kinds = tokens.iter().map(|t| t.kind.clone()).collect::<Vec<_>>();
+
+    assert_eq!(
+      kinds,
+      vec![
+        TokenType::Comment("/// doc".to_string()),
+        TokenType::Comment("// plain".to_string()),
+        TokenType::Var,
+        TokenType::Eof,
+      ]
+    );
+    assert_eq!(tokens[2].doc, Some("doc".to_string()));
+    assert!(tokens.iter().all(|t| t.leading_trivia.is_empty()));
+  }
+
+  #[test]
+  fn test_keywords() {
+    for keyword in KEYWORDS {
+      let tokens = scan(Scanner::new(keyword.to_string()));
+
+      assert!(
+        !matches!(tokens[0].kind, TokenType::Identifier(_)),
+        "{keyword}"
+      );
+    }
+  }
+
+  #[test]
+  fn test_identifiers_with_underscores() {
+    let kinds = scan(Scanner::new("bench_loop _x".to_string()))
+      .into_iter()
+      .map(|t| t.kind)
+      .collect::<Vec<_>>();
+
+    assert_eq!(
+      kinds,
+      vec![
+        TokenType::Identifier("bench_loop".to_string()),
+        TokenType::Identifier("_x".to_string()),
+        TokenType::Eof,
+      ]
+    );
+  }
+
+  #[test]
+  fn test_increments() {
+    let kinds = scan(Scanner::new("i++ --j + -k".to_string()))
+      .into_iter()
+      .map(|t| t.kind)
+      .collect::<Vec<_>>();
+
+    assert_eq!(
+      kinds,
+      vec![
+        TokenType::Identifier("i".to_string()),
+        TokenType::PlusPlus,
+        TokenType::MinusMinus,
+        TokenType::Identifier("j".to_string()),
+        TokenType::Plus,
+        TokenType::Minus,
+        TokenType::Identifier("k".to_string()),
+        TokenType::Eof,
+      ]
+    );
+  }
+
+  #[test]
+  fn test_bitwise_operators() {
+    let kinds = scan(Scanner::new("& | ^ << >> <= <".to_string()))
+      .into_iter()
+      .map(|t| t.kind)
+      .collect::<Vec<_>>();
+
+    assert_eq!(
+      kinds,
+      vec![
+        TokenType::Ampersand,
+        TokenType::Pipe,
+        TokenType::Caret,
+        TokenType::LessLess,
+        TokenType::GreaterGreater,
+        TokenType::LessEqual,
+        TokenType::Less,
+        TokenType::Eof,
+      ]
+    );
+  }
+
+  #[test]
+  fn test_ranges() {
+    let kinds = scan(Scanner::new("0..10 1.5..=x ...".to_string()))
+      .into_iter()
+      .map(|t| t.kind)
+      .collect::<Vec<_>>();
+
+    assert_eq!(
+      kinds,
+      vec![
+        TokenType::Number(0.),
+        TokenType::DotDot,
+        TokenType::Number(10.),
+        TokenType::Number(1.5),
+        TokenType::DotDotEqual,
+        TokenType::Identifier("x".to_string()),
+        TokenType::DotDotDot,
+        TokenType::Eof,
+      ]
+    );
+  }
+
+  #[test]
+  fn test_interpolation() {
+    let kinds = scan(Scanner::new(r#""a ${x + {}} b ${"c${y}"}" }"#.to_string()))
+      .into_iter()
+      .map(|t| t.kind)
+      .collect::<Vec<_>>();
+
+    assert_eq!(
+      kinds,
+      vec![
+        TokenType::Interpolation("a ".to_string()),
+        TokenType::Identifier("x".to_string()),
+        TokenType::Plus,
+        TokenType::LeftBrace,
+        TokenType::RightBrace,
+        TokenType::Interpolation(" b ".to_string()),
+        TokenType::Interpolation("c".to_string()),
+        TokenType::Identifier("y".to_string()),
+        TokenType::String("".to_string()),
+        TokenType::String("".to_string()),
+        TokenType::RightBrace,
+        TokenType::Eof,
+      ]
+    );
+  }
+
+  #[test]
+  fn test_escapes_raw_and_multiline_strings() {
+    let tokens = scan(Scanner::new(
+      "\"a\\n\\\"b\\\" \\${c} \\\\ \\q\" r\"C:\\dir\\${x}\" \"one\ntwo\"\nend".to_string(),
+    ));
+
+    let kinds = tokens.iter().map(|t| t.kind.clone()).collect::<Vec<_>>();
+
+    assert_eq!(
+      kinds,
+      vec![
+        TokenType::String("a\n\"b\" ${c} \\ \\q".to_string()),
+        TokenType::String("C:\\dir\\${x}".to_string()),
+        TokenType::String("one\ntwo".to_string()),
+        TokenType::Identifier("end".to_string()),
+        TokenType::Eof,
+      ]
+    );
+    assert_eq!(
+      tokens.iter().map(|t| t.line).collect::<Vec<_>>(),
+      vec![1, 1, 1, 3, 3]
+    );
+  }
+
+  #[test]
+  fn test_number_literals() {
+    let kinds = scan(Scanner::new(
+      "0xFF 0b1010 1e-3 2.5E+2 1_000_000 0x_ff_ff 3 e 1.5..2".to_string(),
+    ))
+    .into_iter()
+    .map(|t| t.kind)
+    .collect::<Vec<_>>();
+
+    assert_eq!(
+      kinds,
+      vec![
+        TokenType::Number(255.),
+        TokenType::Number(10.),
+        TokenType::Number(0.001),
+        TokenType::Number(250.),
+        TokenType::Number(1_000_000.),
+        TokenType::Number(65535.),
+        TokenType::Number(3.),
+        TokenType::Identifier("e".to_string()),
+        TokenType::Number(1.5),
+        TokenType::DotDot,
+        TokenType::Number(2.),
+        TokenType::Eof,
+      ]
+    );
+
+    for source in ["0x", "0b12", "3e", "1e+", "0xFFFFFFFFFFFFFFFFF"] {
+      assert!(Scanner::new(source.to_string()).any(|token| token.is_err()));
+    }
+
+    let error = Scanner::new("print 1 +\n  0b12;".to_string())
+      .find_map(Result::err)
+      .unwrap();
+
+    assert_eq!(
+      error.downcast::<ScanError>().unwrap(),
+      ScanError::InvalidNumber {
+        lexeme: "0b12".to_string(),
+        line: 2,
+        column: 3,
+      }
+    );
+  }
+
+  #[test]
+  fn test_unterminated_string() {
+    let error = |source: &str| {
+      Scanner::new(source.to_string())
+        .find_map(Result::err)
+        .and_then(|error| error.downcast::<ScanError>().ok())
+    };
+
+    assert_eq!(
+      error("print 1;\nprint \"abc;\nprint 2;"),
+      Some(ScanError::UnterminatedString { line: 2, column: 7 })
+    );
+    assert_eq!(
+      error("\"a ${b} c"),
+      Some(ScanError::UnterminatedString { line: 1, column: 7 })
+    );
+    assert_eq!(
+      error("\"ends with a backslash\\\""),
+      Some(ScanError::UnterminatedString { line: 1, column: 1 })
+    );
+    assert_eq!(error("\"closed\""), None);
+  }
+
+  #[test]
+  fn test_unexpected_characters() {
+    let mut scanner = Scanner::new("var a;\n  é = 1 @ 2;".to_string());
+
+    let error = scanner.find_map(Result::err).unwrap();
+
+    assert_eq!(
+      error.downcast::<ScanError>().unwrap(),
+      ScanError::UnexpectedCharacter {
+        character: '@',
+        line: 2,
+        column: 9,
+      }
+    );
+    // Scanning goes on after the error
+    assert_eq!(scanner.next().unwrap().unwrap().kind, TokenType::Number(2.));
+  }
+
+  #[test]
+  fn test_shebang() {
+    let tokens = scan(Scanner::new("#!/usr/bin/env rslox\nprint 1;".to_string()));
+
+    assert_eq!(tokens[0].kind, TokenType::Print);
+    assert_eq!(tokens[0].line, 2);
+    assert_eq!(
+      tokens[0].leading_trivia,
+      vec![Trivia::Comment("#!/usr/bin/env rslox".to_string())]
+    );
+
+    // Anywhere else `#` is still an error
+    assert!(Scanner::new(" #!x".to_string()).any(|token| token.is_err()));
+    assert!(Scanner::new("1;\n#!x".to_string()).any(|token| token.is_err()));
+  }
+
+  #[test]
+  fn test_spans() {
+    let spans = scan(Scanner::new("var é = \"ab\";\n// c\nx>=1".to_string()))
+      .into_iter()
+      .map(|t| t.span)
+      .collect::<Vec<_>>();
+
+    assert_eq!(
+      spans,
+      vec![
+        0..3,
+        4..6,
+        7..8,
+        9..13,
+        13..14,
+        20..21,
+        21..23,
+        23..24,
+        24..24
+      ]
+    );
+  }
+}
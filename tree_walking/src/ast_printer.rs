@@ -1,83 +1,370 @@
-use crate::parser::{BinaryOperator, Expr, Literal, Stmt, UnaryOperator};
+use crate::parser::{BinaryOperator, Expr, Literal, Stmt, UnaryOperator, UpdateOperator};
 
+/// Prints syntax trees as S-expressions, e.g. `(+ 1 (* 2 3))`, to show how
+/// the source was parsed.
 pub(crate) trait Printer {
   fn print(&self) -> String;
 }
 
-impl Printer for Expr {
+/// `(name part...)`
+fn list<'a>(name: &str, parts: impl IntoIterator<Item = &'a dyn Printer>) -> String {
+  let mut out = format!("({name}");
+
+  for part in parts {
+    out.push(' ');
+    out.push_str(&part.print());
+  }
+
+  out.push(')');
+
+  out
+}
+
+/// Stands in for optional parts that are missing.
+struct Missing;
+
+impl Printer for Missing {
+  fn print(&self) -> String {
+    "_".to_string()
+  }
+}
+
+impl Printer for String {
+  fn print(&self) -> String {
+    self.to_string()
+  }
+}
+
+impl<T: Printer> Printer for Box<T> {
+  fn print(&self) -> String {
+    self.as_ref().print()
+  }
+}
+
+impl<T: Printer> Printer for Option<T> {
   fn print(&self) -> String {
-    // let mut out = String::new();
+    self.as_ref().map_or_else(|| Missing.print(), T::print)
+  }
+}
+
+impl<T: Printer> Printer for [T] {
+  fn print(&self) -> String {
+    let items = self.iter().map(T::print).collect::<Vec<String>>();
+
+    format!("({})", items.join(" "))
+  }
+}
+
+impl<T: Printer> Printer for Vec<T> {
+  fn print(&self) -> String {
+    self.as_slice().print()
+  }
+}
+
+impl Printer for BinaryOperator {
+  fn print(&self) -> String {
+    match self {
+      BinaryOperator::EqualEqual => "==",
+      BinaryOperator::BangEqual => "!=",
+      BinaryOperator::Plus => "+",
+      BinaryOperator::Minus => "-",
+      BinaryOperator::Slash => "/",
+      BinaryOperator::Star => "*",
+      BinaryOperator::Greater => ">",
+      BinaryOperator::GreaterEqual => ">=",
+      BinaryOperator::Less => "<",
+      BinaryOperator::LessEqual => "<=",
+      BinaryOperator::In => "in",
+      BinaryOperator::Comma => ",",
+      BinaryOperator::Or => "or",
+      BinaryOperator::And => "and",
+      BinaryOperator::BitAnd => "&",
+      BinaryOperator::BitOr => "|",
+      BinaryOperator::BitXor => "^",
+      BinaryOperator::ShiftLeft => "<<",
+      BinaryOperator::ShiftRight => ">>",
+    }
+    .to_string()
+  }
+}
 
+impl Printer for Expr {
+  fn print(&self) -> String {
     match self {
       Expr::Unary { operator, expr } => {
-        let op_string = match operator {
+        let operator = match operator {
           UnaryOperator::Bang => "!",
           UnaryOperator::Minus => "-",
         };
 
-        format!("{}{}", op_string, expr.print())
+        list(operator, [expr as &dyn Printer])
       }
       Expr::Binary {
         operator,
         left,
         right,
-      } => {
-        let op_string = match operator {
-          BinaryOperator::BangEqual => "!=",
-          BinaryOperator::Comma => ",",
-          BinaryOperator::EqualEqual => "==",
-          BinaryOperator::Plus => "+",
-          BinaryOperator::Minus => "-",
-          BinaryOperator::Star => "*",
-          BinaryOperator::Slash => "/",
-          _ => "none",
-        };
-
-        let left_string = left.print();
-        let right_string = right.print();
-
-        format!("[{}]({}, {})", op_string, left_string, right_string)
-      }
+      } => list(&operator.print(), [left as &dyn Printer, right]),
       Expr::Ternary {
         conditional,
         true_case,
         false_case,
-      } => format!(
-        "({} ? {} : {})",
-        conditional.print(),
-        true_case.print(),
-        false_case.print()
-      ),
-      Expr::Grouping { expr } => expr.print(),
+      } => list("?", [conditional as &dyn Printer, true_case, false_case]),
+      Expr::Grouping { expr } => list("group", [expr as &dyn Printer]),
       Expr::Literal { value } => match value {
         Literal::True => "true".to_string(),
         Literal::False => "false".to_string(),
         Literal::Number { value } => format!("{}", value),
-        Literal::String { value } => format!("\"{}\"", value),
-        Literal::Identifier { name, .. } => format!("{}", name),
+        Literal::String { value } => format!("{:?}", value),
+        Literal::Identifier { name, .. } => name.to_string(),
         Literal::Nil => "nil".to_string(),
       },
-      Expr::Assignment { .. } => "toto".to_string(),
+      Expr::Assignment {
+        name, expression, ..
+      } => list("=", [name as &dyn Printer, expression]),
       Expr::Call {
         function,
         arguments,
+        ..
+      } => list(
+        "call",
+        std::iter::once(function as &dyn Printer)
+          .chain(arguments.iter().map(|a| a as &dyn Printer)),
+      ),
+      Expr::Range {
+        start,
+        end,
+        inclusive,
+      } => list(
+        if *inclusive { "..=" } else { ".." },
+        [start as &dyn Printer, end],
+      ),
+      Expr::Spread { expr } => list("...", [expr as &dyn Printer]),
+      Expr::Tuple { elements } => list("tuple", elements.iter().map(|e| e as &dyn Printer)),
+      Expr::Is {
+        expr, type_name, ..
+      } => list("is", [expr as &dyn Printer, type_name]),
+      Expr::List { elements } => list("list", elements.iter().map(|e| e as &dyn Printer)),
+      Expr::Map { entries } => {
+        let entries = entries
+          .iter()
+          .map(|(key, value)| format!("({} {})", key.print(), value.print()))
+          .collect::<Vec<String>>();
+
+        list("map", entries.iter().map(|e| e as &dyn Printer))
+      }
+      Expr::Index { object, index } => list("index", [object as &dyn Printer, index]),
+      Expr::Slice { object, start, end } => list("slice", [object as &dyn Printer, start, end]),
+      Expr::IndexSet {
+        object,
+        index,
+        value,
+      } => list("index=", [object as &dyn Printer, index, value]),
+      Expr::Get { object, name } => list(".", [object as &dyn Printer, name]),
+      Expr::Set {
+        object,
+        name,
+        value,
+      } => list(".=", [object as &dyn Printer, name, value]),
+      Expr::Super { method, .. } => list("super", [method as &dyn Printer]),
+      Expr::Update {
+        name,
+        operator,
+        prefix,
+        ..
       } => {
-        todo!()
+        let operator = match (operator, prefix) {
+          (UpdateOperator::Increment, true) => "pre++",
+          (UpdateOperator::Increment, false) => "post++",
+          (UpdateOperator::Decrement, true) => "pre--",
+          (UpdateOperator::Decrement, false) => "post--",
+        };
+
+        list(operator, [name as &dyn Printer])
+      }
+      Expr::Interpolation {
+        strings,
+        expressions,
+      } => {
+        let parts = strings
+          .iter()
+          .map(|string| format!("{:?}", string))
+          .zip(expressions.iter().map(Expr::print).chain([String::new()]))
+          .flat_map(|(string, expression)| [string, expression])
+          .filter(|part| !part.is_empty())
+          .collect::<Vec<String>>();
+
+        list("interpolate", parts.iter().map(|p| p as &dyn Printer))
       }
     }
   }
 }
 
+/// Wraps a printed loop in `(label name ...)` if it has a label.
+fn labeled(label: &Option<String>, printed: String) -> String {
+  match label {
+    Some(label) => format!("(label {} {})", label, printed),
+    None => printed,
+  }
+}
+
 impl Printer for Stmt {
   fn print(&self) -> String {
-    // let mut out = String::new();
-
     match self {
-      Stmt::Expression { expression } => expression.print(),
-      Stmt::Declaration { name, initializer } => {
-        format!("{}: {}", name, initializer.print())
+      Stmt::Expression { expression, .. } => expression.print(),
+      Stmt::Print { expression, .. } => list("print", [expression as &dyn Printer]),
+      Stmt::Assert {
+        condition, message, ..
+      } => list(
+        "assert",
+        std::iter::once(condition as &dyn Printer).chain(message.iter().map(|m| m as &dyn Printer)),
+      ),
+      Stmt::Declaration {
+        name, initializer, ..
+      } => list("var", [name as &dyn Printer, initializer]),
+      Stmt::TupleDeclaration {
+        names, initializer, ..
+      } => list("var", [names as &dyn Printer, initializer]),
+      Stmt::Return { value, .. } => list("return", value.iter().map(|v| v as &dyn Printer)),
+      Stmt::FunDeclaration {
+        name,
+        parameters,
+        body,
+        ..
+      } => list(
+        "fun",
+        [name as &dyn Printer, parameters]
+          .into_iter()
+          .chain(body.iter().map(|s| s as &dyn Printer)),
+      ),
+      Stmt::Class {
+        name,
+        superclass,
+        methods,
+        ..
+      } => {
+        let superclass = superclass
+          .as_ref()
+          .map(|superclass| list("<", [superclass as &dyn Printer]));
+
+        list(
+          "class",
+          std::iter::once(name as &dyn Printer)
+            .chain(superclass.iter().map(|s| s as &dyn Printer))
+            .chain(methods.iter().map(|m| m as &dyn Printer)),
+        )
       }
-      _ => todo!("todo"),
+      Stmt::Block { statements } => list("block", statements.iter().map(|s| s as &dyn Printer)),
+      Stmt::While {
+        condition,
+        statement,
+        label,
+        ..
+      } => labeled(label, list("while", [condition as &dyn Printer, statement])),
+      Stmt::Loop { body, label } => labeled(label, list("loop", [body as &dyn Printer])),
+      Stmt::Break { label, .. } => list("break", label.iter().map(|l| l as &dyn Printer)),
+      Stmt::Continue { label, .. } => list("continue", label.iter().map(|l| l as &dyn Printer)),
+      Stmt::For {
+        initializer,
+        condition,
+        increment,
+        body,
+        label,
+        ..
+      } => labeled(
+        label,
+        list(
+          "for",
+          [initializer as &dyn Printer, condition, increment, body],
+        ),
+      ),
+      Stmt::ForIn {
+        variable,
+        iterable,
+        body,
+        label,
+        ..
+      } => labeled(
+        label,
+        list("for-in", [variable as &dyn Printer, iterable, body]),
+      ),
+      Stmt::If {
+        condition,
+        true_case,
+        false_case,
+        ..
+      } => list(
+        "if",
+        [condition as &dyn Printer, true_case]
+          .into_iter()
+          .chain(false_case.iter().map(|f| f as &dyn Printer)),
+      ),
+      Stmt::Switch {
+        subject,
+        cases,
+        default,
+        ..
+      } => {
+        let cases = cases
+          .iter()
+          .map(|(values, body)| list("case", [values as &dyn Printer, body]))
+          .chain(
+            default
+              .iter()
+              .map(|body| list("default", [body as &dyn Printer])),
+          )
+          .collect::<Vec<String>>();
+
+        list(
+          "switch",
+          std::iter::once(subject as &dyn Printer).chain(cases.iter().map(|c| c as &dyn Printer)),
+        )
+      }
+      Stmt::Trivia { .. } => "(trivia)".to_string(),
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::parser::Parser;
+  use anyhow::Result;
+  use scanner::{Scanner, Token};
+
+  fn print(source: &str) -> Vec<String> {
+    let tokens = Scanner::new(source.to_string())
+      .collect::<Result<Vec<Token>>>()
+      .unwrap();
+
+    Parser::new(tokens)
+      .parse()
+      .unwrap()
+      .iter()
+      .map(Printer::print)
+      .collect()
+  }
+
+  #[test]
+  fn test_print() {
+    assert_eq!(
+      print(
+        r#"
+var x = -1 + 2 * (3 - "a");
+outer: for (var i = 0; i < 3; i++) { if (!x) break outer; else continue; }
+for (;;) {}
+fun f(a, b) { return a.b[1:]; }
+class B < A { init() { super.init(); } }
+switch (x) { case 1, 2: print x; default: assert x, "m"; }
+"#
+      ),
+      [
+        r#"(var x (+ (- 1) (* 2 (group (- 3 "a")))))"#,
+        "(label outer (for (var i 0) (< i 3) (post++ i) (block (if (! x) (break outer) (continue)))))",
+        "(for _ _ _ (block))",
+        "(fun f (a b) (return (slice (. a b) 1 _)))",
+        "(class B (< A) (fun init () (call (super init))))",
+        r#"(switch x (case (1 2) (block (print x))) (default (block (assert x "m"))))"#,
+      ]
+    );
+  }
+}
@@ -0,0 +1,160 @@
+//! On-disk cache of parsed programs, keyed by a hash of their source. Only
+//! the most recently written entries are kept.
+
+use crate::parser::Stmt;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+/// Version of the cached AST. Bump it whenever a change to `Stmt`, `Expr` or
+/// the types they contain changes how they serialize.
+const FORMAT_VERSION: u32 = 2;
+
+/// Most entries kept in a cache directory, the oldest are removed first.
+const MAX_ENTRIES: usize = 500;
+
+const EXTENSION: &str = ".ast.json";
+
+/// Path of the cache entry for `source`. The format and crate versions are
+/// part of the key, so entries written for another AST are never read.
+fn entry_path(dir: &Path, source: &str) -> PathBuf {
+  let mut hasher = Sha256::new();
+
+  hasher.update(FORMAT_VERSION.to_le_bytes());
+  hasher.update(env!("CARGO_PKG_VERSION"));
+  hasher.update([0]);
+  hasher.update(source);
+
+  let hash = hasher
+    .finalize()
+    .iter()
+    .map(|byte| format!("{:02x}", byte))
+    .collect::<String>();
+
+  dir.join(format!("{}{}", hash, EXTENSION))
+}
+
+/// Returns the statements cached for `source`, if any. Unreadable entries are
+/// treated as missing.
+pub(crate) fn load(dir: &Path, source: &str) -> Option<Vec<Stmt>> {
+  let path = entry_path(dir, source);
+  let contents = fs::read(&path).ok()?;
+
+  match serde_json::from_slice(&contents) {
+    Ok(statements) => Some(statements),
+    Err(e) => {
+      debug!(path = %path.display(), error = %e, "ignoring corrupt cache entry");
+
+      None
+    }
+  }
+}
+
+/// Caches `statements` as the parse result of `source`, then removes the
+/// oldest entries beyond `MAX_ENTRIES`. A cache that cannot be written only
+/// costs speed, so failures are logged and ignored.
+pub(crate) fn store(dir: &Path, source: &str, statements: &[Stmt]) {
+  let path = entry_path(dir, source);
+
+  let result = fs::create_dir_all(dir)
+    .map_err(anyhow::Error::from)
+    .and_then(|_| Ok(serde_json::to_vec(statements)?))
+    .and_then(|contents| Ok(fs::write(&path, contents)?))
+    .and_then(|_| prune(dir, MAX_ENTRIES));
+
+  if let Err(e) = result {
+    debug!(path = %path.display(), error = %e, "cannot write cache entry");
+  }
+}
+
+/// Removes the least recently written entries of `dir` until at most `limit`
+/// are left. Other files are left alone.
+fn prune(dir: &Path, limit: usize) -> anyhow::Result<()> {
+  let mut entries = vec![];
+
+  for entry in fs::read_dir(dir)? {
+    let entry = entry?;
+
+    if entry.file_name().to_string_lossy().ends_with(EXTENSION) {
+      entries.push((entry.metadata()?.modified()?, entry.path()));
+    }
+  }
+
+  if entries.len() <= limit {
+    return Ok(());
+  }
+
+  entries.sort();
+
+  for (_, path) in &entries[..entries.len() - limit] {
+    debug!(path = %path.display(), "removing old cache entry");
+    fs::remove_file(path)?;
+  }
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::parser::Parser;
+  use anyhow::Result;
+  use scanner::{Scanner, Token};
+
+  #[test]
+  fn test_store_and_load() {
+    let dir = std::env::temp_dir().join(format!("rslox-cache-test-{}", std::process::id()));
+    let source = "fun f(a) { return a, 1; } for (i in 0..3) { print f(i); }";
+
+    let tokens = Scanner::new(source.to_string())
+      .collect::<Result<Vec<Token>>>()
+      .unwrap();
+    let statements = Parser::new(tokens).parse().unwrap();
+
+    assert!(load(&dir, source).is_none());
+
+    store(&dir, source, &statements);
+
+    let cached = load(&dir, source).unwrap();
+
+    assert_eq!(
+      cached.iter().map(Stmt::node_count).sum::<usize>(),
+      statements.iter().map(Stmt::node_count).sum::<usize>()
+    );
+    assert!(load(&dir, "print 1;").is_none());
+
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn test_prune() {
+    let dir = std::env::temp_dir().join(format!("rslox-cache-prune-test-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    let sources = ["print 1;", "print 2;", "print 3;"];
+
+    for (age, source) in sources.iter().rev().enumerate() {
+      let path = entry_path(&dir, source);
+      let modified = std::time::SystemTime::now() - std::time::Duration::from_secs(60 * age as u64);
+
+      fs::write(&path, "[]").unwrap();
+      fs::File::options()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_modified(modified)
+        .unwrap();
+    }
+    fs::write(dir.join("notes.txt"), "").unwrap();
+
+    prune(&dir, 2).unwrap();
+
+    assert!(load(&dir, "print 1;").is_none());
+    assert!(load(&dir, "print 2;").is_some());
+    assert!(load(&dir, "print 3;").is_some());
+    assert!(dir.join("notes.txt").exists());
+
+    fs::remove_dir_all(&dir).unwrap();
+  }
+}
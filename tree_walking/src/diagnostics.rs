@@ -0,0 +1,410 @@
+use crate::errors::{ResolveErrors, ResolveWarning, SyntaxErrors, Traceback};
+use crate::runner::RunError;
+use scanner::ScanError;
+use serde::Serialize;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+  Error,
+  Warning,
+}
+
+/// Where in the source a diagnostic points to.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Span {
+  pub line: u32,
+  /// `None` when only the line is known
+  pub column: Option<u32>,
+  /// Characters covered, starting at `column`
+  pub length: usize,
+}
+
+/// An error or warning about a program, in a form both people and tools can
+/// read.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Diagnostic {
+  /// File the program was read from, if any
+  pub file: Option<String>,
+  pub span: Option<Span>,
+  pub severity: Severity,
+  /// What kind of problem this is, e.g. `syntax` or a lint code
+  pub code: Option<String>,
+  pub message: String,
+  /// Further context, e.g. the calls in progress when a runtime error happened
+  pub notes: Vec<String>,
+}
+
+/// How diagnostics are written out.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum DiagnosticFormat {
+  /// For people: the message, its location and the notes, with the source
+  /// line marked when the source is known
+  #[default]
+  Text,
+  /// `Text` highlighted with ANSI colors, for terminals
+  ColoredText,
+  /// One JSON object per line
+  Json,
+}
+
+impl Diagnostic {
+  pub fn error(message: impl Into<String>) -> Self {
+    Diagnostic {
+      file: None,
+      span: None,
+      severity: Severity::Error,
+      code: None,
+      message: message.into(),
+      notes: vec![],
+    }
+  }
+
+  pub fn with_code(self, code: &str) -> Self {
+    Diagnostic {
+      code: Some(code.to_string()),
+      ..self
+    }
+  }
+
+  pub fn with_span(self, line: u32, column: Option<u32>, length: usize) -> Self {
+    Diagnostic {
+      span: Some(Span {
+        line,
+        column,
+        length,
+      }),
+      ..self
+    }
+  }
+
+  pub fn with_file(self, file: impl Into<String>) -> Self {
+    Diagnostic {
+      file: Some(file.into()),
+      ..self
+    }
+  }
+
+  /// Writes the diagnostic out, showing the line it points to if `source`
+  /// is given.
+  pub fn render(&self, format: DiagnosticFormat, source: Option<&str>) -> String {
+    match format {
+      DiagnosticFormat::Text => self.render_text(source, false),
+      DiagnosticFormat::ColoredText => self.render_text(source, true),
+      DiagnosticFormat::Json => serde_json::to_string(self).expect("diagnostics serialize to JSON"),
+    }
+  }
+
+  /// Renders the diagnostic like rustc does, e.g.
+  ///
+  /// ```text
+  /// error[syntax]: closing paren ')' was not found
+  ///  --> main.lox:2:9
+  ///   |
+  /// 2 | print (1;
+  ///   |         ^
+  /// ```
+  fn render_text(&self, source: Option<&str>, color: bool) -> String {
+    let paint = |text: &str, style: &str| {
+      if color {
+        format!("\x1b[{}m{}\x1b[0m", style, text)
+      } else {
+        text.to_string()
+      }
+    };
+
+    let (severity, style) = match self.severity {
+      Severity::Error => ("error", RED),
+      Severity::Warning => ("warning", YELLOW),
+    };
+    let title = match &self.code {
+      Some(code) => format!("{}[{}]", severity, code),
+      None => severity.to_string(),
+    };
+
+    let mut out = format!(
+      "{}{}",
+      paint(&title, style),
+      paint(&format!(": {}", self.message), BOLD)
+    );
+
+    let line = self.span.as_ref().map(|span| span.line);
+    // The gutter is as wide as the number of the line shown
+    let gutter = " ".repeat(line.map_or(0, |line| line.to_string().len()));
+
+    let location = match (&self.file, &self.span) {
+      (Some(file), Some(span)) => Some(match span.column {
+        Some(column) => format!("{}:{}:{}", file, span.line, column),
+        None => format!("{}:{}", file, span.line),
+      }),
+      (None, Some(span)) => Some(match span.column {
+        Some(column) => format!("line {}, column {}", span.line, column),
+        None => format!("line {}", span.line),
+      }),
+      (Some(file), None) => Some(file.clone()),
+      (None, None) => None,
+    };
+
+    if let Some(location) = location {
+      out.push_str(&format!("\n{}{} {}", gutter, paint("-->", BLUE), location));
+    }
+
+    if let (Some(span), Some(source)) = (&self.span, source) {
+      let text = source.lines().nth(span.line as usize - 1).unwrap_or("");
+      let bar = paint("|", BLUE);
+
+      out.push_str(&format!("\n{} {}", gutter, bar));
+      out.push_str(&format!(
+        "\n{} {} {}",
+        paint(&span.line.to_string(), BLUE),
+        bar,
+        text
+      ));
+
+      if let Some(column) = span.column {
+        // Tabs are kept so the carets line up with the text above
+        let indent = text
+          .chars()
+          .take(column as usize - 1)
+          .map(|c| if c == '\t' { '\t' } else { ' ' })
+          .collect::<String>();
+        let carets = "^".repeat(span.length.max(1));
+
+        out.push_str(&format!(
+          "\n{} {} {}{}",
+          gutter,
+          bar,
+          indent,
+          paint(&carets, style)
+        ));
+      }
+    }
+
+    for note in &self.notes {
+      out.push_str(&format!("\n{} {} note: {}", gutter, paint("=", BLUE), note));
+    }
+
+    out
+  }
+}
+
+const BOLD: &str = "1";
+const RED: &str = "1;31";
+const YELLOW: &str = "1;33";
+const BLUE: &str = "1;34";
+
+impl fmt::Display for Diagnostic {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.render_text(None, false))
+  }
+}
+
+/// Splits an error returned by the runner into diagnostics, one for each
+/// problem it reports.
+pub fn from_error(error: &anyhow::Error) -> Vec<Diagnostic> {
+  match error.downcast_ref::<RunError>() {
+    Some(RunError::Compile(error)) => from_compile_error(error),
+    Some(RunError::Runtime(error)) => vec![from_runtime_error(error)],
+    None => from_compile_error(error),
+  }
+}
+
+fn from_compile_error(error: &anyhow::Error) -> Vec<Diagnostic> {
+  if let Some(errors) = error.downcast_ref::<SyntaxErrors>() {
+    return errors
+      .0
+      .iter()
+      .map(|e| {
+        Diagnostic::error(e.error.to_string())
+          .with_code("syntax")
+          .with_span(e.line, Some(e.column), e.length)
+      })
+      .collect();
+  }
+
+  if let Some(errors) = error.downcast_ref::<ResolveErrors>() {
+    return errors
+      .0
+      .iter()
+      .map(|e| {
+        Diagnostic::error(e.error.to_string())
+          .with_code("resolve")
+          .with_span(e.line, Some(e.column), e.length)
+      })
+      .collect();
+  }
+
+  if let Some(error) = error.downcast_ref::<ScanError>() {
+    let diagnostic = match error {
+      ScanError::UnterminatedString { line, column } => {
+        Diagnostic::error("unterminated string").with_span(*line, Some(*column), 1)
+      }
+      ScanError::InvalidNumber {
+        lexeme,
+        line,
+        column,
+      } => Diagnostic::error(format!("invalid number literal {:?}", lexeme)).with_span(
+        *line,
+        Some(*column),
+        lexeme.chars().count(),
+      ),
+      ScanError::UnexpectedCharacter {
+        character,
+        line,
+        column,
+      } => Diagnostic::error(format!("unexpected character {:?}", character)).with_span(
+        *line,
+        Some(*column),
+        1,
+      ),
+    };
+
+    return vec![diagnostic.with_code("syntax")];
+  }
+
+  vec![Diagnostic::error(error.to_string())]
+}
+
+fn from_runtime_error(error: &anyhow::Error) -> Diagnostic {
+  match error.downcast_ref::<Traceback>() {
+    Some(traceback) => Diagnostic {
+      notes: traceback.frames(),
+      ..Diagnostic::error(traceback.error.to_string()).with_span(
+        traceback.line,
+        Some(traceback.column),
+        1,
+      )
+    },
+    None => Diagnostic::error(error.to_string()),
+  }
+  .with_code("runtime")
+}
+
+impl From<&ResolveWarning> for Diagnostic {
+  fn from(warning: &ResolveWarning) -> Self {
+    let code = match warning {
+      ResolveWarning::LoopWithoutExit => "infinite-loop",
+    };
+
+    Diagnostic {
+      severity: Severity::Warning,
+      ..Diagnostic::error(warning.to_string()).with_code(code)
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::runner::{run_with_options, RunOptions};
+  use std::cell::RefCell;
+  use std::rc::Rc;
+
+  /// Runs `source`, which must fail, and returns its diagnostics and what it
+  /// printed to stdout.
+  fn diagnostics_and_output(source: &str) -> (Vec<String>, String) {
+    let stdout = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let options = RunOptions {
+      stdout: stdout.clone(),
+      stderr: Rc::new(RefCell::new(Vec::<u8>::new())),
+      ..RunOptions::default()
+    };
+    let error = run_with_options(source.to_string(), &options).unwrap_err();
+
+    let diagnostics = from_error(&error.into())
+      .iter()
+      .map(|diagnostic| diagnostic.render(DiagnosticFormat::Json, None))
+      .collect();
+
+    (diagnostics, String::from_utf8(stdout.take()).unwrap())
+  }
+
+  fn diagnostics(source: &str) -> Vec<String> {
+    diagnostics_and_output(source).0
+  }
+
+  #[test]
+  fn test_from_error() {
+    assert_eq!(
+      diagnostics("print 1 @;"),
+      [
+        r#"{"file":null,"span":{"line":1,"column":9,"length":1},"severity":"error","code":"syntax","message":"unexpected character '@'","notes":[]}"#
+      ]
+    );
+    assert_eq!(
+      diagnostics("var = 1;\nprint (;"),
+      [
+        r#"{"file":null,"span":{"line":1,"column":5,"length":1},"severity":"error","code":"syntax","message":"'var' should be followed by an identifier","notes":[]}"#,
+        r#"{"file":null,"span":{"line":2,"column":8,"length":1},"severity":"error","code":"syntax","message":"unexpected token encountered when parsing an expression","notes":[]}"#,
+      ]
+    );
+    assert_eq!(
+      diagnostics("print missing;"),
+      [
+        r#"{"file":null,"span":{"line":1,"column":7,"length":7},"severity":"error","code":"resolve","message":"undefined variable \"missing\"","notes":[]}"#
+      ]
+    );
+    assert_eq!(
+      diagnostics("fun f() { return -nil; }\nf();"),
+      [
+        r#"{"file":null,"span":{"line":1,"column":11,"length":1},"severity":"error","code":"runtime","message":"expected type \"number\" given \"nil\"","notes":["in f at line 1, column 11","at top level, line 2, column 2"]}"#
+      ]
+    );
+    assert_eq!(
+      diagnostics_and_output("print 1;\nprint -nil;"),
+      (
+        vec![
+          r#"{"file":null,"span":{"line":2,"column":7,"length":1},"severity":"error","code":"runtime","message":"expected type \"number\" given \"nil\"","notes":["at top level, line 2, column 7"]}"#.to_string()
+        ],
+        "1\n".to_string()
+      )
+    );
+    assert_eq!(
+      diagnostics("print \"abc;"),
+      [
+        r#"{"file":null,"span":{"line":1,"column":7,"length":1},"severity":"error","code":"syntax","message":"unterminated string","notes":[]}"#
+      ]
+    );
+    assert_eq!(
+      diagnostics("print 0b12;"),
+      [
+        r#"{"file":null,"span":{"line":1,"column":7,"length":4},"severity":"error","code":"syntax","message":"invalid number literal \"0b12\"","notes":[]}"#
+      ]
+    );
+  }
+
+  #[test]
+  fn test_render_text() {
+    let source = "var a = 1;\n\tprint (a;\n";
+    let error = run_with_options(source.to_string(), &RunOptions::default()).unwrap_err();
+    let diagnostic = from_error(&error.into()).remove(0).with_file("main.lox");
+
+    assert_eq!(
+      diagnostic.render(DiagnosticFormat::Text, Some(source)),
+      "error[syntax]: closing paren ')' was not found
+ --> main.lox:2:10
+  |
+2 | \tprint (a;
+  | \t        ^"
+    );
+    assert_eq!(
+      diagnostic.render(DiagnosticFormat::ColoredText, Some(source)),
+      "\x1b[1;31merror[syntax]\x1b[0m\x1b[1m: closing paren ')' was not found\x1b[0m
+ \x1b[1;34m-->\x1b[0m main.lox:2:10
+  \x1b[1;34m|\x1b[0m
+\x1b[1;34m2\x1b[0m \x1b[1;34m|\x1b[0m \tprint (a;
+  \x1b[1;34m|\x1b[0m \t        \x1b[1;31m^\x1b[0m"
+    );
+
+    let diagnostic = Diagnostic {
+      notes: vec!["in f".to_string()],
+      ..Diagnostic::error("boom").with_code("runtime")
+    };
+
+    assert_eq!(
+      diagnostic.to_string(),
+      "error[runtime]: boom\n = note: in f"
+    );
+  }
+}
@@ -0,0 +1,224 @@
+use crate::parser::{Expr, Literal, Parser, Stmt};
+use anyhow::Result;
+use scanner::{Scanner, Token};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DocFormat {
+  Markdown,
+  Html,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DocItemKind {
+  Function,
+  Variable,
+  Class,
+}
+
+/// A single documented top-level declaration.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DocItem {
+  pub kind: DocItemKind,
+  pub name: String,
+  pub signature: String,
+  pub doc: Option<String>,
+}
+
+/// Documentation of one source file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DocModule {
+  pub name: String,
+  pub items: Vec<DocItem>,
+}
+
+/// Parses `source` and collects its top-level declarations together with
+/// the `///` comments attached to them.
+pub fn extract(name: String, source: String) -> Result<DocModule> {
+  let scanner = Scanner::new(source);
+
+  let tokens = scanner.collect::<Result<Vec<Token>>>()?;
+
+  let mut parser = Parser::new(tokens);
+
+  let statements = parser.parse()?;
+
+  let items = statements
+    .iter()
+    .filter_map(|stmt| match stmt {
+      Stmt::FunDeclaration {
+        name,
+        parameters,
+        doc,
+        ..
+      } => Some(DocItem {
+        kind: DocItemKind::Function,
+        name: name.clone(),
+        signature: format!("fun {}({})", name, parameters.join(", ")),
+        doc: doc.clone(),
+      }),
+      Stmt::Class {
+        name,
+        superclass,
+        doc,
+        ..
+      } => Some(DocItem {
+        kind: DocItemKind::Class,
+        name: name.clone(),
+        signature: match superclass {
+          Some(Expr::Literal {
+            value: Literal::Identifier {
+              name: superclass, ..
+            },
+          }) => format!("class {} < {}", name, superclass),
+          _ => format!("class {}", name),
+        },
+        doc: doc.clone(),
+      }),
+      Stmt::Declaration { name, doc, .. } => Some(DocItem {
+        kind: DocItemKind::Variable,
+        name: name.clone(),
+        signature: format!("var {}", name),
+        doc: doc.clone(),
+      }),
+      Stmt::TupleDeclaration { names, doc, .. } => Some(DocItem {
+        kind: DocItemKind::Variable,
+        name: names.join(", "),
+        signature: format!("var ({})", names.join(", ")),
+        doc: doc.clone(),
+      }),
+      _ => None,
+    })
+    .collect();
+
+  Ok(DocModule { name, items })
+}
+
+pub fn render(modules: &[DocModule], format: DocFormat) -> String {
+  match format {
+    DocFormat::Markdown => render_markdown(modules),
+    DocFormat::Html => render_html(modules),
+  }
+}
+
+fn render_markdown(modules: &[DocModule]) -> String {
+  let mut out = String::new();
+
+  for module in modules {
+    out.push_str(&format!("# {}\n\n", module.name));
+
+    for item in &module.items {
+      out.push_str(&format!("## `{}`\n\n", item.signature));
+
+      if let Some(doc) = &item.doc {
+        out.push_str(doc);
+        out.push_str("\n\n");
+      }
+    }
+  }
+
+  out
+}
+
+fn render_html(modules: &[DocModule]) -> String {
+  let mut out = String::from(
+    "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Documentation</title>\n</head>\n<body>\n",
+  );
+
+  for module in modules {
+    out.push_str(&format!("<h1>{}</h1>\n", escape_html(&module.name)));
+
+    for item in &module.items {
+      out.push_str(&format!(
+        "<h2 id=\"{}\"><code>{}</code></h2>\n",
+        escape_html(&item.name),
+        escape_html(&item.signature)
+      ));
+
+      if let Some(doc) = &item.doc {
+        for paragraph in doc.split("\n\n").filter(|p| !p.trim().is_empty()) {
+          out.push_str(&format!("<p>{}</p>\n", escape_html(paragraph.trim())));
+        }
+      }
+    }
+  }
+
+  out.push_str("</body>\n</html>\n");
+
+  out
+}
+
+fn escape_html(text: &str) -> String {
+  text
+    .replace('&', "&amp;")
+    .replace('<', "&lt;")
+    .replace('>', "&gt;")
+    .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_extract() {
+    let source = r#"
+      /// Adds two numbers.
+      ///
+      /// Works for any numbers.
+      fun add(a, b) {
+        a + b;
+      }
+
+      //// Not a doc comment.
+      var answer = 42;
+
+      // Neither is this.
+      fun undocumented() {}
+    "#;
+
+    let module = extract("math.lox".to_string(), source.to_string()).unwrap();
+
+    assert_eq!(
+      module.items,
+      vec![
+        DocItem {
+          kind: DocItemKind::Function,
+          name: "add".to_string(),
+          signature: "fun add(a, b)".to_string(),
+          doc: Some("Adds two numbers.\n\nWorks for any numbers.".to_string()),
+        },
+        DocItem {
+          kind: DocItemKind::Variable,
+          name: "answer".to_string(),
+          signature: "var answer".to_string(),
+          doc: None,
+        },
+        DocItem {
+          kind: DocItemKind::Function,
+          name: "undocumented".to_string(),
+          signature: "fun undocumented()".to_string(),
+          doc: None,
+        },
+      ]
+    );
+  }
+
+  #[test]
+  fn test_render() {
+    let module = extract(
+      "lib.lox".to_string(),
+      "/// Returns <nothing>.\nfun f(x) {}".to_string(),
+    )
+    .unwrap();
+
+    assert_eq!(
+      render(std::slice::from_ref(&module), DocFormat::Markdown),
+      "# lib.lox\n\n## `fun f(x)`\n\nReturns <nothing>.\n\n"
+    );
+
+    let html = render(&[module], DocFormat::Html);
+
+    assert!(html.contains("<h2 id=\"f\"><code>fun f(x)</code></h2>"));
+    assert!(html.contains("<p>Returns &lt;nothing&gt;.</p>"));
+  }
+}
@@ -0,0 +1,361 @@
+//! Source documents for editor integration. An edit re-lexes and re-parses
+//! only the top-level statements it touches, so diagnostics stay cheap to
+//! refresh on large files.
+
+use crate::parser::{Parser, Stmt};
+use anyhow::{bail, Result};
+use scanner::{Scanner, Token, TokenType};
+use std::ops::Range;
+
+/// A problem found in a document, located by the byte range of the
+/// top-level statement containing it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+  pub range: Range<usize>,
+  pub message: String,
+}
+
+/// A run of source text holding top-level statements, parsed on its own.
+/// It starts right after the previous segment, so leading comments and
+/// blank lines belong to it.
+struct Segment {
+  range: Range<usize>,
+  statements: Vec<Stmt>,
+  errors: Vec<String>,
+  /// An `else` has to be parsed together with the `if` preceding it
+  starts_with_else: bool,
+}
+
+impl Segment {
+  fn parse(tokens: &[Token], range: Range<usize>) -> Self {
+    let last = tokens.last().expect("segments are never empty");
+    let eof = Token {
+      kind: TokenType::Eof,
+      lexeme: String::new(),
+      span: last.span.end..last.span.end,
+      doc: None,
+      leading_trivia: vec![],
+      ..last.clone()
+    };
+
+    let mut tokens = tokens.to_vec();
+    tokens.push(eof);
+
+    let starts_with_else = tokens[0].kind == TokenType::Else;
+    let mut parser = Parser::new(tokens);
+
+    let (statements, errors) = match parser.parse_partial() {
+      Ok(statements) => (
+        statements,
+        parser.errors().iter().map(ToString::to_string).collect(),
+      ),
+      Err(e) => (vec![], vec![e.to_string()]),
+    };
+
+    Segment {
+      range,
+      statements,
+      errors,
+      starts_with_else,
+    }
+  }
+
+  fn empty(range: Range<usize>, errors: Vec<String>) -> Self {
+    Segment {
+      range,
+      statements: vec![],
+      errors,
+      starts_with_else: false,
+    }
+  }
+}
+
+/// Splits `text`, found at `offset` in the document, into segments ending
+/// after each `;` or `}` outside of any parens and braces. The second value
+/// tells whether the text ends exactly at such a boundary. `line` and
+/// `column` locate `offset`, for the positions in scan errors.
+fn split(text: &str, offset: usize, (line, column): (u32, u32)) -> (Vec<Segment>, bool) {
+  let mut tokens = vec![];
+  let mut scan_error = None;
+
+  for token in Scanner::starting_at(text.to_string(), line, column) {
+    match token {
+      Ok(token) => tokens.push(token),
+      Err(e) => {
+        scan_error = Some(e);
+        break;
+      }
+    }
+  }
+
+  let mut segments = vec![];
+  let mut first = 0;
+  let mut start = 0;
+  let mut depth = 0usize;
+
+  for (i, token) in tokens.iter().enumerate() {
+    match token.kind {
+      TokenType::LeftParen | TokenType::LeftBrace | TokenType::LeftBracket => depth += 1,
+      TokenType::RightParen | TokenType::RightBrace | TokenType::RightBracket => {
+        depth = depth.saturating_sub(1)
+      }
+      _ => {}
+    }
+
+    // Bodies of `if` may end with either, so neither ends a statement
+    // followed by `else`
+    let is_boundary = depth == 0
+      && matches!(token.kind, TokenType::Semicolon | TokenType::RightBrace)
+      && tokens
+        .get(i + 1)
+        .is_none_or(|next| next.kind != TokenType::Else);
+
+    if is_boundary {
+      let end = token.span.end;
+
+      segments.push(Segment::parse(
+        &tokens[first..=i],
+        offset + start..offset + end,
+      ));
+
+      first = i + 1;
+      start = end;
+    }
+  }
+
+  let rest = offset + start..offset + text.len();
+
+  if let Some(e) = scan_error {
+    segments.push(Segment::empty(rest, vec![e.to_string()]));
+
+    return (segments, false);
+  }
+
+  // The final token is always `Eof`
+  if first + 1 < tokens.len() {
+    segments.push(Segment::parse(&tokens[first..tokens.len() - 1], rest));
+
+    return (segments, false);
+  }
+
+  let closed = rest.is_empty();
+
+  match segments.last_mut() {
+    Some(last) if !closed => last.range.end = rest.end,
+    Some(_) => {}
+    None => segments.push(Segment::empty(rest, vec![])),
+  }
+
+  (segments, closed)
+}
+
+pub struct Document {
+  text: String,
+  /// Never empty, covers the whole text
+  segments: Vec<Segment>,
+  reparsed: usize,
+}
+
+impl Document {
+  pub fn new(text: String) -> Self {
+    let (segments, _) = split(&text, 0, (1, 1));
+
+    Document {
+      reparsed: segments.len(),
+      text,
+      segments,
+    }
+  }
+
+  pub fn text(&self) -> &str {
+    &self.text
+  }
+
+  /// Number of segments parsed by the last edit (or by `new`).
+  pub fn reparsed(&self) -> usize {
+    self.reparsed
+  }
+
+  /// Replaces the bytes in `range` with `new_text` and re-parses the
+  /// statements around it.
+  pub fn edit(&mut self, range: Range<usize>, new_text: &str) -> Result<()> {
+    if self.text.get(range.clone()).is_none() {
+      bail!("invalid edit range {:?}", range);
+    }
+
+    self.text.replace_range(range.clone(), new_text);
+
+    let delta = new_text.len() as isize - range.len() as isize;
+    let shift = |offset: usize| offset.checked_add_signed(delta).unwrap();
+
+    let mut first = self
+      .segments
+      .iter()
+      .position(|segment| segment.range.end >= range.start)
+      .unwrap();
+    let last = self
+      .segments
+      .iter()
+      .rposition(|segment| segment.range.start < range.end)
+      .unwrap_or(0);
+    // The statement after the edit may depend on it, e.g. an `else`
+    let mut last = (last + 1).min(self.segments.len() - 1);
+
+    loop {
+      let start = self.segments[first].range.start;
+      let end = shift(self.segments[last].range.end);
+      let (segments, closed) = split(&self.text[start..end], start, self.position(start));
+
+      if first > 0 && segments[0].starts_with_else {
+        first -= 1;
+        continue;
+      }
+
+      let at_end = last + 1 == self.segments.len();
+
+      if !at_end && (!closed || self.segments[last + 1].starts_with_else) {
+        last += 1;
+        continue;
+      }
+
+      for segment in &mut self.segments[last + 1..] {
+        segment.range = shift(segment.range.start)..shift(segment.range.end);
+      }
+
+      self.reparsed = segments.len();
+      self.segments.splice(first..=last, segments);
+
+      return Ok(());
+    }
+  }
+
+  /// Line and column of the byte at `offset`, both counted from 1.
+  fn position(&self, offset: usize) -> (u32, u32) {
+    let before = &self.text[..offset];
+    let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+
+    (
+      before.matches('\n').count() as u32 + 1,
+      before[line_start..].chars().count() as u32 + 1,
+    )
+  }
+
+  /// Names declared at the top level, in source order.
+  pub fn declarations(&self) -> Vec<String> {
+    self
+      .segments
+      .iter()
+      .flat_map(|segment| &segment.statements)
+      .flat_map(|stmt| match stmt {
+        Stmt::Declaration { name, .. }
+        | Stmt::FunDeclaration { name, .. }
+        | Stmt::Class { name, .. } => vec![name.clone()],
+        Stmt::TupleDeclaration { names, .. } => names.clone(),
+        _ => vec![],
+      })
+      .collect()
+  }
+
+  pub fn diagnostics(&self) -> Vec<Diagnostic> {
+    self
+      .segments
+      .iter()
+      .flat_map(|segment| {
+        segment.errors.iter().map(|message| Diagnostic {
+          range: segment.range.clone(),
+          message: message.clone(),
+        })
+      })
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Checks that `document` matches a document parsed from scratch.
+  fn assert_consistent(document: &Document) {
+    let summary = |document: &Document| {
+      document
+        .segments
+        .iter()
+        .map(|segment| {
+          (
+            segment.range.clone(),
+            segment.errors.clone(),
+            segment
+              .statements
+              .iter()
+              .map(Stmt::node_count)
+              .sum::<usize>(),
+          )
+        })
+        .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+      summary(document),
+      summary(&Document::new(document.text().to_string()))
+    );
+  }
+
+  fn edit(document: &mut Document, old: &str, new: &str) {
+    let start = document.text().find(old).unwrap();
+
+    document.edit(start..start + old.len(), new).unwrap();
+    assert_consistent(document);
+  }
+
+  #[test]
+  fn test_incremental_edits() {
+    let mut document = Document::new(
+      "var a = 1;\n\
+       /// Identity\n\
+       fun f(x) {\n  return x;\n}\n\
+       if (a) { print a; }\n\
+       var b = 2;\n\
+       print f(a);\n"
+        .to_string(),
+    );
+
+    assert_eq!(document.segments.len(), 5);
+    assert!(document.diagnostics().is_empty());
+    assert_eq!(document.declarations(), ["a", "f", "b"]);
+
+    edit(&mut document, "1;", "10;");
+    assert_eq!(document.reparsed(), 2);
+
+    edit(&mut document, "return x;", "return x");
+    assert!(!document.diagnostics().is_empty());
+    assert!(document.reparsed() <= 3);
+
+    edit(&mut document, "return x", "return x;");
+    assert!(document.diagnostics().is_empty());
+
+    edit(&mut document, "\nvar b", " else { print 0; }\nvar b");
+    assert_eq!(document.segments.len(), 5);
+
+    edit(&mut document, "{ print a; }", "print a;");
+    assert_eq!(document.segments.len(), 5);
+    assert!(document.diagnostics().is_empty());
+
+    edit(&mut document, "var b = 2;", "{ var b = 2;");
+    assert_eq!(document.diagnostics().len(), 1);
+
+    edit(&mut document, "{ var b = 2;", "var b = \"2;");
+    assert_eq!(document.diagnostics().len(), 1);
+
+    edit(&mut document, "\"2;", "2 @;");
+    assert_eq!(
+      document.diagnostics()[0].message,
+      "unexpected character '@' on line 7, column 11"
+    );
+
+    edit(&mut document, "2 @;", "2;");
+    assert!(document.diagnostics().is_empty());
+    assert_eq!(document.declarations(), ["a", "f", "b"]);
+
+    assert!(document.edit(0..1000, "").is_err());
+  }
+}
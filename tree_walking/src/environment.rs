@@ -59,6 +59,19 @@ impl Environment {
   pub(crate) fn get(&self, identifier: &str, distance: usize) -> Option<Rc<Value>> {
     self.execute_at(distance, |env| env.values.get(identifier).map(Rc::clone))
   }
+
+  /// The variables defined directly in this environment, sorted by name.
+  pub(crate) fn bindings(&self) -> Vec<(String, Rc<Value>)> {
+    let mut bindings = self
+      .values
+      .iter()
+      .map(|(name, value)| (name.clone(), Rc::clone(value)))
+      .collect::<Vec<_>>();
+
+    bindings.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    bindings
+  }
 }
 
 #[cfg(test)]
@@ -66,5 +79,15 @@ mod tests {
   use super::*;
 
   #[test]
-  fn test_define() {}
+  fn test_define() {
+    let parent = Rc::new(RefCell::new(Environment::new(None)));
+    parent.borrow_mut().define("a", Rc::new(Value::Nil));
+
+    let mut env = Environment::new(Some(Rc::clone(&parent)));
+    env.define("b", Rc::new(Value::Nil));
+
+    assert!(env.get("b", 0).is_some());
+    assert!(env.get("a", 1).is_some());
+    assert!(env.get("a", 0).is_none());
+  }
 }
@@ -1,3 +1,4 @@
+use std::fmt;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -10,8 +11,151 @@ pub(crate) enum RuntimeError {
 
   #[error("cannot assign to undeclared variable: {identifier:?}")]
   AssignmentToUndeclaredVariable { identifier: String },
+
+  #[error("invalid format string: {message}")]
+  InvalidFormat { message: String },
+
+  #[cfg(feature = "json")]
+  #[error("invalid JSON: {message}")]
+  InvalidJson { message: String },
+
+  #[cfg(feature = "json")]
+  #[error("{message} cannot be converted to JSON")]
+  NotJsonConvertible { message: String },
+
+  #[error("unknown type: {name:?}")]
+  UnknownType { name: String },
+
+  #[error("cannot destructure a tuple of {given} values into {expected} variables")]
+  DestructuringMismatch { expected: usize, given: usize },
+
+  #[error(
+    "assertion failed on line {line}, column {column}{}",
+    message.as_ref().map(|message| format!(": {message}")).unwrap_or_default()
+  )]
+  AssertionFailed {
+    line: u32,
+    column: u32,
+    message: Option<String>,
+  },
+
+  #[error("undefined property: {name:?}")]
+  UndefinedProperty { name: String },
+
+  #[error("index {index} is out of range for a {type_} of length {length}")]
+  IndexOutOfRange {
+    index: f64,
+    length: usize,
+    type_: String,
+  },
+
+  #[error("a {type_} cannot be indexed")]
+  NotIndexable { type_: String },
+
+  #[error("cannot assign to an index of a {type_}, only lists and maps can be changed")]
+  NotIndexAssignable { type_: String },
+
+  #[error("key {key} not found in map")]
+  MissingKey { key: String },
+
+  #[error("a {type_} cannot be used as a map key")]
+  InvalidMapKey { type_: String },
+
+  #[error("cannot pop from an empty list")]
+  PopFromEmptyList,
+
+  #[error("a string can only be repeated a whole, non-negative number of times, not {count}")]
+  InvalidRepeatCount { count: f64 },
+
+  #[error("{name} expects {expected} arguments but got {given}")]
+  ArityMismatch {
+    expected: usize,
+    given: usize,
+    name: String,
+  },
+}
+
+/// A runtime error along with where it happened and the calls in progress
+/// then.
+#[derive(Debug)]
+pub(crate) struct Traceback {
+  pub(crate) error: anyhow::Error,
+  /// Position of the statement or call that failed
+  pub(crate) line: u32,
+  pub(crate) column: u32,
+  /// Called function with the line and column of the call, innermost first
+  pub(crate) calls: Vec<(String, u32, u32)>,
+}
+
+impl Traceback {
+  /// Where each call in progress was stopped, innermost first.
+  pub(crate) fn frames(&self) -> Vec<String> {
+    let mut frames = vec![];
+    // The innermost function is stopped where it failed, every other one
+    // where it called the next
+    let mut position = (self.line, self.column);
+
+    for (name, line, column) in &self.calls {
+      frames.push(format!(
+        "in {} at line {}, column {}",
+        name, position.0, position.1
+      ));
+
+      position = (*line, *column);
+    }
+
+    frames.push(format!(
+      "at top level, line {}, column {}",
+      position.0, position.1
+    ));
+
+    frames
+  }
+}
+
+impl fmt::Display for Traceback {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.error)?;
+
+    for frame in self.frames() {
+      write!(f, "\n  {}", frame)?;
+    }
+
+    Ok(())
+  }
+}
+
+impl std::error::Error for Traceback {}
+
+/// A syntax error with the position of the token it was found at.
+#[derive(Error, Debug, Clone)]
+#[error("line {line}, column {column}: {error}")]
+pub(crate) struct LocatedSyntaxError {
+  pub(crate) error: SyntaxError,
+  pub(crate) line: u32,
+  pub(crate) column: u32,
+  /// Characters in the token
+  pub(crate) length: usize,
+}
+
+/// The syntax errors found in a program.
+#[derive(Debug)]
+pub(crate) struct SyntaxErrors(pub(crate) Vec<LocatedSyntaxError>);
+
+impl fmt::Display for SyntaxErrors {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let lines = self
+      .0
+      .iter()
+      .map(|error| format!("syntax error at {}", error))
+      .collect::<Vec<String>>();
+
+    write!(f, "{}", lines.join("\n"))
+  }
 }
 
+impl std::error::Error for SyntaxErrors {}
+
 #[derive(Error, Debug, Clone)]
 pub(crate) enum SyntaxError {
   #[error("';' expected at the end of a statement")]
@@ -23,15 +167,24 @@ pub(crate) enum SyntaxError {
   #[error("declared variable must be initialized")]
   VariableDeclarationMissingAssignment,
 
-  #[error("left side of an assignment must be an identifier")]
+  #[error("left side of an assignment must be an identifier, a property or an index")]
   LValueMustBeAnIdentifier,
 
+  #[error("'++' and '--' can only be applied to variables")]
+  InvalidUpdateTarget,
+
   #[error("missing ':' in conditional expression")]
   MissingColonInTernary,
 
   #[error("closing paren ')' was not found")]
   MissingRightParen,
 
+  #[error("closing bracket ']' was not found")]
+  MissingRightBracket,
+
+  #[error("expected ':' after a map key")]
+  MissingMapColon,
+
   #[error("unexpected token encountered when parsing an expression")]
   UnexpectedTokenInExpression,
 
@@ -41,17 +194,32 @@ pub(crate) enum SyntaxError {
   #[error("'while' condition must be enclosed in parens")]
   MissingWhileConditionLeftParen,
 
-  #[error("'while' body must be enclosed in block")]
-  WhileBodyNotEnclosedInBlock,
+  #[error("'for' clauses must be enclosed in parens")]
+  MissingForLeftParen,
 
-  #[error("'if' condition must be enclosed in parens")]
-  MissingIfConditionLeftParen,
+  #[error("'for' should be followed by a loop variable")]
+  MissingForVariable,
+
+  #[error("expected 'in' after the 'for' loop variable")]
+  MissingForIn,
+
+  #[error("'loop' body must be enclosed in block")]
+  LoopBodyNotEnclosedInBlock,
+
+  #[error("'switch' subject must be enclosed in parens")]
+  MissingSwitchSubjectLeftParen,
+
+  #[error("'switch' cases must be enclosed in braces")]
+  MissingSwitchBodyOpeningBrace,
 
-  #[error("'if' body must be enclosed in block")]
-  IfBodyNotEnclosedInBlock,
+  #[error("expected ':' after a case")]
+  MissingCaseColon,
 
-  #[error("'else' body must be enclosed in block")]
-  ElseBodyNotEnclosedInBlock,
+  #[error("expected 'case', 'default' or '}}' in a switch")]
+  UnexpectedTokenInSwitch,
+
+  #[error("'if' condition must be enclosed in parens")]
+  MissingIfConditionLeftParen,
 
   #[error("parameters must be enclosed in parens")]
   MissingParametersDeclarationOpeningParen,
@@ -64,4 +232,100 @@ pub(crate) enum SyntaxError {
 
   #[error("missing function body opening brace")]
   MissingBodyOpeningBrace,
+
+  #[error("'is' should be followed by a type name")]
+  MissingTypeName,
+
+  #[error("missing class identifier")]
+  MissingClassIdentifier,
+
+  #[error("missing class body opening brace")]
+  MissingClassBodyOpeningBrace,
+
+  #[error("expected property name after '.'")]
+  MissingPropertyName,
+
+  #[error("expected superclass name after '<'")]
+  MissingSuperclassName,
+
+  #[error("expected '.' after 'super'")]
+  MissingSuperDot,
+
+  #[error("expected superclass method name")]
+  MissingSuperMethodName,
+
+  #[error("expected '}}' closing a string interpolation")]
+  UnterminatedInterpolation,
+
+  #[error("label {0:?} must be followed by a loop")]
+  LabelOnNonLoop(String),
+}
+
+#[derive(Error, Debug, Clone)]
+pub(crate) enum ResolveError {
+  #[error("cannot return from top-level code")]
+  ReturnOutsideFunction,
+
+  #[error("'break' used outside of a loop")]
+  BreakOutsideLoop,
+
+  #[error("'continue' used outside of a loop")]
+  ContinueOutsideLoop,
+
+  #[error("no enclosing loop labeled {label:?}")]
+  UndefinedLabel { label: String },
+
+  #[error("'this' used outside of a class")]
+  ThisOutsideClass,
+
+  #[error("cannot return a value from an initializer")]
+  ReturnValueFromInitializer,
+
+  #[error("a class cannot inherit from itself")]
+  InheritanceFromItself,
+
+  #[error("'super' used outside of a class")]
+  SuperOutsideClass,
+
+  #[error("'super' used in a class without a superclass")]
+  SuperWithoutSuperclass,
+
+  #[error("undefined variable {name:?}")]
+  UndefinedVariable { name: String },
+}
+
+/// A resolve error with the position of the name or keyword it was found
+/// at.
+#[derive(Error, Debug, Clone)]
+#[error("line {line}, column {column}: {error}")]
+pub(crate) struct LocatedResolveError {
+  pub(crate) error: ResolveError,
+  pub(crate) line: u32,
+  pub(crate) column: u32,
+  /// Characters in the name or keyword
+  pub(crate) length: usize,
+}
+
+/// The resolve errors found in a program.
+#[derive(Debug)]
+pub(crate) struct ResolveErrors(pub(crate) Vec<LocatedResolveError>);
+
+impl fmt::Display for ResolveErrors {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let lines = self
+      .0
+      .iter()
+      .map(|error| format!("resolve error at {}", error))
+      .collect::<Vec<String>>();
+
+    write!(f, "{}", lines.join("\n"))
+  }
+}
+
+impl std::error::Error for ResolveErrors {}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub(crate) enum ResolveWarning {
+  #[error("'loop' without 'break' or 'return' never ends")]
+  LoopWithoutExit,
 }
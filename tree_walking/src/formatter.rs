@@ -0,0 +1,793 @@
+use crate::parser::{BinaryOperator, Expr, Literal, Parser, Stmt, UnaryOperator, UpdateOperator};
+use anyhow::Result;
+use scanner::{Scanner, Token, Trivia};
+
+pub struct FormatConfig {
+  /// Number of spaces used for one level of indentation
+  pub indent_width: usize,
+  /// Longer runs of blank lines are collapsed to this many
+  pub max_blank_lines: usize,
+}
+
+impl Default for FormatConfig {
+  fn default() -> Self {
+    FormatConfig {
+      indent_width: 2,
+      max_blank_lines: 1,
+    }
+  }
+}
+
+/// Reformats Lox source code, keeping its comments and blank lines.
+pub fn format_source(source: &str, config: &FormatConfig) -> Result<String> {
+  let scanner = Scanner::new(source.to_string());
+
+  let tokens = scanner.collect::<Result<Vec<Token>>>()?;
+
+  let mut parser = Parser::with_trivia(tokens);
+
+  let statements = parser.parse()?;
+
+  let mut formatter = Formatter {
+    config,
+    lines: vec![],
+    indent: 0,
+    blank_lines: 0,
+  };
+
+  formatter.statements(&statements);
+
+  Ok(formatter.finish())
+}
+
+struct Formatter<'a> {
+  config: &'a FormatConfig,
+  lines: Vec<String>,
+  indent: usize,
+  blank_lines: usize,
+}
+
+impl<'a> Formatter<'a> {
+  fn finish(mut self) -> String {
+    while self.lines.last().is_some_and(|line| line.is_empty()) {
+      self.lines.pop();
+    }
+
+    if self.lines.is_empty() {
+      String::new()
+    } else {
+      self.lines.join("\n") + "\n"
+    }
+  }
+
+  fn line(&mut self, text: String) {
+    self.blank_lines = 0;
+    self
+      .lines
+      .push(" ".repeat(self.indent * self.config.indent_width) + &text);
+  }
+
+  fn append(&mut self, text: &str) {
+    match self.lines.last_mut() {
+      Some(last) => last.push_str(text),
+      None => self.line(text.to_string()),
+    }
+  }
+
+  fn blank_line(&mut self) {
+    let after_block_start = self.lines.last().is_none_or(|line| line.ends_with('{'));
+
+    if !after_block_start && self.blank_lines < self.config.max_blank_lines {
+      self.lines.push(String::new());
+      self.blank_lines += 1;
+    }
+  }
+
+  fn trim_blank_lines(&mut self) {
+    while self.lines.last().is_some_and(|line| line.is_empty()) {
+      self.lines.pop();
+    }
+
+    self.blank_lines = 0;
+  }
+
+  fn statements(&mut self, statements: &[Stmt]) {
+    for stmt in statements {
+      self.stmt(stmt);
+    }
+  }
+
+  fn block(&mut self, statements: &[Stmt]) {
+    if statements.is_empty() {
+      self.append(" {}");
+      return;
+    }
+
+    self.append(" {");
+    self.block_contents(statements);
+  }
+
+  fn block_contents(&mut self, statements: &[Stmt]) {
+    self.indent += 1;
+    self.statements(statements);
+    self.trim_blank_lines();
+    self.indent -= 1;
+    self.line("}".to_string());
+  }
+
+  /// Statements of a `case` or `default` body, indented below the label.
+  fn case_body(&mut self, body: &Stmt) {
+    let Stmt::Block { statements } = body else {
+      unreachable!("case bodies are blocks");
+    };
+
+    self.indent += 1;
+    self.statements(statements);
+    self.trim_blank_lines();
+    self.indent -= 1;
+  }
+
+  /// Bodies written without braces get them, so every body looks the same.
+  fn body(&mut self, stmt: &Stmt) {
+    if let Stmt::Block { statements } = stmt {
+      self.block(statements);
+    } else {
+      self.block(std::slice::from_ref(stmt));
+    }
+  }
+
+  fn stmt(&mut self, stmt: &Stmt) {
+    match stmt {
+      Stmt::Expression { expression, .. } => self.line(format!("{};", expr(expression))),
+      Stmt::Print { expression, .. } => self.line(format!("print {};", expr(expression))),
+      Stmt::Assert {
+        condition, message, ..
+      } => self.line(match message {
+        Some(message) => format!("assert {}, {};", expr(condition), expr(message)),
+        None => format!("assert {};", expr(condition)),
+      }),
+      Stmt::Declaration {
+        name, initializer, ..
+      } => self.line(format!("var {} = {};", name, expr(initializer))),
+      Stmt::TupleDeclaration {
+        names, initializer, ..
+      } => self.line(format!(
+        "var ({}) = {};",
+        names.join(", "),
+        expr(initializer)
+      )),
+      Stmt::Return { value: None, .. } => self.line("return;".to_string()),
+      Stmt::Return {
+        value: Some(value), ..
+      } => self.line(format!("return {};", expr(value))),
+      Stmt::FunDeclaration {
+        name,
+        parameters,
+        body,
+        ..
+      } => {
+        self.line(format!("fun {}({})", name, parameters.join(", ")));
+        self.block(body);
+      }
+      Stmt::Class {
+        name,
+        superclass,
+        methods,
+        ..
+      } => {
+        let header = match superclass {
+          Some(superclass) => format!("class {} < {}", name, expr(superclass)),
+          None => format!("class {}", name),
+        };
+
+        if methods.is_empty() {
+          self.line(format!("{} {{}}", header));
+          return;
+        }
+
+        self.line(format!("{} {{", header));
+        self.indent += 1;
+
+        for method in methods {
+          if let Stmt::FunDeclaration {
+            name,
+            parameters,
+            body,
+            ..
+          } = method
+          {
+            self.line(format!("{}({})", name, parameters.join(", ")));
+            self.block(body);
+          } else {
+            self.stmt(method);
+          }
+        }
+
+        self.trim_blank_lines();
+        self.indent -= 1;
+        self.line("}".to_string());
+      }
+      Stmt::Block { statements } => {
+        if statements.is_empty() {
+          self.line("{}".to_string());
+        } else {
+          self.line("{".to_string());
+          self.block_contents(statements);
+        }
+      }
+      Stmt::While {
+        condition,
+        statement,
+        label,
+        ..
+      } => {
+        self.line(format!(
+          "{}while ({})",
+          label_prefix(label),
+          expr(condition)
+        ));
+        self.body(statement);
+      }
+      Stmt::Loop { body, label } => {
+        self.line(format!("{}loop", label_prefix(label)));
+        self.body(body);
+      }
+      Stmt::Break { label, .. } => self.line(format!("break{};", jump_label(label))),
+      Stmt::Continue { label, .. } => self.line(format!("continue{};", jump_label(label))),
+      Stmt::For {
+        initializer,
+        condition,
+        increment,
+        body,
+        label,
+        ..
+      } => {
+        let initializer = match initializer.as_deref() {
+          Some(Stmt::Declaration {
+            name, initializer, ..
+          }) => format!("var {} = {}", name, expr(initializer)),
+          Some(Stmt::TupleDeclaration {
+            names, initializer, ..
+          }) => format!("var ({}) = {}", names.join(", "), expr(initializer)),
+          Some(Stmt::Expression { expression, .. }) => expr(expression),
+          _ => String::new(),
+        };
+        let clause = |expression: &Option<Box<Expr>>| {
+          expression
+            .as_ref()
+            .map(|expression| format!(" {}", expr(expression)))
+            .unwrap_or_default()
+        };
+
+        self.line(format!(
+          "{}for ({};{};{})",
+          label_prefix(label),
+          initializer,
+          clause(condition),
+          clause(increment)
+        ));
+        self.body(body);
+      }
+      Stmt::ForIn {
+        variable,
+        iterable,
+        body,
+        label,
+        ..
+      } => {
+        self.line(format!(
+          "{}for ({} in {})",
+          label_prefix(label),
+          variable,
+          expr(iterable)
+        ));
+        self.body(body);
+      }
+      Stmt::If {
+        condition,
+        true_case,
+        false_case,
+        ..
+      } => {
+        self.line(format!("if ({})", expr(condition)));
+        self.body(true_case);
+
+        let mut false_case = false_case;
+
+        while let Some(stmt) = false_case {
+          if let Stmt::If {
+            condition,
+            true_case,
+            false_case: next,
+            ..
+          } = stmt.as_ref()
+          {
+            self.append(&format!(" else if ({})", expr(condition)));
+            self.body(true_case);
+            false_case = next;
+          } else {
+            self.append(" else");
+            self.body(stmt);
+            break;
+          }
+        }
+      }
+      Stmt::Switch {
+        subject,
+        cases,
+        default,
+        ..
+      } => {
+        self.line(format!("switch ({}) {{", expr(subject)));
+        self.indent += 1;
+
+        for (values, body) in cases {
+          let values = values.iter().map(expr).collect::<Vec<String>>();
+
+          self.line(format!("case {}:", values.join(", ")));
+          self.case_body(body);
+        }
+
+        if let Some(default) = default {
+          self.line("default:".to_string());
+          self.case_body(default);
+        }
+
+        self.indent -= 1;
+        self.line("}".to_string());
+      }
+      Stmt::Trivia { trivia } => {
+        for trivia in trivia {
+          match trivia {
+            Trivia::Comment(text) => self.line(text.clone()),
+            Trivia::TrailingComment(text) => self.append(&format!(" {}", text)),
+            Trivia::BlankLine => self.blank_line(),
+          }
+        }
+      }
+    }
+  }
+}
+
+fn expr(expression: &Expr) -> String {
+  match expression {
+    Expr::Ternary {
+      conditional,
+      true_case,
+      false_case,
+    } => format!(
+      "{} ? {} : {}",
+      expr(conditional),
+      expr(true_case),
+      expr(false_case)
+    ),
+    Expr::Binary {
+      operator: BinaryOperator::Comma,
+      left,
+      right,
+    } => format!("{}, {}", expr(left), expr(right)),
+    Expr::Binary {
+      operator,
+      left,
+      right,
+    } => format!(
+      "{} {} {}",
+      expr(left),
+      binary_operator(operator),
+      expr(right)
+    ),
+    Expr::Unary {
+      operator,
+      expr: inner,
+    } => {
+      let operator = match operator {
+        UnaryOperator::Bang => "!",
+        UnaryOperator::Minus => "-",
+      };
+
+      let inner = expr(inner);
+
+      // `- -x` must not become `--x`, a decrement
+      if operator == "-" && inner.starts_with('-') {
+        format!("{} {}", operator, inner)
+      } else {
+        format!("{}{}", operator, inner)
+      }
+    }
+    Expr::Grouping { expr: inner } => format!("({})", expr(inner)),
+    Expr::Literal { value } => match value {
+      Literal::Number { value } => value.to_string(),
+      Literal::String { value } => string_literal(value),
+      Literal::True => "true".to_string(),
+      Literal::False => "false".to_string(),
+      Literal::Nil => "nil".to_string(),
+      Literal::Identifier { name, .. } => name.clone(),
+    },
+    Expr::Assignment {
+      name, expression, ..
+    } => format!("{} = {}", name, expr(expression)),
+    Expr::Update {
+      name,
+      operator,
+      prefix,
+      ..
+    } => {
+      let operator = match operator {
+        UpdateOperator::Increment => "++",
+        UpdateOperator::Decrement => "--",
+      };
+
+      if *prefix {
+        format!("{}{}", operator, name)
+      } else {
+        format!("{}{}", name, operator)
+      }
+    }
+    Expr::List { elements } => format!(
+      "[{}]",
+      elements
+        .iter()
+        .map(expr)
+        .collect::<Vec<String>>()
+        .join(", ")
+    ),
+    Expr::Map { entries } => format!(
+      "{{{}}}",
+      entries
+        .iter()
+        .map(|(key, value)| format!("{}: {}", expr(key), expr(value)))
+        .collect::<Vec<String>>()
+        .join(", ")
+    ),
+    Expr::Index { object, index } => format!("{}[{}]", expr(object), expr(index)),
+    Expr::Slice { object, start, end } => format!(
+      "{}[{}:{}]",
+      expr(object),
+      start.as_deref().map_or(String::new(), expr),
+      end.as_deref().map_or(String::new(), expr)
+    ),
+    Expr::IndexSet {
+      object,
+      index,
+      value,
+    } => format!("{}[{}] = {}", expr(object), expr(index), expr(value)),
+    Expr::Get { object, name } => format!("{}.{}", expr(object), name),
+    Expr::Set {
+      object,
+      name,
+      value,
+    } => format!("{}.{} = {}", expr(object), name, expr(value)),
+    Expr::Super { method, .. } => format!("super.{}", method),
+    Expr::Interpolation {
+      strings,
+      expressions,
+    } => {
+      let mut out = format!("\"{}", escape(&strings[0]));
+
+      for (expression, string) in expressions.iter().zip(&strings[1..]) {
+        out.push_str(&format!("${{{}}}{}", expr(expression), escape(string)));
+      }
+
+      out.push('"');
+      out
+    }
+    Expr::Call {
+      function,
+      arguments,
+      ..
+    } => format!(
+      "{}({})",
+      expr(function),
+      arguments
+        .iter()
+        .map(expr)
+        .collect::<Vec<String>>()
+        .join(", ")
+    ),
+    Expr::Range {
+      start,
+      end,
+      inclusive,
+    } => format!(
+      "{}{}{}",
+      expr(start),
+      if *inclusive { "..=" } else { ".." },
+      expr(end)
+    ),
+    Expr::Spread { expr: inner } => format!("...{}", expr(inner)),
+    Expr::Tuple { elements } => elements
+      .iter()
+      .map(expr)
+      .collect::<Vec<String>>()
+      .join(", "),
+    Expr::Is {
+      expr: inner,
+      type_name,
+      ..
+    } => format!("{} is {}", expr(inner), type_name),
+  }
+}
+
+fn binary_operator(operator: &BinaryOperator) -> &'static str {
+  match operator {
+    BinaryOperator::EqualEqual => "==",
+    BinaryOperator::BangEqual => "!=",
+    BinaryOperator::Plus => "+",
+    BinaryOperator::Minus => "-",
+    BinaryOperator::Slash => "/",
+    BinaryOperator::Star => "*",
+    BinaryOperator::Greater => ">",
+    BinaryOperator::GreaterEqual => ">=",
+    BinaryOperator::Less => "<",
+    BinaryOperator::LessEqual => "<=",
+    BinaryOperator::In => "in",
+    BinaryOperator::Comma => ",",
+    BinaryOperator::Or => "or",
+    BinaryOperator::And => "and",
+    BinaryOperator::BitAnd => "&",
+    BinaryOperator::BitOr => "|",
+    BinaryOperator::BitXor => "^",
+    BinaryOperator::ShiftLeft => "<<",
+    BinaryOperator::ShiftRight => ">>",
+  }
+}
+
+/// Quotes `value`, escaping what would otherwise end the string or start an
+/// interpolation. Newlines stay as they are, strings can span lines.
+fn string_literal(value: &str) -> String {
+  format!("\"{}\"", escape(value))
+}
+
+/// Text of a string literal as written between its quotes.
+fn escape(value: &str) -> String {
+  value
+    .replace('\\', "\\\\")
+    .replace('"', "\\\"")
+    .replace("${", "\\${")
+    .replace('\t', "\\t")
+    .replace('\r', "\\r")
+}
+
+/// `label: ` in front of a labeled loop.
+fn label_prefix(label: &Option<String>) -> String {
+  label
+    .as_ref()
+    .map(|label| format!("{}: ", label))
+    .unwrap_or_default()
+}
+
+/// ` label` after `break` or `continue`.
+fn jump_label(label: &Option<String>) -> String {
+  label
+    .as_ref()
+    .map(|label| format!(" {}", label))
+    .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::runner::{run_with_options, RunOptions};
+  use std::cell::RefCell;
+  use std::rc::Rc;
+
+  /// What `source` prints when run.
+  fn output(source: &str) -> String {
+    let stdout = Rc::new(RefCell::new(Vec::<u8>::new()));
+
+    run_with_options(
+      source.to_string(),
+      &RunOptions {
+        stdout: stdout.clone(),
+        ..RunOptions::default()
+      },
+    )
+    .unwrap();
+
+    String::from_utf8(stdout.take()).unwrap()
+  }
+
+  /// Formats `source`, checking the result runs the same.
+  fn format_and_run(source: &str) -> String {
+    let formatted = format_source(source, &FormatConfig::default()).unwrap();
+
+    assert_eq!(output(&formatted), output(source));
+
+    formatted
+  }
+
+  #[test]
+  fn test_format_source() {
+    let source = r#"
+// Greeting helpers
+
+
+/// Says hello.
+fun   greet(name){println("hello " +name);   // inline
+return name,1 ;}
+
+
+
+var x=1+2 ;
+var (a,b)=greet("b");
+if(x>2){print   x;greet("a");}else if(x<0){x=0;}else{
+
+
+  // nothing to do
+}
+while (x < 10) { x = x + (1); }
+loop{break ;}
+for(i in 0 ..x){greet(i, ... rest);}
+for(var i=0;i<3;i=i+1){print i;}
+outer:for(;;){for(i in x){continue outer;}break;}
+while(x>0)x=x-1;
+switch(x){case 1,2:print x;print 2;default:}
+class  Point{ }
+var p=Point();p . x=1;
+class Ticker<Counter{tick(){return super.tick();}}
+class Counter{init(){this.n=0;} tick(){this.n=this.n+1;return this.n;}}
+var path=r"C:\dir";var quote="\"\${a}";
+// the end
+"#;
+
+    let expected = r#"// Greeting helpers
+
+/// Says hello.
+fun greet(name) {
+  println("hello " + name); // inline
+  return name, 1;
+}
+
+var x = 1 + 2;
+var (a, b) = greet("b");
+if (x > 2) {
+  print x;
+  greet("a");
+} else if (x < 0) {
+  x = 0;
+} else {
+  // nothing to do
+}
+while (x < 10) {
+  x = x + (1);
+}
+loop {
+  break;
+}
+for (i in 0..x) {
+  greet(i, ...rest);
+}
+for (var i = 0; i < 3; i = i + 1) {
+  print i;
+}
+outer: for (;;) {
+  for (i in x) {
+    continue outer;
+  }
+  break;
+}
+while (x > 0) {
+  x = x - 1;
+}
+switch (x) {
+  case 1, 2:
+    print x;
+    print 2;
+  default:
+}
+class Point {}
+var p = Point();
+p.x = 1;
+class Ticker < Counter {
+  tick() {
+    return super.tick();
+  }
+}
+class Counter {
+  init() {
+    this.n = 0;
+  }
+  tick() {
+    this.n = this.n + 1;
+    return this.n;
+  }
+}
+var path = "C:\\dir";
+var quote = "\"\${a}";
+// the end
+"#;
+
+    let formatted = format_source(source, &FormatConfig::default()).unwrap();
+
+    assert_eq!(formatted, expected);
+    assert_eq!(
+      format_source(&formatted, &FormatConfig::default()).unwrap(),
+      expected
+    );
+  }
+
+  #[test]
+  fn test_comments_inside_statements_are_kept() {
+    let formatted = format_source(
+      "println(1, // one\n  2);\n",
+      &FormatConfig {
+        indent_width: 4,
+        max_blank_lines: 2,
+      },
+    )
+    .unwrap();
+
+    assert_eq!(formatted, "// one\nprintln(1, 2);\n");
+  }
+
+  #[test]
+  fn test_class_comments_are_kept() {
+    let source = r#"
+class Point {
+  // Creates a point.
+  /// Stores both coordinates.
+  init(x, y) { this.x = x; this.y = y; } // fields
+
+
+
+  /// Sum of the coordinates.
+  sum() { return this.x + this.y; }
+  // nothing else
+}
+class Empty {
+  // reserved
+}
+print Point(1, 2).sum();
+"#;
+
+    assert_eq!(
+      format_and_run(source),
+      r#"class Point {
+  // Creates a point.
+  /// Stores both coordinates.
+  init(x, y) {
+    this.x = x;
+    this.y = y;
+  } // fields
+
+  /// Sum of the coordinates.
+  sum() {
+    return this.x + this.y;
+  }
+  // nothing else
+}
+class Empty {
+  // reserved
+}
+print Point(1, 2).sum();
+"#
+    );
+  }
+
+  #[test]
+  fn test_shebang_is_kept() {
+    let formatted = format_source(
+      "#!/usr/bin/env rslox\nprint  1;\n",
+      &FormatConfig::default(),
+    )
+    .unwrap();
+
+    assert_eq!(formatted, "#!/usr/bin/env rslox\nprint 1;\n");
+  }
+
+  #[test]
+  fn test_interpolation_is_kept() {
+    assert_eq!(
+      format_and_run(r#"var x=2;print "v=${x+1}, ${"in ${x}"}\"${x}\"";"#),
+      "var x = 2;\nprint \"v=${x + 1}, ${\"in ${x}\"}\\\"${x}\\\"\";\n"
+    );
+  }
+
+  #[test]
+  fn test_unary_operators_stay_apart() {
+    assert_eq!(
+      format_and_run("var x = 1; print - -x; print -(-x); print - --x; print !!x; print x;"),
+      "var x = 1;\nprint - -x;\nprint -(-x);\nprint - --x;\nprint !!x;\nprint x;\n"
+    );
+  }
+}
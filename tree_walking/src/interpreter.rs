@@ -1,94 +1,310 @@
 use crate::environment::Environment;
-use crate::errors::RuntimeError;
-use crate::parser::{BinaryOperator, Expr, Literal, Stmt, UnaryOperator};
+use crate::errors::{RuntimeError, Traceback};
+use crate::natives;
+use crate::parser::{BinaryOperator, Expr, Literal, Stmt, UnaryOperator, UpdateOperator};
 use crate::resolver::Locals;
+use crate::runner::Sink;
 use anyhow::{anyhow, Result};
 use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
 use std::rc::Rc;
-use std::time::{SystemTime, UNIX_EPOCH};
+use web_time::Instant;
+
+/// Type names `is` checks without resolving them.
+pub(crate) const BUILT_IN_TYPES: [&str; 10] = [
+  "Number", "String", "Bool", "Nil", "Function", "Range", "Tuple", "List", "Map", "Class",
+];
 
 #[derive(Debug)]
-pub(crate) struct NumberValue(f64);
+pub(crate) struct NumberValue(pub(crate) f64);
 
 #[derive(Debug)]
-pub(crate) struct StringValue(String);
+pub(crate) struct StringValue(pub(crate) String);
 
 #[derive(Debug)]
-pub(crate) struct BoolValue(bool);
+pub(crate) struct BoolValue(pub(crate) bool);
 
-pub(crate) trait Callable {
-  fn call(&self, arguments: Vec<Rc<Value>>, interpreter: &mut Interpreter) -> Result<Rc<Value>>;
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct RangeValue {
+  pub(crate) start: f64,
+  pub(crate) end: f64,
+  pub(crate) inclusive: bool,
 }
 
-pub(crate) struct NativeClock;
+/// Hashable form of the values that can be used as map keys.
+#[derive(PartialEq, Eq, Hash)]
+enum MapKey {
+  Number(u64),
+  String(String),
+  Bool(bool),
+  Nil,
+}
 
-impl Callable for NativeClock {
-  fn call(&self, _arguments: Vec<Rc<Value>>, interpreter: &mut Interpreter) -> Result<Rc<Value>> {
-    let start = SystemTime::now();
-    let since_the_epoch = start
-      .duration_since(UNIX_EPOCH)
-      .expect("Time went backwards");
-    Ok(Rc::new(Value::Number(NumberValue(
-      since_the_epoch.as_secs_f64(),
-    ))))
+impl MapKey {
+  fn new(value: &Value) -> Result<Self> {
+    match value {
+      // `+ 0.` turns -0 into 0, so both find the same entry
+      Value::Number(value) => Ok(MapKey::Number((value.0 + 0.).to_bits())),
+      Value::String(value) => Ok(MapKey::String(value.0.clone())),
+      Value::Bool(value) => Ok(MapKey::Bool(value.0)),
+      Value::Nil => Ok(MapKey::Nil),
+      _ => Err(
+        RuntimeError::InvalidMapKey {
+          type_: value.type_as_string(),
+        }
+        .into(),
+      ),
+    }
   }
 }
 
-pub(crate) struct NativePrintln;
+/// Map entries in insertion order, with the position of every key kept in
+/// a hash map.
+#[derive(Default)]
+pub(crate) struct MapValue {
+  entries: Vec<(Rc<Value>, Rc<Value>)>,
+  indices: HashMap<MapKey, usize>,
+}
+
+impl MapValue {
+  pub(crate) fn get(&self, key: &Value) -> Result<Option<Rc<Value>>> {
+    let index = self.indices.get(&MapKey::new(key)?);
 
-impl Callable for NativePrintln {
-  fn call(&self, arguments: Vec<Rc<Value>>, interpreter: &mut Interpreter) -> Result<Rc<Value>> {
-    println!(
-      "{}",
-      arguments
-        .iter()
-        .map(|value| format!("{}", value))
-        .collect::<Vec<String>>()
-        .join(" ")
-    );
+    Ok(index.map(|&index| Rc::clone(&self.entries[index].1)))
+  }
 
-    Ok(Rc::new(Value::Nil))
+  pub(crate) fn insert(&mut self, key: Rc<Value>, value: Rc<Value>) -> Result<()> {
+    match self.indices.get(&MapKey::new(&key)?) {
+      Some(&index) => self.entries[index].1 = value,
+      None => {
+        self.indices.insert(MapKey::new(&key)?, self.entries.len());
+        self.entries.push((key, value));
+      }
+    }
+
+    Ok(())
+  }
+
+  /// Removes the entry of `key`, returning its value.
+  pub(crate) fn remove(&mut self, key: &Value) -> Result<Option<Rc<Value>>> {
+    let Some(index) = self.indices.remove(&MapKey::new(key)?) else {
+      return Ok(None);
+    };
+
+    let (_, value) = self.entries.remove(index);
+
+    for later in self.indices.values_mut().filter(|later| **later > index) {
+      *later -= 1;
+    }
+
+    Ok(Some(value))
+  }
+
+  pub(crate) fn entries(&self) -> &[(Rc<Value>, Rc<Value>)] {
+    &self.entries
+  }
+}
+
+/// Applies a bitwise operator to numbers truncated to integers. Shifts are
+/// logical, shifting by 64 or more bits gives 0 and negative amounts shift
+/// the other way, like in Lua.
+fn bitwise(operator: &BinaryOperator, a: i64, b: i64) -> i64 {
+  let shift_left = |a: i64, b: i64| match b {
+    64.. | ..=-64 => 0,
+    0.. => ((a as u64) << b) as i64,
+    _ => ((a as u64) >> -b) as i64,
+  };
+
+  match operator {
+    BinaryOperator::BitAnd => a & b,
+    BinaryOperator::BitOr => a | b,
+    BinaryOperator::BitXor => a ^ b,
+    BinaryOperator::ShiftLeft => shift_left(a, b),
+    BinaryOperator::ShiftRight => shift_left(a, b.saturating_neg()),
+    _ => unreachable!("{:?} is not a bitwise operator", operator),
+  }
+}
+
+pub(crate) trait Callable {
+  fn call(&self, arguments: Vec<Rc<Value>>, interpreter: &mut Interpreter) -> Result<Rc<Value>>;
+
+  fn name(&self) -> &str;
+
+  /// Number of parameters, `None` for natives taking any number of them.
+  fn arity(&self) -> Option<usize> {
+    None
   }
 }
 
 pub(crate) struct Fun {
   parameters: Vec<String>,
-  body: Vec<Stmt>,
+  body: Rc<Vec<Stmt>>,
   name: String,
-  environment: Rc<RefCell<Environment>>,
+  /// Environment the function was declared in. Every call gets a fresh
+  /// child of it holding the parameters, so recursive calls don't clobber
+  /// each other's variables.
+  closure: Rc<RefCell<Environment>>,
+  /// Initializers always return the instance they were bound to
+  is_initializer: bool,
 }
 
 impl Fun {
-  fn new(parameters: Vec<String>, body: Vec<Stmt>, name: String, environment: Environment) -> Self {
+  fn new(
+    parameters: Vec<String>,
+    body: Rc<Vec<Stmt>>,
+    name: String,
+    closure: Rc<RefCell<Environment>>,
+  ) -> Self {
     Fun {
       body,
       parameters,
       name,
-      environment: Rc::new(RefCell::new(environment)),
+      closure,
+      is_initializer: false,
     }
   }
+
+  /// The instance bound to `this` in the enclosing environment.
+  fn this(&self) -> Rc<Value> {
+    self
+      .closure
+      .borrow()
+      .get("this", 0)
+      .expect("methods are bound to an instance")
+  }
 }
 
 impl Callable for Fun {
   fn call(&self, arguments: Vec<Rc<Value>>, interpreter: &mut Interpreter) -> Result<Rc<Value>> {
     if arguments.len() != self.parameters.len() {
-      panic!("aaaaaa")
+      return Err(
+        RuntimeError::ArityMismatch {
+          expected: self.parameters.len(),
+          given: arguments.len(),
+          name: self.name.clone(),
+        }
+        .into(),
+      );
+    }
+
+    let mut environment = Environment::new(Some(Rc::clone(&self.closure)));
+
+    for (param, argument) in self.parameters.iter().zip(arguments) {
+      environment.define(param, argument);
     }
 
-    for (index, param) in self.parameters.iter().enumerate() {
-      self
-        .environment
-        .borrow_mut()
-        .define(param, Rc::clone(&arguments[index]));
+    let environment = Rc::new(RefCell::new(environment));
+
+    for stmt in self.body.iter() {
+      if let Flow::Return(value) = interpreter.interpret_stmt(stmt, Rc::clone(&environment))? {
+        return Ok(if self.is_initializer {
+          self.this()
+        } else {
+          value
+        });
+      }
     }
 
-    for stmt in &self.body {
-      interpreter.interpret_stmt(stmt, Rc::clone(&self.environment))?;
+    if self.is_initializer {
+      return Ok(self.this());
     }
 
     Ok(Rc::new(Value::Nil))
   }
+
+  fn name(&self) -> &str {
+    &self.name
+  }
+
+  fn arity(&self) -> Option<usize> {
+    Some(self.parameters.len())
+  }
+}
+
+struct Method {
+  parameters: Vec<String>,
+  body: Rc<Vec<Stmt>>,
+}
+
+pub(crate) struct Class {
+  name: String,
+  superclass: Option<Rc<Class>>,
+  methods: HashMap<String, Method>,
+  /// Environment the class was declared in, with `super` defined for
+  /// subclasses
+  closure: Rc<RefCell<Environment>>,
+}
+
+impl Class {
+  /// Creates an instance and runs `init` with `arguments`, if the class has
+  /// one.
+  fn instantiate(
+    class: &Rc<Class>,
+    arguments: Vec<Rc<Value>>,
+    interpreter: &mut Interpreter,
+  ) -> Result<Rc<Value>> {
+    let instance = Rc::new(Value::Instance(Instance {
+      class: Rc::clone(class),
+      fields: RefCell::new(HashMap::new()),
+    }));
+
+    match class.bind("init", &instance) {
+      Some(initializer) => initializer.call(arguments, interpreter),
+      None if arguments.is_empty() => Ok(instance),
+      None => Err(
+        RuntimeError::ArityMismatch {
+          expected: 0,
+          given: arguments.len(),
+          name: class.name.clone(),
+        }
+        .into(),
+      ),
+    }
+  }
+
+  /// Whether `self` is `class` or inherits from it.
+  fn is_subclass_of(&self, class: &Class) -> bool {
+    std::ptr::eq(self, class)
+      || self
+        .superclass
+        .as_ref()
+        .is_some_and(|superclass| superclass.is_subclass_of(class))
+  }
+
+  /// Finds method `name` along with the class declaring it.
+  fn find_method(&self, name: &str) -> Option<(&Class, &Method)> {
+    match self.methods.get(name) {
+      Some(method) => Some((self, method)),
+      None => self.superclass.as_ref()?.find_method(name),
+    }
+  }
+
+  /// Returns method `name` with `this` bound to `instance`.
+  fn bind(&self, name: &str, instance: &Rc<Value>) -> Option<Fun> {
+    let (class, method) = self.find_method(name)?;
+
+    let mut this = Environment::new(Some(Rc::clone(&class.closure)));
+    this.define("this", Rc::clone(instance));
+
+    let mut fun = Fun::new(
+      method.parameters.clone(),
+      Rc::clone(&method.body),
+      name.to_string(),
+      Rc::new(RefCell::new(this)),
+    );
+    fun.is_initializer = name == "init";
+
+    Some(fun)
+  }
+}
+
+/// An object created by calling a class. Fields come into existence when
+/// they are first assigned.
+pub(crate) struct Instance {
+  class: Rc<Class>,
+  fields: RefCell<HashMap<String, Rc<Value>>>,
 }
 
 pub(crate) enum Value {
@@ -97,117 +313,681 @@ pub(crate) enum Value {
   Bool(BoolValue),
   Nil,
   Function(Box<dyn Callable>),
+  Range(RangeValue),
+  Tuple(Vec<Rc<Value>>),
+  /// Shared by every variable holding it, like instances
+  List(RefCell<Vec<Rc<Value>>>),
+  Map(RefCell<MapValue>),
+  Class(Rc<Class>),
+  Instance(Instance),
 }
 
 impl Display for Value {
   fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-    let value_as_string = match self {
-      Value::Number(value) => value.0.to_string(),
-      Value::String(value) => value.0.clone(),
-      Value::Bool(value) => value.0.to_string(),
-      Value::Nil => "nil".to_string(),
-      Value::Function(_) => "function".to_string(),
-    };
-
-    write!(f, "{}", value_as_string)
+    write!(f, "{}", self.show(false, &mut vec![]))
   }
 }
 
 impl Value {
-  fn type_as_string(&self) -> String {
+  pub(crate) fn type_as_string(&self) -> String {
     match self {
       Value::Bool(_) => "bool".to_string(),
       Value::Number(_) => "number".to_string(),
       Value::String(_) => "string".to_string(),
       Value::Nil => "nil".to_string(),
       Value::Function(_) => "function".to_string(),
+      Value::Range(_) => "range".to_string(),
+      Value::Tuple(_) => "tuple".to_string(),
+      Value::List(_) => "list".to_string(),
+      Value::Map(_) => "map".to_string(),
+      Value::Class(_) => "class".to_string(),
+      Value::Instance(_) => "instance".to_string(),
+    }
+  }
+
+  /// Unambiguous representation used by the `debug` native, as opposed to
+  /// the display form used by `println`.
+  pub(crate) fn debug_string(&self) -> String {
+    self.show(true, &mut vec![])
+  }
+
+  /// The display form, or the debug one if `debug` is set. `seen` holds the
+  /// tuples, lists and maps being shown, so one containing itself is shown
+  /// as `[...]` where it appears again.
+  fn show(&self, debug: bool, seen: &mut Vec<*const Value>) -> String {
+    let (open, close) = match self {
+      Value::Tuple(_) => ("(", ")"),
+      Value::List(_) => ("[", "]"),
+      Value::Map(_) => ("{", "}"),
+      Value::String(value) if debug => {
+        let mut out = String::from('"');
+
+        for char in value.0.chars() {
+          match char {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            char if char.is_control() => out.push_str(&format!("\\u{{{:x}}}", char as u32)),
+            char => out.push(char),
+          }
+        }
+
+        out.push('"');
+        return out;
+      }
+      Value::Function(callable) if debug => {
+        return match callable.arity() {
+          Some(arity) => format!("<fn {}/{}>", callable.name(), arity),
+          None => format!("<fn {}/*>", callable.name()),
+        }
+      }
+      Value::Number(value) => return value.0.to_string(),
+      Value::String(value) => return value.0.clone(),
+      Value::Bool(value) => return value.0.to_string(),
+      Value::Nil => return "nil".to_string(),
+      Value::Function(callable) => return format!("<fn {}>", callable.name()),
+      Value::Range(range) => {
+        return format!(
+          "{}{}{}",
+          range.start,
+          if range.inclusive { "..=" } else { ".." },
+          range.end
+        )
+      }
+      Value::Class(class) => return class.name.clone(),
+      Value::Instance(instance) => return format!("{} instance", instance.class.name),
+    };
+
+    if seen.contains(&(self as *const Value)) {
+      return format!("{}...{}", open, close);
     }
+
+    seen.push(self);
+
+    let mut show = |value: &Value| value.show(debug, seen);
+    let elements: Vec<String> = match self {
+      Value::Tuple(elements) => elements.iter().map(|element| show(element)).collect(),
+      Value::List(elements) => elements
+        .borrow()
+        .iter()
+        .map(|element| show(element))
+        .collect(),
+      Value::Map(map) => map
+        .borrow()
+        .entries
+        .iter()
+        .map(|(key, value)| format!("{}: {}", show(key), show(value)))
+        .collect(),
+      _ => unreachable!("only containers are left"),
+    };
+
+    seen.pop();
+
+    format!("{}{}{}", open, elements.join(", "), close)
   }
 
-  fn is_truthy(&self) -> bool {
+  /// Lox semantics: only `false` and `nil` are falsey.
+  pub(crate) fn is_truthy(&self) -> bool {
     match self {
       Value::Bool(inner) => inner.0,
+      Value::Nil => false,
       _ => true,
     }
   }
 
-  fn is_equal(&self, other: &Value) -> Result<bool> {
-    match (self, other) {
-      (Value::Bool(v1), Value::Bool(v2)) => Ok(v1.0 == v2.0),
-      (Value::Number(v1), Value::Number(v2)) => Ok(v1.0 == v2.0),
-      (Value::String(v1), Value::String(v2)) => Ok(v1.0 == v2.0),
-      _ => Err(anyhow!("todo")),
+  /// Values of different types are never equal; functions, classes and
+  /// instances are equal only to themselves.
+  fn is_equal(&self, other: &Value) -> bool {
+    self.equals(other, &mut vec![])
+  }
+
+  /// `comparing` holds the pairs of tuples, lists and maps being compared.
+  /// A pair met again is taken to be equal, so containers holding
+  /// themselves can be compared.
+  fn equals(&self, other: &Value, comparing: &mut Vec<(*const Value, *const Value)>) -> bool {
+    let pair = (self as *const Value, other as *const Value);
+
+    if comparing.contains(&pair) {
+      return true;
     }
+
+    comparing.push(pair);
+
+    let equal = match (self, other) {
+      (Value::Bool(v1), Value::Bool(v2)) => v1.0 == v2.0,
+      (Value::Number(v1), Value::Number(v2)) => v1.0 == v2.0,
+      (Value::String(v1), Value::String(v2)) => v1.0 == v2.0,
+      (Value::Nil, Value::Nil) => true,
+      (Value::Range(v1), Value::Range(v2)) => v1 == v2,
+      (Value::Tuple(v1), Value::Tuple(v2)) => {
+        v1.len() == v2.len() && v1.iter().zip(v2).all(|(a, b)| a.equals(b, comparing))
+      }
+      (Value::List(v1), Value::List(v2)) => {
+        let (v1, v2) = (v1.borrow(), v2.borrow());
+
+        v1.len() == v2.len()
+          && v1
+            .iter()
+            .zip(v2.iter())
+            .all(|(a, b)| a.equals(b, comparing))
+      }
+      (Value::Map(v1), Value::Map(v2)) => {
+        let (v1, v2) = (v1.borrow(), v2.borrow());
+
+        v1.entries.len() == v2.entries.len()
+          && v1.entries.iter().all(
+            |(key, value)| matches!(v2.get(key), Ok(Some(other)) if value.equals(&other, comparing)),
+          )
+      }
+      (Value::Function(_), Value::Function(_)) | (Value::Instance(_), Value::Instance(_)) => {
+        std::ptr::eq(self, other)
+      }
+      (Value::Class(v1), Value::Class(v2)) => Rc::ptr_eq(v1, v2),
+      _ => false,
+    };
+
+    comparing.pop();
+
+    equal
   }
 
-  fn is_greater_than(&self, other: &Value) -> Result<bool> {
-    match (self, other) {
-      (Value::Number(v1), Value::Number(v2)) => Ok(v1.0 > v2.0),
-      _ => Err(anyhow!("todo")),
+  /// Checks the value against a type name used with `is`. Names other than
+  /// the built-in types refer to `class`, the value they resolved to.
+  fn is_type(&self, type_name: &str, class: Option<&Value>) -> Result<bool> {
+    let matches = match type_name {
+      "Number" => matches!(self, Value::Number(_)),
+      "String" => matches!(self, Value::String(_)),
+      "Bool" => matches!(self, Value::Bool(_)),
+      "Nil" => matches!(self, Value::Nil),
+      "Function" => matches!(self, Value::Function(_)),
+      "Range" => matches!(self, Value::Range(_)),
+      "Tuple" => matches!(self, Value::Tuple(_)),
+      "List" => matches!(self, Value::List(_)),
+      "Map" => matches!(self, Value::Map(_)),
+      "Class" => matches!(self, Value::Class(_)),
+      _ => match class {
+        Some(Value::Class(class)) => {
+          matches!(self, Value::Instance(instance) if instance.class.is_subclass_of(class))
+        }
+        _ => {
+          return Err(
+            RuntimeError::UnknownType {
+              name: type_name.to_string(),
+            }
+            .into(),
+          )
+        }
+      },
+    };
+
+    Ok(matches)
+  }
+
+  /// Membership test behind the `in` operator, with `self` as the container.
+  fn contains(&self, item: &Value) -> Result<bool> {
+    match (self, item) {
+      (Value::String(container), Value::String(item)) => Ok(container.0.contains(&item.0)),
+      (Value::List(elements), _) => Ok(
+        elements
+          .borrow()
+          .iter()
+          .any(|element| element.is_equal(item)),
+      ),
+      (Value::Map(map), _) => Ok(map.borrow().get(item)?.is_some()),
+      (Value::Range(range), Value::Number(item)) => {
+        let RangeValue {
+          start,
+          end,
+          inclusive,
+        } = *range;
+        let below_end = if inclusive {
+          item.0 <= end
+        } else {
+          item.0 < end
+        };
+
+        Ok(item.0 >= start && below_end && (item.0 - start).fract() == 0.)
+      }
+      _ => Err(
+        RuntimeError::TypeError {
+          expected: "string, list, map or range container".to_string(),
+          given: format!("{} in {}", item.type_as_string(), self.type_as_string()),
+        }
+        .into(),
+      ),
+    }
+  }
+
+  /// Values produced by iterating over this one in a `for ... in` loop.
+  /// Every iterable type of the language is handled here.
+  fn iterate(&self) -> Result<Box<dyn Iterator<Item = Rc<Value>>>> {
+    match self {
+      Value::Tuple(elements) => Ok(Box::new(elements.clone().into_iter())),
+      // Iterates over a snapshot, so the loop body may change the list
+      Value::List(elements) => Ok(Box::new(elements.borrow().clone().into_iter())),
+      Value::Map(map) => {
+        let keys = map
+          .borrow()
+          .entries
+          .iter()
+          .map(|(key, _)| Rc::clone(key))
+          .collect::<Vec<_>>();
+
+        Ok(Box::new(keys.into_iter()))
+      }
+      Value::String(value) => {
+        let chars = value.0.chars().collect::<Vec<char>>();
+
+        Ok(Box::new(chars.into_iter().map(|char| {
+          Rc::new(Value::String(StringValue(char.to_string())))
+        })))
+      }
+      Value::Range(range) => {
+        let RangeValue {
+          start,
+          end,
+          inclusive,
+        } = *range;
+
+        Ok(Box::new(
+          std::iter::successors(Some(start), |n| Some(n + 1.))
+            .take_while(move |n| if inclusive { *n <= end } else { *n < end })
+            .map(|n| Rc::new(Value::Number(NumberValue(n)))),
+        ))
+      }
+      _ => Err(
+        RuntimeError::TypeError {
+          expected: "iterable".to_string(),
+          given: self.type_as_string(),
+        }
+        .into(),
+      ),
+    }
+  }
+
+  /// Element at `index` of a list, tuple or string, counting from 0, or
+  /// the value of key `index` of a map.
+  fn index(&self, index: &Value) -> Result<Rc<Value>> {
+    let element = match self {
+      Value::Map(map) => match map.borrow().get(index)? {
+        Some(value) => value,
+        None => {
+          return Err(
+            RuntimeError::MissingKey {
+              key: index.debug_string(),
+            }
+            .into(),
+          )
+        }
+      },
+      Value::List(elements) => {
+        let elements = elements.borrow();
+
+        Rc::clone(&elements[self.element_index(index, elements.len())?])
+      }
+      Value::Tuple(elements) => Rc::clone(&elements[self.element_index(index, elements.len())?]),
+      Value::String(value) => {
+        let chars = value.0.chars().collect::<Vec<char>>();
+        let char = chars[self.element_index(index, chars.len())?];
+
+        Rc::new(Value::String(StringValue(char.to_string())))
+      }
+      _ => {
+        return Err(
+          RuntimeError::NotIndexable {
+            type_: self.type_as_string(),
+          }
+          .into(),
+        )
+      }
+    };
+
+    Ok(element)
+  }
+
+  /// Elements from `start` up to `end` of a list, tuple or string as a new
+  /// value of the same type. Negative bounds count from the end and bounds
+  /// past either end are clamped.
+  fn slice(&self, start: Option<&Value>, end: Option<&Value>) -> Result<Rc<Value>> {
+    let bound = |bound: Option<&Value>, length: usize, default: usize| -> Result<usize> {
+      match bound {
+        None | Some(Value::Nil) => Ok(default),
+        Some(Value::Number(n)) if n.0.fract() == 0. => {
+          let n = if n.0 < 0. { n.0 + length as f64 } else { n.0 };
+
+          Ok(n.clamp(0., length as f64) as usize)
+        }
+        Some(other) => Err(
+          RuntimeError::TypeError {
+            expected: "whole number slice bound".to_string(),
+            given: other.type_as_string(),
+          }
+          .into(),
+        ),
+      }
+    };
+    let range = |length: usize| -> Result<std::ops::Range<usize>> {
+      let start = bound(start, length, 0)?;
+
+      Ok(start..bound(end, length, length)?.max(start))
+    };
+
+    let sliced = match self {
+      Value::List(elements) => {
+        let elements = elements.borrow();
+
+        Value::List(RefCell::new(elements[range(elements.len())?].to_vec()))
+      }
+      Value::Tuple(elements) => Value::Tuple(elements[range(elements.len())?].to_vec()),
+      Value::String(value) => {
+        let chars = value.0.chars().collect::<Vec<char>>();
+
+        Value::String(StringValue(chars[range(chars.len())?].iter().collect()))
+      }
+      _ => {
+        return Err(
+          RuntimeError::NotIndexable {
+            type_: self.type_as_string(),
+          }
+          .into(),
+        )
+      }
+    };
+
+    Ok(Rc::new(sliced))
+  }
+
+  /// Replaces element `index` of a list or sets key `index` of a map.
+  fn set_index(&self, index: Rc<Value>, value: Rc<Value>) -> Result<()> {
+    match self {
+      Value::List(elements) => {
+        let mut elements = elements.borrow_mut();
+        let index = self.element_index(&index, elements.len())?;
+
+        elements[index] = value;
+
+        Ok(())
+      }
+      Value::Map(map) => map.borrow_mut().insert(index, value),
+      _ => Err(
+        RuntimeError::NotIndexAssignable {
+          type_: self.type_as_string(),
+        }
+        .into(),
+      ),
+    }
+  }
+
+  /// Checks that `index` is a whole number below `length`.
+  fn element_index(&self, index: &Value, length: usize) -> Result<usize> {
+    let Value::Number(index) = index else {
+      return Err(
+        RuntimeError::TypeError {
+          expected: "number index".to_string(),
+          given: index.type_as_string(),
+        }
+        .into(),
+      );
+    };
+
+    if index.0 < 0. || index.0.fract() != 0. || index.0 >= length as f64 {
+      return Err(
+        RuntimeError::IndexOutOfRange {
+          index: index.0,
+          length,
+          type_: self.type_as_string(),
+        }
+        .into(),
+      );
+    }
+
+    Ok(index.0 as usize)
+  }
+
+  /// Looks up a field of `object`, falling back to a method of its class.
+  fn get_property(object: &Rc<Value>, name: &str) -> Result<Rc<Value>> {
+    if let Value::String(_) = object.as_ref() {
+      return match natives::string_method(object, name) {
+        Some(method) => Ok(Rc::new(Value::Function(method))),
+        None => Err(
+          RuntimeError::UndefinedProperty {
+            name: name.to_string(),
+          }
+          .into(),
+        ),
+      };
+    }
+
+    let Value::Instance(instance) = object.as_ref() else {
+      return Err(
+        RuntimeError::TypeError {
+          expected: "instance".to_string(),
+          given: object.type_as_string(),
+        }
+        .into(),
+      );
+    };
+
+    if let Some(value) = instance.fields.borrow().get(name) {
+      return Ok(Rc::clone(value));
+    }
+
+    match instance.class.bind(name, object) {
+      Some(method) => Ok(Rc::new(Value::Function(Box::new(method)))),
+      None => Err(
+        RuntimeError::UndefinedProperty {
+          name: name.to_string(),
+        }
+        .into(),
+      ),
     }
   }
 
-  fn is_lesser_than(&self, other: &Value) -> Result<bool> {
+  fn set_property(&self, name: &str, value: Rc<Value>) -> Result<()> {
+    let Value::Instance(instance) = self else {
+      return Err(
+        RuntimeError::TypeError {
+          expected: "instance".to_string(),
+          given: self.type_as_string(),
+        }
+        .into(),
+      );
+    };
+
+    instance.fields.borrow_mut().insert(name.to_string(), value);
+
+    Ok(())
+  }
+
+  /// Orders numbers numerically and strings lexicographically. `None` when
+  /// a NaN is involved, so every comparison with it is false.
+  fn compare(&self, other: &Value) -> Result<Option<Ordering>> {
     match (self, other) {
-      (Value::Number(v1), Value::Number(v2)) => Ok(v1.0 < v2.0),
-      _ => Err(anyhow!("todo")),
+      (Value::Number(v1), Value::Number(v2)) => Ok(v1.0.partial_cmp(&v2.0)),
+      (Value::String(v1), Value::String(v2)) => Ok(Some(v1.0.cmp(&v2.0))),
+      _ => Err(
+        RuntimeError::TypeError {
+          expected: "two numbers or two strings".to_string(),
+          given: format!("{} and {}", self.type_as_string(), other.type_as_string()),
+        }
+        .into(),
+      ),
+    }
+  }
+}
+
+/// How control leaves a statement.
+pub(crate) enum Flow {
+  Next,
+  /// Out of the innermost loop, or of the one with the label
+  Break(Option<String>),
+  /// On to the next iteration of the innermost loop, or of the one with the
+  /// label
+  Continue(Option<String>),
+  Return(Rc<Value>),
+}
+
+/// What a loop does after an iteration of its body.
+enum LoopStep {
+  Next,
+  Exit,
+  /// Leave the loop and pass the flow on to the enclosing statement
+  Propagate(Flow),
+}
+
+impl Flow {
+  fn in_loop(self, label: &Option<String>) -> LoopStep {
+    let targets = |target: &Option<String>| target.is_none() || target == label;
+
+    match self {
+      Flow::Next => LoopStep::Next,
+      Flow::Continue(target) if targets(&target) => LoopStep::Next,
+      Flow::Break(target) if targets(&target) => LoopStep::Exit,
+      flow => LoopStep::Propagate(flow),
     }
   }
 }
 
+/// Wraps an error from `stmt` in a `Traceback` pointing at it, unless one
+/// from a statement nested in it already does.
+fn locate(error: anyhow::Error, stmt: &Stmt) -> anyhow::Error {
+  match stmt.position() {
+    Some((line, column)) if !error.is::<Traceback>() => Traceback {
+      error,
+      line,
+      column,
+      calls: vec![],
+    }
+    .into(),
+    _ => error,
+  }
+}
+
 pub(crate) struct Interpreter {
   pub(crate) locals: Locals,
+  natives: Vec<Box<dyn Callable>>,
+  pub(crate) stdout: Sink,
+  pub(crate) stderr: Sink,
+  /// Reference point of the monotonic `elapsed()` native
+  pub(crate) started: Instant,
+  /// Returned by the `args()` native
+  pub(crate) args: Vec<String>,
 }
 
 impl Interpreter {
-  pub(crate) fn new(locals: Locals) -> Self {
-    Interpreter { locals }
+  pub(crate) fn new(
+    locals: Locals,
+    natives: Vec<Box<dyn Callable>>,
+    stdout: Sink,
+    stderr: Sink,
+  ) -> Self {
+    Interpreter {
+      locals,
+      natives,
+      stdout,
+      stderr,
+      started: Instant::now(),
+      args: vec![],
+    }
+  }
+
+  /// Runs the top-level code and returns the environment holding its
+  /// declarations.
+  pub(crate) fn interpret_program(&mut self, program: &[Stmt]) -> Result<Rc<RefCell<Environment>>> {
+    let top = self.top_environment();
+
+    for stmt in program {
+      // The resolver rejects `return` outside of functions
+      self.interpret_stmt(stmt, Rc::clone(&top))?;
+    }
+
+    Ok(top)
   }
 
-  pub(crate) fn interpret_program(mut self, program: Vec<Stmt>) -> Result<()> {
+  /// Creates the environment of top-level code, enclosed by the one holding
+  /// the natives.
+  pub(crate) fn top_environment(&mut self) -> Rc<RefCell<Environment>> {
     let global = Rc::new(RefCell::new(Environment::new(None)));
 
     {
       let mut env = global.borrow_mut();
 
-      env.define("clock", Rc::new(Value::Function(Box::new(NativeClock {}))));
-      env.define(
-        "println",
-        Rc::new(Value::Function(Box::new(NativePrintln {}))),
-      );
+      for native in std::mem::take(&mut self.natives) {
+        let name = native.name().to_string();
+
+        env.define(&name, Rc::new(Value::Function(native)));
+      }
     }
 
-    let top = Rc::new(RefCell::new(Environment::new(Some(global))));
+    Rc::new(RefCell::new(Environment::new(Some(global))))
+  }
 
-    for stmt in &program {
-      self.interpret_stmt(stmt, Rc::clone(&top))?;
+  /// Runs top-level statements in `environment` and returns the value of
+  /// the last one if it is an expression statement.
+  pub(crate) fn interpret_entry(
+    &mut self,
+    statements: &[Stmt],
+    environment: &Rc<RefCell<Environment>>,
+  ) -> Result<Option<Rc<Value>>> {
+    let mut value = None;
+
+    for stmt in statements {
+      value = match stmt {
+        Stmt::Expression { expression, .. } => Some(
+          self
+            .interpret_expr(expression, Rc::clone(environment))
+            .map_err(|error| locate(error, stmt))?,
+        ),
+        stmt => {
+          self.interpret_stmt(stmt, Rc::clone(environment))?;
+
+          None
+        }
+      };
     }
 
-    Ok(())
+    Ok(value)
   }
 
-  fn interpret_expr(
+  /// Calls a function declared in `environment` without arguments.
+  pub(crate) fn call_function(
     &mut self,
-    expr: &Expr,
-    environment: Rc<RefCell<Environment>>,
+    environment: &Rc<RefCell<Environment>>,
+    name: &str,
+  ) -> Result<Rc<Value>> {
+    let value =
+      environment
+        .borrow()
+        .get(name, 0)
+        .ok_or_else(|| RuntimeError::UndefinedIdentifier {
+          name: name.to_string(),
+        })?;
+
+    let Value::Function(callable) = value.as_ref() else {
+      return Err(
+        RuntimeError::TypeError {
+          expected: "function".to_string(),
+          given: value.type_as_string(),
+        }
+        .into(),
+      );
+    };
+
+    callable.call(vec![], self)
+  }
+
+  fn interpret_expr(
+    &mut self,
+    expr: &Expr,
+    environment: Rc<RefCell<Environment>>,
   ) -> Result<Rc<Value>> {
     match expr {
       Expr::Unary { operator, expr } => {
         let value = self.interpret_expr(expr, environment)?;
         match operator {
-          UnaryOperator::Bang => {
-            if let Value::Bool(inner) = value.as_ref() {
-              Ok(Rc::new(Value::Bool(BoolValue(!inner.0))))
-            } else {
-              Err(
-                RuntimeError::TypeError {
-                  expected: "bool".to_string(),
-                  given: value.type_as_string(),
-                }
-                .into(),
-              )
-            }
-          }
+          UnaryOperator::Bang => Ok(Rc::new(Value::Bool(BoolValue(!value.is_truthy())))),
           UnaryOperator::Minus => {
             if let Value::Number(inner) = value.as_ref() {
               Ok(Rc::new(Value::Number(NumberValue(-inner.0))))
@@ -269,11 +1049,11 @@ impl Interpreter {
 
         match operator {
           BinaryOperator::BangEqual => Ok(Rc::new(Value::Bool(BoolValue(
-            !left_value.is_equal(&right_value)?,
+            !left_value.is_equal(&right_value),
           )))),
           BinaryOperator::Comma => Ok(right_value),
           BinaryOperator::EqualEqual => Ok(Rc::new(Value::Bool(BoolValue(
-            left_value.is_equal(&right_value)?,
+            left_value.is_equal(&right_value),
           )))),
           BinaryOperator::Plus => match (left_value.as_ref(), right_value.as_ref()) {
             (Value::Number(v1), Value::Number(v2)) => {
@@ -291,6 +1071,15 @@ impl Interpreter {
             (Value::Number(v1), Value::Number(v2)) => {
               Ok(Rc::new(Value::Number(NumberValue(v1.0 * v2.0))))
             }
+            (Value::String(string), Value::Number(count)) => {
+              if count.0 < 0. || count.0.fract() != 0. {
+                return Err(RuntimeError::InvalidRepeatCount { count: count.0 }.into());
+              }
+
+              Ok(Rc::new(Value::String(StringValue(
+                string.0.repeat(count.0 as usize),
+              ))))
+            }
             _ => Err(anyhow!("todo")),
           },
           BinaryOperator::Slash => match (left_value.as_ref(), right_value.as_ref()) {
@@ -299,30 +1088,42 @@ impl Interpreter {
             }
             _ => Err(anyhow!("todo")),
           },
-          BinaryOperator::Less => match (left_value.as_ref(), right_value.as_ref()) {
-            (Value::Number(v1), Value::Number(v2)) => {
-              Ok(Rc::new(Value::Bool(BoolValue(v1.0 < v2.0))))
-            }
-            _ => Err(anyhow!("todo")),
-          },
-          BinaryOperator::Greater => match (left_value.as_ref(), right_value.as_ref()) {
-            (Value::Number(v1), Value::Number(v2)) => {
-              Ok(Rc::new(Value::Bool(BoolValue(v1.0 > v2.0))))
-            }
-            _ => Err(anyhow!("todo")),
-          },
-          BinaryOperator::GreaterEqual => match (left_value.as_ref(), right_value.as_ref()) {
-            (Value::Number(v1), Value::Number(v2)) => {
-              Ok(Rc::new(Value::Bool(BoolValue(v1.0 >= v2.0))))
-            }
-            _ => Err(anyhow!("todo")),
-          },
-          BinaryOperator::LessEqual => match (left_value.as_ref(), right_value.as_ref()) {
-            (Value::Number(v1), Value::Number(v2)) => {
-              Ok(Rc::new(Value::Bool(BoolValue(v1.0 <= v2.0))))
-            }
-            _ => Err(anyhow!("todo")),
+          BinaryOperator::In => Ok(Rc::new(Value::Bool(BoolValue(
+            right_value.contains(&left_value)?,
+          )))),
+          BinaryOperator::BitAnd
+          | BinaryOperator::BitOr
+          | BinaryOperator::BitXor
+          | BinaryOperator::ShiftLeft
+          | BinaryOperator::ShiftRight => match (left_value.as_ref(), right_value.as_ref()) {
+            (Value::Number(a), Value::Number(b)) => Ok(Rc::new(Value::Number(NumberValue(
+              bitwise(operator, a.0 as i64, b.0 as i64) as f64,
+            )))),
+            (Value::Number(_), other) | (other, _) => Err(
+              RuntimeError::TypeError {
+                expected: "number".to_string(),
+                given: other.type_as_string(),
+              }
+              .into(),
+            ),
           },
+          BinaryOperator::Less
+          | BinaryOperator::Greater
+          | BinaryOperator::LessEqual
+          | BinaryOperator::GreaterEqual => {
+            let ordering = left_value.compare(&right_value)?;
+
+            let result = match operator {
+              BinaryOperator::Less => ordering == Some(Ordering::Less),
+              BinaryOperator::Greater => ordering == Some(Ordering::Greater),
+              BinaryOperator::LessEqual => {
+                matches!(ordering, Some(Ordering::Less | Ordering::Equal))
+              }
+              _ => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+            };
+
+            Ok(Rc::new(Value::Bool(BoolValue(result))))
+          }
           _ => Err(anyhow!("todo")),
         }
       }
@@ -346,9 +1147,10 @@ impl Interpreter {
         Literal::Number { value } => Ok(Value::Number(NumberValue(*value)).into()),
         Literal::String { value } => Ok(Value::String(StringValue(value.clone())).into()),
         Literal::Nil => Ok(Value::Nil.into()),
-        Literal::Identifier { name, id } => environment
-          .borrow()
-          .get(name, *self.locals.get(id).unwrap())
+        Literal::Identifier { name, id, .. } => self
+          .locals
+          .get(id)
+          .and_then(|distance| environment.borrow().get(name, *distance))
           .ok_or(
             RuntimeError::UndefinedIdentifier {
               name: name.to_string(),
@@ -360,36 +1162,292 @@ impl Interpreter {
         name,
         expression,
         id,
+        ..
       } => {
         let value = self.interpret_expr(expression, Rc::clone(&environment))?;
 
-        Ok(
-          environment
-            .borrow_mut()
-            .assign(name, value, *self.locals.get(id).unwrap()),
-        )
+        let Some(distance) = self.locals.get(id) else {
+          return Err(
+            RuntimeError::AssignmentToUndeclaredVariable {
+              identifier: name.to_string(),
+            }
+            .into(),
+          );
+        };
+
+        Ok(environment.borrow_mut().assign(name, value, *distance))
+      }
+      Expr::Update {
+        name,
+        operator,
+        prefix,
+        id,
+        ..
+      } => {
+        let Some(distance) = self.locals.get(id).copied() else {
+          return Err(
+            RuntimeError::AssignmentToUndeclaredVariable {
+              identifier: name.to_string(),
+            }
+            .into(),
+          );
+        };
+
+        let old = environment.borrow().get(name, distance).ok_or_else(|| {
+          RuntimeError::UndefinedIdentifier {
+            name: name.to_string(),
+          }
+        })?;
+        let Value::Number(NumberValue(number)) = old.as_ref() else {
+          return Err(
+            RuntimeError::TypeError {
+              expected: "number".to_string(),
+              given: old.type_as_string(),
+            }
+            .into(),
+          );
+        };
+
+        let delta = match operator {
+          UpdateOperator::Increment => 1.,
+          UpdateOperator::Decrement => -1.,
+        };
+        let new = Rc::new(Value::Number(NumberValue(number + delta)));
+
+        environment
+          .borrow_mut()
+          .assign(name, Rc::clone(&new), distance);
+
+        Ok(if *prefix { new } else { old })
       }
       Expr::Call {
         function,
         arguments,
+        line,
+        column,
       } => {
         let function_value = self.interpret_expr(function, Rc::clone(&environment))?;
-        let Value::Function(callable) = function_value.as_ref() else {
-          todo!("err")
-        };
 
         let mut eval_arguments: Vec<Rc<Value>> = vec![];
 
         for arg in arguments {
-          eval_arguments.push(self.interpret_expr(arg, Rc::clone(&environment))?);
+          if let Expr::Spread { expr } = arg {
+            let value = self.interpret_expr(expr, Rc::clone(&environment))?;
+
+            eval_arguments.extend(value.iterate()?);
+          } else {
+            eval_arguments.push(self.interpret_expr(arg, Rc::clone(&environment))?);
+          }
         }
 
-        Ok(callable.call(eval_arguments, self)?)
+        let (name, result) = match function_value.as_ref() {
+          Value::Function(callable) => (callable.name(), callable.call(eval_arguments, self)),
+          Value::Class(class) => (
+            class.name.as_str(),
+            Class::instantiate(class, eval_arguments, self),
+          ),
+          _ => {
+            return Err(
+              RuntimeError::TypeError {
+                expected: "function or class".to_string(),
+                given: function_value.type_as_string(),
+              }
+              .into(),
+            )
+          }
+        };
+
+        result.map_err(|error| {
+          let call = (name.to_string(), *line, *column);
+
+          match error.downcast::<Traceback>() {
+            Ok(mut traceback) => {
+              traceback.calls.push(call);
+
+              traceback.into()
+            }
+            // Natives and arity checks fail at the call itself
+            Err(error) => Traceback {
+              error,
+              line: *line,
+              column: *column,
+              calls: vec![call],
+            }
+            .into(),
+          }
+        })
+      }
+      Expr::Super { method, id, .. } => {
+        let distance = self.locals[id];
+        let superclass = environment.borrow().get("super", distance);
+        // `this` is defined right inside the scope holding `super`
+        let this = environment.borrow().get("this", distance - 1);
+
+        let (Some(superclass), Some(this)) = (superclass, this) else {
+          unreachable!("resolver only allows 'super' inside subclass methods");
+        };
+        let Value::Class(superclass) = superclass.as_ref() else {
+          unreachable!("'super' is always bound to a class");
+        };
+
+        match superclass.bind(method, &this) {
+          Some(method) => Ok(Rc::new(Value::Function(Box::new(method)))),
+          None => Err(
+            RuntimeError::UndefinedProperty {
+              name: method.to_string(),
+            }
+            .into(),
+          ),
+        }
+      }
+      Expr::Get { object, name } => {
+        Value::get_property(&self.interpret_expr(object, environment)?, name)
+      }
+      Expr::Set {
+        object,
+        name,
+        value,
+      } => {
+        let object_value = self.interpret_expr(object, Rc::clone(&environment))?;
+        let value = self.interpret_expr(value, environment)?;
+
+        object_value.set_property(name, Rc::clone(&value))?;
+
+        Ok(value)
+      }
+      Expr::Range {
+        start,
+        end,
+        inclusive,
+      } => {
+        let start_value = self.interpret_expr(start, Rc::clone(&environment))?;
+        let end_value = self.interpret_expr(end, environment)?;
+
+        match (start_value.as_ref(), end_value.as_ref()) {
+          (Value::Number(start), Value::Number(end)) => Ok(Rc::new(Value::Range(RangeValue {
+            start: start.0,
+            end: end.0,
+            inclusive: *inclusive,
+          }))),
+          _ => Err(
+            RuntimeError::TypeError {
+              expected: "number range bounds".to_string(),
+              given: format!(
+                "{} and {}",
+                start_value.type_as_string(),
+                end_value.type_as_string()
+              ),
+            }
+            .into(),
+          ),
+        }
+      }
+      Expr::Spread { .. } => Err(anyhow!("spread is only allowed in call arguments")),
+      Expr::Interpolation {
+        strings,
+        expressions,
+      } => {
+        let mut string = strings[0].clone();
+
+        for (expression, part) in expressions.iter().zip(&strings[1..]) {
+          string.push_str(
+            &self
+              .interpret_expr(expression, Rc::clone(&environment))?
+              .to_string(),
+          );
+          string.push_str(part);
+        }
+
+        Ok(Rc::new(Value::String(StringValue(string))))
+      }
+      Expr::Tuple { elements } => {
+        let mut values: Vec<Rc<Value>> = vec![];
+
+        for element in elements {
+          values.push(self.interpret_expr(element, Rc::clone(&environment))?);
+        }
+
+        Ok(Rc::new(Value::Tuple(values)))
+      }
+      Expr::List { elements } => {
+        let mut values: Vec<Rc<Value>> = vec![];
+
+        for element in elements {
+          values.push(self.interpret_expr(element, Rc::clone(&environment))?);
+        }
+
+        Ok(Rc::new(Value::List(RefCell::new(values))))
+      }
+      Expr::Map { entries } => {
+        let mut map = MapValue::default();
+
+        for (key, value) in entries {
+          let key = self.interpret_expr(key, Rc::clone(&environment))?;
+          let value = self.interpret_expr(value, Rc::clone(&environment))?;
+
+          map.insert(key, value)?;
+        }
+
+        Ok(Rc::new(Value::Map(RefCell::new(map))))
+      }
+      Expr::Index { object, index } => {
+        let object_value = self.interpret_expr(object, Rc::clone(&environment))?;
+        let index_value = self.interpret_expr(index, environment)?;
+
+        object_value.index(&index_value)
+      }
+      Expr::Slice { object, start, end } => {
+        let object_value = self.interpret_expr(object, Rc::clone(&environment))?;
+        let start = match start {
+          Some(start) => Some(self.interpret_expr(start, Rc::clone(&environment))?),
+          None => None,
+        };
+        let end = match end {
+          Some(end) => Some(self.interpret_expr(end, environment)?),
+          None => None,
+        };
+
+        object_value.slice(start.as_deref(), end.as_deref())
+      }
+      Expr::IndexSet {
+        object,
+        index,
+        value,
+      } => {
+        let object_value = self.interpret_expr(object, Rc::clone(&environment))?;
+        let index_value = self.interpret_expr(index, Rc::clone(&environment))?;
+        let value = self.interpret_expr(value, environment)?;
+
+        object_value.set_index(index_value, Rc::clone(&value))?;
+
+        Ok(value)
+      }
+      Expr::Is {
+        expr,
+        type_name,
+        id,
+        ..
+      } => {
+        let value = self.interpret_expr(expr, Rc::clone(&environment))?;
+        let class = self
+          .locals
+          .get(id)
+          .and_then(|distance| environment.borrow().get(type_name, *distance));
+
+        let is_type = value.is_type(type_name, class.as_deref())?;
+
+        Ok(Rc::new(Value::Bool(BoolValue(is_type))))
       }
     }
   }
 
-  fn interpret_stmt(&mut self, stmt: &Stmt, environment: Rc<RefCell<Environment>>) -> Result<()> {
+  fn interpret_stmt(&mut self, stmt: &Stmt, environment: Rc<RefCell<Environment>>) -> Result<Flow> {
+    self
+      .execute(stmt, environment)
+      .map_err(|error| locate(error, stmt))
+  }
+
+  fn execute(&mut self, stmt: &Stmt, environment: Rc<RefCell<Environment>>) -> Result<Flow> {
     match stmt {
       Stmt::Block { statements } => {
         let block_environment = Rc::new(RefCell::new(Environment::new(Some(Rc::clone(
@@ -397,60 +1455,1036 @@ impl Interpreter {
         )))));
 
         for stmt in statements {
-          self.interpret_stmt(stmt, Rc::clone(&block_environment))?;
+          match self.interpret_stmt(stmt, Rc::clone(&block_environment))? {
+            Flow::Next => {}
+            flow => return Ok(flow),
+          }
         }
       }
-      Stmt::Expression { expression } => {
+      Stmt::Expression { expression, .. } => {
         self.interpret_expr(expression, environment)?;
       }
-      Stmt::Declaration { name, initializer } => {
+      Stmt::Print { expression, .. } => {
+        let value = self.interpret_expr(expression, environment)?;
+        let mut stdout = self.stdout.borrow_mut();
+
+        writeln!(stdout, "{}", value)?;
+        stdout.flush()?;
+      }
+      Stmt::Assert {
+        condition,
+        message,
+        line,
+        column,
+      } => {
+        if !self
+          .interpret_expr(condition, Rc::clone(&environment))?
+          .is_truthy()
+        {
+          // The message is only evaluated when the assertion fails
+          let message = match message {
+            Some(message) => Some(self.interpret_expr(message, environment)?.to_string()),
+            None => None,
+          };
+
+          return Err(
+            RuntimeError::AssertionFailed {
+              line: *line,
+              column: *column,
+              message,
+            }
+            .into(),
+          );
+        }
+      }
+      Stmt::Declaration {
+        name, initializer, ..
+      } => {
         let value = self.interpret_expr(initializer, Rc::clone(&environment))?;
 
         environment.borrow_mut().define(name, value);
       }
+      Stmt::TupleDeclaration {
+        names, initializer, ..
+      } => {
+        let value = self.interpret_expr(initializer, Rc::clone(&environment))?;
+
+        let Value::Tuple(elements) = value.as_ref() else {
+          return Err(
+            RuntimeError::TypeError {
+              expected: "tuple".to_string(),
+              given: value.type_as_string(),
+            }
+            .into(),
+          );
+        };
+
+        if elements.len() != names.len() {
+          return Err(
+            RuntimeError::DestructuringMismatch {
+              expected: names.len(),
+              given: elements.len(),
+            }
+            .into(),
+          );
+        }
+
+        for (name, element) in names.iter().zip(elements) {
+          environment.borrow_mut().define(name, Rc::clone(element));
+        }
+      }
       Stmt::FunDeclaration {
         name,
         parameters,
         body,
+        ..
       } => {
         let value = Fun::new(
           parameters.clone(),
-          body.clone(),
+          Rc::new(body.clone()),
           name.clone(),
-          Environment::new(Some(Rc::clone(&environment))),
+          Rc::clone(&environment),
         );
 
         environment
           .borrow_mut()
           .define(name, Rc::new(Value::Function(Box::new(value))));
       }
+      Stmt::Class {
+        name,
+        superclass,
+        methods,
+        ..
+      } => {
+        let superclass = match superclass {
+          Some(superclass) => {
+            let value = self.interpret_expr(superclass, Rc::clone(&environment))?;
+
+            let Value::Class(class) = value.as_ref() else {
+              return Err(
+                RuntimeError::TypeError {
+                  expected: "class".to_string(),
+                  given: value.type_as_string(),
+                }
+                .into(),
+              );
+            };
+
+            Some(Rc::clone(class))
+          }
+          None => None,
+        };
+
+        let closure = match &superclass {
+          Some(superclass) => {
+            let mut closure = Environment::new(Some(Rc::clone(&environment)));
+            closure.define("super", Rc::new(Value::Class(Rc::clone(superclass))));
+
+            Rc::new(RefCell::new(closure))
+          }
+          None => Rc::clone(&environment),
+        };
+
+        let methods = methods
+          .iter()
+          .filter_map(|method| match method {
+            Stmt::FunDeclaration {
+              name,
+              parameters,
+              body,
+              ..
+            } => Some((
+              name.clone(),
+              Method {
+                parameters: parameters.clone(),
+                body: Rc::new(body.clone()),
+              },
+            )),
+            _ => None,
+          })
+          .collect();
+
+        let class = Class {
+          name: name.clone(),
+          superclass,
+          methods,
+          closure,
+        };
+
+        environment
+          .borrow_mut()
+          .define(name, Rc::new(Value::Class(Rc::new(class))));
+      }
+      Stmt::Return { value, .. } => {
+        let value = match value {
+          Some(value) => self.interpret_expr(value, environment)?,
+          None => Rc::new(Value::Nil),
+        };
+
+        return Ok(Flow::Return(value));
+      }
       Stmt::While {
         condition,
         statement,
+        label,
+        ..
       } => {
         while self
           .interpret_expr(condition, Rc::clone(&environment))?
           .is_truthy()
         {
-          self.interpret_stmt(statement, Rc::clone(&environment))?;
+          match self
+            .interpret_stmt(statement, Rc::clone(&environment))?
+            .in_loop(label)
+          {
+            LoopStep::Next => {}
+            LoopStep::Exit => break,
+            LoopStep::Propagate(flow) => return Ok(flow),
+          }
+        }
+      }
+      Stmt::Loop { body, label } => loop {
+        match self
+          .interpret_stmt(body, Rc::clone(&environment))?
+          .in_loop(label)
+        {
+          LoopStep::Next => {}
+          LoopStep::Exit => break,
+          LoopStep::Propagate(flow) => return Ok(flow),
+        }
+      },
+      Stmt::Break { label, .. } => return Ok(Flow::Break(label.clone())),
+      Stmt::Continue { label, .. } => return Ok(Flow::Continue(label.clone())),
+      Stmt::For {
+        initializer,
+        condition,
+        increment,
+        body,
+        label,
+        ..
+      } => {
+        let loop_environment = Rc::new(RefCell::new(Environment::new(Some(Rc::clone(
+          &environment,
+        )))));
+
+        if let Some(initializer) = initializer {
+          self.interpret_stmt(initializer, Rc::clone(&loop_environment))?;
+        }
+
+        loop {
+          if let Some(condition) = condition {
+            if !self
+              .interpret_expr(condition, Rc::clone(&loop_environment))?
+              .is_truthy()
+            {
+              break;
+            }
+          }
+
+          match self
+            .interpret_stmt(body, Rc::clone(&loop_environment))?
+            .in_loop(label)
+          {
+            LoopStep::Next => {}
+            LoopStep::Exit => break,
+            LoopStep::Propagate(flow) => return Ok(flow),
+          }
+
+          if let Some(increment) = increment {
+            self.interpret_expr(increment, Rc::clone(&loop_environment))?;
+          }
+        }
+      }
+      Stmt::ForIn {
+        variable,
+        iterable,
+        body,
+        label,
+        ..
+      } => {
+        let iterable_value = self.interpret_expr(iterable, Rc::clone(&environment))?;
+
+        for value in iterable_value.iterate()? {
+          // Every iteration gets a fresh binding, so closures capture its value
+          let loop_environment = Rc::new(RefCell::new(Environment::new(Some(Rc::clone(
+            &environment,
+          )))));
+
+          loop_environment.borrow_mut().define(variable, value);
+
+          match self.interpret_stmt(body, loop_environment)?.in_loop(label) {
+            LoopStep::Next => {}
+            LoopStep::Exit => break,
+            LoopStep::Propagate(flow) => return Ok(flow),
+          }
         }
       }
       Stmt::If {
         condition,
         true_case,
         false_case,
+        ..
       } => {
         if self
           .interpret_expr(condition, Rc::clone(&environment))?
           .is_truthy()
         {
-          self.interpret_stmt(true_case, Rc::clone(&environment))?;
+          return self.interpret_stmt(true_case, Rc::clone(&environment));
         } else if let Some(statement) = false_case {
-          self.interpret_stmt(statement, Rc::clone(&environment))?;
+          return self.interpret_stmt(statement, Rc::clone(&environment));
+        }
+      }
+      Stmt::Switch {
+        subject,
+        cases,
+        default,
+        ..
+      } => {
+        let subject = self.interpret_expr(subject, Rc::clone(&environment))?;
+
+        for (values, body) in cases {
+          for value in values {
+            if self
+              .interpret_expr(value, Rc::clone(&environment))?
+              .is_equal(&subject)
+            {
+              return self.interpret_stmt(body, environment);
+            }
+          }
+        }
+
+        if let Some(default) = default {
+          return self.interpret_stmt(default, environment);
         }
       }
+      Stmt::Trivia { .. } => {}
     };
 
-    Ok(())
+    Ok(Flow::Next)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::natives::NativeClock;
+  use crate::parser::Parser;
+  use crate::runner::{run_with_options, RunOptions};
+  use scanner::{Scanner, Token};
+  use std::cell::RefCell;
+  use std::rc::Rc;
+
+  fn output(source: &str) -> String {
+    let stdout = Rc::new(RefCell::new(Vec::<u8>::new()));
+
+    run_with_options(
+      source.to_string(),
+      &RunOptions {
+        stdout: stdout.clone(),
+        ..RunOptions::default()
+      },
+    )
+    .unwrap();
+
+    String::from_utf8(stdout.take()).unwrap()
+  }
+
+  /// Interprets `source` without resolving it first, so no identifier has a
+  /// known distance.
+  fn unresolved_error(source: &str) -> String {
+    let tokens = Scanner::new(source.to_string())
+      .collect::<Result<Vec<Token>>>()
+      .unwrap();
+    let program = Parser::new(tokens).parse().unwrap();
+
+    let options = RunOptions::default();
+
+    let mut interpreter = Interpreter::new(Locals::new(), vec![], options.stdout, options.stderr);
+    let Err(error) = interpreter.interpret_program(&program) else {
+      panic!("{source:?} should fail");
+    };
+
+    error.to_string()
+  }
+
+  #[test]
+  fn test_display_functions() {
+    assert_eq!(
+      Value::Function(Box::new(NativeClock)).to_string(),
+      "<fn clock>"
+    );
+  }
+
+  #[test]
+  fn test_unresolved_identifiers() {
+    assert_eq!(
+      unresolved_error("missing;"),
+      "undefined: \"missing\"\n  at top level, line 1, column 1"
+    );
+    assert_eq!(
+      unresolved_error("missing = 1;"),
+      "cannot assign to undeclared variable: \"missing\"\n  at top level, line 1, column 1"
+    );
+  }
+
+  #[test]
+  fn test_truthiness() {
+    assert_eq!(
+      output(
+        r#"
+          write(nil ? "t" : "f");
+          write(false ? "t" : "f");
+          write(true ? "t" : "f");
+          write(0 ? "t" : "f");
+          write("" ? "t" : "f");
+          if (nil) { write("t"); } else { write("f"); }
+          while (nil) { write("t"); }
+          write(!nil, !false, !0, !"");
+        "#
+      ),
+      "fftttftrue true false false"
+    );
+  }
+
+  #[test]
+  fn test_equality() {
+    assert_eq!(
+      output(
+        r#"
+          fun f() {}
+          fun g() {}
+          write(nil == nil, nil == false, 1 == "1", "a" == "a", 1 != 2, f == f, f == g, 0 == nil);
+        "#
+      ),
+      "true false false true true true false false"
+    );
+  }
+
+  #[test]
+  fn test_for_in_range() {
+    assert_eq!(
+      output(
+        r#"
+          for (i in 0..3) { write(i); }
+          var n = 2;
+          for (i in 1..=n) { write(i); }
+          for (i in 5..5) { write(i); }
+          write("|", 1..=2);
+        "#
+      ),
+      "01212| 1..=2"
+    );
+  }
+
+  #[test]
+  fn test_for_in_string() {
+    assert_eq!(
+      output(r#"for (c in "héj") { write(c, "|"); }"#),
+      "h |é |j |"
+    );
+    assert!(run_with_options("for (x in 1) {}".to_string(), &RunOptions::default()).is_err());
+  }
+
+  #[test]
+  fn test_spread_arguments() {
+    assert_eq!(
+      output(
+        r#"
+          var xs = 1..=3;
+          write(0, ...xs, 4, ..."ab");
+        "#
+      ),
+      "0 1 2 3 4 a b"
+    );
+  }
+
+  #[test]
+  fn test_is() {
+    assert_eq!(
+      output(
+        r#"
+          fun f() {}
+          println(1 is Number, "1" is Number, nil is Nil, f is Function, 0..1 is Range);
+          println(!(true is Bool) == false);
+          class A {}
+          {
+            class B < A {}
+            println(B() is A, A() is B);
+            var A = 1;
+            println(B() is B);
+          }
+        "#
+      ),
+      "true false true true true\ntrue\ntrue false\ntrue\n"
+    );
+
+    let error = run_with_options("1 is Foo;".to_string(), &RunOptions::default()).unwrap_err();
+    assert!(error.to_string().contains("undefined variable \"Foo\""));
+    assert!(run_with_options("var A = 1; 1 is A;".to_string(), &RunOptions::default()).is_err());
+  }
+
+  #[test]
+  fn test_in() {
+    assert_eq!(
+      output(
+        r#"write("ell" in "hello", "x" in "hello", 2 in 0..3, 3 in 0..3, 3 in 0..=3, 1.5 in 0..3);"#
+      ),
+      "true false true false true false"
+    );
+    assert!(run_with_options("1 in 2;".to_string(), &RunOptions::default()).is_err());
+  }
+
+  #[test]
+  fn test_return_and_tuples() {
+    assert_eq!(
+      output(
+        r#"
+          fun sign(n) {
+            if (n < 0) { return -1; }
+            while (true) { return 1; }
+          }
+          fun point() { return 1, "a"; }
+          fun nothing() { return; }
+          var (x, y) = point();
+          var (a, b) = 3, 4;
+          println(sign(-5), sign(5), nothing(), x, y, a + b, point(), point() == point());
+          debug(point());
+        "#
+      ),
+      "-1 1 nil 1 a 7 (1, a) true\n(1, \"a\")\n"
+    );
+    assert!(run_with_options("var (a, b) = 1, 2, 3;".to_string(), &RunOptions::default()).is_err());
+    assert!(run_with_options("return 1;".to_string(), &RunOptions::default()).is_err());
+  }
+
+  #[test]
+  fn test_loop_and_break() {
+    assert_eq!(
+      output(
+        r#"
+          var i = 0;
+          loop {
+            i = i + 1;
+            if (i > 3) { break; }
+            for (j in 0..10) { if (j == i) { break; } write(j); }
+          }
+          while (true) { write("|"); break; }
+        "#
+      ),
+      "001012|"
+    );
+  }
+
+  #[test]
+  fn test_bitwise_operators() {
+    assert_eq!(
+      output(
+        "write(6 & 3, 6 | 3, 6 ^ 3, 1 << 4, -16 >> 60, 5.9 & 7, 1 | 2 == 3, 1 << 64, 8 << -2);"
+      ),
+      "2 7 5 16 15 5 true 0 2"
+    );
+
+    assert!(run_with_options("1 & nil;".to_string(), &RunOptions::default()).is_err());
+    assert!(run_with_options("\"a\" << 1;".to_string(), &RunOptions::default()).is_err());
+  }
+
+  #[test]
+  fn test_lists() {
+    assert_eq!(
+      output(
+        r#"
+          var list = [1, "two", [3], nil,];
+          write(list, list[1], list[2][0], [], [1, 2] == [1, 2], list is List);
+          write("", 2 in [1, 2], "ab"[1]);
+          for (x in [1, 2, 3]) { write(x); }
+        "#
+      ),
+      "[1, two, [3], nil] two 3 [] true true true b123"
+    );
+
+    for source in ["[1][1];", "[1][-1];", "[1][0.5];", "[1][\"0\"];", "1[0];"] {
+      assert!(run_with_options(source.to_string(), &RunOptions::default()).is_err());
+    }
+  }
+
+  #[test]
+  fn test_maps() {
+    assert_eq!(
+      output(
+        r#"
+          var empty = {};
+          var map = {"a": 1, 2: "two", true: [3], nil: nil, -0: 0,};
+          write(map["a"], map[2], map[true][0], map[nil], map[0], empty, "a" in map);
+          write("", {1: 2, 3: 4} == {3: 4, 1: 2}, {1: 2} == {1: 3}, map is Map);
+          for (key in {"x": 1, "y": 2}) { write("", key); }
+          debug({"k": "v"});
+        "#
+      ),
+      "1 two 3 nil 0 {} true true false true x y{\"k\": \"v\"}\n"
+    );
+
+    for source in [
+      r#"var m = {}; m["a"];"#,
+      r#"var m = {[]: 1};"#,
+      r#"var m = {1 2};"#,
+      r#"var m = {1: 2"#,
+    ] {
+      assert!(run_with_options(source.to_string(), &RunOptions::default()).is_err());
+    }
+  }
+
+  #[test]
+  fn test_cycles() {
+    assert_eq!(
+      output(
+        r#"
+          var a = [1];
+          push(a, a);
+          var b = [1];
+          push(b, b);
+          println(a, a == a, a == b, a == [1, [1]]);
+          debug(a);
+          var m = {"k": "v"};
+          m["self"] = m;
+          m["list"] = [a, m];
+          println(m);
+          debug(m);
+          var shared = [1];
+          println([shared, shared]);
+        "#
+      ),
+      "[1, [...]] true true false
+[1, [...]]
+{k: v, self: {...}, list: [[1, [...]], {...}]}
+{\"k\": \"v\", \"self\": {...}, \"list\": [[1, [...]], {...}]}
+[[1], [1]]
+"
+    );
+  }
+
+  #[test]
+  fn test_index_assignment() {
+    assert_eq!(
+      output(
+        r#"
+          var list = [1, 2, 3];
+          var alias = list;
+          alias[0] = list[2] = 10;
+          var grid = [[0, 0], [0, 0]];
+          grid[1][0] = 5;
+          var counts = {};
+          for (word in ["a", "b", "a"]) {
+            if (word in counts) { counts[word] = counts[word] + 1; } else { counts[word] = 1; }
+          }
+          write(list, grid, counts);
+        "#
+      ),
+      "[10, 2, 10] [[0, 0], [5, 0]] {a: 2, b: 1}"
+    );
+
+    for source in [
+      "var list = [1]; list[1] = 2;",
+      "var s = \"ab\"; s[0] = \"c\";",
+      "var n = 1; n[0] = 2;",
+      "1 + 2 = 3;",
+    ] {
+      assert!(run_with_options(source.to_string(), &RunOptions::default()).is_err());
+    }
+  }
+
+  #[test]
+  fn test_slices() {
+    assert_eq!(
+      output(
+        r#"
+          var list = [0, 1, 2, 3, 4];
+          var copy = list[:];
+          copy[0] = 9;
+          write(list[1:3], list[3:], list[:2], list[-2:], list[:-4], list[3:1], list[-9:9]);
+          write("", "hello"[2:], "hello"[1:-1], "żółw"[:2], copy == list);
+        "#
+      ),
+      "[1, 2] [3, 4] [0, 1] [3, 4] [0] [] [0, 1, 2, 3, 4] llo ell żó false"
+    );
+
+    for source in [
+      "[1][0.5:];",
+      "[1][:\"a\"];",
+      "var n = 1; n[0:1];",
+      "[1][0:1",
+    ] {
+      assert!(run_with_options(source.to_string(), &RunOptions::default()).is_err());
+    }
+  }
+
+  #[test]
+  fn test_string_repetition() {
+    assert_eq!(
+      output(r#"write("ab" * 3, "x" * 0 == "", "-" * 2);"#),
+      "ababab true --"
+    );
+
+    for source in [r#""a" * -1;"#, r#""a" * 1.5;"#, r#""a" * "b";"#] {
+      assert!(run_with_options(source.to_string(), &RunOptions::default()).is_err());
+    }
+  }
+
+  #[test]
+  fn test_increments() {
+    assert_eq!(
+      output(
+        r#"
+          var i = 1;
+          write(i++, i, ++i, i--, --i, " ");
+          for (var j = 0; j < 3; j++) { write(j); }
+          fun count() { var n = 0; n++; return n; }
+          write(count());
+        "#
+      ),
+      "1 2 3 3 1  0121"
+    );
+
+    assert!(run_with_options("var s = \"a\"; s++;".to_string(), &RunOptions::default()).is_err());
+    assert!(run_with_options("1++;".to_string(), &RunOptions::default()).is_err());
+  }
+
+  #[test]
+  fn test_else_if() {
+    assert_eq!(
+      output(
+        r#"
+          fun sign(n) {
+            if (n < 0) { return "-"; } else if (n == 0) { return "0"; } else if (n < 10) {
+              return "+";
+            } else { return "++"; }
+          }
+          write(sign(-5), sign(0), sign(3), sign(20));
+        "#
+      ),
+      "- 0 + ++"
+    );
+  }
+
+  #[test]
+  fn test_single_statement_bodies() {
+    assert_eq!(
+      output(
+        r#"
+          var n = 3;
+          if (n > 2) write("big"); else write("small");
+          if (n < 2) write("never");
+          while (n > 0) n = n - 1;
+          for (var i = 0; i < 3; i = i + 1) write("", i);
+          for (c in "ab") if (c == "b") write("", c);
+          write("", n);
+        "#
+      ),
+      "big 0 1 2 b 0"
+    );
+  }
+
+  #[test]
+  fn test_switch() {
+    assert_eq!(
+      output(
+        r#"
+          fun describe(n) {
+            switch (n) {
+              case 0: return "zero";
+              case 1, 2:
+                var word = "small";
+                return word;
+              case "a": return "letter";
+              default: return "other";
+            }
+          }
+          for (n in [0, 1, 2, "a", 5]) write(describe(n), "");
+          switch (1) { case 2: write("no"); }
+          switch (3) {}
+        "#
+      ),
+      "zero small small letter other "
+    );
+
+    for source in [
+      "switch (1) { print 1; }",
+      "switch (1) { case 1 print 1; }",
+      "switch 1 {}",
+    ] {
+      assert!(run_with_options(source.to_string(), &RunOptions::default()).is_err());
+    }
+  }
+
+  #[test]
+  fn test_string_interpolation() {
+    assert_eq!(
+      output(
+        r#"
+          var x = 2;
+          var m = {"k": [1, 2]};
+          print "x = ${x + 1}, ${m["k"][1]}${"!"}";
+          print "${x}";
+          print "nested ${"x is ${x}"} and ${true}";
+        "#
+      ),
+      "x = 3, 2!\n2\nnested x is 2 and true\n"
+    );
+    assert!(run_with_options("print \"${1\";".to_string(), &RunOptions::default()).is_err());
+    // Interpolation converts values itself, `+` still needs two numbers
+    assert!(run_with_options("print 1 + \"a\";".to_string(), &RunOptions::default()).is_err());
+    assert!(run_with_options("print \"abc;".to_string(), &RunOptions::default()).is_err());
+  }
+
+  #[test]
+  fn test_assert() {
+    assert_eq!(
+      output("assert 1 < 2; assert true, \"unused\"; print 1;"),
+      "1\n"
+    );
+
+    let error = |source: &str| {
+      run_with_options(source.to_string(), &RunOptions::default())
+        .unwrap_err()
+        .to_string()
+    };
+
+    assert_eq!(
+      error("var x = 1;\nassert x == 2, \"x is one\";"),
+      "assertion failed on line 2, column 1: x is one\n  at top level, line 2, column 1"
+    );
+    assert_eq!(
+      error("assert nil;"),
+      "assertion failed on line 1, column 1\n  at top level, line 1, column 1"
+    );
+  }
+
+  #[test]
+  fn test_labeled_loops() {
+    assert_eq!(
+      output(
+        r#"
+          outer: for (var i = 0; i < 3; i = i + 1) {
+            for (var j = 0; j < 3; j = j + 1) {
+              if (j == 1) continue outer;
+              if (i == 2) break outer;
+              write(i, j, "");
+            }
+          }
+          var n = 0;
+          rows: while (n < 5) {
+            n = n + 1;
+            for (x in [1, 2]) {
+              if (n == 2 or n == 4) continue rows;
+              if (n > 3) break rows;
+            }
+            write(n, "");
+          }
+          for (x in [1, 2, 3]) { if (x == 2) continue; write(x, ""); }
+        "#
+      ),
+      "0 0 1 0 1 3 1 3 "
+    );
+
+    let stderr = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let options = RunOptions {
+      stderr: stderr.clone(),
+      ..RunOptions::default()
+    };
+
+    for source in [
+      "a: for (;;) { break b; }",
+      "a: print 1;",
+      "continue;",
+      "fun f() { continue a; } a: loop { break; }",
+    ] {
+      assert!(run_with_options(source.to_string(), &options).is_err());
+    }
+
+    // Only the first loop can't be left, `break b` doesn't target it
+    let warnings = String::from_utf8(stderr.take()).unwrap();
+
+    assert_eq!(warnings.matches("warning[infinite-loop]").count(), 1);
+  }
+
+  #[test]
+  fn test_recursion_and_closures() {
+    assert_eq!(
+      output(
+        r#"
+          fun fib(n) { if (n < 2) { return n; } return fib(n - 1) + fib(n - 2); }
+          fun counter() {
+            var count = 0;
+            fun increment() { count = count + 1; return count; }
+            return increment;
+          }
+          var a = counter();
+          var b = counter();
+          a(); a();
+          write(fib(15), a(), b());
+        "#
+      ),
+      "610 3 1"
+    );
+  }
+
+  #[test]
+  fn test_arity_mismatch() {
+    let error = |source: &str| {
+      run_with_options(source.to_string(), &RunOptions::default())
+        .unwrap_err()
+        .to_string()
+    };
+
+    assert!(error("fun f(a, b) {} f(1);").starts_with("f expects 2 arguments but got 1"));
+    assert!(error("class A { init(a) {} } A();").starts_with("init expects 1 arguments but got 0"));
+    assert!(error("class A {} A(1);").starts_with("A expects 0 arguments but got 1"));
+  }
+
+  #[test]
+  fn test_stack_traces() {
+    let source =
+      "fun inner(x) {\n  return -x;\n}\nfun outer() {\n  return inner(\"a\");\n}\nouter();";
+    let error = run_with_options(source.to_string(), &RunOptions::default()).unwrap_err();
+
+    assert_eq!(
+      error.to_string(),
+      "expected type \"number\" given \"string\"\n  in inner at line 2, column 3\n  in outer at line 5, column 15\n  at top level, line 7, column 6"
+    );
+
+    let stdout = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let options = RunOptions {
+      stdout: stdout.clone(),
+      ..RunOptions::default()
+    };
+    let error = run_with_options("print 1;\nprint -nil;".to_string(), &options);
+
+    assert_eq!(
+      error.unwrap_err().to_string(),
+      "expected type \"number\" given \"nil\"\n  at top level, line 2, column 7"
+    );
+    assert_eq!(String::from_utf8(stdout.take()).unwrap(), "1\n");
+  }
+
+  #[test]
+  fn test_c_style_for() {
+    assert_eq!(
+      output(
+        r#"
+          for (var i = 0; i < 3; i = i + 1) { write(i); }
+          var n = 10;
+          for (n = 0; n < 5; n = n + 2) {}
+          write(n);
+          var k = 0;
+          for (;;) { k = k + 1; if (k == 2) { break; } }
+          write(k);
+          fun first() { for (var i = 5; ; i = i + 1) { return i; } }
+          write(first());
+        "#
+      ),
+      "012625"
+    );
+  }
+
+  #[test]
+  fn test_classes() {
+    assert_eq!(
+      output(
+        r#"
+          class Point {}
+          var p = Point();
+          p.x = 1;
+          p.y = 2;
+          p.x = p.x + p.y;
+          write(p.x, p, Point, p is Point, Point() is Point, 1 is Point, p == Point());
+        "#
+      ),
+      "3 Point instance Point true true false false"
+    );
+
+    for source in [
+      "class A {} A(1);",
+      "class A {} A().x;",
+      "var n = 1; n.x = 2;",
+    ] {
+      assert!(run_with_options(source.to_string(), &RunOptions::default()).is_err());
+    }
+  }
+
+  #[test]
+  fn test_methods() {
+    assert_eq!(
+      output(
+        r#"
+          class Counter {
+            init(start) { this.n = start; }
+            tick() { this.n = this.n + 1; return this; }
+            get() { fun inner() { return this.n; } return inner; }
+          }
+          var c = Counter(1);
+          var tick = c.tick;
+          tick();
+          c.tick().tick();
+          write(c.n, c.get()(), c.init(0) == c, c.n);
+        "#
+      ),
+      "4 4 true 0"
+    );
+
+    for source in [
+      "class A { init() { return 1; } }",
+      "class A {} A().missing();",
+      "print this;",
+    ] {
+      assert!(run_with_options(source.to_string(), &RunOptions::default()).is_err());
+    }
+  }
+
+  #[test]
+  fn test_inheritance() {
+    assert_eq!(
+      output(
+        r#"
+          class A {
+            init(n) { this.n = n; }
+            value() { return this.n; }
+            who() { return "A"; }
+          }
+          class B < A {
+            value() { return super.value() * 10; }
+          }
+          class C < B {
+            init() { super.init(2); }
+            who() { return super.who; }
+          }
+          var c = C();
+          write(c.value(), c.who()(), c is A, c is C, A(1) is B);
+        "#
+      ),
+      "20 A true true false"
+    );
+
+    for source in [
+      "var A = 1; class B < A {}",
+      "class A {} class B < A { f() { return super.missing(); } } B().f();",
+    ] {
+      assert!(run_with_options(source.to_string(), &RunOptions::default()).is_err());
+    }
+  }
+
+  #[test]
+  fn test_print() {
+    assert_eq!(output("print 1 + 2 == 3; print nil;"), "true\nnil\n");
+  }
+
+  #[test]
+  fn test_comparison() {
+    assert_eq!(
+      output(
+        r#"
+          write("apple" < "banana", "b" <= "a", "abc" > "ab", "" >= "", 2 > 10);
+        "#
+      ),
+      "true false true true false"
+    );
   }
 }
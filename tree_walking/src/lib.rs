@@ -1,7 +1,14 @@
 mod ast_printer;
+mod cache;
+pub mod diagnostics;
+pub mod docs;
+pub mod document;
 mod environment;
 mod errors;
+pub mod formatter;
 mod interpreter;
+pub mod lint;
+pub mod natives;
 mod parser;
 mod resolver;
 pub mod runner;
@@ -0,0 +1,764 @@
+//! Static checks reporting code that runs but is likely a mistake. Each
+//! check is a `LintPass` fed by one walk over the syntax tree.
+
+use crate::diagnostics::{self, Severity, Span};
+use crate::parser::{Expr, Literal, Parser, Stmt};
+use anyhow::{anyhow, Result};
+use scanner::{Scanner, Token, TokenType};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Lint {
+  /// A local variable, parameter or function that is never read
+  UnusedVariable,
+  /// Statements following a `return` in the same block
+  UnreachableCode,
+  /// An `if` or `while` condition that is a literal
+  ConstantCondition,
+  /// A declaration hiding a variable of an enclosing scope
+  ShadowedName,
+}
+
+impl Lint {
+  pub const ALL: [Lint; 4] = [
+    Lint::UnusedVariable,
+    Lint::UnreachableCode,
+    Lint::ConstantCondition,
+    Lint::ShadowedName,
+  ];
+
+  /// Name used to report the lint and to allow or deny it.
+  pub fn code(self) -> &'static str {
+    match self {
+      Lint::UnusedVariable => "unused-variable",
+      Lint::UnreachableCode => "unreachable-code",
+      Lint::ConstantCondition => "constant-condition",
+      Lint::ShadowedName => "shadowed-name",
+    }
+  }
+
+  pub fn from_code(code: &str) -> Option<Lint> {
+    Lint::ALL.into_iter().find(|lint| lint.code() == code)
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Level {
+  /// Not reported
+  Allow,
+  /// Reported without failing the check
+  Warn,
+  /// Reported as an error
+  Deny,
+}
+
+/// Level of every lint, `Warn` unless changed.
+#[derive(Default)]
+pub struct LintConfig {
+  levels: HashMap<Lint, Level>,
+}
+
+impl LintConfig {
+  pub fn set(&mut self, lint: Lint, level: Level) {
+    self.levels.insert(lint, level);
+  }
+
+  /// Sets the level of the lints named by `codes`, failing on unknown ones.
+  pub fn set_codes(&mut self, codes: &[String], level: Level) -> Result<()> {
+    for code in codes {
+      let lint = Lint::from_code(code).ok_or_else(|| anyhow!("unknown lint {code:?}"))?;
+
+      self.set(lint, level);
+    }
+
+    Ok(())
+  }
+
+  pub fn level(&self, lint: Lint) -> Level {
+    self.levels.get(&lint).copied().unwrap_or(Level::Warn)
+  }
+}
+
+/// What a lint found. It is shown like the errors of a run by converting it
+/// to a `diagnostics::Diagnostic`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+  pub lint: Lint,
+  pub level: Level,
+  /// The token the finding is about
+  pub span: Span,
+  pub message: String,
+}
+
+impl From<&Diagnostic> for diagnostics::Diagnostic {
+  fn from(diagnostic: &Diagnostic) -> Self {
+    let severity = match diagnostic.level {
+      Level::Deny => Severity::Error,
+      _ => Severity::Warning,
+    };
+
+    diagnostics::Diagnostic {
+      severity,
+      span: Some(diagnostic.span.clone()),
+      ..diagnostics::Diagnostic::error(diagnostic.message.clone()).with_code(diagnostic.lint.code())
+    }
+  }
+}
+
+/// The span of `text` starting at `line` and `column`.
+fn span(line: u32, column: u32, text: &str) -> Span {
+  Span {
+    line,
+    column: Some(column),
+    length: text.chars().count(),
+  }
+}
+
+/// A variable as seen by the walk.
+struct Binding {
+  name: String,
+  kind: &'static str,
+  /// Where the name is declared, `None` for declarations that are never
+  /// reported
+  span: Option<Span>,
+  used: bool,
+}
+
+/// Hooks called while walking the program. Passes push the span and
+/// message of what they find to `report`.
+trait LintPass {
+  fn lint(&self) -> Lint;
+
+  /// Called with the statements of every block and function body.
+  fn block(&mut self, _statements: &[Stmt], _report: &mut Vec<(Span, String)>) {}
+
+  /// Called for every statement before walking into it.
+  fn statement(&mut self, _stmt: &Stmt, _report: &mut Vec<(Span, String)>) {}
+
+  /// Called when `binding` is declared, `scopes` holding the ones visible
+  /// before it, innermost last.
+  fn declared(
+    &mut self,
+    _binding: &Binding,
+    _scopes: &[Vec<Binding>],
+    _report: &mut Vec<(Span, String)>,
+  ) {
+  }
+
+  /// Called when a local scope ends with the bindings it declared.
+  fn scope_ended(&mut self, _bindings: &[Binding], _report: &mut Vec<(Span, String)>) {}
+}
+
+struct UnusedVariable;
+
+impl LintPass for UnusedVariable {
+  fn lint(&self) -> Lint {
+    Lint::UnusedVariable
+  }
+
+  fn scope_ended(&mut self, bindings: &[Binding], report: &mut Vec<(Span, String)>) {
+    for binding in bindings {
+      let Some(span) = &binding.span else {
+        continue;
+      };
+
+      // A leading underscore marks names unused on purpose
+      if !binding.used && !binding.name.starts_with('_') {
+        report.push((
+          span.clone(),
+          format!("{} {:?} is never used", binding.kind, binding.name),
+        ));
+      }
+    }
+  }
+}
+
+struct UnreachableCode;
+
+impl LintPass for UnreachableCode {
+  fn lint(&self) -> Lint {
+    Lint::UnreachableCode
+  }
+
+  fn block(&mut self, statements: &[Stmt], report: &mut Vec<(Span, String)>) {
+    let code = statements
+      .iter()
+      .filter(|stmt| !matches!(stmt, Stmt::Trivia { .. }))
+      .collect::<Vec<&Stmt>>();
+
+    for pair in code.windows(2) {
+      if let Stmt::Return { line, column, .. } = pair[0] {
+        report.push((
+          span(*line, *column, "return"),
+          "code after this return is never run".to_string(),
+        ));
+
+        return;
+      }
+    }
+  }
+}
+
+struct ConstantCondition;
+
+impl LintPass for ConstantCondition {
+  fn lint(&self) -> Lint {
+    Lint::ConstantCondition
+  }
+
+  fn statement(&mut self, stmt: &Stmt, report: &mut Vec<(Span, String)>) {
+    let (keyword, condition, line, column) = match stmt {
+      Stmt::If {
+        condition,
+        line,
+        column,
+        ..
+      } => ("if", condition, line, column),
+      Stmt::While {
+        condition,
+        line,
+        column,
+        ..
+      } => ("while", condition, line, column),
+      _ => return,
+    };
+
+    let truthy = match condition.as_ref() {
+      Expr::Literal {
+        value: Literal::False | Literal::Nil,
+      } => false,
+      Expr::Literal {
+        value: Literal::Identifier { .. },
+      } => return,
+      Expr::Literal { .. } => true,
+      _ => return,
+    };
+
+    let message = match (keyword, truthy) {
+      ("while", true) => "'while' condition is always true, use 'loop'".to_string(),
+      (keyword, truthy) => format!("'{keyword}' condition is always {truthy}"),
+    };
+
+    report.push((span(*line, *column, keyword), message));
+  }
+}
+
+struct ShadowedName;
+
+impl LintPass for ShadowedName {
+  fn lint(&self) -> Lint {
+    Lint::ShadowedName
+  }
+
+  fn declared(
+    &mut self,
+    binding: &Binding,
+    scopes: &[Vec<Binding>],
+    report: &mut Vec<(Span, String)>,
+  ) {
+    let Some(span) = &binding.span else {
+      return;
+    };
+
+    let Some((_, enclosing)) = scopes.split_last() else {
+      return;
+    };
+
+    let shadowed = enclosing
+      .iter()
+      .rev()
+      .find_map(|scope| scope.iter().find(|outer| outer.name == binding.name));
+
+    if let Some(shadowed) = shadowed {
+      let message = match &shadowed.span {
+        Some(outer) => format!(
+          "{} {:?} shadows the one declared on line {}",
+          binding.kind, binding.name, outer.line
+        ),
+        None => format!("{} {:?} shadows an outer one", binding.kind, binding.name),
+      };
+
+      report.push((span.clone(), message));
+    }
+  }
+}
+
+/// Walks the program, keeping track of scopes and calling the passes.
+struct Walker {
+  passes: Vec<Box<dyn LintPass>>,
+  /// The top-level scope first
+  scopes: Vec<Vec<Binding>>,
+  /// Tokens of the program, to find the names the syntax tree keeps no
+  /// position of
+  tokens: Vec<Token>,
+  diagnostics: Vec<(Lint, Span, String)>,
+}
+
+impl Walker {
+  fn each_pass(
+    &mut self,
+    mut hook: impl FnMut(&mut dyn LintPass, &[Vec<Binding>], &mut Vec<(Span, String)>),
+  ) {
+    for pass in &mut self.passes {
+      let mut report = vec![];
+
+      hook(pass.as_mut(), &self.scopes, &mut report);
+
+      let lint = pass.lint();
+      self.diagnostics.extend(
+        report
+          .into_iter()
+          .map(|(span, message)| (lint, span, message)),
+      );
+    }
+  }
+
+  fn declare(&mut self, name: &str, kind: &'static str, span: Option<Span>) {
+    let binding = Binding {
+      name: name.to_string(),
+      kind,
+      span,
+      used: false,
+    };
+
+    self.each_pass(|pass, scopes, report| pass.declared(&binding, scopes, report));
+    self
+      .scopes
+      .last_mut()
+      .expect("the top-level scope")
+      .push(binding);
+  }
+
+  /// The span of the first identifier `name` at or after `line` and
+  /// `column`.
+  fn find_name(&self, name: &str, line: u32, column: u32) -> Option<Span> {
+    self
+      .tokens
+      .iter()
+      .filter(|token| (token.line, token.column) >= (line, column))
+      .find(|token| matches!(&token.kind, TokenType::Identifier(identifier) if identifier == name))
+      .map(|token| span(token.line, token.column, name))
+  }
+
+  fn use_name(&mut self, name: &str) {
+    let binding = self
+      .scopes
+      .iter_mut()
+      .rev()
+      .find_map(|scope| scope.iter_mut().rev().find(|binding| binding.name == name));
+
+    if let Some(binding) = binding {
+      binding.used = true;
+    }
+  }
+
+  fn begin_scope(&mut self) {
+    self.scopes.push(vec![]);
+  }
+
+  fn end_scope(&mut self) {
+    let bindings = self.scopes.pop().expect("a scope to end");
+
+    self.each_pass(|pass, _, report| pass.scope_ended(&bindings, report));
+  }
+
+  fn block(&mut self, statements: &[Stmt]) {
+    self.each_pass(|pass, _, report| pass.block(statements, report));
+
+    for stmt in statements {
+      self.stmt(stmt);
+    }
+  }
+
+  /// Walks a function whose name is at `line` and `column`.
+  fn function(&mut self, parameters: &[String], body: &[Stmt], line: u32, column: u32) {
+    self.begin_scope();
+
+    for parameter in parameters {
+      // The parameters follow the name
+      let span = self.find_name(parameter, line, column + 1);
+
+      self.declare(parameter, "parameter", span);
+    }
+
+    self.block(body);
+    self.end_scope();
+  }
+
+  fn stmt(&mut self, stmt: &Stmt) {
+    self.each_pass(|pass, _, report| pass.statement(stmt, report));
+
+    match stmt {
+      Stmt::Expression { expression, .. } | Stmt::Print { expression, .. } => self.expr(expression),
+      Stmt::Assert {
+        condition, message, ..
+      } => {
+        self.expr(condition);
+
+        if let Some(message) = message {
+          self.expr(message);
+        }
+      }
+      Stmt::Declaration {
+        name,
+        initializer,
+        line,
+        column,
+        ..
+      } => {
+        self.expr(initializer);
+        self.declare(name, "variable", Some(span(*line, *column, name)));
+      }
+      Stmt::TupleDeclaration {
+        names,
+        initializer,
+        line,
+        column,
+        ..
+      } => {
+        self.expr(initializer);
+
+        for name in names {
+          let span = self.find_name(name, *line, *column);
+
+          self.declare(name, "variable", span);
+        }
+      }
+      Stmt::Return { value, .. } => {
+        if let Some(value) = value {
+          self.expr(value);
+        }
+      }
+      Stmt::FunDeclaration {
+        name,
+        parameters,
+        body,
+        line,
+        column,
+        ..
+      } => {
+        self.declare(name, "function", Some(span(*line, *column, name)));
+        self.function(parameters, body, *line, *column);
+      }
+      Stmt::Class {
+        name,
+        superclass,
+        methods,
+        ..
+      } => {
+        if let Some(superclass) = superclass {
+          self.expr(superclass);
+        }
+
+        self.declare(name, "class", None);
+
+        for method in methods {
+          if let Stmt::FunDeclaration {
+            parameters,
+            body,
+            line,
+            column,
+            ..
+          } = method
+          {
+            self.function(parameters, body, *line, *column);
+          }
+        }
+      }
+      Stmt::Block { statements } => {
+        self.begin_scope();
+        self.block(statements);
+        self.end_scope();
+      }
+      Stmt::While {
+        condition,
+        statement,
+        ..
+      } => {
+        self.expr(condition);
+        self.stmt(statement);
+      }
+      Stmt::Loop { body, .. } => self.stmt(body),
+      Stmt::Break { .. } | Stmt::Continue { .. } | Stmt::Trivia { .. } => {}
+      Stmt::For {
+        initializer,
+        condition,
+        increment,
+        body,
+        ..
+      } => {
+        self.begin_scope();
+
+        if let Some(initializer) = initializer {
+          self.stmt(initializer);
+        }
+
+        for expr in [condition, increment].into_iter().flatten() {
+          self.expr(expr);
+        }
+
+        self.stmt(body);
+        self.end_scope();
+      }
+      Stmt::ForIn {
+        variable,
+        iterable,
+        body,
+        ..
+      } => {
+        self.expr(iterable);
+        self.begin_scope();
+        self.declare(variable, "variable", None);
+        self.stmt(body);
+        self.end_scope();
+      }
+      Stmt::If {
+        condition,
+        true_case,
+        false_case,
+        ..
+      } => {
+        self.expr(condition);
+        self.stmt(true_case);
+
+        if let Some(false_case) = false_case {
+          self.stmt(false_case);
+        }
+      }
+      Stmt::Switch {
+        subject,
+        cases,
+        default,
+        ..
+      } => {
+        self.expr(subject);
+
+        for (values, body) in cases {
+          for value in values {
+            self.expr(value);
+          }
+
+          self.stmt(body);
+        }
+
+        if let Some(default) = default {
+          self.stmt(default);
+        }
+      }
+    }
+  }
+
+  fn expr(&mut self, expr: &Expr) {
+    match expr {
+      Expr::Literal {
+        value: Literal::Identifier { name, .. },
+      } => self.use_name(name),
+      Expr::Literal { .. } | Expr::Super { .. } => {}
+      // Assigning a variable does not count as using it
+      Expr::Assignment { expression, .. } => self.expr(expression),
+      Expr::Update { name, .. } => self.use_name(name),
+      Expr::Ternary {
+        conditional,
+        true_case,
+        false_case,
+      } => {
+        for expr in [conditional, true_case, false_case] {
+          self.expr(expr);
+        }
+      }
+      Expr::Binary { left, right, .. }
+      | Expr::Range {
+        start: left,
+        end: right,
+        ..
+      }
+      | Expr::Index {
+        object: left,
+        index: right,
+      }
+      | Expr::Set {
+        object: left,
+        value: right,
+        ..
+      } => {
+        self.expr(left);
+        self.expr(right);
+      }
+      Expr::Unary { expr, .. }
+      | Expr::Grouping { expr }
+      | Expr::Spread { expr }
+      | Expr::Get { object: expr, .. } => self.expr(expr),
+      Expr::Is {
+        expr, type_name, ..
+      } => {
+        self.expr(expr);
+        self.use_name(type_name);
+      }
+      Expr::Call {
+        function,
+        arguments,
+        ..
+      } => {
+        self.expr(function);
+
+        for argument in arguments {
+          self.expr(argument);
+        }
+      }
+      Expr::Tuple { elements }
+      | Expr::List { elements }
+      | Expr::Interpolation {
+        expressions: elements,
+        ..
+      } => {
+        for element in elements {
+          self.expr(element);
+        }
+      }
+      Expr::Map { entries } => {
+        for (key, value) in entries {
+          self.expr(key);
+          self.expr(value);
+        }
+      }
+      Expr::Slice { object, start, end } => {
+        self.expr(object);
+
+        for bound in [start, end].into_iter().flatten() {
+          self.expr(bound);
+        }
+      }
+      Expr::IndexSet {
+        object,
+        index,
+        value,
+      } => {
+        for expr in [object, index, value] {
+          self.expr(expr);
+        }
+      }
+    }
+  }
+}
+
+/// Parses `source` and runs the lints not allowed by `config` over it,
+/// returning what they found ordered by position.
+pub fn lint(source: &str, config: &LintConfig) -> Result<Vec<Diagnostic>> {
+  let tokens = Scanner::new(source.to_string()).collect::<Result<Vec<Token>>>()?;
+  let statements = Parser::new(tokens.clone()).parse()?;
+
+  let passes: Vec<Box<dyn LintPass>> = vec![
+    Box::new(UnusedVariable),
+    Box::new(UnreachableCode),
+    Box::new(ConstantCondition),
+    Box::new(ShadowedName),
+  ];
+
+  let mut walker = Walker {
+    passes: passes
+      .into_iter()
+      .filter(|pass| config.level(pass.lint()) != Level::Allow)
+      .collect(),
+    scopes: vec![vec![]],
+    tokens,
+    diagnostics: vec![],
+  };
+
+  // Top-level declarations may be used by other programs, so the top-level
+  // scope is never ended
+  walker.block(&statements);
+
+  let mut diagnostics = walker
+    .diagnostics
+    .into_iter()
+    .map(|(lint, span, message)| Diagnostic {
+      lint,
+      level: config.level(lint),
+      span,
+      message,
+    })
+    .collect::<Vec<Diagnostic>>();
+
+  diagnostics.sort_by_key(|diagnostic| (diagnostic.span.line, diagnostic.span.column));
+
+  Ok(diagnostics)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::diagnostics::DiagnosticFormat;
+
+  #[test]
+  fn test_lint() {
+    let source = r#"var x = 1;
+fun f(a, _b) {
+  var x = 2;
+  var unused = 3;
+  if (true) print x;
+  return a;
+  print a;
+}
+while (true) { x = x + 1; }
+fun g(n) { for (var i = 0; i < n; i = i + 1) {} }
+fun h(p, q) { var (r, s) = (p, 1); return r; }
+"#;
+
+    let messages = |config: &LintConfig| {
+      lint(source, config)
+        .unwrap()
+        .iter()
+        .map(|diagnostic| {
+          diagnostics::Diagnostic::from(diagnostic).render(DiagnosticFormat::Json, None)
+        })
+        .collect::<Vec<String>>()
+    };
+
+    assert_eq!(
+      messages(&LintConfig::default()),
+      [
+        r#"{"file":null,"span":{"line":3,"column":7,"length":1},"severity":"warning","code":"shadowed-name","message":"variable \"x\" shadows the one declared on line 1","notes":[]}"#,
+        r#"{"file":null,"span":{"line":4,"column":7,"length":6},"severity":"warning","code":"unused-variable","message":"variable \"unused\" is never used","notes":[]}"#,
+        r#"{"file":null,"span":{"line":5,"column":3,"length":2},"severity":"warning","code":"constant-condition","message":"'if' condition is always true","notes":[]}"#,
+        r#"{"file":null,"span":{"line":6,"column":3,"length":6},"severity":"warning","code":"unreachable-code","message":"code after this return is never run","notes":[]}"#,
+        r#"{"file":null,"span":{"line":9,"column":1,"length":5},"severity":"warning","code":"constant-condition","message":"'while' condition is always true, use 'loop'","notes":[]}"#,
+        r#"{"file":null,"span":{"line":11,"column":10,"length":1},"severity":"warning","code":"unused-variable","message":"parameter \"q\" is never used","notes":[]}"#,
+        r#"{"file":null,"span":{"line":11,"column":23,"length":1},"severity":"warning","code":"unused-variable","message":"variable \"s\" is never used","notes":[]}"#,
+      ]
+    );
+
+    let mut config = LintConfig::default();
+    config
+      .set_codes(&["constant-condition".to_string()], Level::Allow)
+      .unwrap();
+    config.set(Lint::UnusedVariable, Level::Deny);
+
+    let found = lint(source, &config).unwrap();
+
+    assert_eq!(
+      found
+        .iter()
+        .map(|diagnostic| (diagnostic.lint, diagnostic.level))
+        .collect::<Vec<_>>(),
+      [
+        (Lint::ShadowedName, Level::Warn),
+        (Lint::UnusedVariable, Level::Deny),
+        (Lint::UnreachableCode, Level::Warn),
+        (Lint::UnusedVariable, Level::Deny),
+        (Lint::UnusedVariable, Level::Deny),
+      ]
+    );
+    // Shown like the errors of a run, with the name marked
+    assert_eq!(
+      diagnostics::Diagnostic::from(&found[1]).render(DiagnosticFormat::Text, Some(source)),
+      r#"error[unused-variable]: variable "unused" is never used
+ --> line 4, column 7
+  |
+4 |   var unused = 3;
+  |       ^^^^^^"#
+    );
+    assert!(config
+      .set_codes(&["typo".to_string()], Level::Deny)
+      .is_err());
+  }
+}
@@ -0,0 +1,1111 @@
+use crate::errors::RuntimeError;
+use crate::interpreter::{
+  BoolValue, Callable, Interpreter, MapValue, NumberValue, StringValue, Value,
+};
+use crate::runner::Sink;
+use anyhow::{anyhow, Result};
+use std::cell::RefCell;
+use std::rc::Rc;
+use web_time::{SystemTime, UNIX_EPOCH};
+
+/// A named bundle of native functions that can be made available to
+/// programs as a whole.
+pub(crate) trait NativeModule {
+  fn name(&self) -> &'static str;
+
+  fn natives(&self) -> Vec<Box<dyn Callable>>;
+}
+
+type NativeFn = fn(&[Rc<Value>], &mut Interpreter) -> Result<Rc<Value>>;
+
+/// A native backed by a plain function, which keeps modules with many small
+/// natives free of boilerplate.
+pub(crate) struct NativeFunction {
+  name: &'static str,
+  function: NativeFn,
+}
+
+impl NativeFunction {
+  fn boxed(name: &'static str, function: NativeFn) -> Box<dyn Callable> {
+    Box::new(NativeFunction { name, function })
+  }
+}
+
+impl Callable for NativeFunction {
+  fn call(&self, arguments: Vec<Rc<Value>>, interpreter: &mut Interpreter) -> Result<Rc<Value>> {
+    (self.function)(&arguments, interpreter)
+  }
+
+  fn name(&self) -> &str {
+    self.name
+  }
+}
+
+pub(crate) struct NativeClock;
+
+impl Callable for NativeClock {
+  fn call(&self, _arguments: Vec<Rc<Value>>, _interpreter: &mut Interpreter) -> Result<Rc<Value>> {
+    let start = SystemTime::now();
+    let since_the_epoch = start
+      .duration_since(UNIX_EPOCH)
+      .expect("Time went backwards");
+    Ok(Rc::new(Value::Number(NumberValue(
+      since_the_epoch.as_secs_f64(),
+    ))))
+  }
+
+  fn name(&self) -> &str {
+    "clock"
+  }
+
+  fn arity(&self) -> Option<usize> {
+    Some(0)
+  }
+}
+
+/// Writes `arguments` shown with `show`, separated by spaces and followed by
+/// `end`.
+fn write_values(
+  sink: &Sink,
+  arguments: &[Rc<Value>],
+  show: fn(&Value) -> String,
+  end: &str,
+) -> Result<Rc<Value>> {
+  let mut sink = sink.borrow_mut();
+
+  write!(
+    sink,
+    "{}{}",
+    arguments
+      .iter()
+      .map(|value| show(value))
+      .collect::<Vec<String>>()
+      .join(" "),
+    end
+  )?;
+  sink.flush()?;
+
+  Ok(Rc::new(Value::Nil))
+}
+
+/// Fills the `{}` placeholders of `template` with the display form of
+/// `arguments`. A placeholder may hold a spec `{:[<|>][0][width][.precision]}`
+/// where precision only applies to numbers; `{{` and `}}` are literal braces.
+fn format_template(template: &str, arguments: &[Rc<Value>]) -> Result<String> {
+  let invalid = |message: &str| -> anyhow::Error {
+    RuntimeError::InvalidFormat {
+      message: message.to_string(),
+    }
+    .into()
+  };
+
+  let mut out = String::new();
+  let mut chars = template.chars().peekable();
+  let mut arguments = arguments.iter();
+
+  while let Some(char) = chars.next() {
+    match char {
+      '{' if chars.peek() == Some(&'{') => {
+        chars.next();
+        out.push('{');
+      }
+      '}' if chars.peek() == Some(&'}') => {
+        chars.next();
+        out.push('}');
+      }
+      '{' => {
+        let mut spec = String::new();
+
+        loop {
+          match chars.next() {
+            Some('}') => break,
+            Some(char) => spec.push(char),
+            None => return Err(invalid("unclosed '{'")),
+          }
+        }
+
+        let Some(value) = arguments.next() else {
+          return Err(invalid("more placeholders than arguments"));
+        };
+
+        let Some(spec) = spec.strip_prefix(':').or(spec.is_empty().then_some("")) else {
+          return Err(invalid(&format!("unknown placeholder '{{{}}}'", spec)));
+        };
+
+        out.push_str(&format_value(spec, value).map_err(|e| invalid(&e))?);
+      }
+      '}' => return Err(invalid("unmatched '}'")),
+      _ => out.push(char),
+    }
+  }
+
+  if arguments.next().is_some() {
+    return Err(invalid("more arguments than placeholders"));
+  }
+
+  Ok(out)
+}
+
+fn format_value(spec: &str, value: &Value) -> std::result::Result<String, String> {
+  let (align, spec) = match spec.chars().next() {
+    Some(align @ ('<' | '>')) => (Some(align), &spec[1..]),
+    _ => (None, spec),
+  };
+  let (zero_pad, spec) = match spec.strip_prefix('0') {
+    Some(rest) => (true, rest),
+    None => (false, spec),
+  };
+  let (width, precision) = match spec.split_once('.') {
+    Some((width, precision)) => (width, Some(precision)),
+    None => (spec, None),
+  };
+
+  let width = match width {
+    "" => 0,
+    width => width
+      .parse::<usize>()
+      .map_err(|_| format!("invalid width '{}'", width))?,
+  };
+
+  let text = match (value, precision) {
+    (Value::Number(number), Some(precision)) => {
+      let precision = precision
+        .parse::<usize>()
+        .map_err(|_| format!("invalid precision '{}'", precision))?;
+
+      format!("{:.*}", precision, number.0)
+    }
+    (_, Some(_)) => return Err(format!("precision given for a {}", value.type_as_string())),
+    _ => value.to_string(),
+  };
+
+  let is_number = matches!(value, Value::Number(_));
+  let padding = width.saturating_sub(text.chars().count());
+
+  Ok(if zero_pad && is_number {
+    let (sign, digits) = match text.strip_prefix('-') {
+      Some(digits) => ("-", digits),
+      None => ("", text.as_str()),
+    };
+
+    format!("{}{}{}", sign, "0".repeat(padding), digits)
+  } else if align.unwrap_or(if is_number { '>' } else { '<' }) == '>' {
+    format!("{}{}", " ".repeat(padding), text)
+  } else {
+    format!("{}{}", text, " ".repeat(padding))
+  })
+}
+
+/// Formats `epoch` milliseconds as UTC time. Supports `%Y`, `%m`, `%d`,
+/// `%H`, `%M`, `%S`, `%L` (milliseconds) and `%%`.
+fn format_time(epoch: f64, format: &str) -> Result<String> {
+  let millis = epoch.floor() as i64;
+  let days = millis.div_euclid(86_400_000);
+  let millis_of_day = millis.rem_euclid(86_400_000);
+  let (year, month, day) = civil_from_days(days);
+
+  let mut out = String::new();
+  let mut chars = format.chars();
+
+  while let Some(char) = chars.next() {
+    if char != '%' {
+      out.push(char);
+      continue;
+    }
+
+    match chars.next() {
+      Some('Y') => out.push_str(&format!("{:04}", year)),
+      Some('m') => out.push_str(&format!("{:02}", month)),
+      Some('d') => out.push_str(&format!("{:02}", day)),
+      Some('H') => out.push_str(&format!("{:02}", millis_of_day / 3_600_000)),
+      Some('M') => out.push_str(&format!("{:02}", millis_of_day / 60_000 % 60)),
+      Some('S') => out.push_str(&format!("{:02}", millis_of_day / 1000 % 60)),
+      Some('L') => out.push_str(&format!("{:03}", millis_of_day % 1000)),
+      Some('%') => out.push('%'),
+      specifier => {
+        return Err(
+          RuntimeError::InvalidFormat {
+            message: format!("unknown time specifier '%{}'", specifier.unwrap_or(' ')),
+          }
+          .into(),
+        )
+      }
+    }
+  }
+
+  Ok(out)
+}
+
+/// Converts days since 1970-01-01 to a (year, month, day) date in the
+/// proleptic Gregorian calendar.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+  let days = days + 719_468;
+  let era = days.div_euclid(146_097);
+  let day_of_era = days.rem_euclid(146_097);
+  let year_of_era =
+    (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+  let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+  let month_index = (5 * day_of_year + 2) / 153;
+  let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+  let month = if month_index < 10 {
+    month_index + 3
+  } else {
+    month_index - 9
+  };
+  let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+  (year, month, day)
+}
+
+/// Natives that are always available.
+struct CoreModule;
+
+impl NativeModule for CoreModule {
+  fn name(&self) -> &'static str {
+    "core"
+  }
+
+  fn natives(&self) -> Vec<Box<dyn Callable>> {
+    vec![
+      Box::new(NativeClock),
+      NativeFunction::boxed("println", |arguments, interpreter| {
+        write_values(&interpreter.stdout, arguments, Value::to_string, "\n")
+      }),
+      NativeFunction::boxed("write", |arguments, interpreter| {
+        write_values(&interpreter.stdout, arguments, Value::to_string, "")
+      }),
+      NativeFunction::boxed("eprintln", |arguments, interpreter| {
+        write_values(&interpreter.stderr, arguments, Value::to_string, "\n")
+      }),
+      NativeFunction::boxed("debug", |arguments, interpreter| {
+        write_values(&interpreter.stdout, arguments, Value::debug_string, "\n")
+      }),
+      NativeFunction::boxed("now", |_, _| {
+        let since_the_epoch = SystemTime::now()
+          .duration_since(UNIX_EPOCH)
+          .expect("Time went backwards");
+
+        Ok(Rc::new(Value::Number(NumberValue(
+          since_the_epoch.as_millis() as f64,
+        ))))
+      }),
+      NativeFunction::boxed("elapsed", |_, interpreter| {
+        Ok(Rc::new(Value::Number(NumberValue(
+          interpreter.started.elapsed().as_secs_f64() * 1000.0,
+        ))))
+      }),
+      NativeFunction::boxed("args", |_, interpreter| {
+        let args = interpreter
+          .args
+          .iter()
+          .map(|arg| Rc::new(Value::String(StringValue(arg.clone()))))
+          .collect();
+
+        Ok(Rc::new(Value::List(RefCell::new(args))))
+      }),
+      NativeFunction::boxed("formatTime", |arguments, _| {
+        let epoch = number_argument(arguments, 0)?;
+        let format = string_argument(arguments, 1)?;
+
+        Ok(Rc::new(Value::String(StringValue(format_time(
+          epoch, format,
+        )?))))
+      }),
+      NativeFunction::boxed("format", |arguments, _| {
+        let template = string_argument(arguments, 0)?;
+        let formatted = format_template(template, &arguments[1..])?;
+
+        Ok(Rc::new(Value::String(StringValue(formatted))))
+      }),
+      NativeFunction::boxed("len", |arguments, _| {
+        let length = match arguments.first().map(Rc::as_ref) {
+          Some(Value::List(elements)) => elements.borrow().len(),
+          Some(Value::Tuple(elements)) => elements.len(),
+          Some(Value::String(value)) => value.0.chars().count(),
+          Some(Value::Map(map)) => map.borrow().entries().len(),
+          other => return Err(argument_type_error("list, tuple, string or map", other)),
+        };
+
+        Ok(Rc::new(Value::Number(NumberValue(length as f64))))
+      }),
+      NativeFunction::boxed("push", |arguments, _| {
+        let list = list_argument(arguments, 0)?;
+
+        list.borrow_mut().extend(arguments[1..].iter().cloned());
+
+        Ok(Rc::new(Value::Nil))
+      }),
+      NativeFunction::boxed("pop", |arguments, _| {
+        let list = list_argument(arguments, 0)?;
+        let last = list.borrow_mut().pop();
+
+        last.ok_or_else(|| RuntimeError::PopFromEmptyList.into())
+      }),
+      NativeFunction::boxed("map", |arguments, interpreter| {
+        let elements = list_argument(arguments, 0)?.borrow().clone();
+        let mut mapped = vec![];
+
+        for element in elements {
+          mapped.push(call_argument(arguments, 1, element, interpreter)?);
+        }
+
+        Ok(Rc::new(Value::List(RefCell::new(mapped))))
+      }),
+      NativeFunction::boxed("filter", |arguments, interpreter| {
+        let elements = list_argument(arguments, 0)?.borrow().clone();
+        let mut kept = vec![];
+
+        for element in elements {
+          if call_argument(arguments, 1, Rc::clone(&element), interpreter)?.is_truthy() {
+            kept.push(element);
+          }
+        }
+
+        Ok(Rc::new(Value::List(RefCell::new(kept))))
+      }),
+      NativeFunction::boxed("sort", |arguments, _| {
+        let mut elements = list_argument(arguments, 0)?.borrow_mut();
+
+        if elements
+          .iter()
+          .all(|element| matches!(element.as_ref(), Value::Number(_)))
+        {
+          elements.sort_by(|a, b| match (a.as_ref(), b.as_ref()) {
+            (Value::Number(a), Value::Number(b)) => a.0.total_cmp(&b.0),
+            _ => unreachable!(),
+          });
+        } else if elements
+          .iter()
+          .all(|element| matches!(element.as_ref(), Value::String(_)))
+        {
+          elements.sort_by(|a, b| match (a.as_ref(), b.as_ref()) {
+            (Value::String(a), Value::String(b)) => a.0.cmp(&b.0),
+            _ => unreachable!(),
+          });
+        } else {
+          return Err(
+            RuntimeError::TypeError {
+              expected: "list of numbers or list of strings".to_string(),
+              given: "list of mixed values".to_string(),
+            }
+            .into(),
+          );
+        }
+
+        Ok(Rc::new(Value::Nil))
+      }),
+      NativeFunction::boxed("keys", |arguments, _| {
+        let map = map_argument(arguments, 0)?.borrow();
+        let keys = map
+          .entries()
+          .iter()
+          .map(|(key, _)| Rc::clone(key))
+          .collect();
+
+        Ok(Rc::new(Value::List(RefCell::new(keys))))
+      }),
+      NativeFunction::boxed("values", |arguments, _| {
+        let map = map_argument(arguments, 0)?.borrow();
+        let values = map
+          .entries()
+          .iter()
+          .map(|(_, value)| Rc::clone(value))
+          .collect();
+
+        Ok(Rc::new(Value::List(RefCell::new(values))))
+      }),
+      NativeFunction::boxed("has", |arguments, _| {
+        let map = map_argument(arguments, 0)?.borrow();
+        let key = arguments.get(1).map_or(&Value::Nil, Rc::as_ref);
+
+        Ok(Rc::new(Value::Bool(BoolValue(map.get(key)?.is_some()))))
+      }),
+      NativeFunction::boxed("remove", |arguments, _| {
+        let mut map = map_argument(arguments, 0)?.borrow_mut();
+        let key = arguments.get(1).map_or(&Value::Nil, Rc::as_ref);
+
+        Ok(map.remove(key)?.unwrap_or_else(|| Rc::new(Value::Nil)))
+      }),
+    ]
+  }
+}
+
+/// A method of a string, like `split` in `"a,b".split(",")`. The native
+/// gets the string as its first argument.
+struct StringMethod {
+  string: Rc<Value>,
+  name: &'static str,
+  /// Arguments besides the string
+  arity: usize,
+  function: NativeFn,
+}
+
+impl Callable for StringMethod {
+  fn call(&self, arguments: Vec<Rc<Value>>, interpreter: &mut Interpreter) -> Result<Rc<Value>> {
+    if arguments.len() != self.arity {
+      return Err(
+        RuntimeError::ArityMismatch {
+          expected: self.arity,
+          given: arguments.len(),
+          name: self.name.to_string(),
+        }
+        .into(),
+      );
+    }
+
+    let arguments = [vec![Rc::clone(&self.string)], arguments].concat();
+
+    (self.function)(&arguments, interpreter)
+  }
+
+  fn name(&self) -> &str {
+    self.name
+  }
+
+  fn arity(&self) -> Option<usize> {
+    Some(self.arity)
+  }
+}
+
+/// The method `name` of `string` bound to it, if strings have one.
+pub(crate) fn string_method(string: &Rc<Value>, name: &str) -> Option<Box<dyn Callable>> {
+  let (name, arity, function): (&'static str, usize, NativeFn) = match name {
+    "len" => ("len", 0, |arguments, _| {
+      let length = string_argument(arguments, 0)?.chars().count();
+
+      Ok(Rc::new(Value::Number(NumberValue(length as f64))))
+    }),
+    "trim" => ("trim", 0, |arguments, _| {
+      string_result(string_argument(arguments, 0)?.trim())
+    }),
+    "upper" => ("upper", 0, |arguments, _| {
+      string_result(&string_argument(arguments, 0)?.to_uppercase())
+    }),
+    "lower" => ("lower", 0, |arguments, _| {
+      string_result(&string_argument(arguments, 0)?.to_lowercase())
+    }),
+    "split" => ("split", 1, |arguments, _| {
+      let string = string_argument(arguments, 0)?;
+      let separator = string_argument(arguments, 1)?;
+      // An empty separator splits the string into its characters
+      let parts: Vec<String> = if separator.is_empty() {
+        string.chars().map(String::from).collect()
+      } else {
+        string.split(separator).map(String::from).collect()
+      };
+
+      Ok(Rc::new(Value::List(RefCell::new(
+        parts
+          .into_iter()
+          .map(|part| Rc::new(Value::String(StringValue(part))))
+          .collect(),
+      ))))
+    }),
+    "replace" => ("replace", 2, |arguments, _| {
+      let string = string_argument(arguments, 0)?;
+
+      string_result(&string.replace(
+        string_argument(arguments, 1)?,
+        string_argument(arguments, 2)?,
+      ))
+    }),
+    "contains" => ("contains", 1, |arguments, _| {
+      let string = string_argument(arguments, 0)?;
+
+      bool_result(string.contains(string_argument(arguments, 1)?))
+    }),
+    "startsWith" => ("startsWith", 1, |arguments, _| {
+      let string = string_argument(arguments, 0)?;
+
+      bool_result(string.starts_with(string_argument(arguments, 1)?))
+    }),
+    "endsWith" => ("endsWith", 1, |arguments, _| {
+      let string = string_argument(arguments, 0)?;
+
+      bool_result(string.ends_with(string_argument(arguments, 1)?))
+    }),
+    _ => return None,
+  };
+
+  Some(Box::new(StringMethod {
+    string: Rc::clone(string),
+    name,
+    arity,
+    function,
+  }))
+}
+
+fn string_result(string: &str) -> Result<Rc<Value>> {
+  Ok(Rc::new(Value::String(StringValue(string.to_string()))))
+}
+
+fn bool_result(value: bool) -> Result<Rc<Value>> {
+  Ok(Rc::new(Value::Bool(BoolValue(value))))
+}
+
+#[cfg(feature = "math")]
+struct MathModule;
+
+#[cfg(feature = "math")]
+impl NativeModule for MathModule {
+  fn name(&self) -> &'static str {
+    "math"
+  }
+
+  fn natives(&self) -> Vec<Box<dyn Callable>> {
+    macro_rules! unary {
+      ($name:expr, $op:expr) => {
+        NativeFunction::boxed($name, |arguments, _| {
+          let x = number_argument(arguments, 0)?;
+
+          Ok(Rc::new(Value::Number(NumberValue($op(x)))))
+        })
+      };
+    }
+
+    macro_rules! binary {
+      ($name:expr, $op:expr) => {
+        NativeFunction::boxed($name, |arguments, _| {
+          let x = number_argument(arguments, 0)?;
+          let y = number_argument(arguments, 1)?;
+
+          Ok(Rc::new(Value::Number(NumberValue($op(x, y)))))
+        })
+      };
+    }
+
+    vec![
+      unary!("abs", f64::abs),
+      unary!("sqrt", f64::sqrt),
+      unary!("floor", f64::floor),
+      unary!("ceil", f64::ceil),
+      unary!("round", f64::round),
+      binary!("pow", f64::powf),
+      binary!("min", f64::min),
+      binary!("max", f64::max),
+    ]
+  }
+}
+
+#[cfg(feature = "fs")]
+struct FsModule;
+
+#[cfg(feature = "fs")]
+impl NativeModule for FsModule {
+  fn name(&self) -> &'static str {
+    "fs"
+  }
+
+  fn natives(&self) -> Vec<Box<dyn Callable>> {
+    vec![
+      NativeFunction::boxed("readFile", |arguments, _| {
+        let path = string_argument(arguments, 0)?;
+        let contents = std::fs::read_to_string(path)?;
+
+        Ok(Rc::new(Value::String(StringValue(contents))))
+      }),
+      NativeFunction::boxed("writeFile", |arguments, _| {
+        let path = string_argument(arguments, 0)?;
+        let contents = string_argument(arguments, 1)?;
+        std::fs::write(path, contents)?;
+
+        Ok(Rc::new(Value::Nil))
+      }),
+      NativeFunction::boxed("fileExists", |arguments, _| {
+        let path = string_argument(arguments, 0)?;
+
+        Ok(Rc::new(Value::Bool(BoolValue(
+          std::path::Path::new(path).exists(),
+        ))))
+      }),
+    ]
+  }
+}
+
+#[cfg(feature = "json")]
+struct JsonModule;
+
+#[cfg(feature = "json")]
+impl NativeModule for JsonModule {
+  fn name(&self) -> &'static str {
+    "json"
+  }
+
+  fn natives(&self) -> Vec<Box<dyn Callable>> {
+    vec![
+      NativeFunction::boxed("parseJson", |arguments, _| {
+        let text = string_argument(arguments, 0)?;
+        let json = serde_json::from_str(text).map_err(|e| RuntimeError::InvalidJson {
+          message: e.to_string(),
+        })?;
+
+        from_json(json)
+      }),
+      NativeFunction::boxed("toJson", |arguments, _| {
+        let value = arguments.first().map_or(&Value::Nil, Rc::as_ref);
+        let json = to_json(value, &mut vec![])?;
+
+        Ok(Rc::new(Value::String(StringValue(json.to_string()))))
+      }),
+    ]
+  }
+}
+
+/// Converts parsed JSON to a value: arrays become lists and objects maps,
+/// with their keys sorted.
+#[cfg(feature = "json")]
+fn from_json(json: serde_json::Value) -> Result<Rc<Value>> {
+  use serde_json::Value as Json;
+
+  let value = match json {
+    Json::Null => Value::Nil,
+    Json::Bool(value) => Value::Bool(BoolValue(value)),
+    Json::Number(number) => Value::Number(NumberValue(number.as_f64().unwrap_or(f64::NAN))),
+    Json::String(value) => Value::String(StringValue(value)),
+    Json::Array(elements) => Value::List(RefCell::new(
+      elements
+        .into_iter()
+        .map(from_json)
+        .collect::<Result<Vec<Rc<Value>>>>()?,
+    )),
+    Json::Object(entries) => {
+      let mut map = MapValue::default();
+
+      for (key, value) in entries {
+        map.insert(Rc::new(Value::String(StringValue(key))), from_json(value)?)?;
+      }
+
+      Value::Map(RefCell::new(map))
+    }
+  };
+
+  Ok(Rc::new(value))
+}
+
+/// Converts `value` to JSON. Only nil, booleans, finite numbers, strings,
+/// lists, tuples and maps with string keys can be converted; `seen` holds the
+/// lists and maps `value` is nested in, which it must not contain again.
+#[cfg(feature = "json")]
+fn to_json(value: &Value, seen: &mut Vec<*const Value>) -> Result<serde_json::Value> {
+  use serde_json::Value as Json;
+
+  let error = |message: String| RuntimeError::NotJsonConvertible { message }.into();
+
+  let is_container = matches!(value, Value::List(_) | Value::Map(_));
+
+  if is_container {
+    if seen.contains(&(value as *const Value)) {
+      return Err(error("a list or map containing itself".to_string()));
+    }
+
+    seen.push(value);
+  }
+
+  let json = match value {
+    Value::Nil => Json::Null,
+    Value::Bool(value) => Json::Bool(value.0),
+    // Whole numbers are written without a fraction, as Lox prints them
+    Value::Number(NumberValue(number)) if number.fract() == 0. && number.abs() < 2f64.powi(53) => {
+      Json::from(*number as i64)
+    }
+    Value::Number(NumberValue(number)) => serde_json::Number::from_f64(*number)
+      .map(Json::Number)
+      .ok_or_else(|| error(format!("the number {}", number)))?,
+    Value::String(value) => Json::String(value.0.clone()),
+    Value::List(elements) => Json::Array(
+      elements
+        .borrow()
+        .iter()
+        .map(|element| to_json(element, seen))
+        .collect::<Result<Vec<Json>>>()?,
+    ),
+    Value::Tuple(elements) => Json::Array(
+      elements
+        .iter()
+        .map(|element| to_json(element, seen))
+        .collect::<Result<Vec<Json>>>()?,
+    ),
+    Value::Map(map) => {
+      let mut object = serde_json::Map::new();
+
+      for (key, value) in map.borrow().entries() {
+        let Value::String(key) = key.as_ref() else {
+          return Err(error(format!("a map with a {} key", key.type_as_string())));
+        };
+
+        object.insert(key.0.clone(), to_json(value, seen)?);
+      }
+
+      Json::Object(object)
+    }
+    value => return Err(error(format!("a {}", value.type_as_string()))),
+  };
+
+  if is_container {
+    seen.pop();
+  }
+
+  Ok(json)
+}
+
+/// Optional modules compiled into this build.
+#[allow(unused_mut, clippy::vec_init_then_push)]
+fn optional_modules() -> Vec<Box<dyn NativeModule>> {
+  let mut modules: Vec<Box<dyn NativeModule>> = vec![];
+
+  #[cfg(feature = "math")]
+  modules.push(Box::new(MathModule));
+
+  #[cfg(feature = "fs")]
+  modules.push(Box::new(FsModule));
+
+  #[cfg(feature = "json")]
+  modules.push(Box::new(JsonModule));
+
+  modules
+}
+
+/// Names of the optional native modules that can be allowed in this build.
+pub fn available_modules() -> Vec<&'static str> {
+  optional_modules()
+    .iter()
+    .map(|module| module.name())
+    .collect()
+}
+
+/// Returns the core natives followed by the natives of every allowed module.
+pub(crate) fn load(allowed: &[String]) -> Result<Vec<Box<dyn Callable>>> {
+  let modules = optional_modules();
+  let mut natives = CoreModule.natives();
+
+  for name in allowed {
+    let Some(module) = modules.iter().find(|module| module.name() == name) else {
+      return Err(anyhow!(
+        "unknown native module `{}` (available: {})",
+        name,
+        available_modules().join(", ")
+      ));
+    };
+
+    natives.extend(module.natives());
+  }
+
+  Ok(natives)
+}
+
+fn number_argument(arguments: &[Rc<Value>], index: usize) -> Result<f64> {
+  match arguments.get(index).map(Rc::as_ref) {
+    Some(Value::Number(value)) => Ok(value.0),
+    other => Err(argument_type_error("number", other)),
+  }
+}
+
+fn string_argument(arguments: &[Rc<Value>], index: usize) -> Result<&str> {
+  match arguments.get(index).map(Rc::as_ref) {
+    Some(Value::String(value)) => Ok(&value.0),
+    other => Err(argument_type_error("string", other)),
+  }
+}
+
+fn list_argument(arguments: &[Rc<Value>], index: usize) -> Result<&RefCell<Vec<Rc<Value>>>> {
+  match arguments.get(index).map(Rc::as_ref) {
+    Some(Value::List(elements)) => Ok(elements),
+    other => Err(argument_type_error("list", other)),
+  }
+}
+
+fn map_argument(arguments: &[Rc<Value>], index: usize) -> Result<&RefCell<MapValue>> {
+  match arguments.get(index).map(Rc::as_ref) {
+    Some(Value::Map(map)) => Ok(map),
+    other => Err(argument_type_error("map", other)),
+  }
+}
+
+/// Calls the function passed as argument `index` with a single `value`.
+fn call_argument(
+  arguments: &[Rc<Value>],
+  index: usize,
+  value: Rc<Value>,
+  interpreter: &mut Interpreter,
+) -> Result<Rc<Value>> {
+  match arguments.get(index).map(Rc::as_ref) {
+    Some(Value::Function(callable)) => callable.call(vec![value], interpreter),
+    other => Err(argument_type_error("function", other)),
+  }
+}
+
+fn argument_type_error(expected: &str, given: Option<&Value>) -> anyhow::Error {
+  RuntimeError::TypeError {
+    expected: expected.to_string(),
+    given: given.map_or("nothing".to_string(), Value::type_as_string),
+  }
+  .into()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::runner::{run_with_options, RunOptions};
+  use std::cell::RefCell;
+
+  /// Runs `source` with `options`, returning what it wrote to stdout and to
+  /// stderr.
+  fn output(source: &str, options: RunOptions) -> (String, String) {
+    let stdout = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let stderr = Rc::new(RefCell::new(Vec::<u8>::new()));
+
+    run_with_options(
+      source.to_string(),
+      &RunOptions {
+        stdout: stdout.clone(),
+        stderr: stderr.clone(),
+        ..options
+      },
+    )
+    .unwrap();
+
+    (
+      String::from_utf8(stdout.take()).unwrap(),
+      String::from_utf8(stderr.take()).unwrap(),
+    )
+  }
+
+  #[test]
+  fn test_load() {
+    let names = |natives: Vec<Box<dyn Callable>>| {
+      natives
+        .iter()
+        .map(|native| native.name().to_string())
+        .collect::<Vec<String>>()
+    };
+
+    assert_eq!(
+      names(load(&[]).unwrap()),
+      vec![
+        "clock",
+        "println",
+        "write",
+        "eprintln",
+        "debug",
+        "now",
+        "elapsed",
+        "args",
+        "formatTime",
+        "format",
+        "len",
+        "push",
+        "pop",
+        "map",
+        "filter",
+        "sort",
+        "keys",
+        "values",
+        "has",
+        "remove"
+      ]
+    );
+    assert!(load(&["nope".to_string()]).is_err());
+
+    #[cfg(feature = "math")]
+    assert!(names(load(&["math".to_string()]).unwrap()).contains(&"sqrt".to_string()));
+  }
+
+  #[test]
+  fn test_format_template() {
+    let number = |n: f64| Rc::new(Value::Number(NumberValue(n)));
+    let string = |s: &str| Rc::new(Value::String(StringValue(s.to_string())));
+
+    let cases = [
+      (
+        "{} + {} = {}",
+        vec![number(1.), number(2.), number(3.)],
+        "1 + 2 = 3",
+      ),
+      ("{:.2}", vec![number(1.23456)], "1.23"),
+      ("[{:5}]", vec![number(42.)], "[   42]"),
+      ("[{:<5}]", vec![number(42.)], "[42   ]"),
+      ("[{:06.1}]", vec![number(-2.5)], "[-002.5]"),
+      (
+        "[{:4}|{:>4}]",
+        vec![string("ab"), string("cd")],
+        "[ab  |  cd]",
+      ),
+      ("{{{}}}", vec![string("x")], "{x}"),
+    ];
+
+    for (template, arguments, expected) in cases {
+      assert_eq!(format_template(template, &arguments).unwrap(), expected);
+    }
+
+    assert!(format_template("{}", &[]).is_err());
+    assert!(format_template("{}", &[number(1.), number(2.)]).is_err());
+    assert!(format_template("{:.2}", &[string("x")]).is_err());
+    assert!(format_template("{x}", &[number(1.)]).is_err());
+    assert!(format_template("{", &[number(1.)]).is_err());
+  }
+
+  #[test]
+  fn test_format_time() {
+    assert_eq!(
+      format_time(0., "%Y-%m-%d %H:%M:%S.%L").unwrap(),
+      "1970-01-01 00:00:00.000"
+    );
+    assert_eq!(
+      format_time(1_709_210_096_789., "%d/%m/%Y %H:%M:%S.%L 100%%").unwrap(),
+      "29/02/2024 12:34:56.789 100%"
+    );
+    assert_eq!(format_time(-1., "%Y-%m-%d %L").unwrap(), "1969-12-31 999");
+    assert!(format_time(0., "%Q").is_err());
+  }
+
+  #[test]
+  fn test_list_natives() {
+    let (stdout, _) = output(
+      r#"
+        var list = [3, 1, 2];
+        var alias = list;
+        push(alias, 10, 0);
+        write(len(list), pop(list), len("żółw"));
+        fun double(x) { return x * 2; }
+        fun isBig(x) { return x > 2; }
+        write("", map(list, double), filter(list, isBig));
+        var words = ["b", "c", "a"];
+        sort(list); sort(words);
+        write("", list, words);
+      "#,
+      RunOptions::default(),
+    );
+
+    assert_eq!(
+      stdout,
+      "5 0 4 [6, 2, 4, 20] [3, 10] [1, 2, 3, 10] [a, b, c]"
+    );
+
+    for source in [
+      "pop([]);",
+      "sort([1, \"a\"]);",
+      "map([1], 2);",
+      "push((1, 2), 3);",
+    ] {
+      assert!(run_with_options(source.to_string(), &RunOptions::default()).is_err());
+    }
+  }
+
+  #[test]
+  fn test_string_methods() {
+    let (stdout, _) = output(
+      r#"
+        var csv = " a,b,,c ";
+        write("żółw".len(), csv.trim().split(","), "ab".split(""), csv.len());
+        write("", "Hi".upper(), "Hi".lower(), "a-b-c".replace("-", "+"));
+        write("", "hello".contains("ell"), "hello".startsWith("he"), "hello".endsWith("x"));
+        var trim = csv.trim;
+        write("", trim(), trim);
+      "#,
+      RunOptions::default(),
+    );
+
+    assert_eq!(
+      stdout,
+      "4 [a, b, , c] [a, b] 8 HI hi a+b+c true true false a,b,,c <fn trim>"
+    );
+
+    for source in [
+      "\"a\".missing();",
+      "\"a\".trim(1);",
+      "\"a\".split(1);",
+      "1.len();",
+    ] {
+      assert!(run_with_options(source.to_string(), &RunOptions::default()).is_err());
+    }
+  }
+
+  #[test]
+  fn test_map_natives() {
+    let (stdout, _) = output(
+      r#"
+        var ages = {"ann": 31, "bob": 25, "cid": 40};
+        write(len(ages), keys(ages), values(ages), has(ages, "bob"), has(ages, 1));
+        write("", remove(ages, "bob"), remove(ages, "bob"), ages);
+      "#,
+      RunOptions::default(),
+    );
+
+    assert_eq!(
+      stdout,
+      "3 [ann, bob, cid] [31, 25, 40] true false 25 nil {ann: 31, cid: 40}"
+    );
+
+    for source in ["keys([]);", "has({}, []);"] {
+      assert!(run_with_options(source.to_string(), &RunOptions::default()).is_err());
+    }
+  }
+
+  #[cfg(all(feature = "json", feature = "math"))]
+  #[test]
+  fn test_json_natives() {
+    let options = || RunOptions {
+      allowed_modules: vec!["json".to_string(), "math".to_string()],
+      ..RunOptions::default()
+    };
+    let (stdout, _) = output(
+      r#"
+        var data = parseJson("{\"b\": [1, 2.5, null], \"a\": {\"ok\": true}, \"s\": \"\\u00e9\"}");
+        write(data["a"]["ok"], data["b"], data["s"], len(data));
+        write("", toJson({"n": 1, "list": [nil, false, "x\"y"]}));
+        write("", toJson(parseJson("[0.5, -3, {}]")));
+      "#,
+      options(),
+    );
+
+    assert_eq!(
+      stdout,
+      r#"true [1, 2.5, nil] é 3 {"list":[null,false,"x\"y"],"n":1} [0.5,-3,{}]"#
+    );
+
+    for source in [
+      "parseJson(\"[1,\");",
+      "toJson({1: 2});",
+      "toJson(clock);",
+      "toJson(sqrt(-1));",
+      "var a = [1]; push(a, a); toJson(a);",
+    ] {
+      assert!(run_with_options(source.to_string(), &options()).is_err());
+    }
+
+    assert!(run_with_options("toJson(1);".to_string(), &RunOptions::default()).is_err());
+  }
+
+  #[test]
+  fn test_output_natives() {
+    let (stdout, stderr) = output(
+      r#"
+        write("a", 1); write("b"); println(); eprintln("oops", true);
+        fun add(a, b) {}
+        debug("C:\dir
+", 1, nil, add, println);
+      "#,
+      RunOptions::default(),
+    );
+
+    assert_eq!(
+      stdout,
+      "a 1b\n\"C:\\\\dir\\n\" 1 nil <fn add/2> <fn println/*>\n"
+    );
+    assert_eq!(stderr, "oops true\n");
+  }
+
+  #[test]
+  fn test_args() {
+    let options = RunOptions {
+      args: vec!["a".to_string(), "b c".to_string()],
+      ..RunOptions::default()
+    };
+
+    assert_eq!(
+      output("write(args(), len(args()));", options).0,
+      "[a, b c] 2"
+    );
+    assert_eq!(output("write(args());", RunOptions::default()).0, "[]");
+  }
+}
@@ -1,41 +1,49 @@
 // Syntax grammar:
 // program       -> declaration* EOF
-// declaration   -> varDecl | statement
+// declaration   -> varDecl | funDecl | classDecl | statement
+// classDecl     -> "class" IDENTIFIER ( "<" IDENTIFIER )? "{" function* "}"
 // funDecl       -> "fun" function
 // function      -> IDENTIFIER "(" parameters? ")" block
-// parameters    -> IDENTIFIER ("," IDENTIFIER)*
-// varDecl       -> "var" IDENTIFIER ("=" expression)? ";"
-// statement     -> exprStmt | block | while | if
+// parameters    -> IDENTIFIER ("," IDENTIFIER)* ","?
+// varDecl       -> "var" ( IDENTIFIER | "(" IDENTIFIER ("," IDENTIFIER)* ")" ) "=" expression ";"
+// statement     -> exprStmt | printStmt | assertStmt | returnStmt | breakStmt | continueStmt | block | labeled | while | loop | if | for | forIn
+// labeled       -> IDENTIFIER ":" ( while | loop | for | forIn )
+// printStmt     -> "print" expression ";"
+// assertStmt    -> "assert" assignment ( "," assignment )? ";"
+// returnStmt    -> "return" tuple? ";"
+// tuple         -> assignment ("," assignment)*
 // while         -> "while" "(" expression ")" block
+// loop          -> "loop" block
+// breakStmt     -> "break" IDENTIFIER? ";"
+// continueStmt  -> "continue" IDENTIFIER? ";"
+// for           -> "for" "(" ( varDecl | exprStmt | ";" ) expression? ";" expression? ")" block
+// forIn         -> "for" "(" IDENTIFIER "in" expression ")" block
 // if            -> "if" "(" expression ")" block ("else" block)?
 // block         -> "{" declaration* "}"
 // exprStmt      -> expression ";"
 // expression    -> comma;
 // comma         -> assignment ("," assignment)*
-// assignment    -> IDENTIFIER "=" assignment | logical_or;
+// assignment    -> ( call "." )? IDENTIFIER "=" assignment | logical_or;
 // logical_or    -> logical_and ("or" logical_and)*
 // logical_and   -> ternary ("and" ternary)*
 // ternary       -> equality ("?" equality ":" ternary)?
 // equality      -> comparison (("==" | "!=") comparison)*
-// comparison    -> term ( ( ">" | ">=" | "<" | "<=" ) term )* ;
+// comparison    -> range ( ( ">" | ">=" | "<" | "<=" | "in" ) range | "is" IDENTIFIER )* ;
+// range         -> term ( ( ".." | "..=" ) term )? ;
 // term          -> factor ( ( "-" | "+" ) factor )* ;
 // factor        -> unary ( ( "/" | "*" ) unary )* ;
 // unary         -> ( "!" | "-" ) unary | call ;
-// call          -> primary ("(" arguments ")")*
-// arguments     -> expression ("," expression)*
-// primary       -> IDENTIFIER | NUMBER | STRING | "true" | "false" | "nil" | "(" expression ")" ;
+// call          -> primary ( "(" arguments ")" | "." IDENTIFIER )*
+// arguments     -> argument ("," argument)* ","?
+// argument      -> "..."? assignment
+// primary       -> IDENTIFIER | NUMBER | STRING | "true" | "false" | "nil" | "this" | "super" "." IDENTIFIER | "(" expression ")" ;
 
-use crate::errors::SyntaxError;
+use crate::errors::{LocatedSyntaxError, SyntaxError, SyntaxErrors};
 use anyhow::Result;
-use scanner::{Token, TokenType};
-use std::sync::atomic::{AtomicUsize, Ordering};
+use scanner::{Token, TokenType, Trivia};
+use serde::{Deserialize, Serialize};
 
-static COUNTER: AtomicUsize = AtomicUsize::new(1);
-fn get_id() -> usize {
-  COUNTER.fetch_add(1, Ordering::Relaxed)
-}
-
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub(crate) enum BinaryOperator {
   EqualEqual,
   BangEqual,
@@ -47,28 +55,51 @@ pub(crate) enum BinaryOperator {
   GreaterEqual,
   Less,
   LessEqual,
+  In,
   Comma,
   Or,
   And,
+  BitAnd,
+  BitOr,
+  BitXor,
+  ShiftLeft,
+  ShiftRight,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub(crate) enum UnaryOperator {
   Minus,
   Bang,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub(crate) enum UpdateOperator {
+  Increment,
+  Decrement,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub(crate) enum Literal {
-  Number { value: f64 },
-  String { value: String },
+  Number {
+    value: f64,
+  },
+  String {
+    value: String,
+  },
   True,
   False,
   Nil,
-  Identifier { name: String, id: usize },
+  Identifier {
+    name: String,
+    id: usize,
+    line: u32,
+    column: u32,
+  },
 }
 
-#[derive(Debug, Clone)]
+/// Serialized by the parse cache, bump `cache::FORMAT_VERSION` when changing
+/// how it serializes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub(crate) enum Expr {
   Ternary {
     conditional: Box<Expr>,
@@ -94,26 +125,164 @@ pub(crate) enum Expr {
     name: String,
     expression: Box<Expr>,
     id: usize,
+    /// Position of the assigned name
+    line: u32,
+    column: u32,
+  },
+  /// `++name` or `name++` and their `--` counterparts, assigning the
+  /// variable a number one greater or smaller
+  Update {
+    name: String,
+    operator: UpdateOperator,
+    /// Prefix updates evaluate to the new value, postfix ones to the old
+    prefix: bool,
+    id: usize,
+    /// Position of the updated name
+    line: u32,
+    column: u32,
   },
   Call {
     function: Box<Expr>,
     arguments: Vec<Expr>,
+    /// Position of the opening paren, reported in stack traces
+    line: u32,
+    column: u32,
+  },
+  Range {
+    start: Box<Expr>,
+    end: Box<Expr>,
+    inclusive: bool,
+  },
+  /// `expr is Type`. Names other than the built-in types are resolved like
+  /// identifiers.
+  Is {
+    expr: Box<Expr>,
+    type_name: String,
+    id: usize,
+    line: u32,
+    column: u32,
+  },
+  /// Two or more values, only produced by `return` and `var`
+  Tuple {
+    elements: Vec<Expr>,
+  },
+  /// `[a, b, c]`
+  List {
+    elements: Vec<Expr>,
+  },
+  /// `{key: value, ...}`
+  Map {
+    entries: Vec<(Expr, Expr)>,
+  },
+  /// `object[index]`
+  Index {
+    object: Box<Expr>,
+    index: Box<Expr>,
+  },
+  /// `object[start:end]`, where both bounds are optional
+  Slice {
+    object: Box<Expr>,
+    start: Option<Box<Expr>>,
+    end: Option<Box<Expr>>,
+  },
+  /// `object[index] = value`
+  IndexSet {
+    object: Box<Expr>,
+    index: Box<Expr>,
+    value: Box<Expr>,
+  },
+  /// `...expr`, only produced as a call argument
+  Spread {
+    expr: Box<Expr>,
+  },
+  /// `object.name`
+  Get {
+    object: Box<Expr>,
+    name: String,
+  },
+  /// `object.name = value`
+  Set {
+    object: Box<Expr>,
+    name: String,
+    value: Box<Expr>,
+  },
+  /// `super.method`
+  Super {
+    method: String,
+    id: usize,
+    line: u32,
+    column: u32,
+  },
+  /// `"a ${b} c"`, with the text around the embedded expressions in
+  /// `strings`, which has one element more than `expressions`
+  Interpolation {
+    strings: Vec<String>,
+    expressions: Vec<Expr>,
   },
 }
 
-#[derive(Debug, Clone)]
+/// Cached on disk like `Expr`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub(crate) enum Stmt {
   Expression {
     expression: Box<Expr>,
+    /// Position of the expression, reported with runtime errors like the
+    /// others below
+    line: u32,
+    column: u32,
+  },
+  Print {
+    expression: Box<Expr>,
+    line: u32,
+    column: u32,
+  },
+  /// `assert condition, message;`, the message is optional
+  Assert {
+    condition: Box<Expr>,
+    message: Option<Box<Expr>>,
+    line: u32,
+    column: u32,
   },
   Declaration {
     name: String,
     initializer: Box<Expr>,
+    doc: Option<String>,
+    /// Position of the name, lints report the line like the others below
+    line: u32,
+    column: u32,
+  },
+  /// `var (a, b) = ...;`
+  TupleDeclaration {
+    names: Vec<String>,
+    initializer: Box<Expr>,
+    doc: Option<String>,
+    line: u32,
+    column: u32,
+  },
+  Return {
+    value: Option<Box<Expr>>,
+    line: u32,
+    column: u32,
   },
   FunDeclaration {
     name: String,
     parameters: Vec<String>,
     body: Vec<Stmt>,
+    doc: Option<String>,
+    /// Position of the name
+    line: u32,
+    column: u32,
+  },
+  Class {
+    name: String,
+    /// An identifier literal
+    superclass: Option<Expr>,
+    /// `FunDeclaration`s of the methods, with `Trivia` between them when
+    /// parsed by `Parser::with_trivia`
+    methods: Vec<Stmt>,
+    doc: Option<String>,
+    line: u32,
+    column: u32,
   },
   Block {
     statements: Vec<Stmt>,
@@ -121,54 +290,332 @@ pub(crate) enum Stmt {
   While {
     condition: Box<Expr>,
     statement: Box<Stmt>,
+    /// Set by `label: while ...`, for `break label;` and `continue label;`
+    label: Option<String>,
+    line: u32,
+    column: u32,
+  },
+  Loop {
+    body: Box<Stmt>,
+    label: Option<String>,
+  },
+  /// `break;` or `break label;`
+  Break {
+    label: Option<String>,
+    line: u32,
+    column: u32,
+  },
+  /// `continue;` or `continue label;`
+  Continue {
+    label: Option<String>,
+    line: u32,
+    column: u32,
+  },
+  /// `for (initializer; condition; increment) body`
+  For {
+    initializer: Option<Box<Stmt>>,
+    condition: Option<Box<Expr>>,
+    increment: Option<Box<Expr>>,
+    body: Box<Stmt>,
+    label: Option<String>,
+    line: u32,
+    column: u32,
+  },
+  ForIn {
+    variable: String,
+    iterable: Box<Expr>,
+    body: Box<Stmt>,
+    label: Option<String>,
+    line: u32,
+    column: u32,
   },
   If {
     condition: Box<Expr>,
     true_case: Box<Stmt>,
     false_case: Option<Box<Stmt>>,
+    line: u32,
+    column: u32,
+  },
+  /// `switch (subject) { case a, b: ... default: ... }`. Only the body of
+  /// the first case with a value equal to the subject runs, there is no
+  /// fall-through.
+  Switch {
+    subject: Box<Expr>,
+    /// Values of a case with its body, a `Block`
+    cases: Vec<(Vec<Expr>, Stmt)>,
+    default: Option<Box<Stmt>>,
+    line: u32,
+    column: u32,
+  },
+  /// Comments and blank lines, only produced by `Parser::with_trivia`
+  Trivia {
+    trivia: Vec<Trivia>,
   },
 }
 
+impl Expr {
+  /// Number of AST nodes in this expression, itself included.
+  pub(crate) fn node_count(&self) -> usize {
+    1 + match self {
+      Expr::Ternary {
+        conditional,
+        true_case,
+        false_case,
+      } => conditional.node_count() + true_case.node_count() + false_case.node_count(),
+      Expr::Binary { left, right, .. } => left.node_count() + right.node_count(),
+      Expr::Unary { expr, .. }
+      | Expr::Grouping { expr }
+      | Expr::Spread { expr }
+      | Expr::Is { expr, .. } => expr.node_count(),
+      Expr::Get { object, .. } => object.node_count(),
+      Expr::Set { object, value, .. } => object.node_count() + value.node_count(),
+      Expr::Super { .. } => 1,
+      Expr::Literal { .. } | Expr::Update { .. } => 0,
+      Expr::Assignment { expression, .. } => expression.node_count(),
+      Expr::Call {
+        function,
+        arguments,
+        ..
+      } => function.node_count() + arguments.iter().map(Expr::node_count).sum::<usize>(),
+      Expr::Range { start, end, .. } => start.node_count() + end.node_count(),
+      Expr::Tuple { elements } | Expr::List { elements } => {
+        elements.iter().map(Expr::node_count).sum()
+      }
+      Expr::Interpolation { expressions, .. } => expressions.iter().map(Expr::node_count).sum(),
+      Expr::Map { entries } => entries
+        .iter()
+        .map(|(key, value)| key.node_count() + value.node_count())
+        .sum(),
+      Expr::Index { object, index } => object.node_count() + index.node_count(),
+      Expr::IndexSet {
+        object,
+        index,
+        value,
+      } => object.node_count() + index.node_count() + value.node_count(),
+      Expr::Slice { object, start, end } => {
+        object.node_count()
+          + start.as_ref().map_or(0, |start| start.node_count())
+          + end.as_ref().map_or(0, |end| end.node_count())
+      }
+    }
+  }
+}
+
+impl Stmt {
+  /// Number of AST nodes in this statement, itself included.
+  pub(crate) fn node_count(&self) -> usize {
+    1 + match self {
+      Stmt::Expression { expression, .. } | Stmt::Print { expression, .. } => {
+        expression.node_count()
+      }
+      Stmt::Declaration { initializer, .. } | Stmt::TupleDeclaration { initializer, .. } => {
+        initializer.node_count()
+      }
+      Stmt::Return { value, .. } => value.as_ref().map_or(0, |value| value.node_count()),
+      Stmt::Assert {
+        condition, message, ..
+      } => condition.node_count() + message.as_ref().map_or(0, |message| message.node_count()),
+      Stmt::FunDeclaration { body, .. }
+      | Stmt::Block { statements: body }
+      | Stmt::Class { methods: body, .. } => body.iter().map(Stmt::node_count).sum(),
+      Stmt::While {
+        condition,
+        statement,
+        ..
+      } => condition.node_count() + statement.node_count(),
+      Stmt::For {
+        initializer,
+        condition,
+        increment,
+        body,
+        ..
+      } => {
+        initializer.as_ref().map_or(0, |stmt| stmt.node_count())
+          + condition.as_ref().map_or(0, |expr| expr.node_count())
+          + increment.as_ref().map_or(0, |expr| expr.node_count())
+          + body.node_count()
+      }
+      Stmt::ForIn { iterable, body, .. } => iterable.node_count() + body.node_count(),
+      Stmt::If {
+        condition,
+        true_case,
+        false_case,
+        ..
+      } => {
+        condition.node_count()
+          + true_case.node_count()
+          + false_case.as_ref().map_or(0, |stmt| stmt.node_count())
+      }
+      Stmt::Switch {
+        subject,
+        cases,
+        default,
+        ..
+      } => {
+        subject.node_count()
+          + cases
+            .iter()
+            .map(|(values, body)| {
+              values.iter().map(Expr::node_count).sum::<usize>() + body.node_count()
+            })
+            .sum::<usize>()
+          + default.as_ref().map_or(0, |stmt| stmt.node_count())
+      }
+      Stmt::Loop { body, .. } => body.node_count(),
+      Stmt::Trivia { .. } | Stmt::Break { .. } | Stmt::Continue { .. } => 0,
+    }
+  }
+
+  /// Line and column runtime errors in this statement are reported at,
+  /// `None` for statements that only run the ones nested in them.
+  pub(crate) fn position(&self) -> Option<(u32, u32)> {
+    match self {
+      Stmt::Expression { line, column, .. }
+      | Stmt::Print { line, column, .. }
+      | Stmt::Assert { line, column, .. }
+      | Stmt::Declaration { line, column, .. }
+      | Stmt::TupleDeclaration { line, column, .. }
+      | Stmt::Return { line, column, .. }
+      | Stmt::Class { line, column, .. }
+      | Stmt::While { line, column, .. }
+      | Stmt::For { line, column, .. }
+      | Stmt::ForIn { line, column, .. }
+      | Stmt::If { line, column, .. }
+      | Stmt::Switch { line, column, .. } => Some((*line, *column)),
+      Stmt::FunDeclaration { .. }
+      | Stmt::Block { .. }
+      | Stmt::Loop { .. }
+      | Stmt::Break { .. }
+      | Stmt::Continue { .. }
+      | Stmt::Trivia { .. } => None,
+    }
+  }
+}
+
 pub(crate) struct Parser {
   tokens: Vec<Token>,
   current: usize,
-  errors: Vec<SyntaxError>,
+  errors: Vec<LocatedSyntaxError>,
+  keep_trivia: bool,
+  inner_trivia: Vec<Trivia>,
+  /// Identifiers get ids unique within the parsed program, so the resolver
+  /// can tell apart uses of the same name
+  next_id: usize,
 }
 
 impl Parser {
   pub(crate) fn new(tokens: Vec<Token>) -> Self {
     Parser {
-      tokens,
+      tokens: fold_comment_tokens(tokens),
       current: 0,
       errors: vec![],
+      keep_trivia: false,
+      inner_trivia: vec![],
+      next_id: 1,
+    }
+  }
+
+  /// Creates a parser giving identifiers ids from `first_id` on, so they
+  /// don't clash with the ids of code parsed before.
+  pub(crate) fn continuing_from(tokens: Vec<Token>, first_id: usize) -> Self {
+    Parser {
+      next_id: first_id,
+      ..Parser::new(tokens)
+    }
+  }
+
+  /// The id the next identifier will get.
+  pub(crate) fn unused_id(&self) -> usize {
+    self.next_id
+  }
+
+  /// Creates a parser that keeps comments and blank lines in the AST as
+  /// `Stmt::Trivia`. Comments found in the middle of a statement are moved
+  /// in front of it.
+  pub(crate) fn with_trivia(tokens: Vec<Token>) -> Self {
+    Parser {
+      keep_trivia: true,
+      ..Parser::new(tokens)
     }
   }
 
   pub(crate) fn parse(&mut self) -> Result<Vec<Stmt>> {
+    let statements = self.parse_partial()?;
+
+    if !self.errors.is_empty() {
+      Err(SyntaxErrors(self.errors.clone()).into())
+    } else {
+      Ok(statements)
+    }
+  }
+
+  /// Parses the statements that are free of syntax errors, skipping the
+  /// others. The skipped statements' errors are left in `errors`.
+  pub(crate) fn parse_partial(&mut self) -> Result<Vec<Stmt>> {
     let mut statements: Vec<Stmt> = vec![];
 
     while !self.is_at_and() {
-      if let Some(stmt) = self.declaration()? {
-        statements.push(stmt);
-      }
+      self.push_declaration(&mut statements)?;
     }
 
-    if !self.errors.is_empty() {
-      for e in &self.errors {
-        eprintln!("Syntax error: {e}");
-      }
+    self.push_trivia(&mut statements, true);
+
+    Ok(statements)
+  }
+
+  pub(crate) fn errors(&self) -> &[LocatedSyntaxError] {
+    &self.errors
+  }
+
+  fn push_declaration(&mut self, statements: &mut Vec<Stmt>) -> Result<()> {
+    self.push_trivia(statements, true);
+
+    if let Some(stmt) = self.declaration()? {
+      self.push_trivia(statements, false);
+      statements.push(stmt);
+    }
+
+    Ok(())
+  }
+
+  /// Moves trivia collected from already consumed tokens (and, if `with_next`
+  /// is set, the leading trivia of the next token) into `statements`.
+  fn push_trivia(&mut self, statements: &mut Vec<Stmt>, with_next: bool) {
+    if !self.keep_trivia {
+      return;
+    }
 
-      Ok(vec![])
+    let mut leading = if with_next {
+      std::mem::take(&mut self.tokens[self.current].leading_trivia)
     } else {
-      Ok(statements)
+      vec![]
+    };
+
+    // A comment trailing the previous line has to stay first
+    let same_line_count = leading
+      .iter()
+      .take_while(|trivia| matches!(trivia, Trivia::TrailingComment(_)))
+      .count();
+    let rest = leading.split_off(same_line_count);
+
+    let mut trivia = leading;
+    trivia.append(&mut self.inner_trivia);
+    trivia.extend(rest);
+
+    if !trivia.is_empty() {
+      statements.push(Stmt::Trivia { trivia });
     }
   }
 
   fn declaration(&mut self) -> Result<Option<Stmt>> {
+    let doc = self.peek().doc.clone();
+
     let stmt = if self.match_(TokenType::Var) {
-      self.variable_declaration()
+      self.variable_declaration(doc)
     } else if self.match_(TokenType::Fun) {
-      self.function_declaration()
+      self.function_declaration(doc)
+    } else if self.match_(TokenType::Class) {
+      self.class_declaration(doc)
     } else {
       self.statement()
     };
@@ -185,7 +632,68 @@ impl Parser {
     })
   }
 
-  fn function_declaration(&mut self) -> Result<Stmt> {
+  fn class_declaration(&mut self, doc: Option<String>) -> Result<Stmt> {
+    let TokenType::Identifier(name) = self.peek().kind.clone() else {
+      return Err(SyntaxError::MissingClassIdentifier.into());
+    };
+    let (line, column) = (self.peek().line, self.peek().column);
+
+    self.advance();
+
+    let superclass = if self.match_(TokenType::Less) {
+      let TokenType::Identifier(name) = self.peek().kind.clone() else {
+        return Err(SyntaxError::MissingSuperclassName.into());
+      };
+      let token = self.advance();
+      let (line, column) = (token.line, token.column);
+
+      Some(Expr::Literal {
+        value: Literal::Identifier {
+          name,
+          id: self.next_id(),
+          line,
+          column,
+        },
+      })
+    } else {
+      None
+    };
+
+    self.consume(
+      TokenType::LeftBrace,
+      SyntaxError::MissingClassBodyOpeningBrace,
+    )?;
+
+    let mut methods = vec![];
+
+    while self.peek().kind != TokenType::RightBrace && !self.is_at_and() {
+      self.push_trivia(&mut methods, true);
+
+      let doc = self.peek().doc.clone();
+      let method = self.function_declaration(doc)?;
+
+      self.push_trivia(&mut methods, false);
+      methods.push(method);
+    }
+
+    self.push_trivia(&mut methods, true);
+
+    if !self.match_(TokenType::RightBrace) {
+      return Err(SyntaxError::MissingRightBrace.into());
+    }
+
+    Ok(Stmt::Class {
+      name,
+      superclass,
+      methods,
+      doc,
+      line,
+      column,
+    })
+  }
+
+  fn function_declaration(&mut self, doc: Option<String>) -> Result<Stmt> {
+    let (line, column) = (self.peek().line, self.peek().column);
     let name = {
       let TokenType::Identifier(ref identifier) = self.peek().kind else {
         return Err(SyntaxError::MissingFunctionDeclarationIdentifier.into());
@@ -201,18 +709,11 @@ impl Parser {
       SyntaxError::MissingParametersDeclarationOpeningParen,
     )?;
 
-    let parameters = if !self.match_(TokenType::RightParen) {
-      let parameters = self.parameters()?;
-
-      self.consume(
-        TokenType::RightParen,
-        SyntaxError::MissingParametersDeclarationOpeningParen,
-      )?;
-
-      parameters
-    } else {
-      vec![]
-    };
+    let parameters = self.comma_separated(
+      TokenType::RightParen,
+      SyntaxError::MissingParametersDeclarationOpeningParen,
+      Self::match_parameter_identifier,
+    )?;
 
     self.consume(TokenType::LeftBrace, SyntaxError::MissingBodyOpeningBrace)?;
 
@@ -222,21 +723,12 @@ impl Parser {
       name: name.clone(),
       body,
       parameters,
+      doc,
+      line,
+      column,
     })
   }
 
-  fn parameters(&mut self) -> Result<Vec<String>> {
-    let mut parameters: Vec<String> = vec![self.match_parameter_identifier()?];
-
-    loop {
-      if self.match_(TokenType::Comma) {
-        parameters.push(self.match_parameter_identifier()?)
-      } else {
-        break Ok(parameters);
-      }
-    }
-  }
-
   fn match_parameter_identifier(&mut self) -> Result<String> {
     let identifier = {
       let TokenType::Identifier(ref identifier) = self.peek().kind else {
@@ -252,6 +744,15 @@ impl Parser {
   }
 
   fn statement(&mut self) -> Result<Stmt> {
+    let is_labeled = matches!(self.peek().kind, TokenType::Identifier(_))
+      && self
+        .peek_next()
+        .is_some_and(|token| token.kind == TokenType::Colon);
+
+    if is_labeled {
+      return self.labeled_statement();
+    }
+
     if self.match_(TokenType::LeftBrace) {
       let statements = self.block()?;
 
@@ -260,20 +761,95 @@ impl Parser {
       self.while_()
     } else if self.match_(TokenType::If) {
       self.if_()
+    } else if self.match_(TokenType::Switch) {
+      self.switch()
+    } else if self.match_(TokenType::For) {
+      self.for_()
+    } else if self.match_(TokenType::Print) {
+      self.print()
+    } else if self.match_(TokenType::Assert) {
+      self.assert()
+    } else if self.match_(TokenType::Return) {
+      self.return_()
+    } else if self.match_(TokenType::Loop) {
+      self.consume(
+        TokenType::LeftBrace,
+        SyntaxError::LoopBodyNotEnclosedInBlock,
+      )?;
+
+      let statements = self.block()?;
+
+      Ok(Stmt::Loop {
+        body: Box::new(Stmt::Block { statements }),
+        label: None,
+      })
+    } else if self.match_(TokenType::Break) {
+      let (line, column) = (self.previous().line, self.previous().column);
+
+      Ok(Stmt::Break {
+        label: self.jump_label()?,
+        line,
+        column,
+      })
+    } else if self.match_(TokenType::Continue) {
+      let (line, column) = (self.previous().line, self.previous().column);
+
+      Ok(Stmt::Continue {
+        label: self.jump_label()?,
+        line,
+        column,
+      })
     } else {
       self.expr_stmt()
     }
   }
 
+  /// `label: loop`, only loops can be labeled.
+  fn labeled_statement(&mut self) -> Result<Stmt> {
+    let TokenType::Identifier(name) = self.advance().kind.clone() else {
+      unreachable!("a label starts with an identifier");
+    };
+
+    self.advance();
+
+    let mut stmt = self.statement()?;
+
+    match &mut stmt {
+      Stmt::While { label, .. }
+      | Stmt::Loop { label, .. }
+      | Stmt::For { label, .. }
+      | Stmt::ForIn { label, .. } => *label = Some(name),
+      _ => return Err(SyntaxError::LabelOnNonLoop(name).into()),
+    }
+
+    Ok(stmt)
+  }
+
+  /// The optional label and the semicolon after `break` or `continue`.
+  fn jump_label(&mut self) -> Result<Option<String>> {
+    let label = match self.peek().kind.clone() {
+      TokenType::Identifier(label) => {
+        self.advance();
+
+        Some(label)
+      }
+      _ => None,
+    };
+
+    self.consume(TokenType::Semicolon, SyntaxError::MissingSemicolon)?;
+
+    Ok(label)
+  }
+
   fn block(&mut self) -> Result<Vec<Stmt>> {
     let mut statements: Vec<Stmt> = vec![];
 
     while self.peek().kind != TokenType::RightBrace && !self.is_at_and() {
-      if let Some(stmt) = self.declaration()? {
-        statements.push(stmt);
-      }
+      self.push_declaration(&mut statements)?;
     }
 
+    self.push_trivia(&mut statements, true);
+
     if self.match_(TokenType::RightBrace) {
       Ok(statements)
     } else {
@@ -282,6 +858,8 @@ impl Parser {
   }
 
   fn while_(&mut self) -> Result<Stmt> {
+    let (line, column) = (self.previous().line, self.previous().column);
+
     self.consume(
       TokenType::LeftParen,
       SyntaxError::MissingWhileConditionLeftParen,
@@ -290,20 +868,93 @@ impl Parser {
     let expression = self.expression()?;
 
     self.consume(TokenType::RightParen, SyntaxError::MissingRightParen)?;
-    self.consume(
-      TokenType::LeftBrace,
-      SyntaxError::WhileBodyNotEnclosedInBlock,
-    )?;
-
-    let statements = self.block()?;
 
     Ok(Stmt::While {
       condition: Box::new(expression),
-      statement: Box::new(Stmt::Block { statements }),
+      statement: Box::new(self.statement()?),
+      label: None,
+      line,
+      column,
+    })
+  }
+
+  fn for_(&mut self) -> Result<Stmt> {
+    let (line, column) = (self.previous().line, self.previous().column);
+
+    self.consume(TokenType::LeftParen, SyntaxError::MissingForLeftParen)?;
+
+    let is_for_in = matches!(self.peek().kind, TokenType::Identifier(_))
+      && self
+        .peek_next()
+        .is_some_and(|token| token.kind == TokenType::In);
+
+    if !is_for_in {
+      return self.c_style_for(line, column);
+    }
+
+    let TokenType::Identifier(variable) = self.peek().kind.clone() else {
+      return Err(SyntaxError::MissingForVariable.into());
+    };
+
+    self.advance();
+
+    self.consume(TokenType::In, SyntaxError::MissingForIn)?;
+
+    let iterable = self.expression()?;
+
+    self.consume(TokenType::RightParen, SyntaxError::MissingRightParen)?;
+
+    Ok(Stmt::ForIn {
+      variable,
+      iterable: Box::new(iterable),
+      body: Box::new(self.statement()?),
+      label: None,
+      line,
+      column,
+    })
+  }
+
+  /// The rest of `for (initializer; condition; increment) body`, after the
+  /// opening paren, with the position of `for`.
+  fn c_style_for(&mut self, line: u32, column: u32) -> Result<Stmt> {
+    let initializer = if self.match_(TokenType::Semicolon) {
+      None
+    } else if self.match_(TokenType::Var) {
+      Some(self.variable_declaration(None)?)
+    } else {
+      Some(self.expr_stmt()?)
+    };
+
+    let condition = if self.peek().kind == TokenType::Semicolon {
+      None
+    } else {
+      Some(self.expression()?)
+    };
+
+    self.consume(TokenType::Semicolon, SyntaxError::MissingSemicolon)?;
+
+    let increment = if self.peek().kind == TokenType::RightParen {
+      None
+    } else {
+      Some(self.expression()?)
+    };
+
+    self.consume(TokenType::RightParen, SyntaxError::MissingRightParen)?;
+
+    Ok(Stmt::For {
+      initializer: initializer.map(Box::new),
+      condition: condition.map(Box::new),
+      increment: increment.map(Box::new),
+      body: Box::new(self.statement()?),
+      label: None,
+      line,
+      column,
     })
   }
 
   fn if_(&mut self) -> Result<Stmt> {
+    let (line, column) = (self.previous().line, self.previous().column);
+
     self.consume(
       TokenType::LeftParen,
       SyntaxError::MissingIfConditionLeftParen,
@@ -312,48 +963,203 @@ impl Parser {
     let condition = self.expression()?;
 
     self.consume(TokenType::RightParen, SyntaxError::MissingRightParen)?;
-    self.consume(TokenType::LeftBrace, SyntaxError::IfBodyNotEnclosedInBlock)?;
 
-    let true_case = self.block()?;
+    let true_case = self.statement()?;
 
-    let else_case = if self.match_(TokenType::Else) {
-      self.consume(
-        TokenType::LeftBrace,
-        SyntaxError::ElseBodyNotEnclosedInBlock,
-      )?;
-
-      let statements = self.block()?;
-
-      Some(Stmt::Block { statements })
+    // Any statement may follow `else`, so `else if` chains need no braces
+    let false_case = if self.match_(TokenType::Else) {
+      Some(self.statement()?)
     } else {
       None
     };
 
     Ok(Stmt::If {
       condition: Box::new(condition),
-      true_case: Box::new(Stmt::Block {
-        statements: true_case,
-      }),
-      false_case: else_case.map(Box::new),
+      true_case: Box::new(true_case),
+      false_case: false_case.map(Box::new),
+      line,
+      column,
     })
   }
 
+  fn switch(&mut self) -> Result<Stmt> {
+    let (line, column) = (self.previous().line, self.previous().column);
+
+    self.consume(
+      TokenType::LeftParen,
+      SyntaxError::MissingSwitchSubjectLeftParen,
+    )?;
+
+    let subject = self.expression()?;
+
+    self.consume(TokenType::RightParen, SyntaxError::MissingRightParen)?;
+    self.consume(
+      TokenType::LeftBrace,
+      SyntaxError::MissingSwitchBodyOpeningBrace,
+    )?;
+
+    let mut cases = vec![];
+
+    while self.match_(TokenType::Case) {
+      let mut values = vec![self.assignment()?];
+
+      while self.match_(TokenType::Comma) {
+        values.push(self.assignment()?);
+      }
+
+      self.consume(TokenType::Colon, SyntaxError::MissingCaseColon)?;
+      cases.push((values, self.case_body()?));
+    }
+
+    let default = if self.match_(TokenType::Default) {
+      self.consume(TokenType::Colon, SyntaxError::MissingCaseColon)?;
+
+      Some(Box::new(self.case_body()?))
+    } else {
+      None
+    };
+
+    self.consume(TokenType::RightBrace, SyntaxError::UnexpectedTokenInSwitch)?;
+
+    Ok(Stmt::Switch {
+      subject: Box::new(subject),
+      cases,
+      default,
+      line,
+      column,
+    })
+  }
+
+  /// Statements following a `case` or `default` label, up to the next one.
+  fn case_body(&mut self) -> Result<Stmt> {
+    let mut statements: Vec<Stmt> = vec![];
+
+    while !matches!(
+      self.peek().kind,
+      TokenType::Case | TokenType::Default | TokenType::RightBrace
+    ) && !self.is_at_and()
+    {
+      self.push_declaration(&mut statements)?;
+    }
+
+    self.push_trivia(&mut statements, true);
+
+    Ok(Stmt::Block { statements })
+  }
+
   fn expr_stmt(&mut self) -> Result<Stmt> {
+    let (line, column) = (self.peek().line, self.peek().column);
     let expression = self.expression()?;
 
     if self.match_(TokenType::Semicolon) {
       Ok(Stmt::Expression {
         expression: Box::new(expression),
+        line,
+        column,
       })
     } else {
       Err(SyntaxError::MissingSemicolon.into())
     }
   }
 
-  fn variable_declaration(&mut self) -> Result<Stmt> {
+  fn print(&mut self) -> Result<Stmt> {
+    let (line, column) = (self.peek().line, self.peek().column);
+    let expression = self.expression()?;
+
+    self.consume(TokenType::Semicolon, SyntaxError::MissingSemicolon)?;
+
+    Ok(Stmt::Print {
+      expression: Box::new(expression),
+      line,
+      column,
+    })
+  }
+
+  fn assert(&mut self) -> Result<Stmt> {
+    let (line, column) = (self.previous().line, self.previous().column);
+    // Not `expression`, the comma separates the message
+    let condition = self.assignment()?;
+
+    let message = if self.match_(TokenType::Comma) {
+      Some(Box::new(self.assignment()?))
+    } else {
+      None
+    };
+
+    self.consume(TokenType::Semicolon, SyntaxError::MissingSemicolon)?;
+
+    Ok(Stmt::Assert {
+      condition: Box::new(condition),
+      message,
+      line,
+      column,
+    })
+  }
+
+  fn return_(&mut self) -> Result<Stmt> {
+    let (line, column) = (self.previous().line, self.previous().column);
+    let value = if self.peek().kind == TokenType::Semicolon {
+      None
+    } else {
+      Some(Box::new(self.tuple()?))
+    };
+
+    self.consume(TokenType::Semicolon, SyntaxError::MissingSemicolon)?;
+
+    Ok(Stmt::Return {
+      value,
+      line,
+      column,
+    })
+  }
+
+  fn tuple_declaration(&mut self, doc: Option<String>) -> Result<Stmt> {
+    let (line, column) = (self.peek().line, self.peek().column);
+    let names = self.comma_separated(
+      TokenType::RightParen,
+      SyntaxError::MissingRightParen,
+      |parser| {
+        let TokenType::Identifier(name) = parser.peek().kind.clone() else {
+          return Err(SyntaxError::VariableDeclarationMissingIdentifier.into());
+        };
+
+        parser.advance();
+
+        Ok(name)
+      },
+    )?;
+
+    if names.is_empty() {
+      return Err(SyntaxError::VariableDeclarationMissingIdentifier.into());
+    }
+
+    self.consume(
+      TokenType::Eqal,
+      SyntaxError::VariableDeclarationMissingAssignment,
+    )?;
+
+    let initializer = self.tuple()?;
+
+    self.consume(TokenType::Semicolon, SyntaxError::MissingSemicolon)?;
+
+    Ok(Stmt::TupleDeclaration {
+      names,
+      initializer: Box::new(initializer),
+      doc,
+      line,
+      column,
+    })
+  }
+
+  fn variable_declaration(&mut self, doc: Option<String>) -> Result<Stmt> {
+    if self.match_(TokenType::LeftParen) {
+      return self.tuple_declaration(doc);
+    }
+
     let TokenType::Identifier(name) = self.peek().kind.clone() else {
       return Err(SyntaxError::VariableDeclarationMissingIdentifier.into());
     };
+    let (line, column) = (self.peek().line, self.peek().column);
 
     self.advance();
 
@@ -367,6 +1173,9 @@ impl Parser {
       Ok(Stmt::Declaration {
         initializer: Box::new(initializer),
         name,
+        doc,
+        line,
+        column,
       })
     } else {
       Err(SyntaxError::MissingSemicolon.into())
@@ -377,24 +1186,52 @@ impl Parser {
     self.comma()
   }
 
+  /// Comma separated values where the comma builds a tuple instead of
+  /// acting as the comma operator.
+  fn tuple(&mut self) -> Result<Expr> {
+    let mut elements = vec![self.assignment()?];
+
+    while self.match_(TokenType::Comma) {
+      elements.push(self.assignment()?);
+    }
+
+    Ok(if elements.len() == 1 {
+      elements.remove(0)
+    } else {
+      Expr::Tuple { elements }
+    })
+  }
+
   fn assignment(&mut self) -> Result<Expr> {
     let l_value = self.logical_or()?;
 
     if self.match_(TokenType::Eqal) {
       let r_value = self.assignment()?;
 
-      let Expr::Literal {
-        value: Literal::Identifier { name, .. },
-      } = l_value
-      else {
-        return Err(SyntaxError::LValueMustBeAnIdentifier.into());
-      };
-
-      Ok(Expr::Assignment {
-        name,
-        expression: Box::new(r_value),
-        id: get_id(),
-      })
+      match l_value {
+        Expr::Literal {
+          value: Literal::Identifier {
+            name, line, column, ..
+          },
+        } if name != "this" => Ok(Expr::Assignment {
+          name,
+          expression: Box::new(r_value),
+          id: self.next_id(),
+          line,
+          column,
+        }),
+        Expr::Get { object, name } => Ok(Expr::Set {
+          object,
+          name,
+          value: Box::new(r_value),
+        }),
+        Expr::Index { object, index } => Ok(Expr::IndexSet {
+          object,
+          index,
+          value: Box::new(r_value),
+        }),
+        _ => Err(SyntaxError::LValueMustBeAnIdentifier.into()),
+      }
     } else {
       Ok(l_value)
     }
@@ -496,7 +1333,7 @@ impl Parser {
   }
 
   fn comparison(&mut self) -> Result<Expr> {
-    let mut expr = self.term()?;
+    let mut expr = self.range()?;
 
     loop {
       let operator = if self.match_(TokenType::Less) {
@@ -507,6 +1344,24 @@ impl Parser {
         BinaryOperator::Greater
       } else if self.match_(TokenType::GreaterEqual) {
         BinaryOperator::GreaterEqual
+      } else if self.match_(TokenType::In) {
+        BinaryOperator::In
+      } else if self.match_(TokenType::Is) {
+        let TokenType::Identifier(type_name) = self.peek().kind.clone() else {
+          return Err(SyntaxError::MissingTypeName.into());
+        };
+        let token = self.advance();
+        let (line, column) = (token.line, token.column);
+
+        expr = Expr::Is {
+          expr: Box::new(expr),
+          type_name,
+          id: self.next_id(),
+          line,
+          column,
+        };
+
+        continue;
       } else {
         break Ok(expr);
       };
@@ -514,11 +1369,87 @@ impl Parser {
       expr = Expr::Binary {
         operator,
         left: Box::new(expr),
-        right: Box::new(self.term()?),
+        right: Box::new(self.range()?),
       };
     }
   }
 
+  fn range(&mut self) -> Result<Expr> {
+    let start = self.bitwise_or()?;
+
+    let inclusive = if self.match_(TokenType::DotDot) {
+      false
+    } else if self.match_(TokenType::DotDotEqual) {
+      true
+    } else {
+      return Ok(start);
+    };
+
+    Ok(Expr::Range {
+      start: Box::new(start),
+      end: Box::new(self.bitwise_or()?),
+      inclusive,
+    })
+  }
+
+  /// Parses left-associative binary expressions with one of `operators`
+  /// between operands parsed by `operand`.
+  fn binary(
+    &mut self,
+    operators: &[(TokenType, BinaryOperator)],
+    operand: fn(&mut Self) -> Result<Expr>,
+  ) -> Result<Expr> {
+    let mut expr = operand(self)?;
+
+    'operators: loop {
+      for (token, operator) in operators {
+        if self.match_(token.clone()) {
+          expr = Expr::Binary {
+            operator: operator.clone(),
+            left: Box::new(expr),
+            right: Box::new(operand(self)?),
+          };
+
+          continue 'operators;
+        }
+      }
+
+      break Ok(expr);
+    }
+  }
+
+  // Bitwise operators bind tighter than comparisons, unlike in C
+  fn bitwise_or(&mut self) -> Result<Expr> {
+    self.binary(
+      &[(TokenType::Pipe, BinaryOperator::BitOr)],
+      Self::bitwise_xor,
+    )
+  }
+
+  fn bitwise_xor(&mut self) -> Result<Expr> {
+    self.binary(
+      &[(TokenType::Caret, BinaryOperator::BitXor)],
+      Self::bitwise_and,
+    )
+  }
+
+  fn bitwise_and(&mut self) -> Result<Expr> {
+    self.binary(
+      &[(TokenType::Ampersand, BinaryOperator::BitAnd)],
+      Self::shift,
+    )
+  }
+
+  fn shift(&mut self) -> Result<Expr> {
+    self.binary(
+      &[
+        (TokenType::LessLess, BinaryOperator::ShiftLeft),
+        (TokenType::GreaterGreater, BinaryOperator::ShiftRight),
+      ],
+      Self::term,
+    )
+  }
+
   fn term(&mut self) -> Result<Expr> {
     let mut expr = self.factor()?;
 
@@ -560,6 +1491,20 @@ impl Parser {
   }
 
   fn unary(&mut self) -> Result<Expr> {
+    if let Some(operator) = self.match_update_operator() {
+      let target = self.unary()?;
+      let (name, line, column) = self.update_target(target)?;
+
+      return Ok(Expr::Update {
+        name,
+        operator,
+        prefix: true,
+        id: self.next_id(),
+        line,
+        column,
+      });
+    }
+
     let operator = if self.match_(TokenType::Bang) {
       UnaryOperator::Bang
     } else if self.match_(TokenType::Minus) {
@@ -586,12 +1531,44 @@ impl Parser {
     let mut primary = match self.peek().kind.clone() {
       TokenType::Number(value) => create_primary_expr!(Literal::Number { value }),
       TokenType::String(value) => create_primary_expr!(Literal::String { value }),
+      TokenType::Interpolation(value) => {
+        self.advance();
+        self.interpolation(value)?
+      }
       TokenType::True => create_primary_expr!(Literal::True),
       TokenType::False => create_primary_expr!(Literal::False),
       TokenType::Nil => create_primary_expr!(Literal::Nil),
       TokenType::Identifier(value) => create_primary_expr!(Literal::Identifier {
         name: value,
-        id: get_id()
+        id: self.next_id(),
+        line: self.previous().line,
+        column: self.previous().column,
+      }),
+      TokenType::Super => {
+        let token = self.advance();
+        let (line, column) = (token.line, token.column);
+
+        self.consume(TokenType::Dot, SyntaxError::MissingSuperDot)?;
+
+        let TokenType::Identifier(method) = self.peek().kind.clone() else {
+          return Err(SyntaxError::MissingSuperMethodName.into());
+        };
+
+        self.advance();
+
+        Expr::Super {
+          method,
+          id: self.next_id(),
+          line,
+          column,
+        }
+      }
+      // `this` is resolved like a variable defined around every method
+      TokenType::This => create_primary_expr!(Literal::Identifier {
+        name: "this".to_string(),
+        id: self.next_id(),
+        line: self.previous().line,
+        column: self.previous().column,
       }),
       TokenType::LeftParen => {
         self.advance();
@@ -606,41 +1583,209 @@ impl Parser {
           return Err(SyntaxError::MissingRightParen.into());
         }
       }
+      TokenType::LeftBrace => {
+        self.advance();
+
+        Expr::Map {
+          entries: self.map_entries()?,
+        }
+      }
+      TokenType::LeftBracket => {
+        self.advance();
+
+        Expr::List {
+          elements: self.list_elements()?,
+        }
+      }
       _ => return Err(SyntaxError::UnexpectedTokenInExpression.into()),
     };
 
     loop {
       if self.match_(TokenType::LeftParen) {
+        let (line, column) = (self.previous().line, self.previous().column);
         let arguments = self.finish_call()?;
 
         primary = Expr::Call {
           function: Box::new(primary),
           arguments,
+          line,
+          column,
         }
+      } else if self.match_(TokenType::LeftBracket) {
+        primary = self.index_or_slice(primary)?;
+      } else if self.match_(TokenType::Dot) {
+        let TokenType::Identifier(name) = self.peek().kind.clone() else {
+          return Err(SyntaxError::MissingPropertyName.into());
+        };
+
+        self.advance();
+
+        primary = Expr::Get {
+          object: Box::new(primary),
+          name,
+        }
+      } else if let Some(operator) = self.match_update_operator() {
+        let (name, line, column) = self.update_target(primary)?;
+
+        break Ok(Expr::Update {
+          name,
+          operator,
+          prefix: false,
+          id: self.next_id(),
+          line,
+          column,
+        });
       } else {
         break Ok(primary);
       }
     }
   }
 
-  fn finish_call(&mut self) -> Result<Vec<Expr>> {
-    let mut arguments: Vec<Expr> = vec![];
+  /// Parses the rest of `"a ${b} c"`, after the text before the first
+  /// embedded expression.
+  fn interpolation(&mut self, first: String) -> Result<Expr> {
+    let mut strings = vec![first];
+    let mut expressions = vec![];
+
+    loop {
+      expressions.push(self.expression()?);
+
+      let (part, is_last) = match self.peek().kind.clone() {
+        TokenType::Interpolation(part) => (part, false),
+        TokenType::String(part) => (part, true),
+        _ => return Err(SyntaxError::UnterminatedInterpolation.into()),
+      };
+
+      self.advance();
+      strings.push(part);
 
-    if self.match_(TokenType::RightParen) {
-      return Ok(arguments);
+      if is_last {
+        break Ok(Expr::Interpolation {
+          strings,
+          expressions,
+        });
+      }
     }
+  }
 
-    loop {
-      arguments.push(self.assignment()?);
+  fn match_update_operator(&mut self) -> Option<UpdateOperator> {
+    if self.match_(TokenType::PlusPlus) {
+      Some(UpdateOperator::Increment)
+    } else if self.match_(TokenType::MinusMinus) {
+      Some(UpdateOperator::Decrement)
+    } else {
+      None
+    }
+  }
+
+  /// The variable updated by `++` or `--`, with its position.
+  fn update_target(&self, target: Expr) -> Result<(String, u32, u32)> {
+    match target {
+      Expr::Literal {
+        value: Literal::Identifier {
+          name, line, column, ..
+        },
+      } if name != "this" => Ok((name, line, column)),
+      _ => Err(SyntaxError::InvalidUpdateTarget.into()),
+    }
+  }
+
+  fn finish_call(&mut self) -> Result<Vec<Expr>> {
+    self.comma_separated(
+      TokenType::RightParen,
+      SyntaxError::MissingRightParen,
+      |parser| {
+        if parser.match_(TokenType::DotDotDot) {
+          Ok(Expr::Spread {
+            expr: Box::new(parser.assignment()?),
+          })
+        } else {
+          parser.assignment()
+        }
+      },
+    )
+  }
+
+  /// `object[index]` or `object[start:end]`, after the opening bracket.
+  fn index_or_slice(&mut self, object: Expr) -> Result<Expr> {
+    let start = if self.match_(TokenType::Colon) {
+      None
+    } else {
+      let index = self.expression()?;
 
+      if !self.match_(TokenType::Colon) {
+        self.consume(TokenType::RightBracket, SyntaxError::MissingRightBracket)?;
+
+        return Ok(Expr::Index {
+          object: Box::new(object),
+          index: Box::new(index),
+        });
+      }
+
+      Some(Box::new(index))
+    };
+
+    let end = if self.peek().kind == TokenType::RightBracket {
+      None
+    } else {
+      Some(Box::new(self.expression()?))
+    };
+
+    self.consume(TokenType::RightBracket, SyntaxError::MissingRightBracket)?;
+
+    Ok(Expr::Slice {
+      object: Box::new(object),
+      start,
+      end,
+    })
+  }
+
+  /// Elements of a list literal, after the opening bracket.
+  fn list_elements(&mut self) -> Result<Vec<Expr>> {
+    self.comma_separated(
+      TokenType::RightBracket,
+      SyntaxError::MissingRightBracket,
+      Self::assignment,
+    )
+  }
+
+  /// Entries of a map literal, after the opening brace.
+  fn map_entries(&mut self) -> Result<Vec<(Expr, Expr)>> {
+    self.comma_separated(
+      TokenType::RightBrace,
+      SyntaxError::MissingRightBrace,
+      |parser| {
+        let key = parser.assignment()?;
+        parser.consume(TokenType::Colon, SyntaxError::MissingMapColon)?;
+
+        Ok((key, parser.assignment()?))
+      },
+    )
+  }
+
+  /// Parses the comma separated items of a list up to and including its
+  /// `closing` token, failing with `missing` if another token ends the list.
+  /// The list may be empty.
+  fn comma_separated<T>(
+    &mut self,
+    closing: TokenType,
+    missing: SyntaxError,
+    mut item: impl FnMut(&mut Self) -> Result<T>,
+  ) -> Result<Vec<T>> {
+    let mut items = vec![];
+
+    while !self.match_(closing.clone()) {
+      items.push(item(self)?);
+
+      // A trailing comma is allowed before the closing token
       if !self.match_(TokenType::Comma) {
+        self.consume(closing, missing)?;
+
         break;
       }
     }
 
-    self.consume(TokenType::RightParen, SyntaxError::MissingRightParen)?;
-
-    Ok(arguments)
+    Ok(items)
   }
 
   fn consume(&mut self, token: TokenType, err: SyntaxError) -> Result<()> {
@@ -651,7 +1796,25 @@ impl Parser {
     }
   }
 
+  fn next_id(&mut self) -> usize {
+    let id = self.next_id;
+    self.next_id += 1;
+
+    id
+  }
+
   fn advance(&mut self) -> &Token {
+    if self.keep_trivia {
+      let trivia = std::mem::take(&mut self.tokens[self.current].leading_trivia);
+
+      self
+        .inner_trivia
+        .extend(trivia.into_iter().filter_map(|trivia| match trivia {
+          Trivia::Comment(text) | Trivia::TrailingComment(text) => Some(Trivia::Comment(text)),
+          Trivia::BlankLine => None,
+        }));
+    }
+
     if !self.is_at_and() {
       self.current += 1;
     }
@@ -673,6 +1836,10 @@ impl Parser {
     &self.tokens[self.current]
   }
 
+  fn peek_next(&self) -> Option<&Token> {
+    self.tokens.get(self.current + 1)
+  }
+
   fn previous(&mut self) -> &Token {
     &self.tokens[self.current - 1]
   }
@@ -681,9 +1848,18 @@ impl Parser {
     self.peek().kind == TokenType::Eof
   }
 
+  /// Records `error`, found at the current token.
   fn report_error(&mut self, error: SyntaxError) {
-    self.errors.push(error);
+    let token = self.peek();
+
+    self.errors.push(LocatedSyntaxError {
+      error,
+      line: token.line,
+      column: token.column,
+      length: token.lexeme.chars().count(),
+    });
   }
+
   fn synchronize(&mut self) {
     self.advance();
 
@@ -706,24 +1882,134 @@ impl Parser {
   }
 }
 
+/// Turns `TokenType::Comment` tokens into leading trivia of the token that
+/// follows them, so the grammar never has to deal with them.
+fn fold_comment_tokens(tokens: Vec<Token>) -> Vec<Token> {
+  let mut folded: Vec<Token> = Vec::with_capacity(tokens.len());
+  let mut pending: Vec<Trivia> = vec![];
+
+  for mut token in tokens {
+    if let TokenType::Comment(text) = token.kind {
+      pending.append(&mut token.leading_trivia);
+      pending.push(if folded.last().map(|t| t.line) == Some(token.line) {
+        Trivia::TrailingComment(text)
+      } else {
+        Trivia::Comment(text)
+      });
+    } else {
+      pending.append(&mut token.leading_trivia);
+      token.leading_trivia = std::mem::take(&mut pending);
+      folded.push(token);
+    }
+  }
+
+  folded
+}
+
 #[cfg(test)]
 mod tests {
-  use crate::{ast_printer::Printer, scanner::Scanner};
+  use crate::ast_printer::Printer;
+  use scanner::Scanner;
 
   use super::*;
 
   #[test]
   fn test_name() {
     let scaner = Scanner::new("test()(1, 2);".to_string());
-    let mut parser = Parser::new(scaner.scan_tokens().unwrap());
+    let mut parser = Parser::new(scaner.collect::<Result<Vec<Token>>>().unwrap());
 
     let ast = parser.parse().unwrap();
 
-    assert_eq!(ast[0].print(), "")
+    assert_eq!(ast[0].print(), "(call (call test) 1 2)")
     //
     // assert_eq!(
     //   ast.print(),
     //   "(([,]([*]([+](1, 2), 2), [==](1, 2)) ? 6 : 7) ? 1 : (2 ? 3 : 4))"
     // )
   }
+
+  #[test]
+  fn test_syntax_errors() {
+    let scanner = Scanner::new("var = 1; var = 2;".to_string());
+    let mut parser = Parser::new(scanner.collect::<Result<Vec<Token>>>().unwrap());
+
+    assert_eq!(
+      parser.parse().unwrap_err().to_string(),
+      "syntax error at line 1, column 5: 'var' should be followed by an identifier
+syntax error at line 1, column 14: 'var' should be followed by an identifier"
+    );
+  }
+
+  #[test]
+  fn test_trailing_commas() {
+    let parse = |source: &str| {
+      let scanner = Scanner::new(source.to_string());
+
+      Parser::new(scanner.collect::<Result<Vec<Token>>>().unwrap()).parse()
+    };
+
+    let ast = parse("fun g(x, y,) {} g(1, (2, 3),);").unwrap();
+
+    assert!(matches!(&ast[0], Stmt::FunDeclaration { parameters, .. } if parameters.len() == 2));
+    assert_eq!(ast[1].print(), "(call g 1 (group (, 2 3)))");
+    assert!(parse("g(,);").is_err());
+    assert!(parse("g(1,,);").is_err());
+
+    for source in [
+      "fun h() {}",
+      "var (a, b,) = 1, 2;",
+      "[1, 2,];",
+      "[];",
+      "print {1: 2,};",
+    ] {
+      assert!(parse(source).is_ok(), "{source}");
+    }
+
+    for source in [
+      "var () = 1;",
+      "[1 2];",
+      "print {1: 2 3: 4};",
+      "fun h(a b) {}",
+    ] {
+      assert!(parse(source).is_err(), "{source}");
+    }
+  }
+
+  #[test]
+  fn test_error_positions() {
+    let scanner = Scanner::new("var a = 1;\nprint a\nprint a;\n  var b = (2;".to_string());
+    let mut parser = Parser::new(scanner.collect::<Result<Vec<Token>>>().unwrap());
+
+    parser.parse_partial().unwrap();
+
+    assert_eq!(
+      parser
+        .errors()
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>(),
+      [
+        "line 3, column 1: ';' expected at the end of a statement",
+        "line 4, column 13: closing paren ')' was not found",
+      ]
+    );
+  }
+
+  #[test]
+  fn test_comment_tokens_are_skipped() {
+    let scanner = Scanner::with_comments("// lead\nf(1); // trail\n".to_string());
+    let mut parser = Parser::with_trivia(scanner.collect::<Result<Vec<Token>>>().unwrap());
+
+    let ast = parser.parse().unwrap();
+
+    assert!(matches!(
+      &ast[..],
+      [
+        Stmt::Trivia { trivia: lead },
+        Stmt::Expression { .. },
+        Stmt::Trivia { trivia: trail },
+      ] if lead == &vec![Trivia::Comment("// lead".to_string())]
+        && trail == &vec![Trivia::TrailingComment("// trail".to_string())]
+    ));
+  }
 }
@@ -1,31 +1,85 @@
+use crate::errors::{LocatedResolveError, ResolveError, ResolveErrors, ResolveWarning};
+use crate::interpreter::BUILT_IN_TYPES;
 use crate::parser::{Expr, Literal, Stmt};
+use anyhow::Result;
 use std::collections::HashMap;
+use tracing::trace;
 
 type Scope = HashMap<String, bool>;
+
+#[derive(Clone, Copy, PartialEq)]
+enum ClassKind {
+  None,
+  Class,
+  Subclass,
+}
 pub(crate) type Locals = HashMap<usize, usize>;
 
 pub(crate) struct Resolver {
   scopes: Vec<Scope>,
   locals: Locals,
+  errors: Vec<LocatedResolveError>,
+  warnings: Vec<ResolveWarning>,
+  function_depth: usize,
+  /// Kind of the innermost class being resolved
+  class: ClassKind,
+  /// Whether the innermost function is a class initializer
+  in_initializer: bool,
+  /// Enclosing loops of the current function, innermost last
+  loops: Vec<EnclosingLoop>,
+}
+
+struct EnclosingLoop {
+  label: Option<String>,
+  /// Set once the loop is found to have a way out
+  has_exit: bool,
+}
+
+impl EnclosingLoop {
+  fn new(label: &Option<String>, has_exit: bool) -> Self {
+    EnclosingLoop {
+      label: label.clone(),
+      has_exit,
+    }
+  }
 }
 
 impl Resolver {
-  pub(crate) fn new() -> Self {
+  pub(crate) fn new(globals: Vec<String>) -> Self {
     Resolver {
       scopes: vec![
-        HashMap::from([("println".to_string(), true), ("clock".to_string(), true)]),
+        globals.into_iter().map(|name| (name, true)).collect(),
         HashMap::new(),
       ],
       locals: HashMap::new(),
+      errors: vec![],
+      warnings: vec![],
+      function_depth: 0,
+      class: ClassKind::None,
+      in_initializer: false,
+      loops: vec![],
     }
   }
 
-  pub(crate) fn resolve_program(mut self, program: &[Stmt]) -> Locals {
+  /// Resolves `program` in the scope left by the programs resolved before,
+  /// returning the locals found in it alone.
+  pub(crate) fn resolve(&mut self, program: &[Stmt]) -> Result<Locals> {
     for stmt in program {
       self.resolve_stmt(stmt);
     }
 
-    self.locals
+    let locals = std::mem::take(&mut self.locals);
+
+    if !self.errors.is_empty() {
+      Err(ResolveErrors(std::mem::take(&mut self.errors)).into())
+    } else {
+      Ok(locals)
+    }
+  }
+
+  /// Takes the warnings found by the programs resolved so far.
+  pub(crate) fn take_warnings(&mut self) -> Vec<ResolveWarning> {
+    std::mem::take(&mut self.warnings)
   }
 
   fn resolve_expr(&mut self, expr: &Expr) {
@@ -46,31 +100,66 @@ impl Resolver {
       Expr::Unary { expr, .. } => {
         self.resolve_expr(expr);
       }
-      Expr::Grouping { expr } => {
+      Expr::Grouping { expr } | Expr::Spread { expr } => {
         self.resolve_expr(expr);
       }
+      Expr::Is {
+        expr,
+        type_name,
+        id,
+        line,
+        column,
+      } => {
+        self.resolve_expr(expr);
+
+        if !BUILT_IN_TYPES.contains(&type_name.as_str()) {
+          self.resolve_local(type_name, id, *line, *column);
+        }
+      }
       Expr::Literal { value } => {
-        if let Literal::Identifier { name, id } = value {
+        if let Literal::Identifier {
+          name,
+          id,
+          line,
+          column,
+        } = value
+        {
+          if name == "this" && self.class == ClassKind::None {
+            self.error(ResolveError::ThisOutsideClass, *line, *column, 4);
+
+            return;
+          }
+
           if let Some(scope) = self.scopes.last() {
             if Some(&false) == scope.get(name) {
               // TODO: report error: "Can't read local variable in its own initializer."
             }
           }
 
-          self.resolve_local(name, id);
+          self.resolve_local(name, id, *line, *column);
         }
       }
       Expr::Assignment {
         name,
         expression,
         id,
+        line,
+        column,
       } => {
         self.resolve_expr(expression);
-        self.resolve_local(name, id);
+        self.resolve_local(name, id, *line, *column);
       }
+      Expr::Update {
+        name,
+        id,
+        line,
+        column,
+        ..
+      } => self.resolve_local(name, id, *line, *column),
       Expr::Call {
         arguments,
         function,
+        ..
       } => {
         self.resolve_expr(function);
 
@@ -78,40 +167,193 @@ impl Resolver {
           self.resolve_expr(arg);
         }
       }
+      Expr::Range { start, end, .. } => {
+        self.resolve_expr(start);
+        self.resolve_expr(end);
+      }
+      Expr::Tuple { elements }
+      | Expr::List { elements }
+      | Expr::Interpolation {
+        expressions: elements,
+        ..
+      } => {
+        for element in elements {
+          self.resolve_expr(element);
+        }
+      }
+      Expr::Map { entries } => {
+        for (key, value) in entries {
+          self.resolve_expr(key);
+          self.resolve_expr(value);
+        }
+      }
+      Expr::Index { object, index } => {
+        self.resolve_expr(object);
+        self.resolve_expr(index);
+      }
+      Expr::Slice { object, start, end } => {
+        self.resolve_expr(object);
+
+        for bound in [start, end].into_iter().flatten() {
+          self.resolve_expr(bound);
+        }
+      }
+      Expr::IndexSet {
+        object,
+        index,
+        value,
+      } => {
+        self.resolve_expr(object);
+        self.resolve_expr(index);
+        self.resolve_expr(value);
+      }
+      Expr::Get { object, .. } => {
+        self.resolve_expr(object);
+      }
+      Expr::Set { object, value, .. } => {
+        self.resolve_expr(value);
+        self.resolve_expr(object);
+      }
+      Expr::Super {
+        id, line, column, ..
+      } => match self.class {
+        ClassKind::None => self.error(ResolveError::SuperOutsideClass, *line, *column, 5),
+        ClassKind::Class => self.error(ResolveError::SuperWithoutSuperclass, *line, *column, 5),
+        ClassKind::Subclass => self.resolve_local("super", id, *line, *column),
+      },
     }
   }
 
   fn resolve_stmt(&mut self, stmt: &Stmt) {
     match stmt {
-      Stmt::Expression { expression } => {
+      Stmt::Assert {
+        condition, message, ..
+      } => {
+        self.resolve_expr(condition);
+
+        if let Some(message) = message {
+          self.resolve_expr(message);
+        }
+      }
+      Stmt::Expression { expression, .. } | Stmt::Print { expression, .. } => {
         self.resolve_expr(expression);
       }
-      Stmt::Declaration { name, initializer } => {
+      Stmt::Declaration {
+        name, initializer, ..
+      } => {
         self.declare(name);
 
         self.resolve_expr(initializer);
 
         self.define(name);
       }
+      Stmt::TupleDeclaration {
+        names, initializer, ..
+      } => {
+        for name in names {
+          self.declare(name);
+        }
+
+        self.resolve_expr(initializer);
+
+        for name in names {
+          self.define(name);
+        }
+      }
+      Stmt::Return {
+        value,
+        line,
+        column,
+      } => {
+        if self.function_depth == 0 {
+          self.error(ResolveError::ReturnOutsideFunction, *line, *column, 6);
+        }
+
+        self
+          .loops
+          .iter_mut()
+          .for_each(|enclosing| enclosing.has_exit = true);
+
+        if let Some(value) = value {
+          if self.in_initializer {
+            self.error(ResolveError::ReturnValueFromInitializer, *line, *column, 6);
+          }
+
+          self.resolve_expr(value);
+        }
+      }
       Stmt::FunDeclaration {
         name,
         body,
         parameters,
+        ..
       } => {
         self.declare(name);
         self.define(name);
 
-        self.begin_scope();
-        for param in parameters {
-          self.declare(param);
-          self.define(param);
+        self.resolve_function(parameters, body, false);
+      }
+      Stmt::Class {
+        name,
+        superclass,
+        methods,
+        ..
+      } => {
+        self.declare(name);
+        self.define(name);
+
+        let enclosing_class = std::mem::replace(&mut self.class, ClassKind::Class);
+
+        if let Some(superclass) = superclass {
+          if let Expr::Literal {
+            value:
+              Literal::Identifier {
+                name: superclass_name,
+                line,
+                column,
+                ..
+              },
+          } = superclass
+          {
+            if superclass_name == name {
+              self.error(
+                ResolveError::InheritanceFromItself,
+                *line,
+                *column,
+                name.chars().count(),
+              );
+            }
+          }
+
+          self.class = ClassKind::Subclass;
+          self.resolve_expr(superclass);
+
+          self.begin_scope();
+          self.define("super");
         }
 
-        for stmt in body {
-          self.resolve_stmt(stmt);
+        self.begin_scope();
+        self.define("this");
+
+        for method in methods {
+          if let Stmt::FunDeclaration {
+            name,
+            parameters,
+            body,
+            ..
+          } = method
+          {
+            self.resolve_function(parameters, body, name == "init");
+          }
         }
 
         self.end_scope();
+
+        if superclass.is_some() {
+          self.end_scope();
+        }
+
+        self.class = enclosing_class;
       }
       Stmt::Block { statements } => {
         self.begin_scope();
@@ -125,14 +367,117 @@ impl Resolver {
       Stmt::While {
         statement,
         condition,
+        label,
+        ..
       } => {
         self.resolve_expr(condition);
-        self.resolve_stmt(statement)
+
+        self.loops.push(EnclosingLoop::new(label, true));
+        self.resolve_stmt(statement);
+        self.loops.pop();
+      }
+      Stmt::Loop { body, label } => {
+        self.loops.push(EnclosingLoop::new(label, false));
+        self.resolve_stmt(body);
+
+        if self
+          .loops
+          .pop()
+          .is_some_and(|enclosing| !enclosing.has_exit)
+        {
+          self.warnings.push(ResolveWarning::LoopWithoutExit);
+        }
+      }
+      Stmt::Break {
+        label,
+        line,
+        column,
+      } => match self.target_loop(label) {
+        // Breaking out of an outer loop also ends the ones in between
+        Ok(index) => self.loops[index..]
+          .iter_mut()
+          .for_each(|enclosing| enclosing.has_exit = true),
+        Err(error) => self.error(
+          error.unwrap_or(ResolveError::BreakOutsideLoop),
+          *line,
+          *column,
+          5,
+        ),
+      },
+      Stmt::Continue {
+        label,
+        line,
+        column,
+      } => {
+        if let Err(error) = self.target_loop(label) {
+          self.error(
+            error.unwrap_or(ResolveError::ContinueOutsideLoop),
+            *line,
+            *column,
+            8,
+          );
+        }
+      }
+      Stmt::For {
+        initializer,
+        condition,
+        increment,
+        body,
+        label,
+        ..
+      } => {
+        self.begin_scope();
+
+        if let Some(initializer) = initializer {
+          self.resolve_stmt(initializer);
+        }
+
+        if let Some(condition) = condition {
+          self.resolve_expr(condition);
+        }
+
+        if let Some(increment) = increment {
+          self.resolve_expr(increment);
+        }
+
+        // Without a condition only `break` or `return` ends the loop
+        self
+          .loops
+          .push(EnclosingLoop::new(label, condition.is_some()));
+        self.resolve_stmt(body);
+
+        if self
+          .loops
+          .pop()
+          .is_some_and(|enclosing| !enclosing.has_exit)
+        {
+          self.warnings.push(ResolveWarning::LoopWithoutExit);
+        }
+
+        self.end_scope();
+      }
+      Stmt::ForIn {
+        variable,
+        iterable,
+        body,
+        label,
+        ..
+      } => {
+        self.resolve_expr(iterable);
+
+        self.begin_scope();
+        self.declare(variable);
+        self.define(variable);
+        self.loops.push(EnclosingLoop::new(label, true));
+        self.resolve_stmt(body);
+        self.loops.pop();
+        self.end_scope();
       }
       Stmt::If {
         condition,
         true_case,
         false_case,
+        ..
       } => {
         self.resolve_expr(condition);
         self.resolve_stmt(true_case);
@@ -140,6 +485,66 @@ impl Resolver {
           self.resolve_stmt(stmt);
         }
       }
+      Stmt::Switch {
+        subject,
+        cases,
+        default,
+        ..
+      } => {
+        self.resolve_expr(subject);
+
+        for (values, body) in cases {
+          for value in values {
+            self.resolve_expr(value);
+          }
+
+          self.resolve_stmt(body);
+        }
+
+        if let Some(default) = default {
+          self.resolve_stmt(default);
+        }
+      }
+      Stmt::Trivia { .. } => {}
+    }
+  }
+
+  fn resolve_function(&mut self, parameters: &[String], body: &[Stmt], is_initializer: bool) {
+    let enclosing_loops = std::mem::take(&mut self.loops);
+    let enclosing_initializer = std::mem::replace(&mut self.in_initializer, is_initializer);
+
+    self.begin_scope();
+    self.function_depth += 1;
+    for param in parameters {
+      self.declare(param);
+      self.define(param);
+    }
+
+    for stmt in body {
+      self.resolve_stmt(stmt);
+    }
+
+    self.function_depth -= 1;
+    self.end_scope();
+
+    self.in_initializer = enclosing_initializer;
+    self.loops = enclosing_loops;
+  }
+
+  /// Index in `loops` of the loop a `break` or `continue` jumps out of. An
+  /// unlabeled jump with no enclosing loop fails with `None`.
+  fn target_loop(&self, label: &Option<String>) -> Result<usize, Option<ResolveError>> {
+    match label {
+      None => self.loops.len().checked_sub(1).ok_or(None),
+      Some(label) => self
+        .loops
+        .iter()
+        .rposition(|enclosing| enclosing.label.as_ref() == Some(label))
+        .ok_or_else(|| {
+          Some(ResolveError::UndefinedLabel {
+            label: label.clone(),
+          })
+        }),
     }
   }
 
@@ -162,8 +567,19 @@ impl Resolver {
       scope.insert(name.to_string(), true);
     }
   }
-  fn resolve_local(&mut self, name: &str, expr_id: &usize) {
-    println!("{:?}", self.scopes);
+  fn error(&mut self, error: ResolveError, line: u32, column: u32, length: usize) {
+    self.errors.push(LocatedResolveError {
+      error,
+      line,
+      column,
+      length,
+    });
+  }
+
+  /// Records the distance to the scope declaring `name`, which is at `line`
+  /// and `column`.
+  fn resolve_local(&mut self, name: &str, expr_id: &usize, line: u32, column: u32) {
+    trace!(name, scopes = ?self.scopes, "resolving");
     for (distance_from_last, scope) in self.scopes.iter().rev().enumerate() {
       if let Some(&true) = scope.get(name) {
         self.locals.insert(*expr_id, distance_from_last);
@@ -172,6 +588,112 @@ impl Resolver {
       }
     }
 
-    panic!("variable {} must be defined before it's used", name);
+    self.error(
+      ResolveError::UndefinedVariable {
+        name: name.to_string(),
+      },
+      line,
+      column,
+      name.chars().count(),
+    );
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::parser::Parser;
+  use scanner::{Scanner, Token};
+
+  fn resolve(source: &str) -> Resolver {
+    let tokens = Scanner::new(source.to_string())
+      .collect::<Result<Vec<Token>>>()
+      .unwrap();
+    let statements = Parser::new(tokens).parse().unwrap();
+    let mut resolver = Resolver::new(vec![]);
+
+    for stmt in &statements {
+      resolver.resolve_stmt(stmt);
+    }
+
+    resolver
+  }
+
+  fn errors(source: &str) -> Vec<ResolveError> {
+    resolve(source)
+      .errors
+      .into_iter()
+      .map(|error| error.error)
+      .collect()
+  }
+
+  #[test]
+  fn test_loop_exits() {
+    assert_eq!(
+      resolve("loop { while (true) { break; } }").warnings,
+      vec![ResolveWarning::LoopWithoutExit]
+    );
+    assert!(resolve("loop { loop { break; } break; }")
+      .warnings
+      .is_empty());
+    assert!(resolve("fun f() { loop { return; } }").warnings.is_empty());
+    assert!(matches!(
+      errors("loop { fun f() { break; } break; }")[..],
+      [ResolveError::BreakOutsideLoop]
+    ));
+    assert!(resolve("a: loop { loop { break a; } }").warnings.is_empty());
+    assert_eq!(
+      resolve("loop { a: loop { break a; } }").warnings,
+      vec![ResolveWarning::LoopWithoutExit]
+    );
+    assert!(matches!(
+      &errors("a: while (true) { continue b; }")[..],
+      [ResolveError::UndefinedLabel { label }] if label == "b"
+    ));
+  }
+
+  #[test]
+  fn test_classes() {
+    assert!(
+      resolve("class A { init() { fun f() { return this; } return; } }")
+        .errors
+        .is_empty()
+    );
+    assert!(matches!(
+      errors("fun f() { return this; }")[..],
+      [ResolveError::ThisOutsideClass]
+    ));
+    assert!(matches!(
+      errors("class A { init() { return 1; } }")[..],
+      [ResolveError::ReturnValueFromInitializer]
+    ));
+    assert!(
+      resolve("class A {} class B < A { f() { return super.f; } }")
+        .errors
+        .is_empty()
+    );
+    assert!(matches!(
+      errors("class A { f() { return super.f; } }")[..],
+      [ResolveError::SuperWithoutSuperclass]
+    ));
+    assert!(matches!(
+      errors("class A {} class B < A {} fun f() { return super.f; }")[..],
+      [ResolveError::SuperOutsideClass]
+    ));
+    assert!(matches!(
+      errors("class A < A {}")[..],
+      [ResolveError::InheritanceFromItself]
+    ));
+  }
+
+  #[test]
+  fn test_error_positions() {
+    let positions = resolve("print a;\nfun f() {\n  b = 1;\n  this;\n}\nbreak;")
+      .errors
+      .iter()
+      .map(|error| (error.line, error.column, error.length))
+      .collect::<Vec<_>>();
+
+    assert_eq!(positions, [(1, 7, 1), (3, 3, 1), (4, 3, 4), (6, 1, 5)]);
   }
 }
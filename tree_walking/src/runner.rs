@@ -1,25 +1,571 @@
-use crate::interpreter::Interpreter;
-use crate::parser::Parser;
-use crate::resolver::Resolver;
+use crate::ast_printer::Printer;
+use crate::cache;
+use crate::diagnostics::{Diagnostic, DiagnosticFormat};
+use crate::environment::Environment;
+use crate::interpreter::{Interpreter, Value};
+use crate::natives;
+use crate::parser::{Parser, Stmt};
+use crate::resolver::{Locals, Resolver};
 use anyhow::Result;
-use scanner::{Scanner, Token};
+use scanner::{ScanError, Scanner, Token, TokenType};
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::Duration;
+use thiserror::Error;
+use tracing::{debug, debug_span, trace};
+use web_time::Instant;
 
-pub fn run(source: String) -> Result<()> {
-  let scanner = Scanner::new(source);
+/// A shared writer receiving program output.
+pub type Sink = Rc<RefCell<dyn Write>>;
 
-  let tokens = scanner.collect::<Result<Vec<Token>>>()?;
+/// How long each phase of running a program took, in the order they ran.
+pub type Timings = Rc<RefCell<Vec<(&'static str, Duration)>>>;
 
-  let mut parser = Parser::new(tokens);
+pub struct RunOptions {
+  /// Names of optional native modules (e.g. `fs`, `math`) the program may use
+  pub allowed_modules: Vec<String>,
+  /// Receives the output of `println` and `write`
+  pub stdout: Sink,
+  /// Receives the output of `eprintln`
+  pub stderr: Sink,
+  /// Directory caching parsed programs between runs, disabled when `None`
+  pub cache_dir: Option<PathBuf>,
+  /// How warnings written to `stderr` are formatted
+  pub diagnostic_format: DiagnosticFormat,
+  /// Command line arguments passed to the program, returned by `args()`
+  pub args: Vec<String>,
+  /// Receives the duration of each phase when set
+  pub timings: Option<Timings>,
+}
 
-  let statements = parser.parse()?;
-  let resolver = Resolver::new();
+impl Default for RunOptions {
+  fn default() -> Self {
+    RunOptions {
+      allowed_modules: vec![],
+      stdout: Rc::new(RefCell::new(io::stdout())),
+      stderr: Rc::new(RefCell::new(io::stderr())),
+      cache_dir: None,
+      diagnostic_format: DiagnosticFormat::default(),
+      args: vec![],
+      timings: None,
+    }
+  }
+}
 
-  let locals = resolver.resolve_program(&statements);
-  println!("{:?}", locals);
+/// Why a program failed to run.
+#[derive(Error, Debug)]
+pub enum RunError {
+  /// The program could not be scanned, parsed or resolved, or asked for
+  /// native modules that are not available
+  #[error(transparent)]
+  Compile(anyhow::Error),
+  /// The program failed while running
+  #[error(transparent)]
+  Runtime(anyhow::Error),
+}
 
-  let interpreter = Interpreter::new(locals);
+pub fn run(source: String) -> Result<(), RunError> {
+  run_with_options(source, &RunOptions::default())
+}
 
-  interpreter.interpret_program(statements)?;
+pub fn run_with_options(source: String, options: &RunOptions) -> Result<(), RunError> {
+  load(source, options)?;
 
   Ok(())
 }
+
+/// A program whose top-level code has already run. It keeps its global state,
+/// so the functions it declares can be called afterwards.
+pub struct Program {
+  interpreter: Interpreter,
+  environment: Rc<RefCell<Environment>>,
+  functions: Vec<String>,
+}
+
+impl Program {
+  /// Names of the functions declared at the top level, in source order.
+  pub fn functions(&self) -> &[String] {
+    &self.functions
+  }
+
+  /// Calls a top-level function without arguments.
+  pub fn call(&mut self, name: &str) -> Result<()> {
+    self.interpreter.call_function(&self.environment, name)?;
+
+    Ok(())
+  }
+}
+
+/// Runs source entered piece by piece, like lines of a REPL. Each piece
+/// sees the declarations of the ones run before it.
+pub struct Session {
+  interpreter: Interpreter,
+  resolver: Resolver,
+  environment: Rc<RefCell<Environment>>,
+  stderr: Sink,
+  diagnostic_format: DiagnosticFormat,
+  /// Names of the natives the pieces can call
+  natives: Vec<String>,
+  /// First identifier id of the next piece
+  next_id: usize,
+}
+
+impl Session {
+  pub fn new(options: &RunOptions) -> Result<Self> {
+    let natives = natives::load(&options.allowed_modules)?;
+    let native_names = natives
+      .iter()
+      .map(|native| native.name().to_string())
+      .collect::<Vec<String>>();
+    let resolver = Resolver::new(native_names.clone());
+
+    let mut interpreter = Interpreter::new(
+      Locals::new(),
+      natives,
+      Rc::clone(&options.stdout),
+      Rc::clone(&options.stderr),
+    );
+    interpreter.args = options.args.clone();
+    let environment = interpreter.top_environment();
+
+    Ok(Session {
+      interpreter,
+      resolver,
+      environment,
+      stderr: Rc::clone(&options.stderr),
+      diagnostic_format: options.diagnostic_format,
+      natives: native_names,
+      next_id: 1,
+    })
+  }
+
+  /// Runs `source` and returns the value of its last statement if that is an
+  /// expression statement with a value other than `nil`. The semicolon
+  /// ending the source may be left out.
+  pub fn eval(&mut self, source: &str) -> Result<Option<String>> {
+    let tokens = Scanner::new(complete_entry(source)?).collect::<Result<Vec<Token>>>()?;
+
+    let mut parser = Parser::continuing_from(tokens, self.next_id);
+    let statements = parser.parse();
+    self.next_id = parser.unused_id();
+
+    let statements = statements?;
+    let locals = self.resolver.resolve(&statements);
+    report_warnings(&mut self.resolver, &self.stderr, self.diagnostic_format);
+
+    let locals = locals?;
+    self.interpreter.locals.extend(locals);
+
+    let value = self
+      .interpreter
+      .interpret_entry(&statements, &self.environment)?;
+
+    Ok(
+      value
+        .filter(|value| !matches!(value.as_ref(), Value::Nil))
+        .map(|value| value.to_string()),
+    )
+  }
+
+  /// Names and values of the variables declared by the code run so far,
+  /// sorted by name.
+  pub fn bindings(&self) -> Vec<(String, String)> {
+    self
+      .environment
+      .borrow()
+      .bindings()
+      .into_iter()
+      .map(|(name, value)| (name, value.debug_string()))
+      .collect()
+  }
+
+  /// Names of the natives the code can call, including those of the allowed
+  /// modules.
+  pub fn natives(&self) -> &[String] {
+    &self.natives
+  }
+}
+
+/// Adds the semicolon that may be left out at the end of a REPL entry.
+pub fn complete_entry(source: &str) -> Result<String> {
+  let tokens = Scanner::new(source.to_string()).collect::<Result<Vec<Token>>>()?;
+  let mut source = source.to_string();
+
+  if let [.., last, _] = tokens.as_slice() {
+    if !matches!(last.kind, TokenType::Semicolon | TokenType::RightBrace) {
+      source.insert(last.span.end, ';');
+    }
+  }
+
+  Ok(source)
+}
+
+/// Whether `source` ends inside a string or with brackets left open, so a
+/// REPL should read another line before running it.
+pub fn needs_more_input(source: &str) -> bool {
+  let mut depth = 0;
+
+  for token in Scanner::new(source.to_string()) {
+    match token {
+      Ok(token) => match token.kind {
+        TokenType::LeftParen | TokenType::LeftBrace | TokenType::LeftBracket => depth += 1,
+        TokenType::RightParen | TokenType::RightBrace | TokenType::RightBracket => depth -= 1,
+        _ => {}
+      },
+      Err(error) => {
+        return matches!(
+          error.downcast_ref(),
+          Some(ScanError::UnterminatedString { .. })
+        )
+      }
+    }
+  }
+
+  depth > 0
+}
+
+/// How `tokens` lists the tokens.
+pub enum TokenFormat {
+  /// One token per line: its line and column, byte span, type and lexeme
+  Text,
+  /// An array of token objects
+  Json,
+}
+
+/// Lists the tokens of `source`.
+pub fn tokens(source: String, format: TokenFormat) -> Result<String> {
+  let tokens = Scanner::new(source).collect::<Result<Vec<Token>>>()?;
+
+  match format {
+    TokenFormat::Text => Ok(
+      tokens
+        .iter()
+        .map(|token| {
+          format!(
+            "{:<8} {:<10} {:?} {:?}",
+            format!("{}:{}", token.line, token.column),
+            format!("{:?}", token.span),
+            token.kind,
+            token.lexeme
+          )
+        })
+        .collect::<Vec<String>>()
+        .join("\n"),
+    ),
+    TokenFormat::Json => Ok(serde_json::to_string_pretty(&tokens)?),
+  }
+}
+
+/// How `ast` prints the syntax tree.
+pub enum AstFormat {
+  /// Nested Rust-like structures with every field
+  Pretty,
+  /// An array of statement objects
+  Json,
+  /// One S-expression per top-level statement, e.g. `(print (+ 1 2))`
+  Sexpr,
+}
+
+/// Parses `source` and returns its syntax tree without running it.
+pub fn ast(source: String, format: AstFormat) -> Result<String> {
+  let statements = parse(source, None)?;
+
+  match format {
+    AstFormat::Pretty => Ok(format!("{:#?}", statements)),
+    AstFormat::Json => Ok(serde_json::to_string_pretty(&statements)?),
+    AstFormat::Sexpr => Ok(
+      statements
+        .iter()
+        .map(Printer::print)
+        .collect::<Vec<String>>()
+        .join("\n"),
+    ),
+  }
+}
+
+/// Scans and parses `source`.
+fn parse(source: String, timings: Option<&Timings>) -> Result<Vec<Stmt>> {
+  let tokens = timed(timings, "scan", || {
+    let _span = debug_span!("scan").entered();
+
+    let scanner = Scanner::new(source);
+
+    let tokens = scanner.collect::<Result<Vec<Token>>>()?;
+    debug!(tokens = tokens.len(), "scanned");
+
+    Ok::<_, anyhow::Error>(tokens)
+  })?;
+
+  timed(timings, "parse", || {
+    let _span = debug_span!("parse").entered();
+
+    let mut parser = Parser::new(tokens);
+
+    let statements = parser.parse()?;
+    debug!(
+      statements = statements.len(),
+      nodes = statements.iter().map(Stmt::node_count).sum::<usize>(),
+      "parsed"
+    );
+
+    Ok(statements)
+  })
+}
+
+/// Runs `phase`, adding how long it took to `timings`. The clock is only
+/// read when timings are collected.
+fn timed<T>(timings: Option<&Timings>, name: &'static str, phase: impl FnOnce() -> T) -> T {
+  let start = timings.map(|_| Instant::now());
+  let result = phase();
+
+  if let (Some(timings), Some(start)) = (timings, start) {
+    timings.borrow_mut().push((name, start.elapsed()));
+  }
+
+  result
+}
+
+/// Writes the warnings found by `resolver` to `stderr`. Failing to write them
+/// does not stop the program.
+fn report_warnings(resolver: &mut Resolver, stderr: &Sink, format: DiagnosticFormat) {
+  for warning in resolver.take_warnings() {
+    let _ = writeln!(
+      stderr.borrow_mut(),
+      "{}",
+      Diagnostic::from(&warning).render(format, None)
+    );
+  }
+}
+
+/// Runs the top-level code of `source` and returns the resulting program.
+pub fn load(source: String, options: &RunOptions) -> Result<Program, RunError> {
+  let natives = natives::load(&options.allowed_modules).map_err(RunError::Compile)?;
+
+  let timings = options.timings.as_ref();
+
+  let statements = match &options.cache_dir {
+    Some(dir) => match timed(timings, "cache", || cache::load(dir, &source)) {
+      Some(statements) => {
+        debug!(statements = statements.len(), "loaded from cache");
+
+        statements
+      }
+      None => {
+        let statements = parse(source.clone(), timings).map_err(RunError::Compile)?;
+        cache::store(dir, &source, &statements);
+
+        statements
+      }
+    },
+    None => parse(source, timings).map_err(RunError::Compile)?,
+  };
+
+  let locals = timed(timings, "resolve", || {
+    let _span = debug_span!("resolve").entered();
+
+    let mut resolver = Resolver::new(
+      natives
+        .iter()
+        .map(|native| native.name().to_string())
+        .collect(),
+    );
+
+    let locals = resolver.resolve(&statements);
+    report_warnings(&mut resolver, &options.stderr, options.diagnostic_format);
+
+    let locals = locals.map_err(RunError::Compile)?;
+    debug!(locals = locals.len(), "resolved");
+    trace!(?locals);
+
+    Ok(locals)
+  })?;
+
+  let _span = debug_span!("execute").entered();
+
+  let mut interpreter = Interpreter::new(
+    locals,
+    natives,
+    Rc::clone(&options.stdout),
+    Rc::clone(&options.stderr),
+  );
+  interpreter.args = options.args.clone();
+
+  let environment = timed(timings, "execute", || {
+    interpreter.interpret_program(&statements)
+  })
+  .map_err(RunError::Runtime)?;
+
+  let functions = statements
+    .iter()
+    .filter_map(|stmt| match stmt {
+      Stmt::FunDeclaration { name, .. } => Some(name.clone()),
+      _ => None,
+    })
+    .collect();
+
+  Ok(Program {
+    interpreter,
+    environment,
+    functions,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_load() {
+    let stdout = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let options = RunOptions {
+      stdout: stdout.clone(),
+      ..RunOptions::default()
+    };
+
+    let mut program = load(
+      "var n = 0; fun tick() { n = n + 1; write(n); } fun other() {} tick();".to_string(),
+      &options,
+    )
+    .unwrap();
+
+    assert_eq!(program.functions(), ["tick", "other"]);
+
+    program.call("tick").unwrap();
+    program.call("tick").unwrap();
+
+    assert_eq!(String::from_utf8(stdout.take()).unwrap(), "123");
+    assert!(program.call("n").is_err());
+    assert!(program.call("missing").is_err());
+  }
+
+  #[test]
+  fn test_session() {
+    let stdout = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let options = RunOptions {
+      stdout: stdout.clone(),
+      ..RunOptions::default()
+    };
+
+    let mut session = Session::new(&options).unwrap();
+
+    assert_eq!(session.eval("var n = 1;").unwrap(), None);
+    assert_eq!(
+      session.eval("fun double(x) { return x * 2; }").unwrap(),
+      None
+    );
+    assert_eq!(session.eval("double(n + 2)").unwrap().as_deref(), Some("6"));
+    assert_eq!(session.eval("n = n + 1; n").unwrap().as_deref(), Some("2"));
+    assert_eq!(session.eval("write(n)").unwrap(), None);
+    assert_eq!(session.eval("").unwrap(), None);
+
+    // Errors leave the session usable
+    assert!(session.eval("print missing;").is_err());
+    assert!(session.eval("var = 1;").is_err());
+    assert!(session.eval("double(nil);").is_err());
+    assert_eq!(session.eval("double(n)").unwrap().as_deref(), Some("4"));
+
+    assert_eq!(String::from_utf8(stdout.take()).unwrap(), "2");
+
+    let bindings = session.bindings();
+
+    assert_eq!(
+      bindings,
+      [
+        ("double".to_string(), "<fn double/1>".to_string()),
+        ("n".to_string(), "2".to_string())
+      ]
+    );
+    assert!(session.natives().contains(&"println".to_string()));
+  }
+
+  #[test]
+  fn test_complete_entry() {
+    assert_eq!(complete_entry("1 + 2 // sum").unwrap(), "1 + 2; // sum");
+    assert_eq!(complete_entry("print 1;").unwrap(), "print 1;");
+    assert_eq!(complete_entry("if (x) {}").unwrap(), "if (x) {}");
+    assert_eq!(complete_entry("").unwrap(), "");
+  }
+
+  #[test]
+  fn test_tokens() {
+    assert_eq!(
+      tokens("print x;".to_string(), TokenFormat::Text).unwrap(),
+      [
+        "1:1      0..5       Print \"print\"",
+        "1:7      6..7       Identifier(\"x\") \"x\"",
+        "1:8      7..8       Semicolon \";\"",
+        "1:9      8..8       Eof \"\"",
+      ]
+      .join("\n")
+    );
+
+    let json = tokens("1".to_string(), TokenFormat::Json).unwrap();
+    let json = serde_json::from_str::<serde_json::Value>(&json).unwrap();
+
+    assert_eq!(json[0]["kind"]["Number"], 1.0);
+    assert_eq!(json[0]["span"]["end"], 1);
+    assert_eq!(json[1]["kind"], "Eof");
+  }
+
+  #[test]
+  fn test_needs_more_input() {
+    assert!(needs_more_input("fun f() {"));
+    assert!(needs_more_input("print [1, (2"));
+    assert!(needs_more_input("var s = \"line"));
+    assert!(!needs_more_input("fun f() {}"));
+    assert!(!needs_more_input("print 1);"));
+    assert!(!needs_more_input("print @;"));
+  }
+
+  #[test]
+  fn test_programs_on_separate_threads() {
+    let handles = (0..4)
+      .map(|n| {
+        std::thread::spawn(move || {
+          let stdout = Rc::new(RefCell::new(Vec::<u8>::new()));
+          let options = RunOptions {
+            stdout: stdout.clone(),
+            ..RunOptions::default()
+          };
+          let source = format!("fun f(x) {{ var y = x; return y * 2; }} write(f({n}));");
+
+          run_with_options(source, &options).unwrap();
+
+          String::from_utf8(stdout.take()).unwrap()
+        })
+      })
+      .collect::<Vec<_>>();
+
+    let outputs = handles
+      .into_iter()
+      .map(|handle| handle.join().unwrap())
+      .collect::<Vec<String>>();
+
+    assert_eq!(outputs, ["0", "2", "4", "6"]);
+  }
+
+  #[test]
+  fn test_timings() {
+    let timings = Timings::default();
+    let options = RunOptions {
+      timings: Some(timings.clone()),
+      ..RunOptions::default()
+    };
+
+    run_with_options("var a = 1;".to_string(), &options).unwrap();
+    assert!(run_with_options("print b;".to_string(), &options).is_err());
+
+    let phases = timings
+      .borrow()
+      .iter()
+      .map(|(phase, _)| *phase)
+      .collect::<Vec<&str>>();
+
+    assert_eq!(
+      phases,
+      ["scan", "parse", "resolve", "execute", "scan", "parse", "resolve"]
+    );
+  }
+}
@@ -0,0 +1,51 @@
+//! Times stack-heavy programs in the VM, to compare value representations:
+//!
+//! ```text
+//! cargo bench -p vm --bench stack
+//! cargo bench -p vm --bench stack --features nan-boxing
+//! ```
+
+use std::time::{Duration, Instant};
+
+const RUNS: usize = 10;
+
+const PROGRAMS: [(&str, &str); 2] = [
+  (
+    "arithmetic",
+    "var sum = 0;
+     for (var i = 0; i < 200000; i = i + 1) {
+       var a = i * 2; var b = a - i; var c = (a + b) / 3;
+       sum = sum + c;
+     }",
+  ),
+  (
+    "locals",
+    "{
+       var a = 1; var b = true; var c = nil; var d = \"d\";
+       for (var i = 0; i < 200000; i = i + 1) {
+         var e = a; var f = b; var g = c; var h = d;
+         a = e; b = f and g == nil; c = g; d = h;
+       }
+     }",
+  ),
+];
+
+fn main() {
+  let representation = if cfg!(feature = "nan-boxing") { "nan-boxing" } else { "enum" };
+
+  println!("values: {representation}");
+
+  for (name, source) in PROGRAMS {
+    let mut times = (0..RUNS)
+      .map(|_| {
+        let start = Instant::now();
+        vm::runner::run(source.to_string()).unwrap();
+        start.elapsed()
+      })
+      .collect::<Vec<Duration>>();
+
+    times.sort();
+
+    println!("  {:<15} median {:>12.3?}", name, times[RUNS / 2]);
+  }
+}
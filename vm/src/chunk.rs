@@ -1,6 +1,23 @@
+use std::collections::HashMap;
 use std::fmt;
-use std::fmt::{Write, Display};
+use std::fmt::Write;
+use thiserror::Error;
+use crate::heap::{ObjHeap, ObjRef};
 
+/// Largest index `ConstantLong` can encode.
+const MAX_CONSTANT_INDEX: usize = (1 << 24) - 1;
+
+#[derive(Error, Debug, Clone)]
+pub(crate) enum ChunkError {
+  #[error("too many constants in one chunk")]
+  TooManyConstants,
+  #[error("too much code to jump over")]
+  JumpTooLong,
+}
+
+/// A decoded instruction. Chunks store instructions as bytes: the opcode
+/// followed by its operands, one byte each except for two-byte jump offsets
+/// and the three-byte index of `ConstantLong`.
 #[derive(Clone)]
 pub(crate) enum Opcode {
   Return,
@@ -12,33 +29,235 @@ pub(crate) enum Opcode {
   Equal,
   Greater,
   Less,
+  /// Dedicated instead of a negated `Less`, which a NaN operand would make
+  /// true
+  GreaterEqual,
+  LessEqual,
   Negate,
   Add,
   Multiply,
   Subtract,
-  Divide
+  Divide,
+  Pop,
+  /// The constant at `index` holds the variable name
+  DefineGlobal { index: usize },
+  GetGlobal { index: usize },
+  SetGlobal { index: usize },
+  /// Locals live on the stack, `slot` counts from its bottom
+  GetLocal { slot: usize },
+  SetLocal { slot: usize },
+  /// Jumps skip `offset` bytes forward, counting from the next instruction
+  Jump { offset: usize },
+  /// Leaves the condition on the stack
+  JumpIfFalse { offset: usize },
+  /// Jumps `offset` bytes backward, counting from the next instruction
+  Loop { offset: usize },
+  Print,
+  /// The callee sits on the stack below its arguments
+  Call { arg_count: usize },
+  /// `Constant` for indices that don't fit in a byte
+  ConstantLong { index: usize },
+  /// Bitwise operators work on numbers truncated to integers
+  BitAnd,
+  BitOr,
+  BitXor,
+  ShiftLeft,
+  ShiftRight,
+  /// Pops the message of a failed `assert`, nil without one, and stops
+  AssertFailed,
+  /// Replaces the value with whether it has the type `type_`
+  Is { type_: ValueType },
+}
+
+/// Built-in types the `is` operator can check for.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum ValueType {
+  Number,
+  String,
+  Bool,
+  Nil,
+  Function,
+}
+
+impl ValueType {
+  /// Every type in declaration order, so `ALL[type_ as usize] == type_`.
+  pub(crate) const ALL: [ValueType; 5] =
+    [ValueType::Number, ValueType::String, ValueType::Bool, ValueType::Nil, ValueType::Function];
+
+  /// Name of the type in source code.
+  pub(crate) fn name(self) -> &'static str {
+    match self {
+      ValueType::Number => "Number",
+      ValueType::String => "String",
+      ValueType::Bool => "Bool",
+      ValueType::Nil => "Nil",
+      ValueType::Function => "Function",
+    }
+  }
 }
 
-#[derive(Debug, Clone)]
+impl Opcode {
+  /// Mnemonic used in listings.
+  pub(crate) fn name(&self) -> &'static str {
+    match self {
+      Opcode::Return => "RETURN",
+      Opcode::Constant { .. } => "CONSTANT",
+      Opcode::Not => "NOT",
+      Opcode::True => "TRUE",
+      Opcode::False => "FALSE",
+      Opcode::Nil => "NIL",
+      Opcode::Equal => "EQUAL",
+      Opcode::Greater => "GREATER",
+      Opcode::Less => "LESS",
+      Opcode::GreaterEqual => "GREATER_EQUAL",
+      Opcode::LessEqual => "LESS_EQUAL",
+      Opcode::Negate => "NEGATE",
+      Opcode::Add => "ADD",
+      Opcode::Multiply => "MULT",
+      Opcode::Subtract => "SUB",
+      Opcode::Divide => "DIV",
+      Opcode::Pop => "POP",
+      Opcode::DefineGlobal { .. } => "DEFINE_GLOBAL",
+      Opcode::GetGlobal { .. } => "GET_GLOBAL",
+      Opcode::SetGlobal { .. } => "SET_GLOBAL",
+      Opcode::GetLocal { .. } => "GET_LOCAL",
+      Opcode::SetLocal { .. } => "SET_LOCAL",
+      Opcode::Jump { .. } => "JUMP",
+      Opcode::JumpIfFalse { .. } => "JUMP_IF_FALSE",
+      Opcode::Loop { .. } => "LOOP",
+      Opcode::Print => "PRINT",
+      Opcode::Call { .. } => "CALL",
+      Opcode::ConstantLong { .. } => "CONSTANT_LONG",
+      Opcode::BitAnd => "BIT_AND",
+      Opcode::BitOr => "BIT_OR",
+      Opcode::BitXor => "BIT_XOR",
+      Opcode::ShiftLeft => "SHIFT_LEFT",
+      Opcode::ShiftRight => "SHIFT_RIGHT",
+      Opcode::AssertFailed => "ASSERT_FAILED",
+      Opcode::Is { .. } => "IS",
+    }
+  }
+
+  /// The byte identifying the instruction in a chunk.
+  fn byte(&self) -> u8 {
+    match self {
+      Opcode::Return => 0,
+      Opcode::Constant { .. } => 1,
+      Opcode::Not => 2,
+      Opcode::True => 3,
+      Opcode::False => 4,
+      Opcode::Nil => 5,
+      Opcode::Equal => 6,
+      Opcode::Greater => 7,
+      Opcode::Less => 8,
+      Opcode::Negate => 9,
+      Opcode::Add => 10,
+      Opcode::Multiply => 11,
+      Opcode::Subtract => 12,
+      Opcode::Divide => 13,
+      Opcode::Pop => 14,
+      Opcode::DefineGlobal { .. } => 15,
+      Opcode::GetGlobal { .. } => 16,
+      Opcode::SetGlobal { .. } => 17,
+      Opcode::GetLocal { .. } => 18,
+      Opcode::SetLocal { .. } => 19,
+      Opcode::Jump { .. } => 20,
+      Opcode::JumpIfFalse { .. } => 21,
+      Opcode::Loop { .. } => 22,
+      Opcode::Print => 23,
+      Opcode::Call { .. } => 24,
+      Opcode::ConstantLong { .. } => 25,
+      Opcode::BitAnd => 26,
+      Opcode::BitOr => 27,
+      Opcode::BitXor => 28,
+      Opcode::ShiftLeft => 29,
+      Opcode::ShiftRight => 30,
+      Opcode::AssertFailed => 31,
+      Opcode::Is { .. } => 32,
+      Opcode::GreaterEqual => 33,
+      Opcode::LessEqual => 34,
+    }
+  }
+
+  /// Number of bytes taken by the instruction, operands included.
+  pub(crate) fn size(&self) -> usize {
+    match self {
+      Opcode::Constant { .. } |
+      Opcode::DefineGlobal { .. } |
+      Opcode::GetGlobal { .. } |
+      Opcode::SetGlobal { .. } |
+      Opcode::GetLocal { .. } |
+      Opcode::SetLocal { .. } |
+      Opcode::Call { .. } |
+      Opcode::Is { .. } => 2,
+      Opcode::Jump { .. } | Opcode::JumpIfFalse { .. } | Opcode::Loop { .. } => 3,
+      Opcode::ConstantLong { .. } => 4,
+      _ => 1,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub(crate) enum Value {
   Number(f64),
-  String(String),
   Bool(bool),
-  Nil
+  Nil,
+  /// A string or another object allocated in the `ObjHeap`
+  Obj(ObjRef)
 }
 
-impl Display for Value {
-  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-    write!(f, "{}", match self {
-      Value::Number(v) => v.to_string(),
-      Value::String(v) => v.to_string(),
-      Value::Nil => "nil".to_string(),
-      Value::Bool(v) => v.to_string()
-    })
+/// How values are stored on the VM stack: as they are, or NaN-boxed in 8
+/// bytes with the `nan-boxing` feature.
+/// Hashable form of a constant, used to find an earlier equal constant.
+/// Numbers are compared bitwise, so `0` and `-0` stay separate constants.
+#[derive(PartialEq, Eq, Hash)]
+enum ConstantKey {
+  Number(u64),
+  Bool(bool),
+  Nil,
+  Obj(ObjRef),
+}
+
+impl From<Value> for ConstantKey {
+  fn from(value: Value) -> Self {
+    match value {
+      Value::Number(n) => ConstantKey::Number(n.to_bits()),
+      Value::Bool(b) => ConstantKey::Bool(b),
+      Value::Nil => ConstantKey::Nil,
+      Value::Obj(handle) => ConstantKey::Obj(handle),
+    }
+  }
+}
+
+#[cfg(not(feature = "nan-boxing"))]
+pub(crate) type Slot = Value;
+#[cfg(feature = "nan-boxing")]
+pub(crate) type Slot = crate::nan_box::NanBox;
+
+#[cfg(not(feature = "nan-boxing"))]
+impl Value {
+  pub(crate) fn pack(self) -> Slot {
+    self
+  }
+
+  pub(crate) fn unpack(self) -> Value {
+    self
   }
 }
 
 impl Value {
+  /// Values of different types are never equal. Objects are compared by
+  /// identity, which for interned strings means by contents.
+  pub(crate) fn is_equal(&self, other: &Value) -> bool {
+    match (self, other) {
+      (Value::Number(a), Value::Number(b)) => a == b,
+      (Value::Obj(a), Value::Obj(b)) => a == b,
+      (Value::Bool(a), Value::Bool(b)) => a == b,
+      (Value::Nil, Value::Nil) => true,
+      _ => false,
+    }
+  }
+
   pub(crate) fn is_truthy(&self) -> bool {
     match self {
       Value::Nil => false,
@@ -50,7 +269,10 @@ impl Value {
 
 pub(crate) struct Chunk {
   constants: Vec<Value>,
-  pub(crate) code: Vec<Opcode>,
+  /// Index of every constant of the pool
+  constant_indices: HashMap<ConstantKey, usize>,
+  pub(crate) code: Vec<u8>,
+  /// Source line of every byte of `code`
   lines: Vec<u32>,
 }
 
@@ -59,103 +281,352 @@ impl Chunk {
     Chunk {
       code: vec![],
       constants: vec![],
+      constant_indices: HashMap::new(),
       lines: vec![],
     }
   }
 
-  pub(crate) fn push_constant(&mut self, value: Value, line: u32) {
+  /// Reassembles a chunk from its parts, as saved in a `.loxb` file.
+  pub(crate) fn from_parts(constants: Vec<Value>, code: Vec<u8>, lines: Vec<u32>) -> Self {
+    let constant_indices = constants
+      .iter()
+      .enumerate()
+      .map(|(index, constant)| (ConstantKey::from(*constant), index))
+      .collect();
+
+    Chunk {
+      constants,
+      constant_indices,
+      code,
+      lines,
+    }
+  }
+
+  /// Adds `value` to the constant pool and returns its index, which fits
+  /// in three operand bytes. Equal values, like a variable name used many
+  /// times, are stored only once.
+  pub(crate) fn add_constant(&mut self, value: Value) -> Result<usize, ChunkError> {
+    let key = ConstantKey::from(value);
+
+    if let Some(&index) = self.constant_indices.get(&key) {
+      return Ok(index);
+    }
+
+    if self.constants.len() > MAX_CONSTANT_INDEX {
+      return Err(ChunkError::TooManyConstants);
+    }
+
     self.constants.push(value);
+    self.constant_indices.insert(key, self.constants.len() - 1);
+
+    Ok(self.constants.len() - 1)
+  }
 
-    let constant_index = self.constants.len() - 1;
+  pub(crate) fn push_constant(&mut self, value: Value, line: u32) -> Result<(), ChunkError> {
+    let index = self.add_constant(value)?;
 
-    self.push_code(
-      Opcode::Constant {
-        index: constant_index,
-      },
-      line,
-    );
+    if index > u8::MAX as usize {
+      self.push_code(Opcode::ConstantLong { index }, line);
+    } else {
+      self.push_code(Opcode::Constant { index }, line);
+    }
+
+    Ok(())
   }
 
   pub(crate) fn get_constant(&self, index: usize) -> &Value {
     &self.constants[index]
   }
 
+  pub(crate) fn constants(&self) -> &[Value] {
+    &self.constants
+  }
+
+  /// Source line of the byte at `offset`.
+  pub(crate) fn line(&self, offset: usize) -> u32 {
+    self.lines[offset]
+  }
+
+  /// Encodes `code`. The compiler makes sure its operands fit.
   pub(crate) fn push_code(&mut self, code: Opcode, line: u32) {
-    self.code.push(code);
-    self.lines.push(line);
+    let byte = |operand: usize| u8::try_from(operand).expect("operand does not fit in a byte");
+
+    self.code.push(code.byte());
+
+    match code {
+      Opcode::Constant { index } |
+      Opcode::DefineGlobal { index } |
+      Opcode::GetGlobal { index } |
+      Opcode::SetGlobal { index } => self.code.push(byte(index)),
+      Opcode::GetLocal { slot } | Opcode::SetLocal { slot } => self.code.push(byte(slot)),
+      Opcode::Call { arg_count } => self.code.push(byte(arg_count)),
+      Opcode::Is { type_ } => self.code.push(type_ as u8),
+      Opcode::ConstantLong { index } => {
+        assert!(index <= MAX_CONSTANT_INDEX, "constant index does not fit in three bytes");
+
+        self.code.extend(&(index as u32).to_be_bytes()[1..]);
+      }
+      Opcode::Jump { offset } | Opcode::JumpIfFalse { offset } | Opcode::Loop { offset } => {
+        let offset = u16::try_from(offset).expect("jump offset does not fit in two bytes");
+
+        self.code.extend(offset.to_be_bytes());
+      }
+      _ => {}
+    }
+
+    self.lines.resize(self.code.len(), line);
+  }
+
+  /// Decodes the instruction starting at byte `offset`.
+  pub(crate) fn decode(&self, offset: usize) -> Opcode {
+    self
+      .try_decode(offset)
+      .unwrap_or_else(|| panic!("invalid instruction at {}", offset))
+  }
+
+  /// Decodes the instruction starting at byte `offset`, or returns `None` if
+  /// its opcode is unknown or its operands run past the end of the code.
+  pub(crate) fn try_decode(&self, offset: usize) -> Option<Opcode> {
+    let byte = |n: usize| self.code.get(offset + n).map(|byte| *byte as usize);
+    let short_at = |n: usize| Some(byte(n)? << 8 | byte(n + 1)?);
+    let short = || short_at(1);
+
+    Some(match byte(0)? {
+      0 => Opcode::Return,
+      1 => Opcode::Constant { index: byte(1)? },
+      2 => Opcode::Not,
+      3 => Opcode::True,
+      4 => Opcode::False,
+      5 => Opcode::Nil,
+      6 => Opcode::Equal,
+      7 => Opcode::Greater,
+      8 => Opcode::Less,
+      9 => Opcode::Negate,
+      10 => Opcode::Add,
+      11 => Opcode::Multiply,
+      12 => Opcode::Subtract,
+      13 => Opcode::Divide,
+      14 => Opcode::Pop,
+      15 => Opcode::DefineGlobal { index: byte(1)? },
+      16 => Opcode::GetGlobal { index: byte(1)? },
+      17 => Opcode::SetGlobal { index: byte(1)? },
+      18 => Opcode::GetLocal { slot: byte(1)? },
+      19 => Opcode::SetLocal { slot: byte(1)? },
+      20 => Opcode::Jump { offset: short()? },
+      21 => Opcode::JumpIfFalse { offset: short()? },
+      22 => Opcode::Loop { offset: short()? },
+      23 => Opcode::Print,
+      24 => Opcode::Call { arg_count: byte(1)? },
+      25 => Opcode::ConstantLong { index: byte(1)? << 16 | short_at(2)? },
+      26 => Opcode::BitAnd,
+      27 => Opcode::BitOr,
+      28 => Opcode::BitXor,
+      29 => Opcode::ShiftLeft,
+      30 => Opcode::ShiftRight,
+      31 => Opcode::AssertFailed,
+      32 => Opcode::Is { type_: *ValueType::ALL.get(byte(1)?)? },
+      33 => Opcode::GreaterEqual,
+      34 => Opcode::LessEqual,
+      _ => return None,
+    })
+  }
+
+  /// Decoded instructions along with their offsets.
+  pub(crate) fn instructions(&self) -> impl Iterator<Item = (usize, Opcode)> + '_ {
+    let mut offset = 0;
+
+    std::iter::from_fn(move || {
+      let start = offset;
+      let opcode = (start < self.code.len()).then(|| self.decode(start))?;
+      offset += opcode.size();
+
+      Some((start, opcode))
+    })
+  }
+
+  /// Pushes a forward jump to be completed by `patch_jump` and returns its
+  /// offset.
+  pub(crate) fn push_jump(&mut self, code: Opcode, line: u32) -> usize {
+    let offset = self.code.len();
+
+    self.push_code(code, line);
+
+    offset
+  }
+
+  /// Makes the jump at `offset` land on the next instruction to be pushed.
+  pub(crate) fn patch_jump(&mut self, offset: usize) -> Result<(), ChunkError> {
+    let jump = self.code.len() - offset - 3;
+    let jump = u16::try_from(jump).map_err(|_| ChunkError::JumpTooLong)?;
+
+    self.code[offset + 1..offset + 3].copy_from_slice(&jump.to_be_bytes());
+
+    Ok(())
+  }
+
+  /// Pushes a jump back to the instruction at `start`.
+  pub(crate) fn push_loop(&mut self, start: usize, line: u32) -> Result<(), ChunkError> {
+    let offset = self.code.len() + 3 - start;
+
+    if offset > u16::MAX as usize {
+      return Err(ChunkError::JumpTooLong);
+    }
+
+    self.push_code(Opcode::Loop { offset }, line);
+
+    Ok(())
   }
 }
 
-impl fmt::Display for Chunk {
-  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-    let result = self
-      .code
+/// Static measurements of a compiled chunk.
+pub(crate) struct ChunkStats {
+  instructions: usize,
+  code_bytes: usize,
+  constants: usize,
+  /// Constants equal to an earlier constant of the pool
+  duplicate_constants: usize,
+  /// Number of instructions per opcode, most frequent first
+  opcodes: Vec<(&'static str, usize)>,
+}
+
+impl Chunk {
+  pub(crate) fn stats(&self) -> ChunkStats {
+    let duplicate_constants = self
+      .constants
       .iter()
       .enumerate()
-      .map(|(index, opcode)| {
-        let mut buf = String::new();
-        write!(&mut buf, "{:0>4}", index).unwrap();
-
-        if index > 0 && self.lines[index] == self.lines[index - 1] {
-          write!(&mut buf, "{: >5}", "|").unwrap();
-        } else {
-          write!(&mut buf, "{: >5}", self.lines[index]).unwrap();
-        }
-
-        match opcode {
-          Opcode::Return => {
-            write!(&mut buf, " {: <15}", "RETURN").unwrap();
-          }
-          Opcode::Constant {
-            index: constant_index,
-          } => {
-            write!(
-              &mut buf,
-              " {: <15}{:0>3}: {:?}",
-              "CONSTANT", constant_index, self.constants[*constant_index]
-            )
-            .unwrap();
-          }
-          Opcode::Add => {
-            write!(&mut buf, " {: <15}", "ADD").unwrap();
-          }
-          Opcode::Multiply => {
-            write!(&mut buf, " {: <15}", "MULT").unwrap();
-          },
-          Opcode::Subtract => {
-            write!(&mut buf, " {: <15}", "SUB").unwrap();
-          },
-          Opcode::Divide => {
-            write!(&mut buf, " {: <15}", "DIV").unwrap();
-          },
-          Opcode::Negate => {
-            write!(&mut buf, " {: <15}", "NEGATE").unwrap();
-          },
-          Opcode::Not => {
-            write!(&mut buf, " {: <15}", "NOT").unwrap();
-          },
-          Opcode::True => {
-            write!(&mut buf, " {: <15}", "TRUE").unwrap();
-          },
-          Opcode::False => {
-            write!(&mut buf, " {: <15}", "FALSE").unwrap();
-          },
-          Opcode::Nil => {
-            write!(&mut buf, " {: <15}", "NIL").unwrap();
-          },
-          Opcode::Equal => {
-            write!(&mut buf, " {: <15}", "EQUAL").unwrap();
-          },
-          Opcode::Less => {
-            write!(&mut buf, " {: <15}", "LESS").unwrap();
+      .filter(|(index, value)| {
+        self.constants[..*index].iter().any(|earlier| earlier.is_equal(value))
+      })
+      .count();
+
+    let mut opcodes: Vec<(&'static str, usize)> = vec![];
+
+    let mut instructions = 0;
+
+    for (_, opcode) in self.instructions() {
+      instructions += 1;
+
+      match opcodes.iter_mut().find(|(name, _)| *name == opcode.name()) {
+        Some((_, count)) => *count += 1,
+        None => opcodes.push((opcode.name(), 1)),
+      }
+    }
+
+    opcodes.sort_by(|(a_name, a), (b_name, b)| b.cmp(a).then(a_name.cmp(b_name)));
+
+    ChunkStats {
+      instructions,
+      code_bytes: self.code.len(),
+      constants: self.constants.len(),
+      duplicate_constants,
+      opcodes,
+    }
+  }
+}
+
+impl fmt::Display for ChunkStats {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let duplication = if self.constants == 0 {
+      0.
+    } else {
+      self.duplicate_constants as f64 / self.constants as f64 * 100.
+    };
+
+    writeln!(f, "  {: <15}{}", "instructions", self.instructions)?;
+    writeln!(f, "  {: <15}{} bytes", "code size", self.code_bytes)?;
+    writeln!(
+      f,
+      "  {: <15}{} ({} duplicates, {:.1}%)",
+      "constants", self.constants, self.duplicate_constants, duplication
+    )?;
+    writeln!(f, "  opcodes")?;
+
+    for (name, count) in &self.opcodes {
+      writeln!(f, "    {: <13}{}", name, count)?;
+    }
+
+    Ok(())
+  }
+}
+
+impl Chunk {
+  /// Listing line of the instruction at `index`. With a heap, object
+  /// constants are shown by contents instead of by handle.
+  pub(crate) fn disassemble_instruction(&self, index: usize, opcode: &Opcode, heap: Option<&ObjHeap>) -> String {
+    let mut buf = String::new();
+    write!(&mut buf, "{:0>4}", index).unwrap();
+
+    if index > 0 && self.lines[index] == self.lines[index - 1] {
+      write!(&mut buf, "{: >5}", "|").unwrap();
+    } else {
+      write!(&mut buf, "{: >5}", self.lines[index]).unwrap();
+    }
+
+    match *opcode {
+      Opcode::Constant {
+        index: constant_index,
+      } |
+      Opcode::ConstantLong {
+        index: constant_index,
+      } |
+      Opcode::DefineGlobal {
+        index: constant_index,
+      } |
+      Opcode::GetGlobal {
+        index: constant_index,
+      } |
+      Opcode::SetGlobal {
+        index: constant_index,
+      } => {
+        let constant = &self.constants[constant_index];
+        let constant = match heap {
+          Some(heap) => match heap.as_str(constant) {
+            Some(string) => format!("{:?}", string),
+            None => heap.display(constant),
           },
-          Opcode::Greater => {
-            write!(&mut buf, " {: <15}", "GREATER").unwrap();
-          }
+          None => format!("{:?}", constant),
         };
 
-        buf
-      })
+        write!(&mut buf, " {: <15}{:0>3}: {}", opcode.name(), constant_index, constant).unwrap();
+      }
+      Opcode::GetLocal { slot: operand } |
+      Opcode::SetLocal { slot: operand } |
+      Opcode::Call { arg_count: operand } => {
+        write!(&mut buf, " {: <15}{:0>3}", opcode.name(), operand).unwrap();
+      }
+      Opcode::Is { type_ } => {
+        write!(&mut buf, " {: <15}{}", opcode.name(), type_.name()).unwrap();
+      }
+      Opcode::Jump { offset } | Opcode::JumpIfFalse { offset } => {
+        write!(&mut buf, " {: <15}{:0>4} -> {:0>4}", opcode.name(), index, index + 3 + offset).unwrap();
+      }
+      Opcode::Loop { offset } => {
+        write!(&mut buf, " {: <15}{:0>4} -> {:0>4}", opcode.name(), index, index + 3 - offset).unwrap();
+      }
+      _ => {
+        write!(&mut buf, " {: <15}", opcode.name()).unwrap();
+      }
+    };
+
+    buf
+  }
+
+  /// The whole listing, with object constants looked up in `heap`.
+  pub(crate) fn disassemble(&self, heap: &ObjHeap) -> String {
+    self
+      .instructions()
+      .map(|(index, opcode)| self.disassemble_instruction(index, &opcode, Some(heap)))
+      .collect::<Vec<String>>()
+      .join("\n")
+  }
+}
+
+impl fmt::Display for Chunk {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let result = self
+      .instructions()
+      .map(|(index, opcode)| self.disassemble_instruction(index, &opcode, None))
       .collect::<Vec<String>>()
       .join("\n");
 
@@ -173,8 +644,84 @@ mod tests {
 
     chunk.push_code(Opcode::Return, 1);
     chunk.push_code(Opcode::Return, 1234);
-    chunk.push_constant(Value::Number(3.14), 2);
+    chunk.push_constant(Value::Number(1.5), 2).unwrap();
+    let jump = chunk.push_jump(Opcode::Jump { offset: 0 }, 2);
+    chunk.push_code(Opcode::GetLocal { slot: 1 }, 3);
+    chunk.patch_jump(jump).unwrap();
+    chunk.push_loop(0, 3).unwrap();
+
+    let listing = chunk.to_string();
+    print!("{}", listing);
+
+    assert_eq!(listing.lines().count(), 6);
+    assert!(listing.contains("0004    | JUMP           0004 -> 0009"));
+    assert!(listing.contains("0009    | LOOP           0009 -> 0000"));
+
+    let mut heap = ObjHeap::new();
+    chunk.push_constant(heap.alloc_string("a".to_string()), 4).unwrap();
+
+    assert!(chunk.disassemble(&heap).ends_with("0012    4 CONSTANT       001: \"a\""));
+    assert!(chunk.to_string().ends_with("0012    4 CONSTANT       001: Obj(ObjRef(0))"));
+  }
+
+  #[test]
+  fn test_is_operand() {
+    let mut chunk = Chunk::new();
+
+    chunk.push_code(Opcode::Is { type_: ValueType::Function }, 1);
+
+    assert!(matches!(chunk.decode(0), Opcode::Is { type_: ValueType::Function }));
+    assert!(chunk.to_string().ends_with("IS             Function"));
+
+    chunk.code[1] = ValueType::ALL.len() as u8;
+
+    assert!(chunk.try_decode(0).is_none());
+  }
+
+  #[test]
+  fn test_is_equal() {
+    let mut heap = ObjHeap::new();
+    let one = heap.alloc_string("1".to_string());
+    let a = heap.alloc_string("a".to_string());
+    let other_a = heap.alloc_string("a".to_string());
+
+    assert!(Value::Nil.is_equal(&Value::Nil));
+    assert!(!Value::Nil.is_equal(&Value::Bool(false)));
+    assert!(!Value::Number(1.).is_equal(&one));
+    assert!(a.is_equal(&other_a));
+    assert!(!a.is_equal(&one));
+    assert!(!Value::Number(1.).is_equal(&Value::Number(2.)));
+    assert!(!Value::Number(0.).is_equal(&Value::Nil));
+  }
+
+  #[test]
+  fn test_is_truthy() {
+    assert!(!Value::Nil.is_truthy());
+    assert!(!Value::Bool(false).is_truthy());
+    assert!(Value::Bool(true).is_truthy());
+    assert!(Value::Number(0.).is_truthy());
+    assert!(ObjHeap::new().alloc_string(String::new()).is_truthy());
+  }
+
+  #[test]
+  fn test_stats() {
+    let mut chunk = Chunk::new();
+    let mut heap = ObjHeap::new();
+
+    chunk.push_constant(Value::Number(1.), 1).unwrap();
+    chunk.push_constant(Value::Number(1.), 1).unwrap();
+    chunk.push_code(Opcode::Add, 1);
+    chunk.push_constant(heap.alloc_string("a".to_string()), 1).unwrap();
+    chunk.push_constant(heap.alloc_string("a".to_string()), 1).unwrap();
+    chunk.push_code(Opcode::Add, 1);
+    chunk.push_code(Opcode::Return, 1);
+
+    let stats = chunk.stats();
 
-    print!("{}", chunk);
+    assert_eq!(stats.instructions, 7);
+    assert_eq!(stats.code_bytes, 11);
+    assert_eq!(stats.constants, 2);
+    assert_eq!(stats.duplicate_constants, 0);
+    assert_eq!(stats.opcodes, [("CONSTANT", 4), ("ADD", 2), ("RETURN", 1)]);
   }
 }
@@ -0,0 +1,135 @@
+use crate::chunk::Value;
+use crate::vm::VM;
+use anyhow::Result;
+use std::collections::HashMap;
+
+/// A stable handle to an object allocated in an `ObjHeap`. Handles are
+/// `Copy`, so values referring to objects are pushed onto the stack without
+/// cloning the objects themselves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct ObjRef(u32);
+
+#[cfg(feature = "nan-boxing")]
+impl ObjRef {
+  pub(crate) fn from_index(index: u32) -> Self {
+    ObjRef(index)
+  }
+
+  pub(crate) fn index(self) -> u32 {
+    self.0
+  }
+}
+
+/// Runtime objects living on the heap. Functions, closures and instances
+/// will join strings here once the VM supports them.
+pub(crate) type NativeFn = fn(&mut VM, &[Value]) -> Result<Value>;
+
+/// A function implemented in Rust.
+#[derive(Debug)]
+pub(crate) struct Native {
+  pub(crate) name: &'static str,
+  /// `None` for natives taking any number of arguments
+  pub(crate) arity: Option<usize>,
+  pub(crate) function: NativeFn,
+}
+
+#[derive(Debug)]
+pub(crate) enum Obj {
+  String(String),
+  Native(Native),
+}
+
+/// An arena owning every object created while compiling and running a
+/// program. Objects never move, so handles stay valid until the object is
+/// freed by the garbage collector.
+///
+/// Strings are interned: there is a single object per distinct contents, so
+/// strings are equal exactly when their handles are.
+#[derive(Default)]
+pub(crate) struct ObjHeap {
+  objects: Vec<Obj>,
+  strings: HashMap<String, ObjRef>,
+}
+
+impl ObjHeap {
+  pub(crate) fn new() -> Self {
+    ObjHeap::default()
+  }
+
+  pub(crate) fn alloc(&mut self, obj: Obj) -> ObjRef {
+    let handle = ObjRef(u32::try_from(self.objects.len()).expect("object heap is full"));
+
+    self.objects.push(obj);
+
+    handle
+  }
+
+  /// Returns the interned string equal to `string`, allocating it on first
+  /// use.
+  pub(crate) fn alloc_string(&mut self, string: String) -> Value {
+    if let Some(handle) = self.strings.get(&string) {
+      return Value::Obj(*handle);
+    }
+
+    let handle = self.alloc(Obj::String(string.clone()));
+    self.strings.insert(string, handle);
+
+    Value::Obj(handle)
+  }
+
+  pub(crate) fn get(&self, handle: ObjRef) -> &Obj {
+    &self.objects[handle.0 as usize]
+  }
+
+  /// Number of objects allocated so far.
+  pub(crate) fn len(&self) -> usize {
+    self.objects.len()
+  }
+
+  /// The contents of `value` if it is a string.
+  pub(crate) fn as_str(&self, value: &Value) -> Option<&str> {
+    match value {
+      Value::Obj(handle) => match self.get(*handle) {
+        Obj::String(string) => Some(string),
+        _ => None,
+      },
+      _ => None,
+    }
+  }
+
+  /// Formats `value` the way `print` shows it.
+  pub(crate) fn display(&self, value: &Value) -> String {
+    match value {
+      Value::Number(v) => v.to_string(),
+      Value::Bool(v) => v.to_string(),
+      Value::Nil => "nil".to_string(),
+      Value::Obj(handle) => match self.get(*handle) {
+        Obj::String(string) => string.clone(),
+        Obj::Native(native) => format!("<native fn {}>", native.name),
+      },
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_handles_are_stable() {
+    let mut heap = ObjHeap::new();
+
+    let a = heap.alloc_string("a".to_string());
+    let handles = (0..100)
+      .map(|i| heap.alloc_string(i.to_string()))
+      .collect::<Vec<Value>>();
+
+    assert_eq!(heap.len(), 101);
+    assert_eq!(heap.alloc_string("42".to_string()), handles[42]);
+    assert_eq!(heap.len(), 101);
+    assert_eq!(heap.as_str(&a), Some("a"));
+    assert_eq!(heap.as_str(&handles[42]), Some("42"));
+    assert_eq!(heap.as_str(&Value::Nil), None);
+    assert_eq!(heap.display(&Value::Number(1.5)), "1.5");
+  }
+}
@@ -1,4 +1,10 @@
 mod chunk;
+mod heap;
+mod loxb;
+#[cfg(feature = "nan-boxing")]
+mod nan_box;
+mod natives;
 mod parser;
 pub mod runner;
+mod verifier;
 mod vm;
@@ -0,0 +1,207 @@
+//! The `.loxb` format: a compiled chunk stored on disk so it can be run
+//! without compiling its source again.
+//!
+//! All integers are little-endian `u32`s unless noted otherwise:
+//!
+//! - the magic bytes `LOXB` and a format version byte
+//! - the constant count, then every constant as a tag byte followed by an
+//!   `f64` for numbers or a length and UTF-8 bytes for strings
+//! - the code length and the code itself
+//! - line numbers, run-length encoded as a count of runs followed by
+//!   `(line, bytes)` pairs
+
+use crate::chunk::{Chunk, Value};
+use crate::heap::ObjHeap;
+use anyhow::{anyhow, Result};
+use thiserror::Error;
+
+const MAGIC: &[u8; 4] = b"LOXB";
+const VERSION: u8 = 1;
+
+const TAG_NUMBER: u8 = 0;
+const TAG_STRING: u8 = 1;
+
+#[derive(Error, Debug, Clone)]
+pub(crate) enum LoxbError {
+  #[error("not a .loxb file")]
+  NotBytecode,
+  #[error("unsupported .loxb version {0}")]
+  UnsupportedVersion(u8),
+  #[error("unexpected end of .loxb file")]
+  Truncated,
+  #[error("invalid constant tag {0}")]
+  InvalidConstantTag(u8),
+  #[error("string constant is not valid UTF-8")]
+  InvalidString,
+  #[error("line numbers do not cover the code")]
+  InvalidLines,
+}
+
+fn write_u32(bytes: &mut Vec<u8>, value: usize) -> Result<()> {
+  let value = u32::try_from(value).map_err(|_| anyhow!("chunk is too large to be saved"))?;
+
+  bytes.extend(value.to_le_bytes());
+
+  Ok(())
+}
+
+/// Serializes `chunk`, looking up its string constants in `heap`.
+pub(crate) fn encode(chunk: &Chunk, heap: &ObjHeap) -> Result<Vec<u8>> {
+  let mut bytes = MAGIC.to_vec();
+  bytes.push(VERSION);
+
+  write_u32(&mut bytes, chunk.constants().len())?;
+
+  for constant in chunk.constants() {
+    match (constant, heap.as_str(constant)) {
+      (Value::Number(n), _) => {
+        bytes.push(TAG_NUMBER);
+        bytes.extend(n.to_le_bytes());
+      }
+      (_, Some(string)) => {
+        bytes.push(TAG_STRING);
+        write_u32(&mut bytes, string.len())?;
+        bytes.extend(string.as_bytes());
+      }
+      _ => return Err(anyhow!("constant {} cannot be saved", heap.display(constant))),
+    }
+  }
+
+  write_u32(&mut bytes, chunk.code.len())?;
+  bytes.extend(&chunk.code);
+
+  let mut runs: Vec<(u32, usize)> = vec![];
+
+  for offset in 0..chunk.code.len() {
+    match runs.last_mut() {
+      Some((line, length)) if *line == chunk.line(offset) => *length += 1,
+      _ => runs.push((chunk.line(offset), 1)),
+    }
+  }
+
+  write_u32(&mut bytes, runs.len())?;
+
+  for (line, length) in runs {
+    bytes.extend(line.to_le_bytes());
+    write_u32(&mut bytes, length)?;
+  }
+
+  Ok(bytes)
+}
+
+/// Reads a `.loxb` file's contents.
+struct Reader<'a> {
+  bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+  fn take(&mut self, n: usize) -> Result<&'a [u8], LoxbError> {
+    if self.bytes.len() < n {
+      return Err(LoxbError::Truncated);
+    }
+
+    let (taken, rest) = self.bytes.split_at(n);
+    self.bytes = rest;
+
+    Ok(taken)
+  }
+
+  fn byte(&mut self) -> Result<u8, LoxbError> {
+    Ok(self.take(1)?[0])
+  }
+
+  fn u32(&mut self) -> Result<u32, LoxbError> {
+    Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+  }
+
+  fn len(&mut self) -> Result<usize, LoxbError> {
+    Ok(self.u32()? as usize)
+  }
+}
+
+/// Deserializes a chunk saved by `encode`, allocating its strings in a new
+/// heap.
+pub(crate) fn decode(bytes: &[u8]) -> Result<(Chunk, ObjHeap), LoxbError> {
+  let mut reader = Reader { bytes };
+  let mut heap = ObjHeap::new();
+
+  if reader.take(MAGIC.len()).ok() != Some(MAGIC.as_slice()) {
+    return Err(LoxbError::NotBytecode);
+  }
+
+  let version = reader.byte()?;
+
+  if version != VERSION {
+    return Err(LoxbError::UnsupportedVersion(version));
+  }
+
+  let mut constants = vec![];
+
+  for _ in 0..reader.len()? {
+    let constant = match reader.byte()? {
+      TAG_NUMBER => Value::Number(f64::from_le_bytes(reader.take(8)?.try_into().unwrap())),
+      TAG_STRING => {
+        let length = reader.len()?;
+        let string = std::str::from_utf8(reader.take(length)?).map_err(|_| LoxbError::InvalidString)?;
+
+        heap.alloc_string(string.to_string())
+      }
+      tag => return Err(LoxbError::InvalidConstantTag(tag)),
+    };
+
+    constants.push(constant);
+  }
+
+  let code_length = reader.len()?;
+  let code = reader.take(code_length)?.to_vec();
+
+  let mut lines = vec![];
+
+  for _ in 0..reader.len()? {
+    let line = reader.u32()?;
+    let length = reader.len()?;
+
+    if lines.len() + length > code.len() {
+      return Err(LoxbError::InvalidLines);
+    }
+
+    lines.resize(lines.len() + length, line);
+  }
+
+  if lines.len() != code.len() {
+    return Err(LoxbError::InvalidLines);
+  }
+
+  Ok((Chunk::from_parts(constants, code, lines), heap))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_encode() {
+    let mut chunk = Chunk::new();
+    let mut heap = ObjHeap::new();
+
+    chunk.push_constant(Value::Number(1.5), 1).unwrap();
+    chunk.push_constant(heap.alloc_string("ab".to_string()), 1).unwrap();
+    chunk.push_code(crate::chunk::Opcode::Return, 2);
+
+    let bytes = encode(&chunk, &heap).unwrap();
+
+    assert_eq!(&bytes[..5], b"LOXB\x01");
+    // Header, constants, code and two runs of lines
+    assert_eq!(bytes.len(), 5 + 4 + 9 + 7 + 4 + 5 + 4 + 16);
+    assert_eq!(&bytes[bytes.len() - 8..], [2, 0, 0, 0, 1, 0, 0, 0]);
+
+    let (decoded, heap) = decode(&bytes).unwrap();
+
+    assert_eq!(decoded.to_string(), chunk.to_string());
+    assert_eq!(decoded.disassemble(&heap), chunk.disassemble(&heap));
+
+    assert!(matches!(decode(b"LOX"), Err(LoxbError::NotBytecode)));
+    assert!(matches!(decode(b"LOXB\x02"), Err(LoxbError::UnsupportedVersion(2))));
+    assert!(matches!(decode(&bytes[..bytes.len() - 1]), Err(LoxbError::Truncated)));
+  }
+}
@@ -0,0 +1,86 @@
+//! NaN-boxed values, enabled by the `nan-boxing` feature. Every value fits in
+//! 8 bytes instead of the 16 taken by `Value`: numbers are stored as they
+//! are, everything else hides in the payload of a quiet NaN.
+
+use crate::chunk::Value;
+use crate::heap::ObjRef;
+use std::fmt;
+
+/// Exponent bits, the quiet bit and one more, so no NaN produced by
+/// arithmetic has all of them set.
+const QNAN: u64 = 0x7ffc_0000_0000_0000;
+/// Set, together with `QNAN`, for objects. The handle is in the low bits.
+const SIGN_BIT: u64 = 0x8000_0000_0000_0000;
+
+const TAG_NIL: u64 = 1;
+const TAG_FALSE: u64 = 2;
+const TAG_TRUE: u64 = 3;
+
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) struct NanBox(u64);
+
+impl NanBox {
+  pub(crate) fn unpack(self) -> Value {
+    let NanBox(bits) = self;
+
+    if bits & QNAN != QNAN {
+      return Value::Number(f64::from_bits(bits));
+    }
+
+    if bits & SIGN_BIT != 0 {
+      return Value::Obj(ObjRef::from_index((bits & u64::from(u32::MAX)) as u32));
+    }
+
+    match bits & !QNAN {
+      TAG_NIL => Value::Nil,
+      TAG_FALSE => Value::Bool(false),
+      TAG_TRUE => Value::Bool(true),
+      _ => unreachable!("invalid NaN-boxed value {:#x}", bits),
+    }
+  }
+}
+
+impl Value {
+  pub(crate) fn pack(self) -> NanBox {
+    NanBox(match self {
+      Value::Number(n) => n.to_bits(),
+      Value::Nil => QNAN | TAG_NIL,
+      Value::Bool(false) => QNAN | TAG_FALSE,
+      Value::Bool(true) => QNAN | TAG_TRUE,
+      Value::Obj(handle) => SIGN_BIT | QNAN | u64::from(handle.index()),
+    })
+  }
+}
+
+impl fmt::Debug for NanBox {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    self.unpack().fmt(f)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::heap::ObjHeap;
+
+  #[test]
+  fn test_round_trip() {
+    let string = ObjHeap::new().alloc_string("a".to_string());
+
+    for value in [
+      Value::Number(1.5),
+      Value::Number(-0.),
+      Value::Number(f64::INFINITY),
+      Value::Nil,
+      Value::Bool(false),
+      Value::Bool(true),
+      string,
+    ] {
+      assert_eq!(value.pack().unpack(), value);
+    }
+
+    assert!(matches!(Value::Number(f64::NAN).pack().unpack(), Value::Number(n) if n.is_nan()));
+    assert!(matches!(Value::Number(-f64::NAN).pack().unpack(), Value::Number(n) if n.is_nan()));
+    assert_eq!(std::mem::size_of::<NanBox>(), 8);
+  }
+}
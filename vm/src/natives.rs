@@ -0,0 +1,165 @@
+use crate::chunk::Value;
+use crate::vm::VM;
+use anyhow::{anyhow, Result};
+use std::cell::RefCell;
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Natives every program can use, matching the tree-walking interpreter.
+pub(crate) fn define_all(vm: &mut VM) {
+  vm.define_native("clock", Some(0), clock);
+  vm.define_native("println", None, println);
+  vm.define_native("write", None, write);
+  vm.define_native("eprintln", None, eprintln);
+}
+
+/// Defines the natives of one optional module.
+pub(crate) type DefineModule = fn(&mut VM);
+
+/// Optional modules programs only get when they are allowed. The tree-walking
+/// interpreter's `json` module needs lists and maps, which the VM lacks.
+const MODULES: [(&str, DefineModule); 2] = [("math", define_math), ("fs", define_fs)];
+
+/// Names of the optional native modules the VM can allow.
+pub(crate) fn available_modules() -> Vec<&'static str> {
+  MODULES.iter().map(|(name, _)| *name).collect()
+}
+
+/// Looks up every allowed module, failing on names the VM doesn't know.
+pub(crate) fn modules(allowed: &[String]) -> Result<Vec<DefineModule>> {
+  allowed
+    .iter()
+    .map(|name| {
+      MODULES.iter().find(|(module, _)| module == name).map(|(_, define)| *define).ok_or_else(|| {
+        anyhow!("unknown native module `{}` (available: {})", name, available_modules().join(", "))
+      })
+    })
+    .collect()
+}
+
+fn define_math(vm: &mut VM) {
+  macro_rules! unary {
+    ($name:expr, $op:expr) => {
+      vm.define_native($name, Some(1), |_, arguments| Ok(Value::Number($op(number_argument(arguments, 0)?))))
+    };
+  }
+
+  macro_rules! binary {
+    ($name:expr, $op:expr) => {
+      vm.define_native($name, Some(2), |_, arguments| {
+        Ok(Value::Number($op(number_argument(arguments, 0)?, number_argument(arguments, 1)?)))
+      })
+    };
+  }
+
+  unary!("abs", f64::abs);
+  unary!("sqrt", f64::sqrt);
+  unary!("floor", f64::floor);
+  unary!("ceil", f64::ceil);
+  unary!("round", f64::round);
+  binary!("pow", f64::powf);
+  binary!("min", f64::min);
+  binary!("max", f64::max);
+}
+
+fn define_fs(vm: &mut VM) {
+  vm.define_native("readFile", Some(1), |vm, arguments| {
+    let contents = std::fs::read_to_string(string_argument(vm, arguments, 0)?)?;
+
+    Ok(vm.heap.alloc_string(contents))
+  });
+  vm.define_native("writeFile", Some(2), |vm, arguments| {
+    std::fs::write(string_argument(vm, arguments, 0)?, string_argument(vm, arguments, 1)?)?;
+
+    Ok(Value::Nil)
+  });
+  vm.define_native("fileExists", Some(1), |vm, arguments| {
+    Ok(Value::Bool(std::path::Path::new(string_argument(vm, arguments, 0)?).exists()))
+  });
+}
+
+fn number_argument(arguments: &[Value], index: usize) -> Result<f64> {
+  match arguments[index] {
+    Value::Number(value) => Ok(value),
+    _ => Err(anyhow!("expected a number")),
+  }
+}
+
+fn string_argument<'a>(vm: &'a VM, arguments: &[Value], index: usize) -> Result<&'a str> {
+  vm.heap.as_str(&arguments[index]).ok_or_else(|| anyhow!("expected a string"))
+}
+
+fn clock(_vm: &mut VM, _arguments: &[Value]) -> Result<Value> {
+  let since_the_epoch = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .expect("Time went backwards");
+
+  Ok(Value::Number(since_the_epoch.as_secs_f64()))
+}
+
+/// Writes `arguments` to `sink` separated by spaces and followed by `end`.
+fn write_values(vm: &VM, sink: &RefCell<dyn Write>, arguments: &[Value], end: &str) -> Result<Value> {
+  let text = arguments
+    .iter()
+    .map(|value| vm.heap.display(value))
+    .collect::<Vec<String>>()
+    .join(" ");
+
+  let mut sink = sink.borrow_mut();
+
+  write!(sink, "{}{}", text, end)?;
+  sink.flush()?;
+
+  Ok(Value::Nil)
+}
+
+fn println(vm: &mut VM, arguments: &[Value]) -> Result<Value> {
+  write_values(vm, &vm.stdout, arguments, "\n")
+}
+
+fn write(vm: &mut VM, arguments: &[Value]) -> Result<Value> {
+  write_values(vm, &vm.stdout, arguments, "")
+}
+
+fn eprintln(vm: &mut VM, arguments: &[Value]) -> Result<Value> {
+  write_values(vm, &vm.stderr, arguments, "\n")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::runner::{run_with_options, RunError, RunOptions};
+  use std::rc::Rc;
+
+  fn run(source: &str, allowed: &[&str]) -> (String, Result<(), RunError>) {
+    let stdout = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let options = RunOptions {
+      stdout: stdout.clone(),
+      allowed_modules: allowed.iter().map(|name| name.to_string()).collect(),
+      ..RunOptions::default()
+    };
+    let result = run_with_options(source.to_string(), &options);
+
+    (String::from_utf8(stdout.take()).unwrap(), result)
+  }
+
+  #[test]
+  fn test_modules() {
+    assert_eq!(available_modules(), vec!["math", "fs"]);
+
+    let (output, result) = run("print sqrt(16); print pow(2, 10); print max(1, round(2.6));", &["math"]);
+    assert!(result.is_ok());
+    assert_eq!(output, "4\n1024\n3\n");
+
+    let path = std::env::temp_dir().join(format!("rslox-vm-fs-{}", std::process::id()));
+    let source = format!(r#"var path = "{}"; writeFile(path, "hi"); print readFile(path); print fileExists(path);"#, path.display());
+    let (output, result) = run(&source, &["fs", "math"]);
+    std::fs::remove_file(&path).unwrap();
+    assert!(result.is_ok());
+    assert_eq!(output, "hi\ntrue\n");
+
+    assert!(matches!(run("print sqrt(4);", &[]).1, Err(RunError::Runtime(_))));
+    assert!(matches!(run("print sqrt(nil);", &["math"]).1, Err(RunError::Runtime(_))));
+    assert!(matches!(run("print 1;", &["nope"]).1, Err(RunError::Compile(_))));
+  }
+}
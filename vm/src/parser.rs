@@ -1,28 +1,118 @@
 use anyhow::Result;
-use scanner::{Scanner, Token, TokenType};
+use scanner::{ScanError, Scanner, Token, TokenType};
 use thiserror::Error;
 
-use crate::chunk::{Chunk, Value, Opcode};
+use crate::chunk::{Chunk, Value, Opcode, ValueType};
+use crate::heap::ObjHeap;
+
+/// A compile error with the position of the token it was found at.
+#[derive(Error, Debug)]
+#[error("[line {line}, column {column}] {error}")]
+pub struct LocatedError {
+  pub line: u32,
+  pub column: u32,
+  /// Characters in the token
+  pub length: usize,
+  pub error: anyhow::Error,
+}
 
 #[derive(Error, Debug, Clone)]
 pub(crate) enum SyntaxError {
   #[error("';' expected at the end of a statement")]
   MissingSemicolon,
+  #[error("expected variable name")]
+  MissingVariableName,
+  #[error("invalid assignment target")]
+  InvalidAssignmentTarget,
+  #[error("'}}' expected at the end of a block")]
+  MissingRightBrace,
+  #[error("'{{' expected")]
+  MissingLeftBrace,
+  #[error("':' expected after a case")]
+  MissingCaseColon,
+  #[error("'case', 'default' or '}}' expected in a switch")]
+  UnexpectedTokenInSwitch,
+  #[error("variable '{0}' is already declared in this scope")]
+  VariableRedeclared(String),
+  #[error("cannot read local variable '{0}' in its own initializer")]
+  LocalInOwnInitializer(String),
+  #[error("'(' expected")]
+  MissingLeftParen,
+  #[error("')' expected")]
+  MissingRightParen,
+  #[error("too many local variables in one scope")]
+  TooManyLocals,
+  #[error("too many arguments in one call")]
+  TooManyArguments,
+  #[error("too many global variable names in one chunk")]
+  TooManyGlobalNames,
+  #[error("'++' and '--' can only be applied to variables")]
+  InvalidUpdateTarget,
+  #[error("'break' used outside of a loop")]
+  BreakOutsideLoop,
+  #[error("'continue' used outside of a loop")]
+  ContinueOutsideLoop,
+  #[error("no enclosing loop labeled '{0}'")]
+  UndefinedLabel(String),
+  #[error("label '{0}' must be followed by a loop")]
+  LabelOnNonLoop(String),
+  #[error("'}}' expected at the end of a string interpolation")]
+  UnterminatedInterpolation,
+  #[error("expression expected")]
+  ExpectedExpression,
+  #[error("'..' or '..=' expected in a for-in loop")]
+  MissingRange,
+  #[error("'is' should be followed by a type name")]
+  MissingTypeName,
+  #[error("unknown type '{0}', the VM only checks for Number, String, Bool, Nil and Function")]
+  UnknownType(String),
 }
 
 const NONE_PREC: u16 = 0;
 const ASSIGNMENT_PREC: u16 = NONE_PREC  + 1;
-const EQUALITY_PREC: u16 = ASSIGNMENT_PREC + 1;
-const TERM_PREC: u16 = EQUALITY_PREC + 1;
+const OR_PREC: u16 = ASSIGNMENT_PREC + 1;
+const AND_PREC: u16 = OR_PREC + 1;
+const EQUALITY_PREC: u16 = AND_PREC + 1;
+// Bitwise operators bind tighter than comparisons, unlike in C
+const BIT_OR_PREC: u16 = EQUALITY_PREC + 1;
+const BIT_XOR_PREC: u16 = BIT_OR_PREC + 1;
+const BIT_AND_PREC: u16 = BIT_XOR_PREC + 1;
+const SHIFT_PREC: u16 = BIT_AND_PREC + 1;
+const TERM_PREC: u16 = SHIFT_PREC + 1;
 const FACTOR_PREC: u16 = TERM_PREC + 1;
 const UNARY_PREC: u16 = FACTOR_PREC + 1;
+const CALL_PREC: u16 = UNARY_PREC + 1;
+
+/// A local variable, living in the stack slot matching its position in
+/// `Parser::locals`.
+struct Local {
+  name: String,
+  /// `None` until the variable's initializer is compiled
+  depth: Option<usize>,
+}
+
+/// A loop whose body is being compiled, for `break` and `continue`.
+struct Loop {
+  label: Option<String>,
+  /// Where `continue` jumps to
+  start: usize,
+  /// Locals deeper than this are popped when jumping out of the body
+  scope_depth: usize,
+  /// `break` jumps, patched once the end of the loop is known
+  breaks: Vec<usize>,
+}
 
 pub(crate) struct Parser {
   scanner: Scanner,
   previous: Option<Token>,
   current: Option<Token>,
-  errors: Vec<SyntaxError>,
-  chunk: Chunk
+  chunk: Chunk,
+  heap: ObjHeap,
+  locals: Vec<Local>,
+  /// Number of blocks surrounding the code being compiled, 0 for globals
+  scope_depth: usize,
+  /// Enclosing loops, innermost last
+  loops: Vec<Loop>,
 }
 
 impl Parser {
@@ -31,20 +121,607 @@ impl Parser {
       scanner,
       current: None,
       previous: None,
-      errors: vec![],
-      chunk: Chunk::new()
+      chunk: Chunk::new(),
+      heap: ObjHeap::new(),
+      locals: vec![],
+      scope_depth: 0,
+      loops: vec![],
     }
   }
 
   pub(crate) fn parse(&mut self) -> Result<()> {
     self.advance()?;
+
+    while !self.check(TokenType::Eof) {
+      if let Err(error) = self.declaration() {
+        return Err(self.locate(error));
+      }
+    }
+
+    let line = self.current().line;
+    self.chunk.push_code(Opcode::Return, line);
+
+    Ok(())
+  }
+
+  /// Prefixes a compile error with the position of the token it was found
+  /// at. Scan errors carry their own.
+  fn locate(&self, error: anyhow::Error) -> anyhow::Error {
+    if error.is::<ScanError>() {
+      return error;
+    }
+
+    let token = self.current();
+
+    LocatedError { line: token.line, column: token.column, length: token.lexeme.chars().count(), error }.into()
+  }
+
+  fn declaration(&mut self) -> Result<()> {
+    if self.match_(TokenType::Var)? {
+      self.var_declaration()
+    } else {
+      self.statement()
+    }
+  }
+
+  fn statement(&mut self) -> Result<()> {
+    if self.match_(TokenType::LeftBrace)? {
+      self.begin_scope();
+      self.block()?;
+      self.end_scope();
+
+      Ok(())
+    } else if self.match_(TokenType::While)? {
+      self.while_statement(None)
+    } else if self.match_(TokenType::For)? {
+      self.for_statement(None)
+    } else if self.match_(TokenType::If)? {
+      self.if_statement()
+    } else if self.match_(TokenType::Switch)? {
+      self.switch_statement()
+    } else if self.match_(TokenType::Print)? {
+      self.print_statement()
+    } else if self.match_(TokenType::Assert)? {
+      self.assert_statement()
+    } else if self.match_(TokenType::Break)? {
+      self.jump_statement(false)
+    } else if self.match_(TokenType::Continue)? {
+      self.jump_statement(true)
+    } else if matches!(self.current().kind, TokenType::Identifier(_)) {
+      self.identifier_statement()
+    } else {
+      self.expression_statement()
+    }
+  }
+
+  /// A labeled loop, `label: while ...`, or an expression statement starting
+  /// with an identifier. Only the token after the identifier tells them apart.
+  fn identifier_statement(&mut self) -> Result<()> {
+    let TokenType::Identifier(label) = self.current().kind.clone() else {
+      unreachable!("checked by the caller");
+    };
+
+    self.advance()?;
+
+    if !self.match_(TokenType::Colon)? {
+      // The identifier is already consumed as the start of the expression
+      self.parse_precedence_from_previous(ASSIGNMENT_PREC)?;
+
+      return self.end_expression_statement();
+    }
+
+    if self.match_(TokenType::While)? {
+      self.while_statement(Some(label))
+    } else if self.match_(TokenType::For)? {
+      self.for_statement(Some(label))
+    } else {
+      Err(SyntaxError::LabelOnNonLoop(label).into())
+    }
+  }
+
+  /// Compiles `break` or `continue`, with an optional label, as a jump after
+  /// popping the locals of the scopes being left.
+  fn jump_statement(&mut self, is_continue: bool) -> Result<()> {
+    let line = self.previous().line;
+
+    let label = match self.current().kind.clone() {
+      TokenType::Identifier(label) => {
+        self.advance()?;
+
+        Some(label)
+      }
+      _ => None,
+    };
+
+    self.consume(TokenType::Semicolon, SyntaxError::MissingSemicolon)?;
+
+    let target = self
+      .loops
+      .iter()
+      .rposition(|enclosing| label.is_none() || enclosing.label == label);
+
+    let Some(target) = target else {
+      return Err(match label {
+        Some(label) => SyntaxError::UndefinedLabel(label),
+        None if is_continue => SyntaxError::ContinueOutsideLoop,
+        None => SyntaxError::BreakOutsideLoop,
+      }.into());
+    };
+
+    // The locals stay declared, the code after the jump in the same scope
+    // still uses their slots
+    let depth = Some(self.loops[target].scope_depth);
+    let count = self.locals.iter().rev().take_while(|local| local.depth > depth).count();
+
+    for _ in 0..count {
+      self.chunk.push_code(Opcode::Pop, line);
+    }
+
+    if is_continue {
+      self.chunk.push_loop(self.loops[target].start, line)?;
+    } else {
+      let jump = self.chunk.push_jump(Opcode::Jump { offset: 0 }, line);
+      self.loops[target].breaks.push(jump);
+    }
+
+    Ok(())
+  }
+
+  /// Compiles a loop body, `continue` in it jumps to `start`.
+  fn loop_body(&mut self, label: Option<String>, start: usize) -> Result<Vec<usize>> {
+    self.loops.push(Loop { label, start, scope_depth: self.scope_depth, breaks: vec![] });
+    let body = self.statement();
+    let enclosing = self.loops.pop().unwrap();
+
+    body.map(|_| enclosing.breaks)
+  }
+
+  fn patch_breaks(&mut self, breaks: Vec<usize>) -> Result<()> {
+    for jump in breaks {
+      self.chunk.patch_jump(jump)?;
+    }
+
+    Ok(())
+  }
+
+  fn if_statement(&mut self) -> Result<()> {
+    let line = self.previous().line;
+
+    self.consume(TokenType::LeftParen, SyntaxError::MissingLeftParen)?;
+    self.expression()?;
+    self.consume(TokenType::RightParen, SyntaxError::MissingRightParen)?;
+
+    let else_jump = self.chunk.push_jump(Opcode::JumpIfFalse { offset: 0 }, line);
+    self.chunk.push_code(Opcode::Pop, line);
+    self.statement()?;
+
+    let end_jump = self.chunk.push_jump(Opcode::Jump { offset: 0 }, line);
+
+    self.chunk.patch_jump(else_jump)?;
+    self.chunk.push_code(Opcode::Pop, line);
+
+    if self.match_(TokenType::Else)? {
+      self.statement()?;
+    }
+
+    self.chunk.patch_jump(end_jump)?;
+
+    Ok(())
+  }
+
+  /// Compiles `switch (subject) { case a, b: ... default: ... }` to a chain
+  /// of comparisons with the subject, kept in a hidden local. Only the body
+  /// of the first matching case runs.
+  fn switch_statement(&mut self) -> Result<()> {
+    let line = self.previous().line;
+
+    self.begin_scope();
+    // No variable can clash with an empty name
+    self.declare_local(String::new())?;
+    let slot = self.locals.len() - 1;
+
+    self.consume(TokenType::LeftParen, SyntaxError::MissingLeftParen)?;
+    self.expression()?;
+    self.consume(TokenType::RightParen, SyntaxError::MissingRightParen)?;
+    self.locals[slot].depth = Some(self.scope_depth);
+    self.consume(TokenType::LeftBrace, SyntaxError::MissingLeftBrace)?;
+
+    let mut end_jumps = vec![];
+
+    while self.match_(TokenType::Case)? {
+      let mut body_jumps = vec![];
+
+      loop {
+        self.chunk.push_code(Opcode::GetLocal { slot }, line);
+        self.expression()?;
+        self.chunk.push_code(Opcode::Equal, line);
+
+        let next_value = self.chunk.push_jump(Opcode::JumpIfFalse { offset: 0 }, line);
+        self.chunk.push_code(Opcode::Pop, line);
+        body_jumps.push(self.chunk.push_jump(Opcode::Jump { offset: 0 }, line));
+        self.chunk.patch_jump(next_value)?;
+        self.chunk.push_code(Opcode::Pop, line);
+
+        if !self.match_(TokenType::Comma)? {
+          break;
+        }
+      }
+
+      self.consume(TokenType::Colon, SyntaxError::MissingCaseColon)?;
+
+      let next_case = self.chunk.push_jump(Opcode::Jump { offset: 0 }, line);
+
+      for jump in body_jumps {
+        self.chunk.patch_jump(jump)?;
+      }
+
+      self.case_body()?;
+      end_jumps.push(self.chunk.push_jump(Opcode::Jump { offset: 0 }, line));
+      self.chunk.patch_jump(next_case)?;
+    }
+
+    if self.match_(TokenType::Default)? {
+      self.consume(TokenType::Colon, SyntaxError::MissingCaseColon)?;
+      self.case_body()?;
+    }
+
+    self.consume(TokenType::RightBrace, SyntaxError::UnexpectedTokenInSwitch)?;
+
+    for jump in end_jumps {
+      self.chunk.patch_jump(jump)?;
+    }
+
+    self.end_scope();
+
+    Ok(())
+  }
+
+  /// Statements following a `case` or `default` label, up to the next one.
+  fn case_body(&mut self) -> Result<()> {
+    self.begin_scope();
+
+    while ![TokenType::Case, TokenType::Default, TokenType::RightBrace, TokenType::Eof]
+      .contains(&self.current().kind)
+    {
+      self.declaration()?;
+    }
+
+    self.end_scope();
+
+    Ok(())
+  }
+
+  /// Compiles `assert condition, message;`. The message is only evaluated
+  /// when the condition is falsey.
+  fn assert_statement(&mut self) -> Result<()> {
+    let line = self.previous().line;
+
+    self.expression()?;
+
+    let failure_jump = self.chunk.push_jump(Opcode::JumpIfFalse { offset: 0 }, line);
+    self.chunk.push_code(Opcode::Pop, line);
+    let end_jump = self.chunk.push_jump(Opcode::Jump { offset: 0 }, line);
+
+    self.chunk.patch_jump(failure_jump)?;
+    self.chunk.push_code(Opcode::Pop, line);
+
+    if self.match_(TokenType::Comma)? {
+      self.expression()?;
+    } else {
+      self.chunk.push_code(Opcode::Nil, line);
+    }
+
+    self.consume(TokenType::Semicolon, SyntaxError::MissingSemicolon)?;
+    self.chunk.push_code(Opcode::AssertFailed, line);
+    self.chunk.patch_jump(end_jump)?;
+
+    Ok(())
+  }
+
+  fn print_statement(&mut self) -> Result<()> {
+    self.expression()?;
+    self.consume(TokenType::Semicolon, SyntaxError::MissingSemicolon)?;
+
+    let line = self.previous().line;
+    self.chunk.push_code(Opcode::Print, line);
+
+    Ok(())
+  }
+
+  fn while_statement(&mut self, label: Option<String>) -> Result<()> {
+    let line = self.previous().line;
+    let loop_start = self.chunk.code.len();
+
+    self.consume(TokenType::LeftParen, SyntaxError::MissingLeftParen)?;
+    self.expression()?;
+    self.consume(TokenType::RightParen, SyntaxError::MissingRightParen)?;
+
+    let exit_jump = self.chunk.push_jump(Opcode::JumpIfFalse { offset: 0 }, line);
+    // The condition is popped on both paths
+    self.chunk.push_code(Opcode::Pop, line);
+
+    let breaks = self.loop_body(label, loop_start)?;
+    self.chunk.push_loop(loop_start, line)?;
+
+    self.chunk.patch_jump(exit_jump)?;
+    self.chunk.push_code(Opcode::Pop, line);
+    self.patch_breaks(breaks)?;
+
+    Ok(())
+  }
+
+  /// Compiles `for (initializer; condition; increment) body`. The increment
+  /// comes before the body in the code, so the body jumps back to it.
+  fn for_statement(&mut self, label: Option<String>) -> Result<()> {
+    let line = self.previous().line;
+
+    self.begin_scope();
+    self.consume(TokenType::LeftParen, SyntaxError::MissingLeftParen)?;
+
+    if let TokenType::Identifier(variable) = self.current().kind.clone() {
+      self.advance()?;
+
+      if self.match_(TokenType::In)? {
+        self.range_for(variable, label, line)?;
+        self.end_scope();
+
+        return Ok(());
+      }
+
+      // The identifier starts the initializer expression
+      self.parse_precedence_from_previous(ASSIGNMENT_PREC)?;
+      self.end_expression_statement()?;
+    } else if self.match_(TokenType::Semicolon)? {
+      // No initializer
+    } else if self.match_(TokenType::Var)? {
+      self.var_declaration()?;
+    } else {
+      self.expression_statement()?;
+    }
+
+    let mut loop_start = self.chunk.code.len();
+
+    let exit_jump = if self.match_(TokenType::Semicolon)? {
+      None
+    } else {
+      self.expression()?;
+      self.consume(TokenType::Semicolon, SyntaxError::MissingSemicolon)?;
+
+      let exit_jump = self.chunk.push_jump(Opcode::JumpIfFalse { offset: 0 }, line);
+      self.chunk.push_code(Opcode::Pop, line);
+
+      Some(exit_jump)
+    };
+
+    if !self.match_(TokenType::RightParen)? {
+      let body_jump = self.chunk.push_jump(Opcode::Jump { offset: 0 }, line);
+      let increment_start = self.chunk.code.len();
+
+      self.expression()?;
+      self.chunk.push_code(Opcode::Pop, line);
+      self.consume(TokenType::RightParen, SyntaxError::MissingRightParen)?;
+
+      self.chunk.push_loop(loop_start, line)?;
+      loop_start = increment_start;
+      self.chunk.patch_jump(body_jump)?;
+    }
+
+    let breaks = self.loop_body(label, loop_start)?;
+    self.chunk.push_loop(loop_start, line)?;
+
+    if let Some(exit_jump) = exit_jump {
+      self.chunk.patch_jump(exit_jump)?;
+      self.chunk.push_code(Opcode::Pop, line);
+    }
+
+    self.patch_breaks(breaks)?;
+
+    self.end_scope();
+
+    Ok(())
+  }
+
+  /// The rest of `for (variable in start..end) body`, after `in`. The next
+  /// value and the end are kept in hidden locals, and every iteration binds
+  /// a fresh `variable` to the next value, so assigning to it in the body
+  /// doesn't change the iteration.
+  fn range_for(&mut self, variable: String, label: Option<String>, line: u32) -> Result<()> {
+    // No variable can clash with a name containing a space
+    self.declare_local("range next".to_string())?;
+    let next = self.locals.len() - 1;
+    self.parse_precedence(BIT_OR_PREC)?;
+    self.locals[next].depth = Some(self.scope_depth);
+
+    let inclusive = if self.match_(TokenType::DotDot)? {
+      false
+    } else if self.match_(TokenType::DotDotEqual)? {
+      true
+    } else {
+      return Err(SyntaxError::MissingRange.into());
+    };
+
+    self.declare_local("range end".to_string())?;
+    let end = self.locals.len() - 1;
+    self.parse_precedence(BIT_OR_PREC)?;
+    self.locals[end].depth = Some(self.scope_depth);
+
+    self.consume(TokenType::RightParen, SyntaxError::MissingRightParen)?;
+
+    let loop_start = self.chunk.code.len();
+
+    self.chunk.push_code(Opcode::GetLocal { slot: next }, line);
+    self.chunk.push_code(Opcode::GetLocal { slot: end }, line);
+
+    let comparison = if inclusive { Opcode::LessEqual } else { Opcode::Less };
+    self.chunk.push_code(comparison, line);
+
+    let exit_jump = self.chunk.push_jump(Opcode::JumpIfFalse { offset: 0 }, line);
+    self.chunk.push_code(Opcode::Pop, line);
+    let body_jump = self.chunk.push_jump(Opcode::Jump { offset: 0 }, line);
+
+    let increment_start = self.chunk.code.len();
+    self.push_update(Opcode::GetLocal { slot: next }, Opcode::SetLocal { slot: next }, Opcode::Add, line)?;
+    self.chunk.push_code(Opcode::Pop, line);
+    self.chunk.push_loop(loop_start, line)?;
+    self.chunk.patch_jump(body_jump)?;
+
+    // `break` and `continue` pop the variable too, so the loop is entered
+    // before its scope
+    self.loops.push(Loop { label, start: increment_start, scope_depth: self.scope_depth, breaks: vec![] });
+    self.begin_scope();
+    self.declare_local(variable)?;
+    let slot = self.locals.len() - 1;
+    self.chunk.push_code(Opcode::GetLocal { slot: next }, line);
+    self.locals[slot].depth = Some(self.scope_depth);
+
+    let body = self.statement();
+    self.end_scope();
+    let enclosing = self.loops.pop().unwrap();
+    body?;
+
+    self.chunk.push_loop(increment_start, line)?;
+    self.chunk.patch_jump(exit_jump)?;
+    self.chunk.push_code(Opcode::Pop, line);
+    self.patch_breaks(enclosing.breaks)
+  }
+
+  fn block(&mut self) -> Result<()> {
+    while !self.check(TokenType::RightBrace) && !self.check(TokenType::Eof) {
+      self.declaration()?;
+    }
+
+    self.consume(TokenType::RightBrace, SyntaxError::MissingRightBrace)
+  }
+
+  fn begin_scope(&mut self) {
+    self.scope_depth += 1;
+  }
+
+  /// Pops the locals declared in the scope being closed.
+  fn end_scope(&mut self) {
+    self.scope_depth -= 1;
+
+    let line = self.previous().line;
+
+    while self.locals.last().is_some_and(|local| local.depth > Some(self.scope_depth)) {
+      self.locals.pop();
+      self.chunk.push_code(Opcode::Pop, line);
+    }
+  }
+
+  fn var_declaration(&mut self) -> Result<()> {
+    let TokenType::Identifier(name) = self.current().kind.clone() else {
+      return Err(SyntaxError::MissingVariableName.into());
+    };
+    self.advance()?;
+
+    let line = self.previous().line;
+
+    // Globals take their name constant first, while it still fits in a byte
+    let global = if self.scope_depth > 0 {
+      self.declare_local(name)?;
+
+      None
+    } else {
+      Some(self.identifier_constant(name)?)
+    };
+
+    if self.match_(TokenType::Eqal)? {
+      self.expression()?;
+    } else {
+      self.chunk.push_code(Opcode::Nil, line);
+    }
+
+    self.consume(TokenType::Semicolon, SyntaxError::MissingSemicolon)?;
+
+    match (global, self.locals.last_mut()) {
+      (Some(index), _) => self.chunk.push_code(Opcode::DefineGlobal { index }, line),
+      // The value is already in the local's slot
+      (None, Some(local)) => local.depth = Some(self.scope_depth),
+      (None, None) => unreachable!("local was declared"),
+    }
+
+    Ok(())
+  }
+
+  fn declare_local(&mut self, name: String) -> Result<()> {
+    let redeclared = self
+      .locals
+      .iter()
+      .rev()
+      .take_while(|local| local.depth.is_none_or(|depth| depth == self.scope_depth))
+      .any(|local| local.name == name);
+
+    if redeclared {
+      return Err(SyntaxError::VariableRedeclared(name).into());
+    }
+
+    // Slots are addressed by a single byte
+    if self.locals.len() > u8::MAX as usize {
+      return Err(SyntaxError::TooManyLocals.into());
+    }
+
+    self.locals.push(Local { name, depth: None });
+
+    Ok(())
+  }
+
+  /// Slot of the innermost local called `name`, `None` for globals.
+  fn resolve_local(&self, name: &str) -> Result<Option<usize>> {
+    let Some(slot) = self.locals.iter().rposition(|local| local.name == name) else {
+      return Ok(None);
+    };
+
+    if self.locals[slot].depth.is_none() {
+      return Err(SyntaxError::LocalInOwnInitializer(name.to_string()).into());
+    }
+
+    Ok(Some(slot))
+  }
+
+  fn expression_statement(&mut self) -> Result<()> {
     self.expression()?;
+    self.end_expression_statement()
+  }
+
+  /// The semicolon ending an expression statement, whose value is dropped.
+  fn end_expression_statement(&mut self) -> Result<()> {
+    self.consume(TokenType::Semicolon, SyntaxError::MissingSemicolon)?;
+
+    let line = self.previous().line;
+    self.chunk.push_code(Opcode::Pop, line);
 
     Ok(())
   }
 
-  pub(crate) fn take_chunk(self) -> Chunk {
-    self.chunk
+  /// Stores `name` in the constant pool, for instructions referring to
+  /// variables by name.
+  fn identifier_constant(&mut self, name: String) -> Result<usize> {
+    let name = self.heap.alloc_string(name);
+    let index = self.chunk.add_constant(name)?;
+
+    // Global instructions have a single operand byte
+    if index > u8::MAX as usize {
+      return Err(SyntaxError::TooManyGlobalNames.into());
+    }
+
+    Ok(index)
+  }
+
+  /// Returns the compiled chunk and the heap holding the objects its
+  /// constants refer to.
+  pub(crate) fn take_chunk(self) -> (Chunk, ObjHeap) {
+    (self.chunk, self.heap)
+  }
+
+  /// Whether `parse_prefix` can compile an expression starting with `token_type`.
+  fn starts_expression(&self, token_type: &TokenType) -> bool {
+    matches!(
+      token_type,
+      TokenType::Identifier(_) | TokenType::PlusPlus | TokenType::MinusMinus | TokenType::Number(_) | TokenType::String(_)
+        | TokenType::Interpolation(_) | TokenType::True | TokenType::False | TokenType::Nil | TokenType::Minus
+        | TokenType::Bang | TokenType::LeftParen
+    )
   }
 
   fn get_precedence(&self, token_type: &TokenType) -> u16 {
@@ -59,18 +736,63 @@ impl Parser {
       TokenType::LessEqual => EQUALITY_PREC,
       TokenType::Greater => EQUALITY_PREC,
       TokenType::GreaterEqual => EQUALITY_PREC,
+      TokenType::Is => EQUALITY_PREC,
+      TokenType::Pipe => BIT_OR_PREC,
+      TokenType::Caret => BIT_XOR_PREC,
+      TokenType::Ampersand => BIT_AND_PREC,
+      TokenType::LessLess => SHIFT_PREC,
+      TokenType::GreaterGreater => SHIFT_PREC,
+      TokenType::And => AND_PREC,
+      TokenType::Or => OR_PREC,
+      TokenType::LeftParen => CALL_PREC,
       _ => NONE_PREC,
     }
   }
 
-  fn parse_prefix(&mut self) -> Result<()> {
+  fn parse_prefix(&mut self, can_assign: bool) -> Result<()> {
     let token = self.previous();
     match &token.kind {
+      TokenType::Identifier(name) => {
+        let line = token.line;
+        let name = name.clone();
+
+        let (get, set) = self.variable_opcodes(name)?;
+
+        if can_assign && self.match_(TokenType::Eqal)? {
+          self.expression()?;
+          self.chunk.push_code(set, line);
+        } else if let Some(operator) = self.match_update_operator()? {
+          // The old value stays below the updated one, which is popped
+          self.chunk.push_code(get.clone(), line);
+          self.push_update(get, set, operator, line)?;
+          self.chunk.push_code(Opcode::Pop, line);
+        } else {
+          self.chunk.push_code(get, line);
+        }
+      },
+      TokenType::PlusPlus | TokenType::MinusMinus => {
+        let line = token.line;
+        let operator = if token.kind == TokenType::PlusPlus { Opcode::Add } else { Opcode::Subtract };
+
+        let TokenType::Identifier(name) = self.current().kind.clone() else {
+          return Err(SyntaxError::InvalidUpdateTarget.into());
+        };
+        self.advance()?;
+
+        let (get, set) = self.variable_opcodes(name)?;
+        self.push_update(get, set, operator, line)?;
+      },
       TokenType::Number(value) => {
-        self.chunk.push_constant(Value::Number(*value), token.line);
+        self.chunk.push_constant(Value::Number(*value), token.line)?;
       },
       TokenType::String(value) => {
-        self.chunk.push_constant(Value::String(value.clone()), token.line);
+        let line = token.line;
+        let string = self.heap.alloc_string(value.clone());
+        self.chunk.push_constant(string, line)?;
+      },
+      TokenType::Interpolation(value) => {
+        let value = value.clone();
+        self.interpolation(value)?;
       },
       TokenType::True => {
         self.chunk.push_code(Opcode::True, token.line);
@@ -81,24 +803,121 @@ impl Parser {
       TokenType::Nil => {
         self.chunk.push_code(Opcode::Nil, token.line);
       }
-      TokenType::Minus => {
+      TokenType::Minus | TokenType::Bang => {
         self.parse_unary()?;
       },
       TokenType::LeftParen => {
         self.expression()?;
-        self.consume(TokenType::RightParen, SyntaxError::MissingSemicolon)?;
+        self.consume(TokenType::RightParen, SyntaxError::MissingRightParen)?;
       },
-      _ => panic!("Unexpected token for prefix: {:?}", token)
+      _ => unreachable!("{:?} does not start an expression", token.kind)
     };
 
     Ok(())
   }
 
+  /// Instructions reading and writing the variable `name`.
+  fn variable_opcodes(&mut self, name: String) -> Result<(Opcode, Opcode)> {
+    Ok(match self.resolve_local(&name)? {
+      Some(slot) => (Opcode::GetLocal { slot }, Opcode::SetLocal { slot }),
+      None => {
+        let index = self.identifier_constant(name)?;
+
+        (Opcode::GetGlobal { index }, Opcode::SetGlobal { index })
+      }
+    })
+  }
+
+  /// Matches a postfix `++` or `--` and returns the instruction it applies.
+  fn match_update_operator(&mut self) -> Result<Option<Opcode>> {
+    if self.match_(TokenType::PlusPlus)? {
+      Ok(Some(Opcode::Add))
+    } else if self.match_(TokenType::MinusMinus)? {
+      Ok(Some(Opcode::Subtract))
+    } else {
+      Ok(None)
+    }
+  }
+
+  /// Adds or subtracts one from a variable, leaving the new value on the
+  /// stack.
+  fn push_update(&mut self, get: Opcode, set: Opcode, operator: Opcode, line: u32) -> Result<()> {
+    self.chunk.push_code(get, line);
+    self.chunk.push_constant(Value::Number(1.), line)?;
+    self.chunk.push_code(operator, line);
+    self.chunk.push_code(set, line);
+
+    Ok(())
+  }
+
+  /// Compiles the arguments of a call up to and including the closing paren,
+  /// returning how many there are.
+  fn arguments(&mut self) -> Result<usize> {
+    let mut arg_count = 0;
+
+    while !self.match_(TokenType::RightParen)? {
+      self.expression()?;
+      arg_count += 1;
+
+      if arg_count > u8::MAX as usize {
+        return Err(SyntaxError::TooManyArguments.into());
+      }
+
+      // A trailing comma is allowed before the closing paren
+      if !self.match_(TokenType::Comma)? {
+        self.consume(TokenType::RightParen, SyntaxError::MissingRightParen)?;
+
+        break;
+      }
+    }
+
+    Ok(arg_count)
+  }
+
   fn parse_infix(&mut self) -> Result<()> {
     let operator_token = self.previous().clone();
 
     match operator_token.kind {
+      TokenType::LeftParen => {
+        let arg_count = self.arguments()?;
+
+        self.chunk.push_code(Opcode::Call { arg_count }, operator_token.line);
+      }
+      TokenType::Is => {
+        // Checked before consuming the name, so errors point at it
+        let TokenType::Identifier(name) = self.current().kind.clone() else {
+          return Err(SyntaxError::MissingTypeName.into());
+        };
+        let Some(type_) = ValueType::ALL.into_iter().find(|type_| type_.name() == name) else {
+          return Err(SyntaxError::UnknownType(name).into());
+        };
+
+        self.advance()?;
+        self.chunk.push_code(Opcode::Is { type_ }, operator_token.line);
+      }
+      TokenType::And => {
+        let line = operator_token.line;
+
+        let left_false = self.chunk.push_jump(Opcode::JumpIfFalse { offset: 0 }, line);
+        self.chunk.push_code(Opcode::Pop, line);
+        self.parse_precedence(AND_PREC + 1)?;
+
+        self.push_truthy_or_false(&[left_false], line)?;
+      }
+      TokenType::Or => {
+        let line = operator_token.line;
+
+        let right = self.chunk.push_jump(Opcode::JumpIfFalse { offset: 0 }, line);
+        let left_true = self.chunk.push_jump(Opcode::Jump { offset: 0 }, line);
+        self.chunk.patch_jump(right)?;
+        self.chunk.push_code(Opcode::Pop, line);
+        self.parse_precedence(OR_PREC + 1)?;
+
+        self.push_truthy_or_false(&[], line)?;
+        self.chunk.patch_jump(left_true)?;
+      }
       TokenType::Plus |
+      TokenType::Minus |
       TokenType::Star |
       TokenType::Slash |
       TokenType::BangEqual |
@@ -106,7 +925,12 @@ impl Parser {
       TokenType::LessEqual |
       TokenType::GreaterEqual |
       TokenType::Less |
-      TokenType::Greater => {
+      TokenType::Greater |
+      TokenType::Ampersand |
+      TokenType::Pipe |
+      TokenType::Caret |
+      TokenType::LessLess |
+      TokenType::GreaterGreater => {
         // parse right
         // TODO: support left and right associativity
         self.parse_precedence(self.get_precedence(&operator_token.kind) + 1)?;
@@ -132,12 +956,10 @@ impl Parser {
             self.chunk.push_code(Opcode::Equal, operator_token.line);
           },
           TokenType::LessEqual => {
-            self.chunk.push_code(Opcode::Greater, operator_token.line);
-            self.chunk.push_code(Opcode::Not, operator_token.line);
+            self.chunk.push_code(Opcode::LessEqual, operator_token.line);
           },
           TokenType::GreaterEqual => {
-            self.chunk.push_code(Opcode::Less, operator_token.line);
-            self.chunk.push_code(Opcode::Not, operator_token.line);
+            self.chunk.push_code(Opcode::GreaterEqual, operator_token.line);
           },
           TokenType::Less => {
             self.chunk.push_code(Opcode::Less, operator_token.line);
@@ -145,6 +967,21 @@ impl Parser {
           TokenType::Greater => {
             self.chunk.push_code(Opcode::Greater, operator_token.line);
           }
+          TokenType::Ampersand => {
+            self.chunk.push_code(Opcode::BitAnd, operator_token.line);
+          }
+          TokenType::Pipe => {
+            self.chunk.push_code(Opcode::BitOr, operator_token.line);
+          }
+          TokenType::Caret => {
+            self.chunk.push_code(Opcode::BitXor, operator_token.line);
+          }
+          TokenType::LessLess => {
+            self.chunk.push_code(Opcode::ShiftLeft, operator_token.line);
+          }
+          TokenType::GreaterGreater => {
+            self.chunk.push_code(Opcode::ShiftRight, operator_token.line);
+          }
           _ => panic!("This will not happen, but compiler needs to be happpy.")
         }
       }
@@ -154,10 +991,71 @@ impl Parser {
     Ok(())
   }
 
+  /// Completes `and`/`or`, which like in the tree-walking interpreter
+  /// evaluate to `false` rather than to a falsy operand. Keeps the truthy
+  /// value on top of the stack, otherwise replaces it with `false`, and
+  /// makes `falsy_jumps` land on the replacement.
+  fn push_truthy_or_false(&mut self, falsy_jumps: &[usize], line: u32) -> Result<()> {
+    let right_false = self.chunk.push_jump(Opcode::JumpIfFalse { offset: 0 }, line);
+    let end = self.chunk.push_jump(Opcode::Jump { offset: 0 }, line);
+
+    for jump in falsy_jumps.iter().chain([&right_false]) {
+      self.chunk.patch_jump(*jump)?;
+    }
+
+    self.chunk.push_code(Opcode::Pop, line);
+    self.chunk.push_code(Opcode::False, line);
+    self.chunk.patch_jump(end)?;
+
+    Ok(())
+  }
+
   pub(crate) fn parse_precedence(&mut self, prec: u16) -> Result<()> {
+    // Checked before consuming the token, so the error points at it
+    if !self.starts_expression(&self.current().kind) {
+      return Err(SyntaxError::ExpectedExpression.into());
+    }
+
     self.advance()?;
+    self.parse_precedence_from_previous(prec)
+  }
+
+  /// Compiles `"a ${b} c"` as `"a " + b + " c"`, after the first part. That
+  /// part is kept even when empty, so `Add` always concatenates strings.
+  fn interpolation(&mut self, first: String) -> Result<()> {
+    let line = self.previous().line;
+    let string = self.heap.alloc_string(first);
+    self.chunk.push_constant(string, line)?;
+
+    loop {
+      self.expression()?;
+      self.chunk.push_code(Opcode::Add, line);
+
+      let (part, is_last) = match self.current().kind.clone() {
+        TokenType::Interpolation(part) => (part, false),
+        TokenType::String(part) => (part, true),
+        _ => return Err(SyntaxError::UnterminatedInterpolation.into()),
+      };
+
+      self.advance()?;
+
+      if !part.is_empty() {
+        let string = self.heap.alloc_string(part);
+        self.chunk.push_constant(string, line)?;
+        self.chunk.push_code(Opcode::Add, line);
+      }
+
+      if is_last {
+        return Ok(());
+      }
+    }
+  }
 
-    self.parse_prefix()?;
+  /// Like `parse_precedence`, with the first token of the expression already
+  /// consumed.
+  fn parse_precedence_from_previous(&mut self, prec: u16) -> Result<()> {
+    let can_assign = prec <= ASSIGNMENT_PREC;
+    self.parse_prefix(can_assign)?;
 
     // parse infix
     while prec <= self.get_precedence(&self.current().kind) {
@@ -166,6 +1064,10 @@ impl Parser {
       self.parse_infix()?;
     }
 
+    if can_assign && self.check(TokenType::Eqal) {
+      return Err(SyntaxError::InvalidAssignmentTarget.into());
+    }
+
     Ok(())
   }
 
@@ -179,7 +1081,7 @@ impl Parser {
 
     match operator_token.kind {
       TokenType::Bang => {
-        println!("PREFIX BANG")
+        self.chunk.push_code(Opcode::Not, operator_token.line)
       }
       TokenType::Minus => {
         self.chunk.push_code(Opcode::Negate, operator_token.line)
@@ -192,6 +1094,20 @@ impl Parser {
     Ok(())
   }
   
+  fn check(&self, token_type: TokenType) -> bool {
+    self.current().kind == token_type
+  }
+
+  fn match_(&mut self, token_type: TokenType) -> Result<bool> {
+    if !self.check(token_type) {
+      return Ok(false);
+    }
+
+    self.advance()?;
+
+    Ok(true)
+  }
+
   fn consume(&mut self, token_type: TokenType, err: SyntaxError) -> Result<()> {
     if self.current().kind == token_type {
       self.advance()
@@ -203,9 +1119,18 @@ impl Parser {
   fn advance(&mut self) -> Result<()> {
     std::mem::swap(&mut self.previous, &mut self.current);
 
-    self.current = match self.scanner.next() {
-      Some(token_result) => Some(token_result?),
-      None => None,
+    // Comment tokens carry no meaning for the compiler
+    self.current = loop {
+      match self.scanner.next() {
+        Some(token_result) => {
+          let token = token_result?;
+
+          if !matches!(token.kind, TokenType::Comment(_)) {
+            break Some(token);
+          }
+        }
+        None => break None,
+      }
     };
 
     Ok(())
@@ -226,10 +1151,119 @@ mod tests {
 
   #[test]
   fn test_name() {
-    let scanner = Scanner::new("-(1 + 2) * 2".to_string());
+    let scanner = Scanner::new("print -(1 + 2) * 2;".to_string());
 
     let mut parser = Parser::new(scanner);
 
     parser.parse().unwrap();
   }
+
+  #[test]
+  fn test_ends_with_return() {
+    let mut parser = Parser::new(Scanner::new("print 1 + 2;".to_string()));
+
+    parser.parse().unwrap();
+
+    let (chunk, _) = parser.take_chunk();
+
+    assert!(matches!(chunk.decode(chunk.code.len() - 1), Opcode::Return));
+  }
+
+  #[test]
+  fn test_globals() {
+    let compile = |source: &str| Parser::new(Scanner::new(source.to_string())).parse();
+
+    assert!(compile("var a = 1; var b; a = b = 2; a + b;").is_ok());
+    assert!(compile("var a = 1").is_err());
+    assert!(compile("var a = 1; a").is_err());
+    assert!(compile("var 1 = 1;").is_err());
+    assert!(compile("var a; 1 + a = 2;").is_err());
+  }
+
+  #[test]
+  fn test_locals() {
+    let compile = |source: &str| Parser::new(Scanner::new(source.to_string())).parse();
+
+    assert!(compile("var a = 1; { var a = a; { var a = 2; } }").is_err());
+    assert!(compile("{ var a = 1; var a = 2; }").is_err());
+    assert!(compile("{ var a = 1; { var a = 2; } }").is_ok());
+    assert!(compile("{ var a = 1;").is_err());
+  }
+
+  #[test]
+  fn test_loops() {
+    let compile = |source: &str| Parser::new(Scanner::new(source.to_string())).parse();
+
+    assert!(compile("while (true) {}").is_ok());
+    assert!(compile("for (;;) {}").is_ok());
+    assert!(compile("var i; for (i = 0; i < 3;) i = i + 1;").is_ok());
+    assert!(compile("while true {}").is_err());
+    assert!(compile("for (var i = 0; i < 3; i = i + 1 {}").is_err());
+    assert!(compile("a: while (true) { for (;;) { break a; } continue a; }").is_ok());
+    assert!(compile("var a = 1; a = a + 1; a;").is_ok());
+    assert!(compile("a: print 1;").is_err());
+    assert!(compile("a: while (true) {} break a;").is_err());
+    assert!(compile("continue;").is_err());
+    assert!(compile("for (i in 0..3) print i;").is_ok());
+    assert!(compile("for (i in 0..=3) { var i = 1; }").is_ok());
+    assert!(compile("for (i in 0) print i;").is_err());
+    assert!(compile("for (i in 0..3 print i;").is_err());
+  }
+
+  #[test]
+  fn test_error_positions() {
+    let error = |source: &str| {
+      Parser::new(Scanner::new(source.to_string())).parse().unwrap_err().to_string()
+    };
+
+    assert_eq!(error("var a = 1;\nprint a\nprint a;"), "[line 3, column 1] ';' expected at the end of a statement");
+    assert_eq!(error("print 1;\n  print (2;"), "[line 2, column 11] ')' expected");
+    assert_eq!(error("print 1 @;"), "unexpected character '@' on line 1, column 9");
+    assert_eq!(error("print (;"), "[line 1, column 8] expression expected");
+    assert_eq!(error("var a = 1 +\n  ;"), "[line 2, column 3] expression expected");
+  }
+
+  #[test]
+  fn test_statements() {
+    let compile = |source: &str| Parser::new(Scanner::new(source.to_string())).parse();
+
+    assert!(compile("if (true) print 1; else { print 2; }").is_ok());
+    assert!(compile("if (true) print 1;").is_ok());
+    assert!(compile("print 1").is_err());
+    assert!(compile("if true print 1;").is_err());
+    assert!(compile("switch (1) { case 1, 2: print 1; default: }").is_ok());
+    assert!(compile("switch (1) { print 1; }").is_err());
+    assert!(compile("switch (1) { case 1 print 1; }").is_err());
+    assert!(compile("switch (1) { default: case 1: }").is_err());
+    assert!(compile("print \"${1}\";").is_ok());
+    assert!(compile("print \"${1\";").is_err());
+    assert!(compile("print \"abc;").is_err());
+    assert!(compile("print 1 is Number;").is_ok());
+    assert!(compile("print 1 is Point;").is_err());
+    assert!(compile("print 1 is 2;").is_err());
+  }
+
+  #[test]
+  fn test_limits() {
+    let compile = |source: String| Parser::new(Scanner::new(source)).parse();
+
+    let declarations = |n: usize| (0..n).map(|i| format!("var v{i};")).collect::<String>();
+    let arguments = |n: usize| vec!["nil"; n].join(", ");
+
+    assert!(compile(declarations(256)).is_ok());
+    assert!(compile(declarations(257)).is_err());
+    assert!(compile(format!("{{ {} }}", declarations(256))).is_ok());
+    assert!(compile(format!("{{ {} }}", declarations(257))).is_err());
+    assert!(compile(format!("clock({});", arguments(255))).is_ok());
+    assert!(compile(format!("clock({});", arguments(256))).is_err());
+    assert!(compile(format!("while (true) {{ {} }}", "nil;".repeat(33000))).is_err());
+
+    let numbers = (0..300).map(|i| format!("{i}")).collect::<Vec<String>>().join(" + ");
+    let mut parser = Parser::new(Scanner::new(format!("var total = {numbers};")));
+    parser.parse().unwrap();
+    let (chunk, _) = parser.take_chunk();
+
+    assert!(chunk.instructions().any(|(_, opcode)| matches!(opcode, Opcode::ConstantLong { index: 300 })));
+    assert!(compile(format!("var a = {numbers}; var b;")).is_err());
+  }
 }
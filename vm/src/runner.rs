@@ -1,20 +1,170 @@
+use crate::chunk::Chunk;
+use crate::heap::ObjHeap;
+use crate::loxb;
 use crate::parser::Parser;
+use crate::natives::{self, DefineModule};
+use crate::verifier;
+use crate::vm::{VM, DEFAULT_MAX_STACK};
 use anyhow::Result;
 use scanner::Scanner;
-use crate::vm::VM;
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+use std::time::Duration;
+use thiserror::Error;
+use tracing::{debug, debug_span, trace};
+use web_time::Instant;
+
+pub use crate::parser::LocatedError;
+pub use crate::vm::LocatedRuntimeError;
+
+fn compile(source: String) -> Result<(Chunk, ObjHeap)> {
+  // Scanning happens lazily while compiling, so both share one span
+  let _span = debug_span!("compile").entered();
 
-pub fn run(source: String) -> Result<()> {
   let scanner = Scanner::new(source);
 
   let mut parser = Parser::new(scanner);
 
   parser.parse()?;
 
-  let chunk = parser.take_chunk();
+  let (chunk, heap) = parser.take_chunk();
+  debug!(bytes = chunk.code.len(), "compiled");
+
+  Ok((chunk, heap))
+}
+
+/// Compiles `source` without running it and returns the chunk listing.
+pub fn disassemble(source: String) -> Result<String> {
+  let (chunk, heap) = compile(source)?;
+
+  Ok(chunk.disassemble(&heap))
+}
+
+/// Compiles `source` into the contents of a `.loxb` file.
+pub fn compile_to_bytes(source: String) -> Result<Vec<u8>> {
+  let (chunk, heap) = compile(source)?;
+
+  loxb::encode(&chunk, &heap)
+}
+
+/// Compiles `source` and reports the size and composition of its bytecode.
+/// Everything is compiled into the top-level script until the VM supports
+/// functions, so it is the only entry of the breakdown.
+pub fn stats(source: String) -> Result<String> {
+  let (chunk, _) = compile(source)?;
+
+  let report = format!("<script>\n{}", chunk.stats());
+
+  Ok(report.trim_end().to_string())
+}
+
+/// Names of the optional native modules programs can be allowed to use.
+pub fn available_modules() -> Vec<&'static str> {
+  natives::available_modules()
+}
+
+/// How long each phase of running a program took, in the order they ran.
+pub type Timings = Rc<RefCell<Vec<(&'static str, Duration)>>>;
+
+pub struct RunOptions {
+  /// Print the stack and every instruction to stderr before executing it
+  pub trace: bool,
+  /// Most values the stack may hold before the program fails with a stack
+  /// overflow
+  pub max_stack: usize,
+  /// Receives the output of `print` and the output natives
+  pub stdout: Rc<RefCell<dyn Write>>,
+  /// Receives the output of `eprintln` and the trace
+  pub stderr: Rc<RefCell<dyn Write>>,
+  /// Receives the duration of each phase when set
+  pub timings: Option<Timings>,
+  /// Optional native modules the program may use, see `available_modules`
+  pub allowed_modules: Vec<String>,
+}
+
+impl Default for RunOptions {
+  fn default() -> Self {
+    RunOptions {
+      trace: false,
+      max_stack: DEFAULT_MAX_STACK,
+      stdout: Rc::new(RefCell::new(io::stdout())),
+      stderr: Rc::new(RefCell::new(io::stderr())),
+      timings: None,
+      allowed_modules: vec![],
+    }
+  }
+}
+
+/// Why a program failed to run.
+#[derive(Error, Debug)]
+pub enum RunError {
+  /// The program could not be compiled, or its bytecode could not be loaded
+  #[error(transparent)]
+  Compile(anyhow::Error),
+  /// The program failed while running
+  #[error(transparent)]
+  Runtime(anyhow::Error),
+}
+
+pub fn run(source: String) -> Result<(), RunError> {
+  run_with_options(source, &RunOptions::default())
+}
+
+pub fn run_with_options(source: String, options: &RunOptions) -> Result<(), RunError> {
+  let modules = natives::modules(&options.allowed_modules).map_err(RunError::Compile)?;
+  let timings = options.timings.as_ref();
+  let (chunk, heap) = timed(timings, "compile", || compile(source)).map_err(RunError::Compile)?;
+
+  // The compiler should only produce valid code, check it while developing.
+  // Invalid code is a bug of the compiler, not of the program.
+  if cfg!(debug_assertions) {
+    timed(timings, "verify", || verifier::verify(&chunk, &heap)).map_err(|e| RunError::Runtime(e.into()))?;
+  }
+
+  timed(timings, "execute", || execute(chunk, heap, &modules, options)).map_err(RunError::Runtime)
+}
+
+/// Runs a program compiled to a `.loxb` file by `compile_to_bytes`.
+pub fn run_bytes(bytes: &[u8], options: &RunOptions) -> Result<(), RunError> {
+  let modules = natives::modules(&options.allowed_modules).map_err(RunError::Compile)?;
+  let timings = options.timings.as_ref();
+  let (chunk, heap) = timed(timings, "decode", || loxb::decode(bytes)).map_err(|e| RunError::Compile(e.into()))?;
+  timed(timings, "verify", || verifier::verify(&chunk, &heap)).map_err(|e| RunError::Compile(e.into()))?;
+
+  timed(timings, "execute", || execute(chunk, heap, &modules, options)).map_err(RunError::Runtime)
+}
+
+/// Runs `phase`, adding how long it took to `timings`. The clock is only
+/// read when timings are collected.
+fn timed<T>(timings: Option<&Timings>, name: &'static str, phase: impl FnOnce() -> T) -> T {
+  let start = timings.map(|_| Instant::now());
+  let result = phase();
+
+  if let (Some(timings), Some(start)) = (timings, start) {
+    timings.borrow_mut().push((name, start.elapsed()));
+  }
+
+  result
+}
+
+fn execute(chunk: Chunk, heap: ObjHeap, modules: &[DefineModule], options: &RunOptions) -> Result<()> {
+  trace!("\n{}", chunk);
+
+  let _span = debug_span!("execute").entered();
+
+  let mut vm = VM::new(chunk, heap);
+  vm.max_stack = options.max_stack;
+  vm.stdout = Rc::clone(&options.stdout);
+  vm.stderr = Rc::clone(&options.stderr);
 
-  println!("{}\n", chunk);
+  for define in modules {
+    define(&mut vm);
+  }
 
-  let mut vm = VM::new(chunk);
+  if options.trace {
+    vm.trace = Some(Rc::clone(&options.stderr));
+  }
 
   vm.interpret()?;
 
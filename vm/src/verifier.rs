@@ -0,0 +1,213 @@
+//! Checks chunks before they run, so that bytecode loaded from a `.loxb`
+//! file fails with an error instead of a panic halfway through.
+
+use crate::chunk::{Chunk, Opcode};
+use crate::heap::ObjHeap;
+use thiserror::Error;
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub(crate) enum VerifyError {
+  #[error("invalid instruction at {0:04}")]
+  InvalidInstruction(usize),
+  #[error("constant {index} used at {offset:04} does not exist")]
+  ConstantOutOfRange { offset: usize, index: usize },
+  #[error("constant {index} used at {offset:04} is not a variable name")]
+  NotAVariableName { offset: usize, index: usize },
+  #[error("local slot {slot} used at {offset:04} is not on the stack")]
+  LocalOutOfRange { offset: usize, slot: usize },
+  #[error("jump at {0:04} does not land on an instruction")]
+  InvalidJumpTarget(usize),
+  #[error("instruction at {0:04} pops more values than the stack holds")]
+  StackUnderflow(usize),
+  #[error("stack height at {0:04} depends on how it is reached")]
+  StackMismatch(usize),
+}
+
+/// Values popped and pushed by an instruction that falls through.
+fn stack_effect(opcode: &Opcode) -> (usize, usize) {
+  match opcode {
+    Opcode::Return | Opcode::Jump { .. } | Opcode::Loop { .. } => (0, 0),
+    Opcode::Constant { .. } |
+    Opcode::ConstantLong { .. } |
+    Opcode::True |
+    Opcode::False |
+    Opcode::Nil |
+    Opcode::GetGlobal { .. } |
+    Opcode::GetLocal { .. } => (0, 1),
+    Opcode::Not | Opcode::Negate | Opcode::Is { .. } => (1, 1),
+    Opcode::Equal |
+    Opcode::Greater |
+    Opcode::Less |
+    Opcode::GreaterEqual |
+    Opcode::LessEqual |
+    Opcode::Add |
+    Opcode::Multiply |
+    Opcode::Subtract |
+    Opcode::Divide |
+    Opcode::BitAnd |
+    Opcode::BitOr |
+    Opcode::BitXor |
+    Opcode::ShiftLeft |
+    Opcode::ShiftRight => (2, 1),
+    Opcode::Pop | Opcode::DefineGlobal { .. } | Opcode::Print | Opcode::AssertFailed => (1, 0),
+    Opcode::SetGlobal { .. } | Opcode::SetLocal { .. } | Opcode::JumpIfFalse { .. } => (1, 1),
+    Opcode::Call { arg_count } => (arg_count + 1, 1),
+  }
+}
+
+/// Checks that every instruction decodes, refers to existing constants and
+/// locals, jumps to the start of an instruction, and that the stack never
+/// underflows whichever way an instruction is reached.
+pub(crate) fn verify(chunk: &Chunk, heap: &ObjHeap) -> Result<(), VerifyError> {
+  let mut instructions = vec![];
+  let mut boundaries = vec![false; chunk.code.len() + 1];
+  let mut offset = 0;
+
+  while offset < chunk.code.len() {
+    let opcode = chunk.try_decode(offset).ok_or(VerifyError::InvalidInstruction(offset))?;
+
+    boundaries[offset] = true;
+    offset += opcode.size();
+    instructions.push(opcode);
+  }
+
+  // Running off the end of the code stops the program like `Return`
+  boundaries[chunk.code.len()] = true;
+
+  let mut heights: Vec<Option<usize>> = vec![None; chunk.code.len() + 1];
+  let mut pending = vec![(0, 0)];
+
+  while let Some((offset, height)) = pending.pop() {
+    match heights[offset] {
+      Some(known) if known == height => continue,
+      Some(_) => return Err(VerifyError::StackMismatch(offset)),
+      None => heights[offset] = Some(height),
+    }
+
+    if offset == chunk.code.len() {
+      continue;
+    }
+
+    let opcode = chunk.decode(offset);
+    let next = offset + opcode.size();
+
+    match opcode {
+      Opcode::Constant { index } |
+      Opcode::ConstantLong { index } |
+      Opcode::DefineGlobal { index } |
+      Opcode::GetGlobal { index } |
+      Opcode::SetGlobal { index } => {
+        let Some(constant) = chunk.constants().get(index) else {
+          return Err(VerifyError::ConstantOutOfRange { offset, index });
+        };
+
+        let is_constant = matches!(opcode, Opcode::Constant { .. } | Opcode::ConstantLong { .. });
+
+        if !is_constant && heap.as_str(constant).is_none() {
+          return Err(VerifyError::NotAVariableName { offset, index });
+        }
+      }
+      Opcode::GetLocal { slot } | Opcode::SetLocal { slot } if slot >= height => {
+        return Err(VerifyError::LocalOutOfRange { offset, slot });
+      }
+      _ => {}
+    }
+
+    let (pops, pushes) = stack_effect(&opcode);
+    let height = height.checked_sub(pops).ok_or(VerifyError::StackUnderflow(offset))? + pushes;
+
+    let target = match opcode {
+      Opcode::Jump { offset: jump } | Opcode::JumpIfFalse { offset: jump } => Some(next + jump),
+      Opcode::Loop { offset: jump } => {
+        Some(next.checked_sub(jump).ok_or(VerifyError::InvalidJumpTarget(offset))?)
+      }
+      _ => None,
+    };
+
+    if let Some(target) = target {
+      if !boundaries.get(target).is_some_and(|boundary| *boundary) {
+        return Err(VerifyError::InvalidJumpTarget(offset));
+      }
+
+      pending.push((target, height));
+    }
+
+    if !matches!(opcode, Opcode::Return | Opcode::Jump { .. } | Opcode::Loop { .. }) {
+      pending.push((next, height));
+    }
+  }
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::chunk::Value;
+  use crate::parser::Parser;
+  use scanner::Scanner;
+
+  fn compile(source: &str) -> (Chunk, ObjHeap) {
+    let mut parser = Parser::new(Scanner::new(source.to_string()));
+    parser.parse().unwrap();
+
+    parser.take_chunk()
+  }
+
+  #[test]
+  fn test_compiled_code_verifies() {
+    for source in [
+      "var a = 1; { var b = a; b = b + 1; print b; }",
+      "for (var i = 0; i < 3; i = i + 1) if (i == 1 and true or false) print i; else print -i;",
+      "var a; while (a == nil) a = println(clock(), 1);",
+      "switch (2) { case 1, 2: { var x = 1; print x; } default: print 0; }",
+    ] {
+      let (chunk, heap) = compile(source);
+
+      assert_eq!(verify(&chunk, &heap), Ok(()), "{}", source);
+    }
+  }
+
+  #[test]
+  fn test_invalid_code() {
+    let heap = ObjHeap::new();
+    let verify_code = |code: &[Opcode]| {
+      let mut chunk = Chunk::new();
+      chunk.add_constant(Value::Number(1.)).unwrap();
+
+      for opcode in code {
+        chunk.push_code(opcode.clone(), 1);
+      }
+
+      verify(&chunk, &heap)
+    };
+
+    assert_eq!(verify_code(&[Opcode::Add]), Err(VerifyError::StackUnderflow(0)));
+    assert_eq!(
+      verify_code(&[Opcode::Constant { index: 1 }]),
+      Err(VerifyError::ConstantOutOfRange { offset: 0, index: 1 })
+    );
+    assert_eq!(
+      verify_code(&[Opcode::GetGlobal { index: 0 }]),
+      Err(VerifyError::NotAVariableName { offset: 0, index: 0 })
+    );
+    assert_eq!(
+      verify_code(&[Opcode::Nil, Opcode::GetLocal { slot: 1 }]),
+      Err(VerifyError::LocalOutOfRange { offset: 1, slot: 1 })
+    );
+    assert_eq!(
+      verify_code(&[Opcode::Jump { offset: 1 }, Opcode::Constant { index: 0 }]),
+      Err(VerifyError::InvalidJumpTarget(0))
+    );
+    assert_eq!(
+      verify_code(&[Opcode::True, Opcode::JumpIfFalse { offset: 1 }, Opcode::Nil, Opcode::Pop]),
+      Err(VerifyError::StackMismatch(5))
+    );
+
+    let mut chunk = Chunk::new();
+    chunk.push_code(Opcode::Constant { index: 0 }, 1);
+    chunk.code.pop();
+
+    assert_eq!(verify(&chunk, &heap), Err(VerifyError::InvalidInstruction(0)));
+  }
+}
@@ -1,47 +1,248 @@
-use crate::chunk::{Chunk, Opcode, Value};
+use crate::chunk::{Chunk, Opcode, Slot, Value, ValueType};
+use crate::heap::{Native, NativeFn, Obj, ObjHeap, ObjRef};
+use crate::natives;
 use anyhow::{anyhow, Context, Result};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::rc::Rc;
+use thiserror::Error;
+use tracing::debug;
+
+/// Values the stack may hold unless configured otherwise.
+pub(crate) const DEFAULT_MAX_STACK: usize = 1 << 16;
+
+#[derive(Error, Debug)]
+pub(crate) enum RuntimeError {
+  #[error("stack overflow: more than {0} values on the stack")]
+  StackOverflow(usize),
+  #[error("{name} expects {expected} arguments but got {given}")]
+  ArityMismatch {
+    expected: usize,
+    given: usize,
+    name: &'static str,
+  },
+  #[error("a string can only be repeated a whole, non-negative number of times, not {0}")]
+  InvalidRepeatCount(f64),
+  #[error("assertion failed{}", .0.as_ref().map(|message| format!(": {message}")).unwrap_or_default())]
+  AssertionFailed(Option<String>),
+}
+
+/// A runtime error with the line of the instruction that failed.
+#[derive(Error, Debug)]
+#[error("[line {line}] {error}")]
+pub struct LocatedRuntimeError {
+  pub line: u32,
+  pub error: anyhow::Error,
+}
+
+/// Applies a bitwise instruction to numbers truncated to integers. Shifts
+/// are logical, shifting by 64 or more bits gives 0 and negative amounts
+/// shift the other way, like in Lua.
+fn bitwise(opcode: &Opcode, a: i64, b: i64) -> i64 {
+  let shift_left = |a: i64, b: i64| match b {
+    64.. | ..=-64 => 0,
+    0.. => ((a as u64) << b) as i64,
+    _ => ((a as u64) >> -b) as i64,
+  };
+
+  match opcode {
+    Opcode::BitAnd => a & b,
+    Opcode::BitOr => a | b,
+    Opcode::BitXor => a ^ b,
+    Opcode::ShiftLeft => shift_left(a, b),
+    Opcode::ShiftRight => shift_left(a, b.saturating_neg()),
+    _ => unreachable!("{} is not a bitwise instruction", opcode.name()),
+  }
+}
 
 pub(crate) struct VM {
   chunk: Chunk,
-  stack: Vec<Value>,
+  stack: Vec<Slot>,
+  pub(crate) heap: ObjHeap,
+  /// Keyed by the interned variable name
+  globals: HashMap<ObjRef, Value>,
+  /// Receives the output of `print` and the output natives
+  pub(crate) stdout: Rc<RefCell<dyn Write>>,
+  /// Receives the output of `eprintln`
+  pub(crate) stderr: Rc<RefCell<dyn Write>>,
+  /// When set, receives the stack and each instruction before it executes
+  pub(crate) trace: Option<Rc<RefCell<dyn Write>>>,
+  /// Most values the stack may hold before the program is stopped
+  pub(crate) max_stack: usize,
 }
 
 impl VM {
-  pub(crate) fn new(chunk: Chunk) -> Self {
-    VM {
+  pub(crate) fn new(chunk: Chunk, heap: ObjHeap) -> Self {
+    let mut vm = VM {
       stack: vec![],
       chunk,
+      heap,
+      globals: HashMap::new(),
+      stdout: Rc::new(RefCell::new(io::stdout())),
+      stderr: Rc::new(RefCell::new(io::stderr())),
+      trace: None,
+      max_stack: DEFAULT_MAX_STACK,
+    };
+
+    natives::define_all(&mut vm);
+
+    vm
+  }
+
+  /// Makes `function` available to programs as the global `name`. An
+  /// `arity` of `None` accepts any number of arguments.
+  pub(crate) fn define_native(&mut self, name: &'static str, arity: Option<usize>, function: NativeFn) {
+    let native = self.heap.alloc(Obj::Native(Native { name, arity, function }));
+    let Value::Obj(name) = self.heap.alloc_string(name.to_string()) else {
+      unreachable!("strings are objects");
+    };
+
+    self.globals.insert(name, Value::Obj(native));
+  }
+
+  /// The variable name stored in the constant at `index`.
+  fn global_name(&self, index: usize) -> ObjRef {
+    match self.chunk.get_constant(index) {
+      Value::Obj(name) => *name,
+      constant => panic!("constant {:?} is not a variable name", constant),
+    }
+  }
+
+  fn call(&mut self, arg_count: usize) -> Result<()> {
+    let callee_slot = self.stack.len().checked_sub(arg_count + 1).context("empty stack")?;
+
+    let Value::Obj(handle) = self.stack[callee_slot].unpack() else {
+      return Err(anyhow!("can only call functions"));
+    };
+    let Obj::Native(native) = self.heap.get(handle) else {
+      return Err(anyhow!("can only call functions"));
+    };
+
+    if let Some(arity) = native.arity.filter(|arity| *arity != arg_count) {
+      return Err(RuntimeError::ArityMismatch { expected: arity, given: arg_count, name: native.name }.into());
     }
+
+    let function = native.function;
+    let arguments = self
+      .stack
+      .split_off(callee_slot + 1)
+      .into_iter()
+      .map(Slot::unpack)
+      .collect::<Vec<Value>>();
+    self.stack.pop();
+
+    let result = function(self, &arguments)?;
+    self.stack.push(result.pack());
+
+    Ok(())
+  }
+
+  /// The value `distance` slots below the top of the stack.
+  fn peek(&self, distance: usize) -> Value {
+    self.stack[self.stack.len() - 1 - distance].unpack()
   }
 
+  /// Writes the stack and the instruction at `ip`, like the listing shows it.
+  fn trace_step(&self, trace: &RefCell<dyn Write>, ip: usize, opcode: &Opcode) -> Result<()> {
+    let stack = self
+      .stack
+      .iter()
+      .map(|slot| format!("[ {} ]", self.heap.display(&slot.unpack())))
+      .collect::<String>();
+
+    let mut trace = trace.borrow_mut();
+
+    writeln!(trace, "          {}", stack)?;
+    writeln!(trace, "{}", self.chunk.disassemble_instruction(ip, opcode, Some(&self.heap)))?;
+
+    Ok(())
+  }
+
+  /// Runs the chunk. Errors are `LocatedRuntimeError`s with the line of the
+  /// instruction that failed.
   pub(crate) fn interpret(&mut self) -> Result<()> {
+    let mut start = 0;
+
+    self
+      .run(&mut start)
+      .map_err(|error| LocatedRuntimeError { line: self.chunk.line(start), error }.into())
+  }
+
+  /// Executes instructions until the end of the chunk, keeping `start` at
+  /// the offset of the current one.
+  fn run(&mut self, start: &mut usize) -> Result<()> {
     macro_rules! pop_stack {
         () => {
-          self.stack.pop().context("empty stack")?
+          self.stack.pop().context("empty stack")?.unpack()
         };
     }
 
-    // TODO: make `Chunk` an iterator
-    for opcode in self.chunk.code.iter() {
-      match opcode {
+    let mut executed = 0;
+    let mut ip = 0;
+
+    while ip < self.chunk.code.len() {
+      let opcode = self.chunk.decode(ip);
+      *start = ip;
+
+      if let Some(trace) = &self.trace {
+        self.trace_step(trace, ip, &opcode)?;
+      }
+
+      executed += 1;
+      ip += opcode.size();
+
+      match &opcode {
         Opcode::Return => {
-          println!("{:?}", self.stack.pop());
+          break;
+        }
+        Opcode::Call { arg_count } => {
+          let arg_count = *arg_count;
+
+          self.call(arg_count)?;
+        }
+        Opcode::AssertFailed => {
+          let message = match pop_stack!() {
+            Value::Nil => None,
+            message => Some(self.heap.display(&message)),
+          };
+
+          return Err(RuntimeError::AssertionFailed(message).into());
+        }
+        Opcode::Print => {
+          let value = pop_stack!();
+
+          writeln!(self.stdout.borrow_mut(), "{}", self.heap.display(&value))?;
         }
         Opcode::Constant {
           index: constant_index,
+        } |
+        Opcode::ConstantLong {
+          index: constant_index,
         } => {
-          self.stack.push(self.chunk.get_constant(*constant_index).clone());
+          self.stack.push(self.chunk.get_constant(*constant_index).pack());
         }
         Opcode::Negate => {
-          let value = self.stack.last_mut().unwrap();
-
-          if let Value::Number(n) = value {
-            *n = -*n;
-          } else {
+          let Value::Number(n) = pop_stack!() else {
             return Err(anyhow!("only numbers can be negated"));
+          };
+
+          self.stack.push(Value::Number(-n).pack());
+        }
+        Opcode::Multiply if self.heap.as_str(&self.peek(1)).is_some() => {
+          let Value::Number(count) = pop_stack!() else {
+            return Err(anyhow!("expected a number"));
+          };
+          let string = pop_stack!();
+
+          if count < 0. || count.fract() != 0. {
+            return Err(RuntimeError::InvalidRepeatCount(count).into());
           }
+
+          let repeated = self.heap.as_str(&string).unwrap_or_default().repeat(count as usize);
+          self.stack.push(self.heap.alloc_string(repeated).pack());
         }
-        Opcode::Multiply | Opcode::Subtract | Opcode::Divide | Opcode::Less | Opcode::Greater => {
+        Opcode::Multiply | Opcode::Subtract | Opcode::Divide => {
           let Value::Number(b) = pop_stack!() else {
             return Err(anyhow!("expected a number"));
           };
@@ -53,21 +254,51 @@ impl VM {
             Opcode::Subtract => Value::Number(a - b),
             Opcode::Multiply => Value::Number(a * b),
             Opcode::Divide => Value::Number(a / b),
-            Opcode::Less => Value::Bool(a < b),
-            Opcode::Greater => Value::Bool(a > b),
             _ => panic!("Will not happen.")
           };
 
-          self.stack.push(result);
+          self.stack.push(result.pack());
+        },
+        Opcode::BitAnd | Opcode::BitOr | Opcode::BitXor | Opcode::ShiftLeft | Opcode::ShiftRight => {
+          let (Value::Number(b), Value::Number(a)) = (pop_stack!(), pop_stack!()) else {
+            return Err(anyhow!("operands of bitwise operators must be numbers"));
+          };
+
+          self.stack.push(Value::Number(bitwise(&opcode, a as i64, b as i64) as f64).pack());
+        }
+        Opcode::Less | Opcode::Greater | Opcode::LessEqual | Opcode::GreaterEqual => {
+          let b = pop_stack!();
+          let a = pop_stack!();
+
+          // Strings are ordered lexicographically, like in the tree-walking interpreter
+          let ordering = match (&a, &b) {
+            (Value::Number(a), Value::Number(b)) => a.partial_cmp(b),
+            _ => match (self.heap.as_str(&a), self.heap.as_str(&b)) {
+              (Some(a), Some(b)) => Some(a.cmp(b)),
+              _ => return Err(anyhow!("operands must be two numbers or two strings")),
+            },
+          };
+
+          // Unordered operands, like NaN, fail every comparison
+          let result = ordering.is_some_and(|ordering| match opcode {
+            Opcode::Less => ordering.is_lt(),
+            Opcode::Greater => ordering.is_gt(),
+            Opcode::LessEqual => ordering.is_le(),
+            _ => ordering.is_ge(),
+          });
+
+          self.stack.push(Value::Bool(result).pack());
         },
         Opcode::Add => {
           let b = pop_stack!();
           let a = pop_stack!();
 
-          self.stack.push(if let Value::String(_) = a {
-            Value::String(format!("{}{}", a, b))
-          } else if let Value::String(_) = b {
-            Value::String(format!("{}{}", a, b))
+          let is_string = |value| self.heap.as_str(value).is_some();
+
+          let result = if is_string(&a) || is_string(&b) {
+            let concatenated = format!("{}{}", self.heap.display(&a), self.heap.display(&b));
+
+            self.heap.alloc_string(concatenated)
           } else {
             let Value::Number(b) = b else {
               return Err(anyhow!("expected a number"));
@@ -77,32 +308,99 @@ impl VM {
             };
 
             Value::Number(a + b)
-          });
+          };
+
+          self.stack.push(result.pack());
         },
         Opcode::Equal => {
           let a = pop_stack!();
           let b = pop_stack!();
 
-          self.stack.push(Value::Bool(a.is_truthy() == b.is_truthy()));
+          self.stack.push(Value::Bool(a.is_equal(&b)).pack());
         },
         Opcode::Not => {
           let v = pop_stack!().is_truthy();
 
-          self.stack.push(Value::Bool(!v));
+          self.stack.push(Value::Bool(!v).pack());
+        },
+        Opcode::Is { type_ } => {
+          let is_type = match (type_, pop_stack!()) {
+            (ValueType::Number, Value::Number(_)) |
+            (ValueType::Bool, Value::Bool(_)) |
+            (ValueType::Nil, Value::Nil) => true,
+            (ValueType::String, Value::Obj(handle)) => matches!(self.heap.get(handle), Obj::String(_)),
+            (ValueType::Function, Value::Obj(handle)) => matches!(self.heap.get(handle), Obj::Native(_)),
+            _ => false,
+          };
+
+          self.stack.push(Value::Bool(is_type).pack());
         },
         Opcode::True => {
-          self.stack.push(Value::Bool(true));
+          self.stack.push(Value::Bool(true).pack());
         },
         Opcode::False => {
-          self.stack.push(Value::Bool(false));
+          self.stack.push(Value::Bool(false).pack());
         },
         Opcode::Nil => {
-          self.stack.push(Value::Nil);
+          self.stack.push(Value::Nil.pack());
+        },
+        Opcode::Pop => {
+          pop_stack!();
+        },
+        Opcode::DefineGlobal { index } => {
+          let name = self.global_name(*index);
+          let value = pop_stack!();
+
+          self.globals.insert(name, value);
+        },
+        Opcode::GetGlobal { index } => {
+          let name = self.global_name(*index);
+
+          let Some(value) = self.globals.get(&name) else {
+            return Err(anyhow!("undefined variable '{}'", self.heap.display(&Value::Obj(name))));
+          };
+
+          self.stack.push(value.pack());
+        },
+        Opcode::GetLocal { slot } => {
+          self.stack.push(self.stack[*slot]);
+        },
+        Opcode::SetLocal { slot } => {
+          self.stack[*slot] = *self.stack.last().context("empty stack")?;
+        },
+        Opcode::Jump { offset } => {
+          ip += offset;
+        },
+        Opcode::JumpIfFalse { offset } => {
+          if !self.stack.last().context("empty stack")?.unpack().is_truthy() {
+            ip += offset;
+          }
+        },
+        Opcode::Loop { offset } => {
+          ip -= offset;
+        },
+        Opcode::SetGlobal { index } => {
+          let name = self.global_name(*index);
+          let value = self.stack.last().context("empty stack")?.unpack();
+
+          // Assignment never creates a variable
+          let Some(global) = self.globals.get_mut(&name) else {
+            return Err(anyhow!("undefined variable '{}'", self.heap.display(&Value::Obj(name))));
+          };
+
+          *global = value;
         },
       }
+
+      // No instruction pushes more than one value
+      if self.stack.len() > self.max_stack {
+        return Err(RuntimeError::StackOverflow(self.max_stack).into());
+      }
     }
 
-    println!("Result: {:?}", self.stack);
+    debug!(instructions = executed, "executed");
+
+    debug!(stack = ?self.stack, objects = self.heap.len(), "finished");
 
     Ok(())
   }
@@ -111,19 +409,413 @@ impl VM {
 #[cfg(test)]
 mod tests {
   use super::*;
+  use crate::parser::Parser;
+  use scanner::Scanner;
+
+  fn interpret(source: &str) -> (VM, Result<()>) {
+    let mut parser = Parser::new(Scanner::new(source.to_string()));
+    parser.parse().unwrap();
+
+    let (chunk, heap) = parser.take_chunk();
+    let mut vm = VM::new(chunk, heap);
+    vm.stdout = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let result = vm.interpret();
+
+    (vm, result)
+  }
+
+  fn global(vm: &mut VM, name: &str) -> Value {
+    let Value::Obj(name) = vm.heap.alloc_string(name.to_string()) else {
+      unreachable!("strings are objects");
+    };
+
+    vm.globals[&name]
+  }
+
+  fn output(source: &str) -> String {
+    let stdout = Rc::new(RefCell::new(Vec::<u8>::new()));
+
+    let mut parser = Parser::new(Scanner::new(source.to_string()));
+    parser.parse().unwrap();
+
+    let (chunk, heap) = parser.take_chunk();
+    let mut vm = VM::new(chunk, heap);
+    vm.stdout = stdout.clone();
+    vm.interpret().unwrap();
+
+    String::from_utf8(stdout.take()).unwrap()
+  }
 
   #[test]
   fn test_interpret() {
     let mut chunk = Chunk::new();
 
-    chunk.push_constant(Value::Number(1.), 1);
-    chunk.push_constant(Value::Number(2.), 1);
+    chunk.push_constant(Value::Number(1.), 1).unwrap();
+    chunk.push_constant(Value::Number(2.), 1).unwrap();
     chunk.push_code(Opcode::Add, 1);
     chunk.push_code(Opcode::Negate, 1);
     chunk.push_code(Opcode::Return, 1);
 
-    let mut vm = VM::new(chunk);
+    let mut vm = VM::new(chunk, ObjHeap::new());
+
+    vm.interpret().unwrap();
+  }
+
+  #[test]
+  fn test_string_comparison() {
+    let mut chunk = Chunk::new();
+    let mut heap = ObjHeap::new();
+
+    chunk.push_constant(heap.alloc_string("apple".to_string()), 1).unwrap();
+    chunk.push_constant(heap.alloc_string("banana".to_string()), 1).unwrap();
+    chunk.push_code(Opcode::Less, 1);
+    chunk.push_constant(heap.alloc_string("abc".to_string()), 1).unwrap();
+    chunk.push_constant(heap.alloc_string("ab".to_string()), 1).unwrap();
+    chunk.push_code(Opcode::Greater, 1);
+    chunk.push_constant(heap.alloc_string("a".to_string()), 1).unwrap();
+    chunk.push_constant(Value::Number(1.), 1).unwrap();
+    chunk.push_code(Opcode::Less, 1);
+
+    let mut vm = VM::new(chunk, heap);
+
+    assert!(vm.interpret().is_err());
+    assert!(matches!(
+      vm.stack.iter().map(|slot| slot.unpack()).collect::<Vec<Value>>()[..],
+      [Value::Bool(true), Value::Bool(true)]
+    ));
+  }
+
+  #[test]
+  fn test_compiled_string_comparison() {
+    assert_eq!(
+      output(r#"print "b" <= "a"; print "" >= ""; print "Zebra" < "apple"; print "ab" <= "abc";"#),
+      "false\ntrue\ntrue\ntrue\n"
+    );
+    assert!(interpret(r#"print "a" >= 1;"#).1.is_err());
+  }
+
+  #[test]
+  fn test_nan_comparison() {
+    assert_eq!(
+      output("var nan = 0 / 0; print nan <= nan; print nan >= nan; print nan < 1; print 1 >= nan; print 1 <= 1;"),
+      "false\nfalse\nfalse\nfalse\ntrue\n"
+    );
+  }
+
+  #[test]
+  fn test_globals() {
+    let (mut vm, result) = interpret(r#"var a = 1; var b; var s = "x"; b = a = a + 2; s = s + b;"#);
+
+    assert!(result.is_ok());
+    assert!(vm.stack.is_empty());
+    assert!(matches!(global(&mut vm, "a"), Value::Number(3.)));
+    assert!(matches!(global(&mut vm, "b"), Value::Number(3.)));
+    let s = global(&mut vm, "s");
+    assert_eq!(vm.heap.display(&s), "x3");
+
+    assert!(interpret("a;").1.is_err());
+    assert!(interpret("a = 1;").1.is_err());
+  }
+
+  #[test]
+  fn test_locals() {
+    let (mut vm, result) = interpret(
+      "var a = 1; var b; { var a = 10; var c = a + 1; { var a = c; b = a; } a = a + c; b = b + a; } a = a + 1;"
+    );
+
+    assert!(result.is_ok());
+    assert!(vm.stack.is_empty());
+    assert!(matches!(global(&mut vm, "a"), Value::Number(2.)));
+    assert!(matches!(global(&mut vm, "b"), Value::Number(32.)));
+  }
+
+  #[test]
+  fn test_loops() {
+    let (mut vm, result) = interpret(
+      "var n = 0; var sum = 0; while (n < 5) { n = n + 1; sum = sum + n; }
+       var product = 1; for (var i = 1; i <= 4; i = i + 1) { var twice = i + i; product = product * twice; }
+       var countdown = 3; for (; countdown > 0;) countdown = countdown - 1;"
+    );
+
+    assert!(result.is_ok());
+    assert!(vm.stack.is_empty());
+    assert!(matches!(global(&mut vm, "sum"), Value::Number(15.)));
+    assert!(matches!(global(&mut vm, "product"), Value::Number(384.)));
+    assert!(matches!(global(&mut vm, "countdown"), Value::Number(0.)));
+  }
+
+  #[test]
+  fn test_range_loops() {
+    assert_eq!(
+      output(
+        r#"
+          for (i in 0..3) print i;
+          for (i in 1..=2) { i = i * 10; print i; }
+          for (i in 3..0) print "never";
+          var n = 2;
+          outer: for (i in 0..n + 3) {
+            for (j in i..=i + 1) {
+              if (j == 3) continue outer;
+              if (i == 4) break outer;
+              print "${i}${j}";
+            }
+          }
+          { var kept = "kept"; for (i in 0..10) { var x = i; break; } print kept; }
+        "#
+      ),
+      "0\n1\n2\n10\n20\n00\n01\n11\n12\n22\nkept\n"
+    );
+    assert!(interpret("for (i in \"a\"..1) print i;").1.is_err());
+  }
+
+  #[test]
+  fn test_is() {
+    assert_eq!(
+      output(
+        r#"
+          var s = "a";
+          print 1 is Number; print s is String; print s is Number; print nil is Nil; print false is Bool;
+          print println is Function; print "println" is Function; print 1 < 2 is Bool; print !(nil is Nil);
+        "#
+      ),
+      "true\ntrue\nfalse\ntrue\ntrue\ntrue\nfalse\ntrue\nfalse\n"
+    );
+  }
+
+  #[test]
+  fn test_logical_operators() {
+    let (mut vm, result) = interpret(
+      "var calls = 0; var a = 1 and 2; var b = nil and (calls = 1); var c = 1 and nil;
+       var d = 1 or (calls = 2); var e = nil or 3; var f = nil or false; var g = 1 < 2 and 2 < 3 or x;"
+    );
+
+    assert!(result.is_ok());
+    assert!(vm.stack.is_empty());
+    assert!(matches!(global(&mut vm, "calls"), Value::Number(0.)));
+    assert!(matches!(global(&mut vm, "a"), Value::Number(2.)));
+    assert!(matches!(global(&mut vm, "b"), Value::Bool(false)));
+    assert!(matches!(global(&mut vm, "c"), Value::Bool(false)));
+    assert!(matches!(global(&mut vm, "d"), Value::Number(1.)));
+    assert!(matches!(global(&mut vm, "e"), Value::Number(3.)));
+    assert!(matches!(global(&mut vm, "f"), Value::Bool(false)));
+    assert!(matches!(global(&mut vm, "g"), Value::Bool(true)));
+  }
+
+  #[test]
+  fn test_bitwise_operators() {
+    assert_eq!(
+      output("print 6 & 3; print 6 | 3; print 6 ^ 3; print 1 << 4; print -16 >> 60; print 5.9 & 7;
+              print 1 | 2 == 3; print 1 << 64; print 8 << -2;"),
+      "2\n7\n5\n16\n15\n5\ntrue\n0\n2\n"
+    );
+
+    assert!(interpret("print 1 & nil;").1.is_err());
+    assert!(interpret("print \"a\" << 1;").1.is_err());
+  }
+
+  #[test]
+  fn test_string_repetition() {
+    assert_eq!(output("print \"ab\" * 3; print \"x\" * 0 == \"\"; print \"-\" * 2 + \">\";"), "ababab\ntrue\n-->\n");
+
+    assert!(interpret("print \"a\" * -1;").1.is_err());
+    assert!(interpret("print \"a\" * 1.5;").1.is_err());
+    assert!(interpret("print \"a\" * \"b\";").1.is_err());
+  }
+
+  #[test]
+  fn test_switch() {
+    assert_eq!(
+      output(
+        r#"
+          for (var n = 0; n < 5; n = n + 1) {
+            switch (n) {
+              case 0: print "zero";
+              case 1, 2:
+                var word = "small";
+                print word;
+              default: print n;
+            }
+          }
+          switch ("a") { case "b": print "no"; }
+          { var x = 1; switch (x + 1) { case 2: print x; } print x; }
+        "#
+      ),
+      "zero\nsmall\nsmall\n3\n4\n1\n1\n"
+    );
+  }
+
+  #[test]
+  fn test_break_and_continue() {
+    assert_eq!(
+      output(
+        r#"
+          outer: for (var i = 0; i < 3; i++) {
+            var a = i;
+            for (var j = 0; j < 3; j++) {
+              var b = j;
+              if (b == 1) continue outer;
+              if (a == 2) break outer;
+              print a * 10 + b;
+            }
+          }
+          var n = 0;
+          while (true) {
+            n++;
+            { var skipped = n; if (skipped == 2) continue; }
+            if (n > 3) break;
+            print n;
+          }
+          { var kept = "kept"; for (;;) { var x = 1; break; } print kept; }
+        "#
+      ),
+      "0
+10
+1
+3
+kept
+"
+    );
+  }
+
+  #[test]
+  fn test_increments() {
+    assert_eq!(
+      output("var i = 1; print i++; print i; print ++i; print i--; print --i;
+              for (var j = 0; j < 3; j++) { var k = j; print 10 - k--; print k; }"),
+      "1\n2\n3\n3\n1\n10\n-1\n9\n0\n8\n1\n"
+    );
+  }
+
+  #[test]
+  fn test_not() {
+    assert_eq!(
+      output("print !true; print !!nil; print !(1 < 2); print !0; print !-1 == false;"),
+      "false\nfalse\nfalse\nfalse\ntrue\n"
+    );
+  }
+
+  #[test]
+  fn test_print() {
+    assert_eq!(
+      output(
+        r#"
+          var greeting = "hello";
+          print greeting + "" == "hel" + "lo";
+          print greeting + " " + "world";
+          for (var i = 0; i < 3; i = i + 1) {
+            if (i == 1) print "one"; else print i;
+          }
+          if (nil) print "never";
+          print 1 < 2;
+        "#
+      ),
+      "true\nhello world\n0\none\n2\ntrue\n"
+    );
+  }
+
+  #[test]
+  fn test_error_lines() {
+    let error = |source: &str| interpret(source).1.unwrap_err().to_string();
+
+    assert_eq!(error("print 1;\nprint -nil;"), "[line 2] only numbers can be negated");
+    assert_eq!(error("var a;\n\na = b;"), "[line 3] undefined variable 'b'");
+    assert_eq!(error("print 1;\nclock(1);"), "[line 2] clock expects 0 arguments but got 1");
+    assert_eq!(error("print \"a\nb\";\nprint -nil;"), "[line 3] only numbers can be negated");
+
+    let error = interpret("print 1;\nprint -nil;").1.unwrap_err();
+    let located = error.downcast_ref::<LocatedRuntimeError>().unwrap();
+
+    assert_eq!((located.line, located.error.to_string().as_str()), (2, "only numbers can be negated"));
+  }
+
+  #[test]
+  fn test_number_literals() {
+    assert_eq!(output("print 0xFF + 0b10 + 1e2 + 1_000; print 2.5e-1;"), "1357\n0.25\n");
+  }
+
+  #[test]
+  fn test_string_interpolation() {
+    assert_eq!(
+      output(r#"var x = 2; { var y = "b"; print "x = ${x + 1}, ${y}${"!"}"; } print "${x}"; print "a ${"b ${x}"}";"#),
+      "x = 3, b!\n2\na b 2\n"
+    );
+  }
+
+  #[test]
+  fn test_assert() {
+    let error = |source: &str| interpret(source).1.unwrap_err().to_string();
+
+    assert_eq!(output("assert 1 < 2, nil + 1; { var a = 1; assert a; print a; }"), "1\n");
+    assert_eq!(error("var x = 1;\nassert x == 2, \"x is \" + \"one\";"), "[line 2] assertion failed: x is one");
+    assert_eq!(error("assert false;"), "[line 1] assertion failed");
+  }
+
+  #[test]
+  fn test_stack_overflow() {
+    let mut parser = Parser::new(Scanner::new("print 1;\nprint 1 + (2 + 3);".to_string()));
+    parser.parse().unwrap();
+
+    let (chunk, heap) = parser.take_chunk();
+    let mut vm = VM::new(chunk, heap);
+    vm.stdout = Rc::new(RefCell::new(io::sink()));
+    vm.max_stack = 2;
 
+    assert_eq!(
+      vm.interpret().unwrap_err().to_string(),
+      "[line 2] stack overflow: more than 2 values on the stack"
+    );
+  }
+
+  #[test]
+  fn test_trace() {
+    let trace = Rc::new(RefCell::new(Vec::<u8>::new()));
+
+    let mut parser = Parser::new(Scanner::new("var a = \"x\"; print 1 + 2;".to_string()));
+    parser.parse().unwrap();
+
+    let (chunk, heap) = parser.take_chunk();
+    let mut vm = VM::new(chunk, heap);
+    vm.stdout = Rc::new(RefCell::new(io::sink()));
+    vm.trace = Some(trace.clone());
     vm.interpret().unwrap();
+
+    let trace = String::from_utf8(trace.take()).unwrap();
+
+    assert!(trace.contains("0002    | DEFINE_GLOBAL  000: \"a\"\n"));
+    assert!(trace.contains("          [ 1 ][ 2 ]\n0008    | ADD"));
+  }
+
+  #[test]
+  fn test_constant_long() {
+    let numbers = (0..300).map(|i| format!("{i}")).collect::<Vec<String>>().join(" + ");
+
+    assert_eq!(output(&format!("print {numbers};")), "44850\n");
+  }
+
+  #[test]
+  fn test_natives() {
+    assert_eq!(
+      output(r#"println("a", 1 + 1, nil); write("b"); write(); print clock() > 0; print println;"#),
+      "a 2 nil\nbtrue\n<native fn println>\n"
+    );
+
+    let stderr = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let mut parser = Parser::new(Scanner::new(r#"eprintln("oops", 1,); println(2,);"#.to_string()));
+    parser.parse().unwrap();
+
+    let (chunk, heap) = parser.take_chunk();
+    let mut vm = VM::new(chunk, heap);
+    vm.stdout = Rc::new(RefCell::new(io::sink()));
+    vm.stderr = stderr.clone();
+    vm.interpret().unwrap();
+
+    assert_eq!(String::from_utf8(stderr.take()).unwrap(), "oops 1\n");
+
+    assert!(interpret("clock(1);").1.is_err());
+    assert!(interpret("var f = 1; f();").1.is_err());
+
+    let (mut vm, _) = interpret("");
+    vm.define_native("answer", Some(0), |_, _| Ok(Value::Number(42.)));
+    assert!(matches!(global(&mut vm, "answer"), Value::Obj(_)));
   }
 }